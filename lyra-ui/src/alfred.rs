@@ -0,0 +1,600 @@
+pub mod schema;
+
+use crate::config::{AlfredAction, AlfredConfig, Config};
+use crate::icon_ui::{file_data_url, Icon};
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginName, PluginV, PluginValue, Renderable,
+  SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use anyhow::{anyhow, Context};
+use arboard::Clipboard;
+use egui::RichText;
+use parking_lot::Mutex;
+use schema::{AlfredArg, AlfredItem, ScriptFilterOutput};
+use std::{
+  collections::HashMap,
+  io::{self, Read},
+  path::{Path, PathBuf},
+  process::{Child, Command, Stdio},
+  sync::{
+    mpsc::{self, Receiver},
+    Arc,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+use tracing::{error, warn};
+
+/// Config section holding every workflow, keyed by the name it's enabled under
+pub const CONFIG_SECTION: &str = "alfred";
+const QUERY_MARKER: &str = "{query}";
+
+/// Runs an existing Alfred Script Filter unchanged: the configured command is executed with
+/// the search, and the Alfred items it prints become options. Actioning an item hands its
+/// `arg` to the configured action, much like the object wired after the Script Filter
+/// in the original workflow would have.
+pub struct AlfredPlugin {
+  name: PluginName,
+  cfg: AlfredConfig,
+  clip: Option<Mutex<Clipboard>>,
+  icons: Mutex<HashMap<PathBuf, Option<String>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AlfredValue {
+  pub plugin: PluginName,
+  pub item: AlfredItem,
+  icon: Option<String>,
+}
+
+impl PluginValue for AlfredValue {
+  fn item_id(&self) -> Option<String> {
+    Some(
+      self
+        .item
+        .uid
+        .clone()
+        .unwrap_or_else(|| self.item.title.clone()),
+    )
+  }
+}
+impl Renderable for AlfredValue {
+  fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
+    let item = &self.item;
+    ui.horizontal(|ui| {
+      if let Some(Ok(ico)) = self
+        .icon
+        .as_ref()
+        .map(|icon| Icon::try_from((icon.as_str(), item.title.as_str())))
+      {
+        ico.render(ui);
+      }
+      ui.label(RichText::new(&item.title));
+      if let Some(subtitle) = &item.subtitle {
+        ui.label(RichText::new(subtitle).weak());
+      }
+    });
+  }
+}
+impl SearchBlocker for AlfredValue {}
+
+impl AlfredPlugin {
+  pub fn init(name: &str, cfg: AlfredConfig) -> Result<Self, anyhow::Error> {
+    check_query_args(&cfg.args)?;
+    if let AlfredAction::Command { args, .. } = &cfg.action {
+      check_query_args(args)?;
+    }
+    let clip = match cfg.action {
+      AlfredAction::Copy => Some(Mutex::new(Clipboard::new()?)),
+      _ => None,
+    };
+    Ok(AlfredPlugin {
+      name: name.to_string(),
+      cfg,
+      clip,
+      icons: Mutex::new(HashMap::new()),
+    })
+  }
+
+  pub fn name(&self) -> &PluginName {
+    &self.name
+  }
+
+  fn run_script_filter(&self, query: &str) -> Result<ScriptFilterOutput, anyhow::Error> {
+    let mut cmd = Command::new(&self.cfg.command);
+    cmd
+      .args(with_query(&self.cfg.args, query))
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped());
+    if let Some(dir) = &self.cfg.cwd {
+      cmd.current_dir(dir);
+    }
+    let mut child = cmd
+      .spawn()
+      .with_context(|| format!("Failed to run script filter {}", self.name))?;
+
+    // Drain output on the side, otherwise a chatty script fills the pipe and never exits
+    let out = read_all(child.stdout.take().context("Script stdout unavailable")?);
+    let err = read_all(child.stderr.take().context("Script stderr unavailable")?);
+
+    let deadline = Instant::now() + Duration::from_millis(self.cfg.timeout_ms);
+    let status = loop {
+      if let Some(status) = child.try_wait()? {
+        break status;
+      }
+      if Instant::now() >= deadline {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(anyhow!(
+          "Script filter {} timed out after {}ms",
+          self.name,
+          self.cfg.timeout_ms
+        ));
+      }
+      thread::sleep(Duration::from_millis(5));
+    };
+
+    // Anything the script left running in the background can hold the pipes open after
+    // it's exited, so reading them is held to the same deadline
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let stdout = out.recv_timeout(remaining());
+    let stderr = err.recv_timeout(remaining()).ok().and_then(Result::ok);
+    if let Some(stderr) = stderr.filter(|e| !e.trim().is_empty()) {
+      warn!("[{}] {}", self.name, stderr.trim());
+    }
+    if !status.success() {
+      return Err(anyhow!("Script filter {} failed: {}", self.name, status));
+    }
+    let stdout = stdout
+      .map_err(|_| anyhow!("Script filter {} kept its output open", self.name))?
+      .with_context(|| format!("Failed reading script filter {}", self.name))?;
+    serde_json::from_str(&stdout)
+      .with_context(|| format!("Script filter {} printed invalid JSON", self.name))
+  }
+
+  /// Alfred icons are paths relative to the workflow, which need to become data urls to be
+  /// rendered. Icons that are really requests for a file's icon can't be honoured.
+  fn resolve_icon(&self, item: &AlfredItem) -> Option<String> {
+    let icon = item.icon.as_ref().filter(|ico| ico.kind.is_none())?;
+    let path = match &self.cfg.cwd {
+      Some(dir) => dir.join(expand_home(&icon.path)),
+      None => PathBuf::from(expand_home(&icon.path)),
+    };
+    self
+      .icons
+      .lock()
+      .entry(path)
+      .or_insert_with_key(|p| file_data_url(p))
+      .clone()
+  }
+
+  fn run_action(&self, args: Vec<String>) -> Result<(), anyhow::Error> {
+    match &self.cfg.action {
+      AlfredAction::Open => args
+        .iter()
+        .try_for_each(|arg| open::that(expand_home(arg)).map_err(|err| err.into())),
+      AlfredAction::Copy => self
+        .clip
+        .as_ref()
+        .ok_or_else(|| anyhow!("Clipboard unavailable"))?
+        .lock()
+        .set_text(args.join("\n"))
+        .map_err(|err| anyhow!(err)),
+      AlfredAction::Command {
+        command,
+        args: cmd_args,
+      } => Command::new(command)
+        .args(with_query(cmd_args, &args.join(" ")))
+        .spawn()
+        .map(|child| reap(self.name.clone(), child))
+        .map_err(|err| err.into()),
+    }
+  }
+}
+
+/// Each `[alfred.<name>]` section is a workflow of its own, enabled under that name
+pub fn register(registry: &mut PluginRegistry, config: &Config) -> Result<(), anyhow::Error> {
+  let workflows: HashMap<PluginName, AlfredConfig> = config.get().plugin_config(CONFIG_SECTION)?;
+  for (name, cfg) in workflows {
+    registry.register(&name, move |ctx| {
+      Ok(Box::new(AlfredPlugin::init(ctx.name, cfg.clone())?))
+    });
+  }
+  Ok(())
+}
+
+impl Plugin for AlfredPlugin {
+  type PV = AlfredValue;
+
+  fn prefix(&self) -> Option<String> {
+    // Alfred keywords are separated from the query by a space
+    self.cfg.keyword.as_ref().map(|kw| format!("{} ", kw))
+  }
+
+  fn action(&self, input: &AlfredValue) -> Result<OkAction, anyhow::Error> {
+    let item = &input.item;
+    if !item.valid {
+      // Same as Alfred: non-actionable items fill the input with their autocomplete
+      return match &item.autocomplete {
+        Some(ac) => Ok(OkAction {
+          close_win: false,
+          update_input: Some(format!("{}{}", self.prefix().unwrap_or_default(), ac)),
+          ..Default::default()
+        }),
+        None => Err(anyhow!("{} is not actionable", item.title)),
+      };
+    }
+    let args = item.arg.as_ref().map(AlfredArg::values).unwrap_or_default();
+    self
+      .run_action(args)
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Action failed for {:?}, err: {:?}", item.title, err))
+  }
+
+  /// Runs the item's alternative for the configured modifier, the same as holding it down
+  /// while actioning in Alfred. The alternative's `arg` replaces the item's when it has one.
+  fn secondary_action(&self, input: &AlfredValue) -> Result<OkAction, anyhow::Error> {
+    let item = &input.item;
+    let modifier = &self.cfg.secondary_mod;
+    let alt = item
+      .mods
+      .get(modifier)
+      .ok_or_else(|| anyhow!("{} has no {} alternative", item.title, modifier))?;
+    if !alt.valid.unwrap_or(item.valid) {
+      return Err(anyhow!(
+        "{} is not actionable with {}",
+        item.title,
+        modifier
+      ));
+    }
+    let args = alt
+      .arg
+      .as_ref()
+      .or(item.arg.as_ref())
+      .map(AlfredArg::values)
+      .unwrap_or_default();
+    self
+      .run_action(args)
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Action failed for {:?}, err: {:?}", item.title, err))
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    let prefix = self.prefix();
+    let query = match &prefix {
+      Some(pre) => search.strip_prefix(pre.as_str()).unwrap_or(search),
+      None => search,
+    };
+    let items = match self.run_script_filter(query) {
+      Ok(out) => out.items,
+      Err(err) => {
+        error!("{:?}", err);
+        return vec![];
+      }
+    };
+    items
+      .into_iter()
+      .map(|item| FuzzyMatchItem {
+        // Keyword workflows filter for themselves, so let everything through
+        against: match (&prefix, &item.match_on) {
+          (Some(_), _) => Arc::new(search.to_owned()),
+          (None, Some(m)) => Arc::new(m.clone()),
+          (None, None) => Arc::new(item.title.clone()),
+        },
+        value: PluginV::new(
+          self.name.clone(),
+          AlfredValue {
+            plugin: self.name.clone(),
+            icon: self.resolve_icon(&item),
+            item,
+          },
+        ),
+        source: self.name.clone(),
+      })
+      .collect()
+  }
+}
+
+/// Passes the query as an arg of its own, in place of an arg that's just `{query}` or
+/// otherwise after the rest. It's never written into another arg, where it could end up
+/// run as part of a script, so scripts pick it up as `$1` instead
+fn with_query(args: &[String], query: &str) -> Vec<String> {
+  if args.iter().any(|a| a == QUERY_MARKER) {
+    return args
+      .iter()
+      .map(|a| match a == QUERY_MARKER {
+        true => query.to_string(),
+        false => a.clone(),
+      })
+      .collect();
+  }
+  let mut args = args.to_vec();
+  args.push(query.to_string());
+  args
+}
+
+/// Args can only be the query as a whole, see `with_query`
+fn check_query_args(args: &[String]) -> Result<(), anyhow::Error> {
+  match args
+    .iter()
+    .find(|a| *a != QUERY_MARKER && a.contains(QUERY_MARKER))
+  {
+    Some(arg) => Err(anyhow!(
+      "{} has to be an arg of its own, not part of {:?}. Scripts can use $1 for it",
+      QUERY_MARKER,
+      arg
+    )),
+    None => Ok(()),
+  }
+}
+
+/// Reads everything from the pipe on a thread of its own, sending it once the pipe closes
+fn read_all(mut pipe: impl Read + Send + 'static) -> Receiver<io::Result<String>> {
+  let (tx, rx) = mpsc::channel();
+  thread::spawn(move || {
+    let mut buf = String::new();
+    let _ = tx.send(pipe.read_to_string(&mut buf).map(|_| buf));
+  });
+  rx
+}
+
+/// Waits on the action's command in the background, so it isn't left a zombie once it exits
+fn reap(name: String, mut child: Child) {
+  thread::spawn(move || match child.wait() {
+    Ok(status) if !status.success() => warn!("[{}] Action failed: {}", name, status),
+    Ok(_) => {}
+    Err(err) => warn!("[{}] Failed waiting on action: {}", name, err),
+  });
+}
+
+fn expand_home(p: &str) -> String {
+  match (p.strip_prefix("~/"), std::env::var("HOME")) {
+    (Some(rest), Ok(home)) => Path::new(&home).join(rest).to_string_lossy().to_string(),
+    _ => p.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+
+  const SCRIPT_FILTER: &str = r#"
+    case "$1" in
+      slow) sleep 1;;
+      background) sleep 5 > /dev/null & printf '{"items":[{"title":"Background"}]}';;
+      broken) echo "not json";;
+      fail) exit 3;;
+      *) printf '{"items":[
+            {"title":"Result %s","subtitle":"sub","arg":"https://example.com/%s"},
+            {"title":"More","valid":false,"autocomplete":"more %s","match":"more results"}
+          ]}' "$1" "$1" "$1";;
+    esac
+  "#;
+
+  fn plugin(keyword: Option<&str>) -> AlfredPlugin {
+    AlfredPlugin::init(
+      "wf",
+      AlfredConfig {
+        command: "sh".into(),
+        args: vec!["-c".into(), SCRIPT_FILTER.into(), "sh".into()],
+        keyword: keyword.map(|k| k.to_string()),
+        timeout_ms: 300,
+        ..Default::default()
+      },
+    )
+    .unwrap()
+  }
+
+  fn values(items: Vec<FuzzyMatchItem>) -> Vec<AlfredValue> {
+    items
+      .into_iter()
+      .map(|fm| {
+        fm.value
+          .downcast::<AlfredValue>()
+          .cloned()
+          .expect("Unexpected plugin value")
+      })
+      .collect()
+  }
+
+  #[test]
+  fn query_is_appended_or_substituted() {
+    let args = vec!["-q".to_string()];
+    assert_eq!(with_query(&args, "dogs"), vec!["-q", "dogs"]);
+    let args = vec![
+      "--query".to_string(),
+      "{query}".to_string(),
+      "-v".to_string(),
+    ];
+    assert_eq!(with_query(&args, "dogs"), vec!["--query", "dogs", "-v"]);
+    let args = vec!["-c".to_string(), "echo {query}".to_string()];
+    assert!(check_query_args(&args).is_err());
+    let cfg = AlfredConfig {
+      args,
+      ..Default::default()
+    };
+    assert!(AlfredPlugin::init("wf", cfg).is_err());
+  }
+
+  #[test]
+  fn script_filter_items_become_options() {
+    let items = plugin(None).options("dogs");
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref(), "Result dogs");
+    assert_eq!(items[1].as_ref(), "more results");
+    let vals = values(items);
+    assert_eq!(vals[0].item.subtitle, Some("sub".into()));
+    assert_eq!(
+      vals[0].item.arg,
+      Some(AlfredArg::One("https://example.com/dogs".into()))
+    );
+  }
+
+  #[test]
+  fn keyword_is_stripped_from_query() {
+    let items = plugin(Some("wf")).options("wf dogs");
+    assert_eq!(items[0].as_ref(), "wf dogs");
+    assert_eq!(values(items)[0].item.title, "Result dogs");
+  }
+
+  #[test]
+  fn autocomplete_updates_input() {
+    let pl = plugin(Some("wf"));
+    let vals = values(pl.options("wf dogs"));
+    let res = pl.action(&vals[1]).unwrap();
+    assert!(!res.close_win);
+    assert_eq!(res.update_input, Some("wf more dogs".into()));
+  }
+
+  #[test]
+  fn invalid_without_autocomplete_errors() {
+    let pl = plugin(None);
+    let val = AlfredValue {
+      plugin: "wf".into(),
+      item: AlfredItem {
+        title: "Nope".into(),
+        valid: false,
+        ..Default::default()
+      },
+      icon: None,
+    };
+    assert!(pl.action(&val).is_err());
+  }
+
+  #[test]
+  fn secondary_action_uses_modifier_alternative() {
+    let dir = TestDir::new("alfred-mod");
+    let out = dir.join("out");
+    let pl = AlfredPlugin::init(
+      "wf",
+      AlfredConfig {
+        command: "true".into(),
+        action: AlfredAction::Command {
+          command: "sh".into(),
+          args: vec![
+            "-c".into(),
+            format!("echo \"$1\" > {}", out.to_string_lossy()),
+            "sh".into(),
+          ],
+        },
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    let item: AlfredItem = serde_json::from_str(
+      r#"{
+        "title": "Item", "arg": "primary",
+        "mods": {
+          "cmd": { "arg": "alternate" },
+          "alt": { "valid": false }
+        }
+      }"#,
+    )
+    .unwrap();
+    let val = AlfredValue {
+      plugin: "wf".into(),
+      item,
+      icon: None,
+    };
+
+    assert!(pl.secondary_action(&val).unwrap().close_win);
+    let until = Instant::now() + Duration::from_secs(2);
+    while !fs::read_to_string(&out).is_ok_and(|o| !o.is_empty()) && Instant::now() < until {
+      thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(fs::read_to_string(&out).unwrap().trim(), "alternate");
+
+    let pl = AlfredPlugin::init(
+      "wf",
+      AlfredConfig {
+        secondary_mod: "alt".into(),
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    assert!(pl.secondary_action(&val).is_err());
+    let pl = AlfredPlugin::init(
+      "wf",
+      AlfredConfig {
+        secondary_mod: "ctrl".into(),
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    assert!(pl.secondary_action(&val).is_err());
+  }
+
+  #[test]
+  fn misbehaving_scripts_yield_nothing() {
+    let pl = plugin(None);
+    assert!(pl.options("slow").is_empty());
+    assert!(pl.options("broken").is_empty());
+    assert!(pl.options("fail").is_empty());
+  }
+
+  #[test]
+  fn background_processes_dont_hold_up_results() {
+    let start = Instant::now();
+    let items = plugin(None).options("background");
+    assert_eq!(values(items)[0].item.title, "Background");
+    assert!(start.elapsed() < Duration::from_secs(2));
+  }
+
+  #[test]
+  fn queries_are_never_run_as_script() {
+    let dir = std::env::temp_dir().join(format!("lyra-alfred-inject-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let (out, injected) = (dir.join("out"), dir.join("injected"));
+    let touch = format!("touch {}", injected.to_string_lossy());
+
+    let query = format!("$({}); {}", touch, touch);
+    let items = plugin(None).options(&query);
+    assert_eq!(values(items)[0].item.title, format!("Result {}", query));
+
+    let pl = AlfredPlugin::init(
+      "wf",
+      AlfredConfig {
+        command: "true".into(),
+        action: AlfredAction::Command {
+          command: "sh".into(),
+          args: vec![
+            "-c".into(),
+            format!("echo \"$1\" > {}", out.to_string_lossy()),
+            "sh".into(),
+          ],
+        },
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    let arg = format!("`{}`", touch);
+    let val = AlfredValue {
+      plugin: "wf".into(),
+      item: AlfredItem {
+        title: "Item".into(),
+        arg: Some(AlfredArg::One(arg.clone())),
+        valid: true,
+        ..Default::default()
+      },
+      icon: None,
+    };
+    pl.action(&val).unwrap();
+    let until = Instant::now() + Duration::from_secs(2);
+    while !fs::read_to_string(&out).is_ok_and(|o| !o.is_empty()) && Instant::now() < until {
+      thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(fs::read_to_string(&out).unwrap().trim(), arg);
+    assert!(!injected.exists());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The subset of Alfred's Script Filter JSON format that Lyra understands. Anything else
+/// a workflow emits (variables, rerun, cache, ...) is ignored.
+/// See https://www.alfredapp.com/help/workflows/inputs/script-filter/json/
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct ScriptFilterOutput {
+  pub items: Vec<AlfredItem>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct AlfredItem {
+  pub uid: Option<String>,
+  pub title: String,
+  pub subtitle: Option<String>,
+  pub arg: Option<AlfredArg>,
+  pub icon: Option<AlfredIcon>,
+  pub valid: bool,
+  #[serde(rename = "match")]
+  pub match_on: Option<String>,
+  pub autocomplete: Option<String>,
+  pub mods: HashMap<String, AlfredMod>,
+}
+
+impl Default for AlfredItem {
+  fn default() -> Self {
+    // Alfred treats items as actionable unless told otherwise
+    AlfredItem {
+      uid: None,
+      title: String::new(),
+      subtitle: None,
+      arg: None,
+      icon: None,
+      valid: true,
+      match_on: None,
+      autocomplete: None,
+      mods: HashMap::new(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum AlfredArg {
+  One(String),
+  Many(Vec<String>),
+}
+
+impl AlfredArg {
+  pub fn values(&self) -> Vec<String> {
+    match self {
+      AlfredArg::One(v) => vec![v.clone()],
+      AlfredArg::Many(vs) => vs.clone(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct AlfredIcon {
+  /// Either absent (path is an image), "fileicon" or "filetype". Only the first
+  /// can be rendered, the others need macOS to resolve the icon for us
+  #[serde(rename = "type")]
+  pub kind: Option<String>,
+  pub path: String,
+}
+
+/// Alternate behaviour for an item while a modifier (cmd, alt, ctrl, shift, fn) is held
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct AlfredMod {
+  pub valid: Option<bool>,
+  pub arg: Option<AlfredArg>,
+  pub subtitle: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_alfred_items() {
+    let out: ScriptFilterOutput = serde_json::from_str(
+      r#"{
+        "rerun": 1,
+        "items": [
+          {
+            "uid": "desktop",
+            "type": "file",
+            "title": "Desktop",
+            "subtitle": "~/Desktop",
+            "arg": "~/Desktop",
+            "autocomplete": "Desktop",
+            "icon": { "type": "fileicon", "path": "~/Desktop" },
+            "mods": {
+              "cmd": { "valid": true, "arg": "alfredapp.com/powerpack", "subtitle": "Buy" }
+            }
+          },
+          { "title": "Many", "arg": ["a", "b"], "valid": false, "match": "many things" }
+        ]
+      }"#,
+    )
+    .unwrap();
+
+    assert_eq!(out.items.len(), 2);
+    let first = &out.items[0];
+    assert_eq!(first.uid, Some("desktop".into()));
+    assert_eq!(first.title, "Desktop");
+    assert!(first.valid);
+    assert_eq!(first.arg, Some(AlfredArg::One("~/Desktop".into())));
+    assert_eq!(first.autocomplete, Some("Desktop".into()));
+    assert_eq!(
+      first.icon,
+      Some(AlfredIcon {
+        kind: Some("fileicon".into()),
+        path: "~/Desktop".into()
+      })
+    );
+    assert_eq!(first.mods["cmd"].subtitle, Some("Buy".into()));
+
+    let second = &out.items[1];
+    assert!(!second.valid);
+    assert_eq!(second.match_on, Some("many things".into()));
+    assert_eq!(
+      second.arg.as_ref().map(AlfredArg::values),
+      Some(vec!["a".to_string(), "b".to_string()])
+    );
+  }
+}
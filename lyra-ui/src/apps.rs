@@ -1,25 +1,30 @@
 pub mod app_convert;
 mod appcache;
+mod appindex;
 mod applookup;
+mod desktop;
+mod icontheme;
 
 use crate::config::AppsConfig;
 use crate::icon_ui::Icon;
 use crate::plugin::{
   AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
 };
+use crate::plugin_registry::PluginRegistry;
 use anyhow::{anyhow, Context};
+use appindex::AppIndex;
 use applookup::AppLookup;
+use desktop::DesktopExec;
 use egui::RichText;
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use self::appcache::AppsCache;
 
 pub const PLUGIN_NAME: &'static str = "apps";
 
 pub struct AppsPlugin {
-  cfg: AppsConfig,
-  apps: AppLookup,
+  index: Arc<AppIndex>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -27,8 +32,24 @@ pub struct AppLaunch {
   pub label: String,
   pub icon: String,
   pub path: String,
+  /// What to run for apps described by a desktop entry, which can't just be opened
+  #[serde(default)]
+  pub exec: Option<DesktopExec>,
+  /// The desktop action this launches, rather than the app itself
+  #[serde(default)]
+  pub action: Option<String>,
+  /// Other words the app can be found by
+  #[serde(default)]
+  pub keywords: Vec<String>,
+}
+impl PluginValue for AppLaunch {
+  fn item_id(&self) -> Option<String> {
+    match &self.action {
+      Some(action) => Some(format!("{}#{}", self.path, action)),
+      None => Some(self.path.clone()),
+    }
+  }
 }
-impl PluginValue for AppLaunch {}
 impl Renderable for AppLaunch {
   fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
     ui.horizontal(|ui| {
@@ -44,20 +65,32 @@ impl SearchBlocker for AppLaunch {}
 impl AppsPlugin {
   pub fn init(cfg: AppsConfig, cache_dir: &PathBuf) -> Result<Self, anyhow::Error> {
     let cache = AppsCache::init(cache_dir.join(format!("apps_icons.toml")))?;
-    let apps = AppLookup {
-      config: cfg.clone(),
-      cache: Arc::new(cache),
-    };
-    apps.init().context("Failed to initialize app icons")?;
-    Ok(AppsPlugin { cfg, apps })
+    let rescan = Some(Duration::from_secs(cfg.rescan_secs)).filter(|d| !d.is_zero());
+    let index = Arc::new(AppIndex::new(AppLookup::new(cfg, Arc::new(cache))));
+    index.refresh().context("Failed to initialize app index")?;
+    index.watch(rescan);
+    Ok(AppsPlugin { index })
   }
 }
 
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |ctx| {
+    Ok(Box::new(AppsPlugin::init(
+      ctx.section()?,
+      &ctx.config.cache_dir,
+    )?))
+  });
+}
+
 impl Plugin for AppsPlugin {
   type PV = AppLaunch;
 
   fn action(&self, input: &AppLaunch) -> Result<OkAction, anyhow::Error> {
-    open::that(input.path.clone())
+    let launched = match &input.exec {
+      Some(exec) => exec.spawn(),
+      None => open::that(input.path.clone()).map_err(|err| err.into()),
+    };
+    launched
       .map(|_| OkAction {
         close_win: true,
         ..Default::default()
@@ -65,17 +98,21 @@ impl Plugin for AppsPlugin {
       .map_err(|err| anyhow!("Action failed for {:?}, err: {:?}", input.label, err))
   }
 
-  fn options(&self, _: &str) -> Vec<FuzzyMatchItem> {
-    self.apps.iter().map(AppLaunch::into).collect()
+  /// Reveals the app in the file manager rather than launching it
+  fn secondary_action(&self, input: &AppLaunch) -> Result<OkAction, anyhow::Error> {
+    let path = PathBuf::from(&input.path);
+    let dir = path
+      .parent()
+      .ok_or_else(|| anyhow!("{} has no containing folder", input.path))?;
+    open::that(dir)
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Reveal failed for {:?}, err: {:?}", input.label, err))
   }
-}
 
-impl From<AppLaunch> for FuzzyMatchItem {
-  fn from(app: AppLaunch) -> FuzzyMatchItem {
-    FuzzyMatchItem {
-      against: Arc::new(app.label.clone()),
-      value: PluginV::Apps(app),
-      source: PLUGIN_NAME.to_string(),
-    }
+  fn options(&self, _: &str) -> Vec<FuzzyMatchItem> {
+    self.index.options()
   }
 }
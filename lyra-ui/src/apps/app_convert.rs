@@ -1,9 +1,16 @@
+use crate::icon_ui::file_data_url;
 use anyhow::{anyhow, Context};
 use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::engine::Engine as _;
 use icns::IconFamily;
+use image::{ImageOutputFormat, Rgba, RgbaImage};
 use plist::Value;
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+  collections::HashMap,
+  fs::{self, File},
+  io::{BufReader, Cursor},
+  path::Path,
+};
 
 pub fn to_icon(p: &Path) -> Result<String, anyhow::Error> {
   let icns = Value::from_file(p.join("Contents/info.plist"))
@@ -39,3 +46,136 @@ pub fn to_icon(p: &Path) -> Result<String, anyhow::Error> {
   image.write_png(&mut out)?;
   Ok(format!("data:image/png;base64,{}", BASE64.encode(&out)))
 }
+
+/// Converts an icon image file, such as one from an icon theme
+pub fn file_icon(p: &Path) -> Result<String, anyhow::Error> {
+  if p.extension().is_some_and(|ext| ext == "xpm") {
+    let png = xpm_to_png(&fs::read_to_string(p)?).context(format!("Bad XPM: {:?}", p))?;
+    return Ok(format!("data:image/png;base64,{}", BASE64.encode(&png)));
+  }
+  file_data_url(p).ok_or_else(|| anyhow!("Unsupported icon file {:?}", p))
+}
+
+/// The most pixels an XPM can be on either side, well past any icon, so a bad header can't
+/// have us allocate gigabytes
+const MAX_XPM_SIZE: u32 = 1024;
+
+/// Decodes an XPM (v3) image, which nothing can render directly, into a PNG
+fn xpm_to_png(xpm: &str) -> Result<Vec<u8>, anyhow::Error> {
+  // The image is the string literals of a C array, one per line
+  let mut strings = xpm.lines().filter_map(|line| {
+    let start = line.find('"')?;
+    let end = line.rfind('"').filter(|end| *end > start)?;
+    Some(&line[start + 1..end])
+  });
+  let header: Vec<u32> = strings
+    .next()
+    .ok_or_else(|| anyhow!("No header"))?
+    .split_whitespace()
+    .take(4)
+    .map(|n| n.parse())
+    .collect::<Result<_, _>>()?;
+  let [width, height, colors, cpp] = header[..] else {
+    return Err(anyhow!(
+      "Header needs a width, height, colour count and chars per pixel"
+    ));
+  };
+  if width > MAX_XPM_SIZE || height > MAX_XPM_SIZE {
+    return Err(anyhow!("Too big at {}x{}", width, height));
+  }
+  let cpp = cpp as usize;
+
+  let mut palette = HashMap::new();
+  for _ in 0..colors {
+    let line = strings.next().ok_or_else(|| anyhow!("Missing colours"))?;
+    let key = line
+      .get(..cpp)
+      .ok_or_else(|| anyhow!("Short colour {:?}", line))?;
+    palette.insert(key, xpm_color(&line[cpp..])?);
+  }
+
+  let mut image = RgbaImage::new(width, height);
+  for y in 0..height {
+    let row = strings.next().ok_or_else(|| anyhow!("Missing row {}", y))?;
+    for x in 0..width {
+      let start = x as usize * cpp;
+      let color = row
+        .get(start..start + cpp)
+        .and_then(|key| palette.get(key))
+        .ok_or_else(|| anyhow!("Bad pixel at {},{}", x, y))?;
+      image.put_pixel(x, y, *color);
+    }
+  }
+  let mut png = Cursor::new(Vec::new());
+  image.write_to(&mut png, ImageOutputFormat::Png)?;
+  Ok(png.into_inner())
+}
+
+/// The colour of a palette entry, preferring the colour (`c`) visual over the greyscale and
+/// mono ones
+fn xpm_color(spec: &str) -> Result<Rgba<u8>, anyhow::Error> {
+  let visuals = ["c", "g", "g4", "m", "s"];
+  let mut values: HashMap<&str, Vec<&str>> = HashMap::new();
+  let mut current = None;
+  for word in spec.split_whitespace() {
+    match visuals.iter().find(|v| **v == word) {
+      Some(visual) => current = Some(*visual),
+      None => values.entry(current.unwrap_or("c")).or_default().push(word),
+    }
+  }
+  let value = ["c", "g", "g4", "m"]
+    .iter()
+    .find_map(|v| values.get(v))
+    .map(|words| words.concat().to_lowercase())
+    .ok_or_else(|| anyhow!("No colour in {:?}", spec))?;
+  let hex = |s: &str| u8::from_str_radix(s, 16);
+  let rgb = match value.as_str() {
+    "none" | "transparent" => return Ok(Rgba([0, 0, 0, 0])),
+    "black" => [0, 0, 0],
+    "white" => [255, 255, 255],
+    "red" => [255, 0, 0],
+    "green" => [0, 255, 0],
+    "blue" => [0, 0, 255],
+    "yellow" => [255, 255, 0],
+    "gray" | "grey" => [190, 190, 190],
+    "lightgray" | "lightgrey" => [211, 211, 211],
+    "darkgray" | "darkgrey" => [169, 169, 169],
+    v if !v.is_ascii() => return Err(anyhow!("Unknown colour {:?}", v)),
+    v if v.starts_with('#') && v.len() == 7 => [hex(&v[1..3])?, hex(&v[3..5])?, hex(&v[5..7])?],
+    v if v.starts_with('#') && v.len() == 13 => [hex(&v[1..3])?, hex(&v[5..7])?, hex(&v[9..11])?],
+    v if v.starts_with('#') && v.len() == 4 => {
+      let [r, g, b] = [hex(&v[1..2])?, hex(&v[2..3])?, hex(&v[3..4])?];
+      [r * 17, g * 17, b * 17]
+    }
+    v => return Err(anyhow!("Unknown colour {:?}", v)),
+  };
+  Ok(Rgba([rgb[0], rgb[1], rgb[2], 255]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decodes_xpm() {
+    let xpm = r##"/* XPM */
+static char *icon[] = {
+/* columns rows colors chars-per-pixel */
+"3 2 3 2 ",
+".. c None",
+"#a c #FF0000",
+"b  s border c light grey",
+"..#ab ",
+"b b ..",
+};"##;
+    let png = xpm_to_png(xpm).unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (3, 2));
+    assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+    assert_eq!(image.get_pixel(1, 0), &Rgba([255, 0, 0, 255]));
+    assert_eq!(image.get_pixel(2, 0), &Rgba([211, 211, 211, 255]));
+    assert!(xpm_to_png("\"1 1 1 1\",\"a c mauve-ish\",\"a\"").is_err());
+    assert!(xpm_to_png("\"1 1 1 1\",\"a c #aébcd\",\"a\"").is_err());
+    assert!(xpm_to_png("\"100000 100000 1 1\",\"a c None\"").is_err());
+  }
+}
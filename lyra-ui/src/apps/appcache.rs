@@ -20,28 +20,44 @@ impl AppsCache {
 
   pub fn get_app_icon(&self, updated: &Path) -> Result<String, anyhow::Error> {
     let key = updated.to_str().unwrap().to_string();
-    // TODO Tricky deadlock, but ideally we would persist newly discovered items
-    //      rather than waiting until next restart
-    Ok(
-      self
-        .0
-        .get()
-        .app_icons
-        .get(&key)
-        .map(|v| v.to_string())
-        .get_or_insert_with(|| app_convert::to_icon(updated).unwrap_or_default())
-        .clone(),
-    )
+    if let Some(icon) = self.0.get().app_icons.get(&key) {
+      return Ok(icon.clone());
+    }
+    // The read lock must be released before updating, or we deadlock on ourselves
+    let icon = app_convert::to_icon(updated).unwrap_or_default();
+    self.0.update(|mut ad| {
+      ad.app_icons.insert(key, icon.clone());
+    });
+    self.0.persist()?;
+    Ok(icon)
+  }
+
+  /// The icon already converted from the path, if any
+  pub fn cached_icon(&self, p: &Path) -> Option<String> {
+    self.0.get().app_icons.get(p.to_str()?).cloned()
   }
 
   pub fn update_app_icons(&self, updated: Vec<PathBuf>) -> Result<(), anyhow::Error> {
+    self.update_icons(updated, app_convert::to_icon)
+  }
+
+  /// Like [`Self::update_app_icons`] for image files, such as those from an icon theme
+  pub fn update_file_icons(&self, updated: Vec<PathBuf>) -> Result<(), anyhow::Error> {
+    self.update_icons(updated, app_convert::file_icon)
+  }
+
+  fn update_icons(
+    &self,
+    updated: Vec<PathBuf>,
+    convert: fn(&Path) -> Result<String, anyhow::Error>,
+  ) -> Result<(), anyhow::Error> {
     let new_app_icons = {
       let inner = self.0.get();
       let mut new_app_icons: HashMap<String, String> = updated
         .iter()
         .map(|p| (p.to_str().unwrap().to_string(), p))
         .filter(|(k, _)| !inner.app_icons.contains_key(k))
-        .map(|(k, p)| (k, app_convert::to_icon(p).unwrap_or_default()))
+        .map(|(k, p)| (k, convert(p).unwrap_or_default()))
         .collect();
       if new_app_icons.is_empty() {
         return Ok(());
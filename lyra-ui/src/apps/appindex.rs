@@ -0,0 +1,202 @@
+use super::{applookup::AppLookup, AppLaunch, PLUGIN_NAME};
+use crate::plugin::{FuzzyMatchItem, PluginV};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use parking_lot::RwLock;
+use std::{
+  sync::{
+    mpsc::{self, RecvTimeoutError},
+    Arc, Weak,
+  },
+  thread,
+  time::Duration,
+};
+use tracing::{error, info, warn};
+
+/// Installs tend to come as a burst of events, so wait for them to quiet down before
+/// rescanning
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// Every known app, ready to be matched against. Searching only reads from here, the
+/// filesystem is rescanned in the background as apps come and go.
+pub struct AppIndex {
+  lookup: AppLookup,
+  apps: RwLock<Arc<Vec<IndexedApp>>>,
+}
+
+struct IndexedApp {
+  label: Arc<String>,
+  value: PluginV,
+}
+
+impl AppIndex {
+  pub fn new(lookup: AppLookup) -> Self {
+    AppIndex {
+      lookup,
+      apps: RwLock::default(),
+    }
+  }
+
+  pub fn refresh(&self) -> Result<(), anyhow::Error> {
+    let apps = self
+      .lookup
+      .scan()?
+      .into_iter()
+      .map(|app: AppLaunch| IndexedApp {
+        label: Arc::new(match app.keywords.is_empty() {
+          true => app.label.clone(),
+          false => format!("{} {}", app.label, app.keywords.join(" ")),
+        }),
+        value: PluginV::new(PLUGIN_NAME, app),
+      })
+      .collect();
+    *self.apps.write() = Arc::new(apps);
+    Ok(())
+  }
+
+  pub fn options(&self) -> Vec<FuzzyMatchItem> {
+    let apps = self.apps.read().clone();
+    apps
+      .iter()
+      .map(|app| FuzzyMatchItem {
+        against: app.label.clone(),
+        value: app.value.clone(),
+        source: PLUGIN_NAME.to_string(),
+      })
+      .collect()
+  }
+
+  /// Keeps the index up to date until it's dropped. Changes to the app paths are picked up
+  /// as they happen where the platform can tell us about them, and everything is rescanned
+  /// every `interval` regardless in case it can't
+  pub fn watch(self: &Arc<Self>, interval: Option<Duration>) {
+    let index = Arc::downgrade(self);
+    let spawned = thread::Builder::new()
+      .name("apps-index".into())
+      .spawn(move || run_refresher(index, interval));
+    if let Err(err) = spawned {
+      error!("Failed to start app index refresher: {}", err);
+    }
+  }
+}
+
+fn run_refresher(index: Weak<AppIndex>, interval: Option<Duration>) {
+  let (tx, rx) = mpsc::channel();
+  let _watcher = match index.upgrade().map(|idx| watch_paths(&idx.lookup, tx)) {
+    Some(Ok(watcher)) => Some(watcher),
+    Some(Err(err)) => {
+      warn!("Not watching app paths for changes: {}", err);
+      None
+    }
+    None => return,
+  };
+  // Without a timer we'd otherwise wait forever
+  let interval = interval.unwrap_or(Duration::MAX);
+
+  loop {
+    match rx.recv_timeout(interval) {
+      Ok(_) => while rx.recv_timeout(SETTLE).is_ok() {},
+      Err(RecvTimeoutError::Timeout) => {}
+      Err(RecvTimeoutError::Disconnected) if interval == Duration::MAX => return,
+      Err(RecvTimeoutError::Disconnected) => thread::sleep(interval),
+    }
+    let Some(index) = index.upgrade() else {
+      return;
+    };
+    match index.refresh() {
+      Ok(()) => info!("Refreshed app index"),
+      Err(err) => error!("Failed to refresh app index: {:?}", err),
+    }
+  }
+}
+
+fn watch_paths(
+  lookup: &AppLookup,
+  tx: mpsc::Sender<notify::Result<notify::Event>>,
+) -> Result<RecommendedWatcher, anyhow::Error> {
+  let mut watcher = notify::recommended_watcher(tx)?;
+  for path in lookup.config.app_paths.iter().filter(|p| p.exists()) {
+    watcher.watch(path, RecursiveMode::NonRecursive)?;
+  }
+  // Desktop entries can sit in vendor subdirs
+  for path in lookup.desktop_dirs().iter().filter(|p| p.exists()) {
+    watcher.watch(path, RecursiveMode::Recursive)?;
+  }
+  Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::apps::appcache::AppsCache;
+  use crate::config::AppsConfig;
+  use crate::testdir::TestDir;
+  use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+  };
+
+  fn apps_dir(name: &str) -> TestDir {
+    let dir = TestDir::new(name);
+    fs::create_dir(dir.join("apps")).unwrap();
+    dir
+  }
+
+  fn index(dir: &Path) -> Arc<AppIndex> {
+    let cache = AppsCache::init(dir.join("icons.toml")).unwrap();
+    let config = AppsConfig {
+      app_paths: vec![dir.join("apps")],
+      app_extension: ".app".into(),
+      desktop_dirs: Some(vec![]),
+      ..Default::default()
+    };
+    Arc::new(AppIndex::new(AppLookup::new(config, Arc::new(cache))))
+  }
+
+  fn labels(index: &AppIndex) -> Vec<String> {
+    let mut labels: Vec<_> = index
+      .options()
+      .iter()
+      .map(|fm| fm.as_ref().to_string())
+      .collect();
+    labels.sort();
+    labels
+  }
+
+  #[test]
+  fn refresh_picks_up_new_apps() {
+    let dir = apps_dir("apps-refresh");
+    fs::create_dir(dir.join("apps/Safari.app")).unwrap();
+    let index = index(&dir);
+    assert!(labels(&index).is_empty());
+
+    index.refresh().unwrap();
+    assert_eq!(labels(&index), vec!["Safari"]);
+
+    fs::create_dir(dir.join("apps/Notes.app")).unwrap();
+    fs::write(dir.join("apps/readme.txt"), "").unwrap();
+    index.refresh().unwrap();
+    assert_eq!(labels(&index), vec!["Notes", "Safari"]);
+
+    // Icons are remembered as soon as they're seen
+    let cached = fs::read_to_string(dir.join("icons.toml")).unwrap();
+    assert!(cached.contains("Notes.app"));
+  }
+
+  #[test]
+  fn watcher_refreshes_on_change() {
+    let dir = apps_dir("apps-watch");
+    let index = index(&dir);
+    index.refresh().unwrap();
+    index.watch(None);
+    // Give the watcher a moment to start
+    thread::sleep(Duration::from_millis(100));
+
+    fs::create_dir(dir.join("apps/Maps.app")).unwrap();
+    let until = Instant::now() + Duration::from_secs(5);
+    while labels(&index).is_empty() && Instant::now() < until {
+      thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(labels(&index), vec!["Maps"]);
+  }
+}
@@ -1,14 +1,27 @@
-use super::{appcache::AppsCache, AppLaunch};
+use super::{
+  appcache::AppsCache,
+  desktop::{self, DesktopEntry, Locale},
+  icontheme::{self, IconThemes},
+  AppLaunch,
+};
 use crate::config::AppsConfig;
 use glob::glob;
+use parking_lot::Mutex;
 use std::{
+  collections::HashMap,
   path::{Path, PathBuf},
   sync::Arc,
 };
+use tracing::warn;
+
+/// Size to pick theme icons at, so they stay sharp when scaled down in the results
+const ICON_SIZE: u32 = 48;
 
 pub struct AppLookup {
   pub config: AppsConfig,
   pub cache: Arc<AppsCache>,
+  /// Loaded on the first scan with desktop entries, and again only once the themes change
+  icon_themes: Mutex<Option<IconThemes>>,
 }
 
 impl AppLaunch {
@@ -22,8 +35,52 @@ impl AppLaunch {
         .to_string(),
       icon,
       path: p.to_string_lossy().to_string(),
+      exec: None,
+      action: None,
+      keywords: vec![],
     }
   }
+
+  /// The app described by the entry, followed by each of its actions
+  fn from_desktop(entry: DesktopEntry, icons: &HashMap<String, String>) -> Vec<AppLaunch> {
+    let icon = |name: Option<&String>| {
+      name
+        .and_then(|name| icons.get(name))
+        .cloned()
+        .unwrap_or_default()
+    };
+    let path = entry.file.to_string_lossy().to_string();
+    let app = match entry.command(None) {
+      Ok(exec) => AppLaunch {
+        label: entry.name.clone(),
+        icon: icon(entry.icon.as_ref()),
+        path: path.clone(),
+        exec: Some(exec),
+        action: None,
+        keywords: entry.keywords.clone(),
+      },
+      Err(err) => {
+        warn!("Skipping app {}: {:?}", entry.id, err);
+        return vec![];
+      }
+    };
+    let actions = entry.actions.iter().filter_map(|action| {
+      let exec = entry
+        .command(Some(action))
+        .map_err(|err| warn!("Skipping action {} of {}: {:?}", action.id, entry.id, err))
+        .ok()?;
+      Some(AppLaunch {
+        label: format!("{}: {}", entry.name, action.name),
+        icon: icon(action.icon.as_ref().or(entry.icon.as_ref())),
+        path: path.clone(),
+        exec: Some(exec),
+        action: Some(action.id.clone()),
+        keywords: vec![],
+      })
+    });
+    let actions: Vec<_> = actions.collect();
+    std::iter::once(app).chain(actions).collect()
+  }
 }
 
 pub struct AppLookupIter<T> {
@@ -38,33 +95,95 @@ pub struct AppLookupIter<T> {
 }
 
 impl AppLookup {
-  pub fn iter(&self) -> AppLookupIter<AppLaunch> {
+  pub fn new(config: AppsConfig, cache: Arc<AppsCache>) -> Self {
+    AppLookup {
+      config,
+      cache,
+      icon_themes: Mutex::new(None),
+    }
+  }
+
+  /// Finds every app in the configured paths, caching the icons of any not seen before
+  pub fn scan(&self) -> Result<Vec<AppLaunch>, anyhow::Error> {
     let conf = &self.config;
-    AppLookupIter {
+    let paths: Vec<PathBuf> = AppLookupIter {
       cache: self.cache.clone(),
       extension: conf.app_extension.clone(),
       paths_remaining: conf.app_paths.clone(),
       current: None,
-      maker: Box::new(|p, suffix, cache| {
-        let icon = cache.get_app_icon(&p).unwrap_or_default();
-        AppLaunch::from(p, suffix, icon)
-      }),
+      maker: Box::new(|a, _, _| a),
     }
+    .collect();
+    self.cache.update_app_icons(paths.clone())?;
+    let bundles = paths.into_iter().map(|p| {
+      let icon = self.cache.get_app_icon(&p).unwrap_or_default();
+      AppLaunch::from(p, &conf.app_extension, icon)
+    });
+    let entries = desktop::scan(
+      &self.desktop_dirs(),
+      &Locale::current(),
+      &desktop::current_desktops(),
+    );
+    let icons = self.desktop_icons(&entries)?;
+    Ok(
+      bundles
+        .chain(
+          entries
+            .into_iter()
+            .flat_map(|entry| AppLaunch::from_desktop(entry, &icons)),
+        )
+        .collect(),
+    )
   }
 
-  pub fn init(&self) -> Result<(), anyhow::Error> {
-    let items = {
-      let conf = &self.config;
-      let apps = AppLookupIter {
-        cache: self.cache.clone(),
-        extension: conf.app_extension.clone(),
-        paths_remaining: conf.app_paths.clone(),
-        current: None,
-        maker: Box::new(|a, _, _| a),
-      };
-      apps.collect()
+  /// Resolves the icons the entries name through the icon theme, caching the image of any
+  /// file not seen before
+  fn desktop_icons(
+    &self,
+    entries: &[DesktopEntry],
+  ) -> Result<HashMap<String, String>, anyhow::Error> {
+    if entries.is_empty() {
+      return Ok(HashMap::new());
+    }
+    let theme = self
+      .config
+      .icon_theme
+      .clone()
+      .unwrap_or_else(icontheme::current_theme);
+    let base_dirs = icontheme::base_dirs();
+    let mut icon_themes = self.icon_themes.lock();
+    let themes = match &mut *icon_themes {
+      Some(themes) if themes.is_current(&base_dirs, &theme) => themes,
+      stale => stale.insert(IconThemes::new(base_dirs, &theme)),
     };
-    self.cache.update_app_icons(items)
+    let scale = self.config.icon_scale.max(1);
+    let files: HashMap<&String, PathBuf> = entries
+      .iter()
+      .flat_map(|e| {
+        e.icon
+          .iter()
+          .chain(e.actions.iter().filter_map(|a| a.icon.as_ref()))
+      })
+      .filter_map(|name| Some((name, themes.find(name, ICON_SIZE, scale)?)))
+      .collect();
+    self
+      .cache
+      .update_file_icons(files.values().cloned().collect())?;
+    Ok(
+      files
+        .into_iter()
+        .filter_map(|(name, file)| Some((name.clone(), self.cache.cached_icon(&file)?)))
+        .collect(),
+    )
+  }
+
+  /// Where to look for desktop entries, which only Linux has by default
+  pub fn desktop_dirs(&self) -> Vec<PathBuf> {
+    match &self.config.desktop_dirs {
+      Some(dirs) => dirs.clone(),
+      None if cfg!(target_os = "linux") => desktop::application_dirs(),
+      None => vec![],
+    }
   }
 }
 
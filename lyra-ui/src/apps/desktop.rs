@@ -0,0 +1,709 @@
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{HashMap, HashSet},
+  fs,
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  thread,
+};
+use tracing::warn;
+
+const MAIN_GROUP: &str = "Desktop Entry";
+const ACTION_GROUP: &str = "Desktop Action ";
+
+/// An app as described by a freedesktop Desktop Entry, which is how apps make themselves
+/// known on Linux. See https://specifications.freedesktop.org/desktop-entry-spec/latest/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DesktopEntry {
+  /// The desktop file id, which is unique across the data dirs
+  pub id: String,
+  pub file: PathBuf,
+  pub kind: String,
+  pub name: String,
+  pub icon: Option<String>,
+  pub exec: Option<String>,
+  /// Directory to run the app from
+  pub working_dir: Option<PathBuf>,
+  pub terminal: bool,
+  pub keywords: Vec<String>,
+  pub no_display: bool,
+  pub hidden: bool,
+  pub only_show_in: Vec<String>,
+  pub not_show_in: Vec<String>,
+  pub actions: Vec<DesktopAction>,
+}
+
+/// Something else the app can do besides launching normally, like opening a private window
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DesktopAction {
+  pub id: String,
+  pub name: String,
+  pub icon: Option<String>,
+  pub exec: Option<String>,
+}
+
+/// How to run a desktop entry, with its field codes already expanded
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct DesktopExec {
+  pub args: Vec<String>,
+  pub working_dir: Option<PathBuf>,
+  pub terminal: bool,
+}
+
+/// The parts of a POSIX locale (`lang_COUNTRY.ENCODING@MODIFIER`) used to pick localized
+/// keys. The encoding plays no part in it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Locale {
+  lang: String,
+  country: Option<String>,
+  modifier: Option<String>,
+}
+
+impl Locale {
+  /// The locale messages are shown in, going by the environment
+  pub fn current() -> Locale {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+      .iter()
+      .filter_map(|var| std::env::var(var).ok())
+      .find(|val| !val.is_empty())
+      .map(|val| Locale::parse(&val))
+      .unwrap_or_default()
+  }
+
+  pub fn parse(s: &str) -> Locale {
+    let (rest, modifier) = match s.split_once('@') {
+      Some((rest, m)) => (rest, Some(m.to_string())),
+      None => (s, None),
+    };
+    let rest = rest.split('.').next().unwrap_or_default();
+    let (lang, country) = match rest.split_once('_') {
+      Some((lang, country)) => (lang, Some(country.to_string())),
+      None => (rest, None),
+    };
+    if lang == "C" || lang == "POSIX" {
+      return Locale::default();
+    }
+    Locale {
+      lang: lang.to_string(),
+      country,
+      modifier,
+    }
+  }
+
+  /// Locale suffixes to try a key with, most specific first
+  fn variants(&self) -> Vec<String> {
+    if self.lang.is_empty() {
+      return vec![];
+    }
+    let lang = &self.lang;
+    let mut variants = vec![];
+    if let (Some(country), Some(modifier)) = (&self.country, &self.modifier) {
+      variants.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = &self.country {
+      variants.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = &self.modifier {
+      variants.push(format!("{}@{}", lang, modifier));
+    }
+    variants.push(lang.clone());
+    variants
+  }
+}
+
+struct Group<'a> {
+  entries: HashMap<String, String>,
+  locale: &'a Locale,
+}
+
+impl Group<'_> {
+  fn raw(&self, key: &str) -> Option<&str> {
+    self.entries.get(key).map(|v| v.as_str())
+  }
+
+  fn string(&self, key: &str) -> Option<String> {
+    self.raw(key).map(unescape)
+  }
+
+  /// A `localestring`, preferring the value for the most specific match of the locale
+  fn localized(&self, key: &str) -> Option<String> {
+    self
+      .locale
+      .variants()
+      .iter()
+      .find_map(|v| self.raw(&format!("{}[{}]", key, v)))
+      .or_else(|| self.raw(key))
+      .map(unescape)
+  }
+
+  fn list(&self, key: &str) -> Vec<String> {
+    self.raw(key).map(split_list).unwrap_or_default()
+  }
+
+  fn localized_list(&self, key: &str) -> Vec<String> {
+    self
+      .locale
+      .variants()
+      .iter()
+      .find_map(|v| self.raw(&format!("{}[{}]", key, v)))
+      .or_else(|| self.raw(key))
+      .map(split_list)
+      .unwrap_or_default()
+  }
+
+  fn bool(&self, key: &str) -> bool {
+    self.raw(key) == Some("true")
+  }
+}
+
+impl DesktopEntry {
+  pub fn parse(
+    id: &str,
+    file: &Path,
+    contents: &str,
+    locale: &Locale,
+  ) -> Result<Self, anyhow::Error> {
+    let mut groups = parse_groups(contents)?;
+    let main = Group {
+      entries: groups
+        .remove(MAIN_GROUP)
+        .ok_or_else(|| anyhow!("Missing [{}] group", MAIN_GROUP))?,
+      locale,
+    };
+    let name = main
+      .localized("Name")
+      .ok_or_else(|| anyhow!("Missing required key Name"))?;
+    let actions = main
+      .list("Actions")
+      .into_iter()
+      .filter_map(|id| {
+        let Some(entries) = groups.remove(&format!("{}{}", ACTION_GROUP, id)) else {
+          warn!("{:?} lists action {} without a group for it", file, id);
+          return None;
+        };
+        let group = Group { entries, locale };
+        Some(DesktopAction {
+          name: group.localized("Name")?,
+          icon: group.localized("Icon"),
+          exec: group.string("Exec"),
+          id,
+        })
+      })
+      .collect();
+
+    Ok(DesktopEntry {
+      id: id.to_string(),
+      file: file.to_path_buf(),
+      kind: main.string("Type").unwrap_or_default(),
+      name,
+      icon: main.localized("Icon"),
+      exec: main.string("Exec"),
+      working_dir: main.string("Path").map(PathBuf::from),
+      terminal: main.bool("Terminal"),
+      keywords: main.localized_list("Keywords"),
+      no_display: main.bool("NoDisplay"),
+      hidden: main.bool("Hidden"),
+      only_show_in: main.list("OnlyShowIn"),
+      not_show_in: main.list("NotShowIn"),
+      actions,
+    })
+  }
+
+  /// Whether the entry should be offered on any of the given desktops (`XDG_CURRENT_DESKTOP`)
+  pub fn shown_in(&self, desktops: &[String]) -> bool {
+    if self.hidden || self.no_display || self.kind != "Application" || self.exec.is_none() {
+      return false;
+    }
+    if !self.only_show_in.is_empty() && !desktops.iter().any(|d| self.only_show_in.contains(d)) {
+      return false;
+    }
+    !desktops.iter().any(|d| self.not_show_in.contains(d))
+  }
+
+  /// How to launch the app, or one of its actions
+  pub fn command(&self, action: Option<&DesktopAction>) -> Result<DesktopExec, anyhow::Error> {
+    let exec = match action {
+      Some(action) => action.exec.as_ref(),
+      None => self.exec.as_ref(),
+    }
+    .ok_or_else(|| anyhow!("{} has nothing to execute", self.id))?;
+    let icon = action.and_then(|a| a.icon.as_ref()).or(self.icon.as_ref());
+    let args = expand_exec(exec, &self.name, icon.map(|i| i.as_str()), &self.file)
+      .with_context(|| format!("Invalid Exec in {:?}", self.file))?;
+    Ok(DesktopExec {
+      args,
+      working_dir: self.working_dir.clone(),
+      terminal: self.terminal,
+    })
+  }
+}
+
+impl DesktopExec {
+  pub fn spawn(&self) -> Result<(), anyhow::Error> {
+    let mut args = self.args.iter();
+    let mut cmd = match self.terminal {
+      true => {
+        let term = std::env::var("TERMINAL").unwrap_or_else(|_| "x-terminal-emulator".into());
+        let mut cmd = Command::new(term);
+        cmd.arg("-e");
+        cmd
+      }
+      false => Command::new(args.next().ok_or_else(|| anyhow!("Empty command"))?),
+    };
+    cmd
+      .args(args)
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(Stdio::null());
+    if let Some(dir) = &self.working_dir {
+      cmd.current_dir(dir);
+    }
+    let mut child = cmd.spawn()?;
+    // Reap the app once it exits so it doesn't linger as a zombie
+    thread::spawn(move || child.wait());
+    Ok(())
+  }
+}
+
+/// The XDG data dirs in order of precedence: the user's, then every system one
+pub fn data_dirs() -> Vec<PathBuf> {
+  let var = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+  let home = var("XDG_DATA_HOME")
+    .map(PathBuf::from)
+    .or_else(|| var("HOME").map(|home| Path::new(&home).join(".local/share")));
+  let system = var("XDG_DATA_DIRS").unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+  home
+    .into_iter()
+    .chain(
+      system
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from),
+    )
+    .collect()
+}
+
+/// Where desktop entries are found, in order of precedence
+pub fn application_dirs() -> Vec<PathBuf> {
+  data_dirs()
+    .into_iter()
+    .map(|dir| dir.join("applications"))
+    .collect()
+}
+
+/// The desktops being run, whose names are used in `OnlyShowIn` and `NotShowIn`
+pub fn current_desktops() -> Vec<String> {
+  std::env::var("XDG_CURRENT_DESKTOP")
+    .unwrap_or_default()
+    .split(':')
+    .filter(|d| !d.is_empty())
+    .map(|d| d.to_string())
+    .collect()
+}
+
+/// Finds every entry to offer from the dirs. An entry in an earlier dir replaces any with
+/// the same id in later ones, even when it's there to hide the app.
+pub fn scan(dirs: &[PathBuf], locale: &Locale, desktops: &[String]) -> Vec<DesktopEntry> {
+  let mut seen = HashSet::new();
+  let mut entries = vec![];
+  for dir in dirs {
+    let mut files = vec![];
+    find_entries(dir, dir, &mut files);
+    files.sort();
+    for (id, file) in files {
+      if seen.contains(&id) {
+        continue;
+      }
+      let parsed = fs::read_to_string(&file)
+        .map_err(|err| anyhow!(err))
+        .and_then(|contents| DesktopEntry::parse(&id, &file, &contents, locale));
+      match parsed {
+        Ok(entry) => {
+          seen.insert(id);
+          if entry.shown_in(desktops) {
+            entries.push(entry);
+          }
+        }
+        Err(err) => warn!("Skipping desktop entry {:?}: {:?}", file, err),
+      }
+    }
+  }
+  entries
+}
+
+/// Collects the `.desktop` files under the dir along with their ids, which is their path
+/// relative to the applications dir with `/` swapped for `-`
+fn find_entries(root: &Path, dir: &Path, found: &mut Vec<(String, PathBuf)>) {
+  let Ok(read) = fs::read_dir(dir) else {
+    return;
+  };
+  for path in read.filter_map(|e| e.ok()).map(|e| e.path()) {
+    if path.is_dir() {
+      find_entries(root, &path, found);
+    } else if path.extension().is_some_and(|ext| ext == "desktop") {
+      if let Ok(rel) = path.strip_prefix(root) {
+        let id = rel.to_string_lossy().replace('/', "-");
+        found.push((id, path));
+      }
+    }
+  }
+}
+
+pub fn parse_groups(
+  contents: &str,
+) -> Result<HashMap<String, HashMap<String, String>>, anyhow::Error> {
+  let mut groups: HashMap<String, HashMap<String, String>> = HashMap::new();
+  let mut current: Option<String> = None;
+  for (num, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+      if groups.contains_key(name) {
+        return Err(anyhow!("Group {} appears twice", name));
+      }
+      groups.insert(name.to_string(), HashMap::new());
+      current = Some(name.to_string());
+      continue;
+    }
+    let (key, value) = line
+      .split_once('=')
+      .ok_or_else(|| anyhow!("Line {} is not a key=value pair", num + 1))?;
+    let group = current
+      .as_ref()
+      .and_then(|g| groups.get_mut(g))
+      .ok_or_else(|| anyhow!("Line {} comes before any group", num + 1))?;
+    // The first value for a key wins
+    group
+      .entry(key.trim().to_string())
+      .or_insert_with(|| value.trim().to_string());
+  }
+  Ok(groups)
+}
+
+/// Undoes the escapes allowed in string values
+fn unescape(value: &str) -> String {
+  let mut out = String::with_capacity(value.len());
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      out.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('s') => out.push(' '),
+      Some('n') => out.push('\n'),
+      Some('t') => out.push('\t'),
+      Some('r') => out.push('\r'),
+      Some('\\') => out.push('\\'),
+      Some(other) => {
+        out.push('\\');
+        out.push(other);
+      }
+      None => out.push('\\'),
+    }
+  }
+  out
+}
+
+/// Splits a `;` separated list value, where `\;` is a literal semicolon
+fn split_list(value: &str) -> Vec<String> {
+  let mut items = vec![];
+  let mut item = String::new();
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => match chars.next() {
+        Some(';') => item.push(';'),
+        Some(other) => {
+          item.push('\\');
+          item.push(other);
+        }
+        None => item.push('\\'),
+      },
+      ';' => items.push(unescape(&std::mem::take(&mut item))),
+      _ => item.push(c),
+    }
+  }
+  if !item.is_empty() {
+    items.push(unescape(&item));
+  }
+  items.into_iter().filter(|i| !i.is_empty()).collect()
+}
+
+/// Turns an `Exec` value into the args to run. No files or urls are ever passed, so the
+/// field codes for them are dropped, along with any arg that was only that code.
+fn expand_exec(
+  exec: &str,
+  name: &str,
+  icon: Option<&str>,
+  file: &Path,
+) -> Result<Vec<String>, anyhow::Error> {
+  let mut args = vec![];
+  for arg in split_exec(exec)? {
+    match arg.as_str() {
+      "%f" | "%F" | "%u" | "%U" => continue,
+      "%i" => {
+        if let Some(icon) = icon {
+          args.push("--icon".to_string());
+          args.push(icon.to_string());
+        }
+        continue;
+      }
+      _ => {}
+    }
+    let mut expanded = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+      if c != '%' {
+        expanded.push(c);
+        continue;
+      }
+      match chars.next() {
+        Some('%') => expanded.push('%'),
+        Some('c') => expanded.push_str(name),
+        Some('k') => expanded.push_str(&file.to_string_lossy()),
+        // Files, urls and the deprecated codes all expand to nothing
+        Some('f' | 'F' | 'u' | 'U' | 'i' | 'd' | 'D' | 'n' | 'N' | 'v' | 'm') => {}
+        Some(other) => return Err(anyhow!("Unknown field code %{}", other)),
+        None => return Err(anyhow!("Dangling % at the end of {}", arg)),
+      }
+    }
+    args.push(expanded);
+  }
+  if args.is_empty() {
+    return Err(anyhow!("Nothing to execute"));
+  }
+  Ok(args)
+}
+
+/// Splits the command line into args, honouring double quoted args and the escapes
+/// allowed within them
+fn split_exec(exec: &str) -> Result<Vec<String>, anyhow::Error> {
+  let mut args = vec![];
+  let mut arg: Option<String> = None;
+  let mut chars = exec.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      ' ' | '\t' => {
+        if let Some(done) = arg.take() {
+          args.push(done);
+        }
+      }
+      '"' => {
+        let quoted = arg.get_or_insert_with(String::new);
+        loop {
+          match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+              Some(esc @ ('"' | '`' | '$' | '\\')) => quoted.push(esc),
+              Some(other) => {
+                quoted.push('\\');
+                quoted.push(other);
+              }
+              None => return Err(anyhow!("Unterminated quote in {}", exec)),
+            },
+            Some(other) => quoted.push(other),
+            None => return Err(anyhow!("Unterminated quote in {}", exec)),
+          }
+        }
+      }
+      _ => arg.get_or_insert_with(String::new).push(c),
+    }
+  }
+  args.extend(arg);
+  Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+  use std::time::{Duration, Instant};
+
+  const FIREFOX: &str = r#"
+# A comment
+[Desktop Entry]
+Version=1.0
+Type=Application
+Name=Firefox
+Name[de]=Feuerfuchs
+Name[de_CH]=Füürfuchs
+Icon=firefox
+Exec=firefox %u
+Keywords=web;browser;Internet;
+Keywords[de]=Netz;Browser;
+Actions=new-window;private;missing;
+
+[Desktop Action new-window]
+Name=New Window
+Exec=firefox --new-window %u
+
+[Desktop Action private]
+Name=New Private Window
+Name[de]=Neues privates Fenster
+Icon=firefox-private
+Exec=firefox --private-window %i
+"#;
+
+  fn parse(contents: &str, locale: &str) -> DesktopEntry {
+    DesktopEntry::parse(
+      "firefox.desktop",
+      Path::new("/apps/firefox.desktop"),
+      contents,
+      &Locale::parse(locale),
+    )
+    .unwrap()
+  }
+
+  fn write(root: &Path, rel: &str, contents: &str) {
+    let path = root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+  }
+
+  fn entry(name: &str, extra: &str) -> String {
+    format!(
+      "[Desktop Entry]\nType=Application\nName={}\nExec={}\n{}",
+      name,
+      name.to_lowercase(),
+      extra
+    )
+  }
+
+  #[test]
+  fn parses_entries() {
+    let ff = parse(FIREFOX, "en_US.UTF-8");
+    assert_eq!(ff.name, "Firefox");
+    assert_eq!(ff.icon, Some("firefox".into()));
+    assert_eq!(ff.keywords, vec!["web", "browser", "Internet"]);
+    assert_eq!(ff.actions.len(), 2);
+    assert_eq!(ff.actions[1].id, "private");
+    assert_eq!(ff.actions[1].name, "New Private Window");
+    assert!(ff.shown_in(&[]));
+  }
+
+  #[test]
+  fn prefers_most_specific_locale() {
+    assert_eq!(parse(FIREFOX, "de_CH.UTF-8@euro").name, "Füürfuchs");
+    assert_eq!(parse(FIREFOX, "de_AT").name, "Feuerfuchs");
+    assert_eq!(parse(FIREFOX, "C").name, "Firefox");
+    let de = parse(FIREFOX, "de_DE");
+    assert_eq!(de.keywords, vec!["Netz", "Browser"]);
+    assert_eq!(de.actions[1].name, "Neues privates Fenster");
+  }
+
+  #[test]
+  fn expands_field_codes() {
+    let ff = parse(FIREFOX, "");
+    assert_eq!(ff.command(None).unwrap().args, vec!["firefox"]);
+    assert_eq!(
+      ff.command(Some(&ff.actions[1])).unwrap().args,
+      vec!["firefox", "--private-window", "--icon", "firefox-private"]
+    );
+
+    let file = Path::new("/apps/app.desktop");
+    let exec = |e| expand_exec(e, "My App", None, file);
+    assert_eq!(
+      exec(r#"app --name=%c --from %k --url=%U 100%% %i"#).unwrap(),
+      vec![
+        "app",
+        "--name=My App",
+        "--from",
+        "/apps/app.desktop",
+        "--url=",
+        "100%"
+      ]
+    );
+    assert_eq!(
+      exec(r#""/opt/my app/run" "say \"hi\" \$HOME \\ \`x\`""#).unwrap(),
+      vec!["/opt/my app/run", r#"say "hi" $HOME \ `x`"#]
+    );
+    assert!(exec("app %z").is_err());
+    assert!(exec("\"app").is_err());
+    assert!(exec("%F").is_err());
+  }
+
+  #[test]
+  fn unescapes_values() {
+    assert_eq!(unescape(r"a\sb\\n\n"), "a b\\n\n");
+    assert_eq!(
+      split_list(r"one;two\;three;;four"),
+      vec!["one", "two;three", "four"]
+    );
+  }
+
+  #[test]
+  fn rejects_malformed_entries() {
+    let locale = Locale::default();
+    let parse = |c: &str| DesktopEntry::parse("x.desktop", Path::new("x.desktop"), c, &locale);
+    assert!(parse("Name=Orphan").is_err());
+    assert!(parse("[Desktop Entry]\nType=Application").is_err());
+    assert!(parse("[Desktop Entry]\nName=A\nnonsense").is_err());
+    assert!(parse("[Other]\nName=A").is_err());
+  }
+
+  #[test]
+  fn visibility_rules() {
+    let shown = |extra: &str, desktops: &[&str]| {
+      let desktops: Vec<String> = desktops.iter().map(|d| d.to_string()).collect();
+      parse(&entry("App", extra), "").shown_in(&desktops)
+    };
+    assert!(shown("", &["GNOME"]));
+    assert!(!shown("NoDisplay=true", &[]));
+    assert!(!shown("Hidden=true", &[]));
+    assert!(!shown("OnlyShowIn=KDE;", &["GNOME"]));
+    assert!(shown("OnlyShowIn=KDE;GNOME;", &["ubuntu", "GNOME"]));
+    assert!(!shown("NotShowIn=GNOME;", &["GNOME"]));
+    assert!(shown("NotShowIn=GNOME;", &["KDE"]));
+    assert!(!parse("[Desktop Entry]\nType=Link\nName=L\nURL=x", "").shown_in(&[]));
+  }
+
+  #[test]
+  fn scans_data_dirs_in_order() {
+    let root = TestDir::new("desktop");
+    let (user, system) = (root.join("user"), root.join("system"));
+    write(&system, "firefox.desktop", FIREFOX);
+    write(&system, "gimp.desktop", &entry("Gimp", ""));
+    write(&system, "kde/konsole.desktop", &entry("Konsole", ""));
+    write(&system, "hidden.desktop", &entry("Hidden", ""));
+    write(&system, "broken.desktop", "not an entry");
+    write(&system, "readme.txt", &entry("Readme", ""));
+    // The user's entries replace the system's, including to hide them
+    write(&user, "gimp.desktop", &entry("Gimp Edge", ""));
+    write(&user, "hidden.desktop", &entry("Hidden", "Hidden=true"));
+
+    let entries = scan(&[user, system], &Locale::default(), &[]);
+    let ids: Vec<_> = entries
+      .iter()
+      .map(|e| (e.id.as_str(), e.name.as_str()))
+      .collect();
+    assert_eq!(
+      ids,
+      vec![
+        ("gimp.desktop", "Gimp Edge"),
+        ("firefox.desktop", "Firefox"),
+        ("kde-konsole.desktop", "Konsole"),
+      ]
+    );
+  }
+
+  #[test]
+  fn spawns_in_working_dir() {
+    let dir = TestDir::new("desktop-spawn");
+    let exec = DesktopExec {
+      args: vec!["sh".into(), "-c".into(), "touch spawned".into()],
+      working_dir: Some(dir.to_path_buf()),
+      terminal: false,
+    };
+    exec.spawn().unwrap();
+
+    let until = Instant::now() + Duration::from_secs(2);
+    while !dir.join("spawned").exists() && Instant::now() < until {
+      thread::sleep(Duration::from_millis(20));
+    }
+    assert!(dir.join("spawned").exists());
+  }
+}
@@ -0,0 +1,481 @@
+use super::desktop::{self, parse_groups};
+use itertools::Itertools;
+use std::{
+  collections::{HashMap, HashSet},
+  fs,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+use tracing::warn;
+
+/// Every theme falls back to this one, which is where apps install their own icons
+const FALLBACK_THEME: &str = "hicolor";
+/// Formats icons can be in, most preferred first
+const EXTENSIONS: [&str; 3] = ["png", "svg", "xpm"];
+
+/// Finds icons by name in an icon theme and the themes it inherits from, following the
+/// freedesktop Icon Theme spec. Theme dirs are read once up front, so lookups don't touch
+/// the filesystem.
+pub struct IconThemes {
+  base_dirs: Vec<PathBuf>,
+  theme: String,
+  themes: HashMap<String, Theme>,
+  /// Every dir and index the themes were read from, with when it was last modified
+  read: Stamps,
+}
+
+type Stamps = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// Notes down when the path was modified, before it's read so a change while it's being read
+/// isn't missed
+fn stamp(stamps: &mut Stamps, path: PathBuf) {
+  let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+  stamps.push((path, modified));
+}
+
+struct Theme {
+  inherits: Vec<String>,
+  dirs: Vec<ThemeDir>,
+}
+
+struct ThemeDir {
+  size: u32,
+  scale: u32,
+  kind: DirKind,
+  /// Icon files in the dir by name, across every base dir
+  icons: HashMap<String, Vec<PathBuf>>,
+}
+
+enum DirKind {
+  Fixed,
+  Scalable { min: u32, max: u32 },
+  Threshold(u32),
+}
+
+impl ThemeDir {
+  fn matches(&self, size: u32, scale: u32) -> bool {
+    if self.scale != scale {
+      return false;
+    }
+    match self.kind {
+      DirKind::Fixed => self.size == size,
+      DirKind::Scalable { min, max } => min <= size && size <= max,
+      DirKind::Threshold(t) => self.size.saturating_sub(t) <= size && size <= self.size + t,
+    }
+  }
+
+  fn distance(&self, size: u32, scale: u32) -> u32 {
+    let (min, max) = match self.kind {
+      DirKind::Fixed => (self.size, self.size),
+      DirKind::Scalable { min, max } => (min, max),
+      DirKind::Threshold(t) => (self.size.saturating_sub(t), self.size + t),
+    };
+    let wanted = size * scale;
+    // At most one of these is non-zero
+    (min * self.scale).saturating_sub(wanted) + wanted.saturating_sub(max * self.scale)
+  }
+
+  fn icon(&self, name: &str) -> Option<&PathBuf> {
+    let files = self.icons.get(name)?;
+    EXTENSIONS.iter().find_map(|ext| {
+      files
+        .iter()
+        .find(|f| f.extension().is_some_and(|e| e == *ext))
+    })
+  }
+}
+
+impl Theme {
+  fn load(base_dirs: &[PathBuf], name: &str, read: &mut Stamps) -> Option<Theme> {
+    for base in base_dirs {
+      stamp(read, base.join(name));
+      stamp(read, base.join(name).join("index.theme"));
+    }
+    let index = base_dirs
+      .iter()
+      .map(|base| base.join(name).join("index.theme"))
+      .find(|index| index.exists())?;
+    let groups = match fs::read_to_string(&index)
+      .map_err(|err| err.into())
+      .and_then(|contents| parse_groups(&contents))
+    {
+      Ok(groups) => groups,
+      Err(err) => {
+        warn!("Skipping icon theme {:?}: {:?}", index, err);
+        return None;
+      }
+    };
+    let list = |group: &HashMap<String, String>, key: &str| -> Vec<String> {
+      group
+        .get(key)
+        .map(|v| {
+          v.split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+        })
+        .unwrap_or_default()
+    };
+    let theme = groups.get("Icon Theme")?;
+    let inherits = list(theme, "Inherits");
+    let dirs = list(theme, "Directories")
+      .into_iter()
+      .chain(list(theme, "ScaledDirectories"))
+      .unique()
+      .filter_map(|dir| {
+        let group = groups.get(&dir)?;
+        let num = |key: &str| group.get(key).and_then(|v| v.parse::<u32>().ok());
+        let size = num("Size")?;
+        let kind = match group.get("Type").map(|t| t.as_str()) {
+          Some("Fixed") => DirKind::Fixed,
+          Some("Scalable") => DirKind::Scalable {
+            min: num("MinSize").unwrap_or(size),
+            max: num("MaxSize").unwrap_or(size),
+          },
+          _ => DirKind::Threshold(num("Threshold").unwrap_or(2)),
+        };
+        Some(ThemeDir {
+          size,
+          scale: num("Scale").unwrap_or(1),
+          kind,
+          icons: read_icons(
+            base_dirs.iter().map(|base| base.join(name).join(&dir)),
+            read,
+          ),
+        })
+      })
+      .collect();
+    Some(Theme { inherits, dirs })
+  }
+
+  fn lookup(&self, name: &str, size: u32, scale: u32) -> Option<&PathBuf> {
+    let exact = self
+      .dirs
+      .iter()
+      .filter(|dir| dir.matches(size, scale))
+      .find_map(|dir| dir.icon(name));
+    exact.or_else(|| {
+      self
+        .dirs
+        .iter()
+        .filter_map(|dir| dir.icon(name).map(|icon| (dir.distance(size, scale), icon)))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, icon)| icon)
+    })
+  }
+}
+
+impl IconThemes {
+  /// Loads the theme, everything it inherits from and the fallback theme
+  pub fn new(base_dirs: Vec<PathBuf>, theme: &str) -> Self {
+    let mut themes = HashMap::new();
+    // Themes that get installed or removed change the base dirs
+    let mut read = Stamps::new();
+    for base in &base_dirs {
+      stamp(&mut read, base.clone());
+    }
+    let mut pending = vec![theme.to_string(), FALLBACK_THEME.to_string()];
+    let mut tried = HashSet::new();
+    while let Some(name) = pending.pop() {
+      if !tried.insert(name.clone()) {
+        continue;
+      }
+      if let Some(loaded) = Theme::load(&base_dirs, &name, &mut read) {
+        pending.extend(loaded.inherits.iter().cloned());
+        themes.insert(name, loaded);
+      }
+    }
+    IconThemes {
+      base_dirs,
+      theme: theme.to_string(),
+      themes,
+      read,
+    }
+  }
+
+  /// Whether these are still the themes that'd be loaded, so it's the same theme and nothing
+  /// was added to or removed from the dirs it was read from since
+  pub fn is_current(&self, base_dirs: &[PathBuf], theme: &str) -> bool {
+    self.theme == theme
+      && self.base_dirs == base_dirs
+      && self
+        .read
+        .iter()
+        .all(|(path, modified)| fs::metadata(path).and_then(|m| m.modified()).ok() == *modified)
+  }
+
+  /// The file for the icon that's closest to the size, which is in logical pixels. Icons
+  /// given as a path are used as is.
+  pub fn find(&self, icon: &str, size: u32, scale: u32) -> Option<PathBuf> {
+    let path = Path::new(icon);
+    if path.is_absolute() {
+      return path.exists().then(|| path.to_path_buf());
+    }
+    // Names shouldn't have an extension, but some do anyway
+    let name = match path.extension().and_then(|e| e.to_str()) {
+      Some(ext) if EXTENSIONS.contains(&ext) => path.file_stem()?.to_str()?,
+      _ => icon,
+    };
+    let mut visited = HashSet::new();
+    self
+      .find_in(&self.theme, name, size, scale, &mut visited)
+      .or_else(|| self.find_in(FALLBACK_THEME, name, size, scale, &mut visited))
+      .cloned()
+      .or_else(|| self.fallback(name))
+  }
+
+  fn find_in<'a>(
+    &'a self,
+    theme: &str,
+    name: &str,
+    size: u32,
+    scale: u32,
+    visited: &mut HashSet<String>,
+  ) -> Option<&'a PathBuf> {
+    if !visited.insert(theme.to_string()) {
+      return None;
+    }
+    let theme = self.themes.get(theme)?;
+    theme.lookup(name, size, scale).or_else(|| {
+      theme
+        .inherits
+        .iter()
+        .find_map(|parent| self.find_in(parent, name, size, scale, visited))
+    })
+  }
+
+  /// Icons that aren't in any theme, straight in a base dir
+  fn fallback(&self, name: &str) -> Option<PathBuf> {
+    self.base_dirs.iter().find_map(|base| {
+      EXTENSIONS
+        .iter()
+        .map(|ext| base.join(format!("{}.{}", name, ext)))
+        .find(|p| p.exists())
+    })
+  }
+}
+
+/// Where icon themes are found, in order of precedence
+pub fn base_dirs() -> Vec<PathBuf> {
+  let home = std::env::var("HOME")
+    .ok()
+    .filter(|h| !h.is_empty())
+    .map(|home| Path::new(&home).join(".icons"));
+  home
+    .into_iter()
+    .chain(
+      desktop::data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("icons")),
+    )
+    .chain([PathBuf::from("/usr/share/pixmaps")])
+    .collect()
+}
+
+/// The icon theme picked in the GTK settings, which most desktops keep in sync with their own
+pub fn current_theme() -> String {
+  let config = std::env::var("XDG_CONFIG_HOME")
+    .ok()
+    .filter(|c| !c.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| {
+      std::env::var("HOME")
+        .ok()
+        .map(|home| Path::new(&home).join(".config"))
+    });
+  ["gtk-4.0", "gtk-3.0"]
+    .iter()
+    .filter_map(|gtk| {
+      let settings = fs::read_to_string(config.as_ref()?.join(gtk).join("settings.ini")).ok()?;
+      settings.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "gtk-icon-theme-name").then(|| value.trim().trim_matches('"').to_string())
+      })
+    })
+    .find(|theme| !theme.is_empty())
+    .unwrap_or_else(|| FALLBACK_THEME.to_string())
+}
+
+fn read_icons(
+  dirs: impl Iterator<Item = PathBuf>,
+  stamps: &mut Stamps,
+) -> HashMap<String, Vec<PathBuf>> {
+  let mut icons: HashMap<String, Vec<PathBuf>> = HashMap::new();
+  for dir in dirs {
+    stamp(stamps, dir.clone());
+    let Ok(read) = fs::read_dir(&dir) else {
+      continue;
+    };
+    for path in read.filter_map(|e| e.ok()).map(|e| e.path()) {
+      let known = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| EXTENSIONS.contains(&e));
+      let name = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string());
+      if let (true, Some(name)) = (known, name) {
+        icons.entry(name).or_default().push(path);
+      }
+    }
+  }
+  icons
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+
+  fn write(root: &Path, rel: &str, contents: &str) {
+    let path = root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+  }
+
+  fn theme(root: &Path, name: &str, inherits: &str, dirs: &[(&str, &str)]) {
+    let names: Vec<_> = dirs.iter().map(|(d, _)| *d).collect();
+    let mut index = format!(
+      "[Icon Theme]\nName={}\nInherits={}\nDirectories={}\n",
+      name,
+      inherits,
+      names.join(",")
+    );
+    for (dir, spec) in dirs {
+      index.push_str(&format!("\n[{}]\n{}\n", dir, spec));
+    }
+    write(root, &format!("{}/index.theme", name), &index);
+  }
+
+  fn found(themes: &IconThemes, icon: &str, size: u32, scale: u32) -> Option<String> {
+    let root = &themes.base_dirs[0];
+    themes.find(icon, size, scale).map(|p| {
+      p.strip_prefix(root)
+        .unwrap_or(&p)
+        .to_string_lossy()
+        .to_string()
+    })
+  }
+
+  #[test]
+  fn prefers_matching_sizes_then_closest() {
+    let root = TestDir::new("icons-sizes");
+    theme(
+      &root,
+      "Test",
+      "",
+      &[
+        ("16x16/apps", "Size=16\nType=Fixed"),
+        ("48x48/apps", "Size=48\nType=Fixed"),
+        ("48x48@2/apps", "Size=48\nScale=2\nType=Fixed"),
+        (
+          "scalable/apps",
+          "Size=64\nType=Scalable\nMinSize=8\nMaxSize=40",
+        ),
+      ],
+    );
+    write(&root, "Test/16x16/apps/a.png", "");
+    write(&root, "Test/48x48/apps/a.png", "");
+    write(&root, "Test/48x48@2/apps/a.png", "");
+    write(&root, "Test/scalable/apps/b.svg", "");
+    write(&root, "Test/16x16/apps/b.png", "");
+    let themes = IconThemes::new(vec![root.to_path_buf()], "Test");
+
+    assert_eq!(found(&themes, "a", 48, 1).unwrap(), "Test/48x48/apps/a.png");
+    assert_eq!(
+      found(&themes, "a", 48, 2).unwrap(),
+      "Test/48x48@2/apps/a.png"
+    );
+    assert_eq!(found(&themes, "a", 20, 1).unwrap(), "Test/16x16/apps/a.png");
+    assert_eq!(
+      found(&themes, "b", 32, 1).unwrap(),
+      "Test/scalable/apps/b.svg"
+    );
+    assert_eq!(
+      found(&themes, "a.png", 48, 1).unwrap(),
+      "Test/48x48/apps/a.png"
+    );
+    assert_eq!(found(&themes, "missing", 48, 1), None);
+  }
+
+  #[test]
+  fn prefers_png_then_svg_then_xpm() {
+    let root = TestDir::new("icons-formats");
+    theme(&root, "Test", "", &[("48", "Size=48")]);
+    write(&root, "Test/48/a.xpm", "");
+    write(&root, "Test/48/a.svg", "");
+    write(&root, "Test/48/b.xpm", "");
+    write(&root, "Test/48/c.ico", "");
+    let themes = IconThemes::new(vec![root.to_path_buf()], "Test");
+
+    assert_eq!(found(&themes, "a", 48, 1).unwrap(), "Test/48/a.svg");
+    assert_eq!(found(&themes, "b", 48, 1).unwrap(), "Test/48/b.xpm");
+    assert_eq!(found(&themes, "c", 48, 1), None);
+  }
+
+  #[test]
+  fn falls_back_through_inherited_themes() {
+    let root = TestDir::new("icons-inherits");
+    let other = TestDir::new("icons-inherits-other");
+    theme(&root, "Child", "Parent", &[("48", "Size=48")]);
+    theme(&root, "Parent", "Child", &[("48", "Size=48")]);
+    theme(&other, "hicolor", "", &[("48", "Size=48")]);
+    write(&root, "Child/48/a.png", "");
+    write(&root, "Parent/48/a.png", "");
+    write(&root, "Parent/48/b.png", "");
+    // Themes can be split across base dirs
+    write(&other, "Parent/48/c.png", "");
+    write(&other, "hicolor/48/d.png", "");
+    write(&other, "e.xpm", "");
+    let themes = IconThemes::new(vec![root.to_path_buf(), other.to_path_buf()], "Child");
+
+    assert_eq!(found(&themes, "a", 48, 1).unwrap(), "Child/48/a.png");
+    assert_eq!(found(&themes, "b", 48, 1).unwrap(), "Parent/48/b.png");
+    let other_file = |rel: &str| other.join(rel).to_string_lossy().to_string();
+    assert_eq!(
+      found(&themes, "c", 48, 1).unwrap(),
+      other_file("Parent/48/c.png")
+    );
+    assert_eq!(
+      found(&themes, "d", 48, 1).unwrap(),
+      other_file("hicolor/48/d.png")
+    );
+    assert_eq!(found(&themes, "e", 48, 1).unwrap(), other_file("e.xpm"));
+    assert_eq!(found(&themes, "f", 48, 1), None);
+  }
+
+  #[test]
+  fn uses_absolute_paths_as_is() {
+    let root = TestDir::new("icons-absolute");
+    write(&root, "icon.png", "");
+    let themes = IconThemes::new(vec![], "Test");
+    let icon = root.join("icon.png");
+
+    assert_eq!(themes.find(icon.to_str().unwrap(), 48, 1), Some(icon));
+    assert_eq!(
+      themes.find(root.join("gone.png").to_str().unwrap(), 48, 1),
+      None
+    );
+  }
+
+  #[test]
+  fn notices_theme_changes() {
+    let root = TestDir::new("icons-changes");
+    theme(&root, "Test", "Parent", &[("48", "Size=48")]);
+    let base_dirs = vec![root.to_path_buf()];
+    let themes = IconThemes::new(base_dirs.clone(), "Test");
+    assert!(themes.is_current(&base_dirs, "Test"));
+    assert!(!themes.is_current(&base_dirs, "Other"));
+    assert!(!themes.is_current(&[], "Test"));
+
+    write(&root, "Test/48/a.png", "");
+    assert!(!themes.is_current(&base_dirs, "Test"));
+
+    // Including a theme it inherits from being installed
+    let themes = IconThemes::new(base_dirs.clone(), "Test");
+    assert!(themes.is_current(&base_dirs, "Test"));
+    theme(&root, "Parent", "", &[("48", "Size=48")]);
+    assert!(!themes.is_current(&base_dirs, "Test"));
+  }
+}
@@ -1,19 +1,77 @@
-use crate::config::CalcConfig;
+mod dates;
+mod exact;
+mod format;
+mod plot;
+mod program;
+mod rates;
+mod session;
+mod stats;
+mod syntax;
+mod units;
+
+use crate::cacher::Cache;
+use crate::config::{CalcBackend, CalcConfig};
 use crate::plugin::{
   AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
 };
+use crate::plugin_registry::PluginRegistry;
 use anyhow::anyhow;
-use arboard::Clipboard;
+use arboard::{Clipboard, ImageData};
 use calc::Context;
+use chrono::{Local, TimeZone, Utc};
+use dates::Zone;
 use egui::{Color32, RichText};
+use format::Digits;
 use parking_lot::Mutex;
-use std::sync::Arc;
+use plot::{Func, Plot};
+use program::{Base, Int};
+use rates::RateStore;
+use serde::{Deserialize, Serialize};
+use session::{Session, Statement};
+use stats::{Mistake, Stats};
+use std::{borrow::Cow, ops::Range, path::Path, sync::Arc, time::Duration};
+use tracing::warn;
 
 pub const PLUGIN_NAME: &'static str = "calc";
+/// How big plots are when they're copied as images
+const PLOT_IMAGE_SIZE: (u32, u32) = (800, 400);
 
 #[derive(Clone)]
 pub enum Evaluated {
   Ok(String),
+  /// A number written the way the config says, along with how it's written in calculations,
+  /// which is what's kept as `ans`
+  Num {
+    shown: String,
+    value: String,
+    /// What the number is of, like the mean of a list
+    form: Option<&'static str>,
+  },
+  /// Worked out with exchange rates, which are only as good as when they're from
+  Rated {
+    value: String,
+    /// When the rates are from, in seconds since the unix epoch
+    as_of: i64,
+    stale: bool,
+  },
+  /// A whole number in one of the bases it's offered up in
+  Int {
+    int: Int,
+    base: Base,
+  },
+  /// An expression with a variable in it, plotted. Copied as a table of its values, or as
+  /// an image of the plot
+  Plot {
+    plot: Arc<Plot>,
+    image: bool,
+  },
+  /// A date, time or duration in one of the forms it's offered up in
+  Time {
+    value: String,
+    form: &'static str,
+  },
+  /// What's wrong, and where in the input it is as a range of bytes. Mistakes that can't be
+  /// pointed at have an empty range
   Err {
     message: String,
     start: usize,
@@ -21,125 +79,578 @@ pub enum Evaluated {
   },
 }
 
+#[derive(Clone)]
+pub enum CalcValue {
+  /// What's typed worked out, along with what it assigns so that's kept once it's picked
+  Typed {
+    input: String,
+    statement: Option<Statement>,
+    evaluated: Evaluated,
+  },
+  /// A calculation from before, put back in the input when picked
+  History(String),
+}
+
 pub struct CalcPlugin {
   cfg: CalcConfig,
+  rates: Arc<RateStore>,
+  /// Variables, functions and `ans`, which last until the app's closed
+  session: Mutex<Session>,
+  history: Cache<CalcHistory>,
   clip: Mutex<Clipboard>,
 }
 
-impl PluginValue for Evaluated {}
-impl Renderable for Evaluated {
-  fn render(&self, ui: &mut egui::Ui, state: &AppState) {
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct CalcHistory {
+  /// Newest first
+  calculations: Vec<String>,
+}
+
+impl CalcHistory {
+  /// Puts the calculation at the top, dropping the oldest ones past the max
+  fn record(&mut self, input: &str, max: usize) {
+    self.calculations.retain(|c| c != input);
+    self.calculations.insert(0, input.to_string());
+    self.calculations.truncate(max);
+  }
+}
+
+impl PluginValue for CalcValue {}
+impl Renderable for CalcValue {
+  fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
+    match self {
+      CalcValue::Typed {
+        input, evaluated, ..
+      } => evaluated.render(ui, input),
+      CalcValue::History(input) => {
+        ui.horizontal(|ui| {
+          ui.label(RichText::new(input));
+          ui.label(RichText::new("History").weak().small());
+        });
+      }
+    }
+  }
+}
+impl SearchBlocker for CalcValue {}
+
+impl Evaluated {
+  /// What's copied, exactly as it's shown
+  fn result(&self) -> Option<String> {
+    match self {
+      Evaluated::Ok(v)
+      | Evaluated::Num { shown: v, .. }
+      | Evaluated::Rated { value: v, .. }
+      | Evaluated::Time { value: v, .. } => Some(v.clone()),
+      Evaluated::Int { int, base } => Some(int.format(*base)),
+      Evaluated::Plot { .. } | Evaluated::Err { .. } => None,
+    }
+  }
+
+  /// What's kept as `ans`, which has to read back in later calculations
+  fn ans(&self) -> Option<String> {
+    match self {
+      Evaluated::Num { value, .. } => Some(value.clone()),
+      evaluated => evaluated.result(),
+    }
+  }
+}
+
+impl Evaluated {
+  /// Shows the result, pointing out any mistake in the `input` it was worked out from
+  fn render(&self, ui: &mut egui::Ui, input: &str) {
     ui.horizontal(|ui| match self {
       Evaluated::Ok(v) => {
         ui.label(RichText::new(v));
         return;
       }
+      Evaluated::Num { shown, form, .. } => {
+        ui.label(RichText::new(shown));
+        if let Some(form) = form {
+          ui.label(RichText::new(*form).weak().small());
+        }
+      }
+      Evaluated::Rated {
+        value,
+        as_of,
+        stale,
+      } => {
+        let as_of = Local
+          .timestamp_opt(*as_of, 0)
+          .single()
+          .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+          .unwrap_or_else(|| "an unknown time".into());
+        ui.label(RichText::new(value));
+        match stale {
+          true => ui.label(
+            RichText::new(format!("Rates from {} are out of date", as_of))
+              .small()
+              .color(ui.visuals().warn_fg_color),
+          ),
+          false => ui.label(
+            RichText::new(format!("Rates from {}", as_of))
+              .weak()
+              .small(),
+          ),
+        };
+      }
+      Evaluated::Int { int, base } => {
+        ui.label(RichText::new(int.format(*base)).monospace());
+        ui.label(
+          RichText::new(format!("{} {}", base, int.width))
+            .weak()
+            .small(),
+        );
+      }
+      Evaluated::Time { value, form } => {
+        ui.label(RichText::new(value));
+        ui.label(RichText::new(*form).weak().small());
+      }
+      Evaluated::Plot { plot, image: false } => {
+        ui.vertical(|ui| {
+          plot.show(ui);
+          let hint = "Drag to pan, scroll to zoom, Enter copies a table of values";
+          ui.label(RichText::new(hint).weak().small());
+        });
+      }
+      Evaluated::Plot { plot, image: true } => {
+        ui.label(RichText::new(format!("Plot of f({})", plot.func.var)));
+        ui.label(RichText::new("as an image").weak().small());
+      }
       Evaluated::Err {
         message,
         start,
         end,
-      } => match (start, end, message) {
-        (s, e, _) if *s != 0 && *e != 0 => {
-          let inp = &state.input;
-          ui.label(RichText::new(&inp[1..*s]));
-          ui.label(RichText::new(&inp[*s..*e + 1]).color(Color32::RED));
-          ui.label(RichText::new(&inp[*e + 1..]));
+      } => {
+        let before = input.get(..*start);
+        let at = input.get(*start..*end).filter(|at| !at.is_empty());
+        match (before, at, input.get(*end..)) {
+          (Some(before), Some(at), Some(after)) => {
+            ui.label(RichText::new(before));
+            ui.label(RichText::new(at).color(Color32::RED));
+            ui.label(RichText::new(after));
+          }
+          _ => {
+            ui.label(RichText::new(message));
+          }
         }
-        (_, _, message) => {
-          ui.label(RichText::new(message));
-        }
-      },
+      }
     });
   }
 }
-impl SearchBlocker for Evaluated {}
 
 impl CalcPlugin {
-  pub fn init(cfg: CalcConfig, clip: Clipboard) -> Result<Self, anyhow::Error> {
+  pub fn init(cfg: CalcConfig, cache_dir: &Path, clip: Clipboard) -> Result<Self, anyhow::Error> {
+    let rates = Arc::new(RateStore::new(cache_dir));
+    // Fetching every 0 hours would never stop, so that's taken as not fetching at all
+    if let (Some(url), 1..) = (&cfg.rates_url, cfg.rates_refresh_hours) {
+      let every = Duration::from_secs(cfg.rates_refresh_hours * 60 * 60);
+      rates.refresh_in_background(url.clone(), every);
+    }
     Ok(CalcPlugin {
       cfg,
+      rates,
+      session: Mutex::default(),
+      history: Cache::load(cache_dir.join("calc_history.toml"))?,
       clip: Mutex::new(clip),
     })
   }
 
-  fn eval(&self, search_input: &str) -> Evaluated {
-    let mut context = Context::<f64>::default();
-    let res = context
-      .evaluate_annotated(&search_input.strip_prefix(&self.prefix().unwrap()).unwrap())
-      .map_err(|err| match err {
-        calc::Error::Parse(err) => match err {
-          lalrpop_util::ParseError::InvalidToken { location } => Evaluated::Err {
-            message: "Invalid Token".into(),
-            start: location,
-            end: location,
-          },
-          lalrpop_util::ParseError::UnrecognizedEOF { location, .. } => Evaluated::Err {
-            message: "Unfinished Expression".into(),
-            start: location,
-            end: location,
-          },
-          lalrpop_util::ParseError::UnrecognizedToken {
-            token: (start, _token, end),
-            ..
-          } => Evaluated::Err {
-            message: "Unknown Token".into(),
-            start,
-            end,
-          },
-          lalrpop_util::ParseError::ExtraToken {
-            token: (start, _token, end),
-            ..
-          } => Evaluated::Err {
-            message: "Extra Token".into(),
-            start,
-            end,
-          },
-          lalrpop_util::ParseError::User { error } => Evaluated::Err {
-            message: format!("Unknown Error {}", error),
+  /// Works out what's typed, once for each form the result can be copied in
+  fn eval(&self, input: &str) -> Vec<CalcValue> {
+    let statement = Statement::parse(input);
+    let evaluated = match &statement {
+      Err(message) => vec![Evaluated::Err {
+        message: message.clone(),
+        start: 0,
+        end: 0,
+      }],
+      Ok(Statement::Define { name, params, .. }) => vec![Evaluated::Ok(format!(
+        "Defines {}({})",
+        name,
+        params.join(", ")
+      ))],
+      Ok(statement) => {
+        let expr = statement.expr().unwrap_or_default();
+        match self.session.lock().expand(expr) {
+          Err(message) => vec![Evaluated::Err {
+            message,
             start: 0,
             end: 0,
-          },
+          }],
+          // Where errors are only lines up with the input when it's worked out as typed
+          Ok(expanded) => self
+            .work_out(&expanded)
+            .into_iter()
+            .map(|evaluated| match evaluated {
+              Evaluated::Err { message, .. } if expanded != input => Evaluated::Err {
+                message,
+                start: 0,
+                end: 0,
+              },
+              evaluated => evaluated,
+            })
+            .collect(),
+        }
+      }
+    };
+    let statement = statement.ok();
+    evaluated
+      .into_iter()
+      .map(|evaluated| CalcValue::Typed {
+        input: input.to_string(),
+        statement: statement.clone(),
+        evaluated,
+      })
+      .collect()
+  }
+
+  /// Works out the expression with whatever it's written for. Results that can be copied in
+  /// more than one form come back once for each, the one asked for first
+  fn work_out(&self, expr: &str) -> Vec<Evaluated> {
+    let err = |message| {
+      vec![Evaluated::Err {
+        message,
+        start: 0,
+        end: 0,
+      }]
+    };
+    if let Some(res) = program::evaluate(expr) {
+      return match res {
+        Ok((int, base)) => {
+          let rest = Base::ALL.into_iter().filter(|b| *b != base);
+          let bases = [base].into_iter().chain(rest);
+          bases.map(|base| Evaluated::Int { int, base }).collect()
+        }
+        Err(message) => err(message),
+      };
+    }
+    if let Some(res) = dates::evaluate(expr, Utc::now(), Zone::Local) {
+      return match res {
+        Ok(forms) => forms
+          .into_iter()
+          .map(|(form, value)| Evaluated::Time { value, form })
+          .collect(),
+        Err(message) => err(message),
+      };
+    }
+    // Before plots, which would take the `of` in `15% of 240` for a variable
+    if let Some(res) = stats::evaluate(expr, format::decimal_separator(&self.cfg.format)) {
+      return match res {
+        Ok(Stats::Value(n)) => vec![self.number(n, None)],
+        Ok(Stats::Summary(stats)) => stats
+          .into_iter()
+          .map(|(form, n)| self.number(n, Some(form)))
+          .collect(),
+        Err(Mistake { message, span }) => vec![mistake(expr, message, span)],
+      };
+    }
+    let rates = self.rates.rates();
+    if let Some(res) = Func::parse(expr, rates.as_deref()) {
+      return match res {
+        Ok(func) => {
+          let plot = Arc::new(Plot::new(func, self.cfg.plot_domain));
+          [false, true]
+            .into_iter()
+            .map(|image| Evaluated::Plot {
+              plot: plot.clone(),
+              image,
+            })
+            .collect()
+        }
+        Err(message) => err(message),
+      };
+    }
+    // Quantities with units are worked out here, leaving plain arithmetic to calc
+    if let Some(res) = units::evaluate(expr, rates.as_deref()) {
+      let evaluated = match (res, rates) {
+        (Ok(converted), Some(rates)) if converted.with_rates => Evaluated::Rated {
+          value: converted.value,
+          as_of: rates.timestamp,
+          stale: rates.age() > Duration::from_secs(self.cfg.rates_stale_hours * 60 * 60),
         },
-        _ => Evaluated::Err {
-          message: format!("Unknown Error {}", err),
+        (Ok(converted), _) => Evaluated::Ok(converted.value),
+        (Err(message), _) => Evaluated::Err {
+          message,
           start: 0,
           end: 0,
         },
-      });
+      };
+      return vec![evaluated];
+    }
+    self.arithmetic(expr)
+  }
+
+  /// Plain arithmetic, worked out exactly when the config asks for it and it can be
+  fn arithmetic(&self, expr: &str) -> Vec<Evaluated> {
+    let exact = match self.cfg.backend {
+      CalcBackend::Float => None,
+      CalcBackend::Decimal | CalcBackend::Rational => exact::evaluate(expr),
+    };
+    let ratio = match exact {
+      None => return vec![self.calculate(expr)],
+      Some(Ok(ratio)) => ratio,
+      Some(Err(message)) => {
+        return vec![Evaluated::Err {
+          message,
+          start: 0,
+          end: 0,
+        }]
+      }
+    };
+    let format = &self.cfg.format;
+    let limit = match format.significant_digits {
+      0 => exact::DIGITS,
+      limit => limit,
+    };
+    let decimal = Evaluated::Num {
+      shown: format::write(ratio.digits(limit), format),
+      value: ratio.fraction(),
+      form: None,
+    };
+    match self.cfg.backend {
+      CalcBackend::Rational if !ratio.is_whole() => {
+        let fraction = Evaluated::Num {
+          shown: ratio.fraction(),
+          value: ratio.fraction(),
+          form: None,
+        };
+        vec![fraction, decimal]
+      }
+      _ => vec![decimal],
+    }
+  }
+
+  /// A number worked out here rather than by calc, written the way the config says
+  fn number(&self, n: f64, form: Option<&'static str>) -> Evaluated {
+    if !n.is_finite() {
+      return Evaluated::Ok(n.to_string());
+    }
+    Evaluated::Num {
+      shown: format::write(Digits::from_f64(n), &self.cfg.format),
+      value: n.to_string(),
+      form,
+    }
+  }
+
+  /// Plain arithmetic, worked out by calc
+  fn calculate(&self, expr: &str) -> Evaluated {
+    let mut context = Context::<f64>::default();
+    let res = context.evaluate_annotated(expr).map_err(|err| match err {
+      calc::Error::Parse(err) => match err {
+        lalrpop_util::ParseError::InvalidToken { location } => {
+          mistake(expr, "Invalid Token".into(), location..location)
+        }
+        lalrpop_util::ParseError::UnrecognizedEOF { location, .. } => {
+          mistake(expr, "Unfinished Expression".into(), location..location)
+        }
+        lalrpop_util::ParseError::UnrecognizedToken {
+          token: (start, _token, end),
+          ..
+        } => mistake(expr, "Unknown Token".into(), start..end),
+        lalrpop_util::ParseError::ExtraToken {
+          token: (start, _token, end),
+          ..
+        } => mistake(expr, "Extra Token".into(), start..end),
+        lalrpop_util::ParseError::User { error } => Evaluated::Err {
+          message: format!("Unknown Error {}", error),
+          start: 0,
+          end: 0,
+        },
+      },
+      _ => Evaluated::Err {
+        message: format!("Unknown Error {}", err),
+        start: 0,
+        end: 0,
+      },
+    });
     match res {
-      Ok(v) => Evaluated::Ok(v),
+      Ok(v) => match v.parse::<f64>() {
+        Ok(n) if n.is_finite() => Evaluated::Num {
+          shown: format::write(Digits::from_f64(n), &self.cfg.format),
+          value: v,
+          form: None,
+        },
+        _ => Evaluated::Ok(v),
+      },
       Err(e) => e,
     }
   }
+
+  /// Keeps what the calculation assigns and puts it in the history
+  fn keep(&self, input: &str, statement: &Statement, result: Option<&str>) {
+    self.session.lock().commit(statement, result);
+    let max = self.cfg.history_size;
+    self
+      .history
+      .update(|mut history| history.record(input, max));
+    if let Err(err) = self.history.persist() {
+      warn!("Failed to save calc history: {:?}", err);
+    }
+  }
+}
+
+/// Points out the mistake in the expression. Somewhere between characters, like the end for
+/// a missing bracket, points at the character there or the last one at the end
+fn mistake(expr: &str, message: String, span: Range<usize>) -> Evaluated {
+  let (start, end) = match span.is_empty() {
+    false => (span.start, span.end),
+    true => match expr[span.start..].chars().next() {
+      Some(c) => (span.start, span.start + c.len_utf8()),
+      None => {
+        let last = expr.char_indices().next_back();
+        (last.map_or(0, |(idx, _)| idx), expr.len())
+      }
+    },
+  };
+  Evaluated::Err {
+    message,
+    start,
+    end,
+  }
+}
+
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |ctx| {
+    Ok(Box::new(CalcPlugin::init(
+      ctx.section()?,
+      &ctx.config.cache_dir,
+      Clipboard::new()?,
+    )?))
+  });
 }
 
 impl Plugin for CalcPlugin {
-  type PV = Evaluated;
+  type PV = CalcValue;
 
   fn prefix(&self) -> Option<String> {
     Some(self.cfg.prefix.clone())
   }
 
-  fn action(&self, input: &Evaluated) -> Result<OkAction, anyhow::Error> {
-    match input {
-      Evaluated::Ok(v) => self
-        .clip
-        .lock()
-        .set_text(v)
+  /// Copies the result, keeping what it assigns, or puts a calculation from before back in
+  /// the input
+  fn action(&self, value: &CalcValue) -> Result<OkAction, anyhow::Error> {
+    let (input, statement, evaluated) = match value {
+      CalcValue::History(input) => {
+        return Ok(OkAction {
+          update_input: Some(format!("{}{}", self.cfg.prefix, input)),
+          ..Default::default()
+        })
+      }
+      CalcValue::Typed {
+        input,
+        statement,
+        evaluated,
+      } => (input, statement, evaluated),
+    };
+    if let (Some(statement), Evaluated::Plot { plot, image }) = (statement, evaluated) {
+      self.keep(input, statement, None);
+      let mut clip = self.clip.lock();
+      let copied = match image {
+        true => {
+          let (width, height) = PLOT_IMAGE_SIZE;
+          clip.set_image(ImageData {
+            width: width as usize,
+            height: height as usize,
+            bytes: Cow::Owned(plot.image(width, height).into_raw()),
+          })
+        }
+        false => clip.set_text(plot.table(&self.cfg.format)),
+      };
+      return copied
         .map(|_| OkAction {
           close_win: true,
           ..Default::default()
         })
-        .map_err(|e| anyhow!(e)),
-      Evaluated::Err { .. } => Err(anyhow!("Can't take action on invalid calculation")),
+        .map_err(|e| anyhow!(e));
+    }
+    match (statement, evaluated.result()) {
+      (Some(statement @ Statement::Define { .. }), _) => {
+        self.keep(input, statement, None);
+        // Nothing to copy, so it's left open for the function to be used
+        Ok(OkAction {
+          update_input: Some(self.cfg.prefix.clone()),
+          ..Default::default()
+        })
+      }
+      (Some(statement), Some(v)) => {
+        self.keep(input, statement, evaluated.ans().as_deref());
+        self
+          .clip
+          .lock()
+          .set_text(v)
+          .map(|_| OkAction {
+            close_win: true,
+            ..Default::default()
+          })
+          .map_err(|e| anyhow!(e))
+      }
+      _ => Err(anyhow!("Can't take action on invalid calculation")),
     }
   }
 
+  /// Works out what's typed, or offers up the calculations from before when nothing is
   fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
-    vec![FuzzyMatchItem {
-      value: PluginV::Calc(self.eval(search)),
-      against: Arc::new(search.to_owned()),
+    let input = search.strip_prefix(&self.cfg.prefix).unwrap_or(search);
+    let item = |value: CalcValue, against: String| FuzzyMatchItem {
+      value: PluginV::new(PLUGIN_NAME, value),
+      against: Arc::new(against),
       source: PLUGIN_NAME.to_string(),
-    }]
+    };
+    if !input.trim().is_empty() {
+      let values = self.eval(input).into_iter();
+      return values.map(|v| item(v, search.to_owned())).collect();
+    }
+    let history = self.history.get();
+    history
+      .calculations
+      .iter()
+      .map(|calc| {
+        let against = format!("{}{}", self.cfg.prefix, calc);
+        item(CalcValue::History(calc.clone()), against)
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(expr: &str, span: Range<usize>) -> (usize, usize) {
+    match mistake(expr, "Oops".into(), span) {
+      Evaluated::Err { start, end, .. } => (start, end),
+      _ => panic!("Should have been a mistake"),
+    }
+  }
+
+  #[test]
+  fn mistakes_point_at_characters() {
+    assert_eq!(at("1 + (", 5..5), (4, 5));
+    assert_eq!(at("1 # 2", 2..2), (2, 3));
+    assert_eq!(at("2 × é", 7..7), (5, 7));
+    assert_eq!(at("sum([1, 2]", 4..10), (4, 10));
+  }
+
+  #[test]
+  fn mistakes_are_shown_in_what_was_typed() {
+    // The bar can already hold a shorter input by the time the option's shown
+    let state = AppState {
+      input: "=1".into(),
+      ..Default::default()
+    };
+    let values = ["mean(1, 2", "1 + ("].map(|input| CalcValue::Typed {
+      input: input.into(),
+      statement: None,
+      evaluated: mistake(input, "Missing )".into(), input.len()..input.len()),
+    });
+    let ctx = egui::Context::default();
+    let _ = ctx.run(Default::default(), |ctx| {
+      egui::CentralPanel::default().show(ctx, |ui| {
+        for value in &values {
+          value.render(ui, &state);
+        }
+      });
+    });
   }
 }
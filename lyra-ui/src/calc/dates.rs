@@ -0,0 +1,907 @@
+use super::syntax::Syntax;
+use chrono::{
+  DateTime, Duration, FixedOffset, Local, Months, NaiveDate, NaiveDateTime, NaiveTime, Offset,
+  SecondsFormat, TimeZone, Utc,
+};
+use chrono_tz::{Tz, TZ_VARIANTS};
+
+/// Dates read their own operators, so this is just for numbers and words
+const SYNTAX: Syntax = Syntax::new(&[]);
+/// Words that split off what to show the result as, like `in Berlin` or `as unix`
+const TARGETS: [&str; 3] = ["in", "to", "as"];
+/// Words for moments, any of which makes an expression one for here
+const MOMENTS: [&str; 6] = ["now", "today", "tomorrow", "yesterday", "unix", "epoch"];
+
+/// Regions the time zone database names zones under. It has older names too, like
+/// `US/Pacific`, which would otherwise make words like `pacific` into time zones
+const AREAS: [&str; 9] = [
+  "Africa",
+  "America",
+  "Antarctica",
+  "Asia",
+  "Atlantic",
+  "Australia",
+  "Europe",
+  "Indian",
+  "Pacific",
+];
+
+/// Places people know time zones by that the time zone database doesn't name its zones
+/// after. Any city it does name a zone after, like `Berlin` or `Mexico City`, works as is
+#[rustfmt::skip]
+static ALIASES: &[(&str, Tz)] = &[
+  ("NYC", Tz::America__New_York), ("Boston", Tz::America__New_York),
+  ("Washington", Tz::America__New_York), ("Miami", Tz::America__New_York),
+  ("Atlanta", Tz::America__New_York), ("Montreal", Tz::America__Toronto),
+  ("Dallas", Tz::America__Chicago), ("Houston", Tz::America__Chicago),
+  ("San Francisco", Tz::America__Los_Angeles), ("Seattle", Tz::America__Los_Angeles),
+  ("Munich", Tz::Europe__Berlin), ("Frankfurt", Tz::Europe__Berlin),
+  ("Milan", Tz::Europe__Rome), ("Barcelona", Tz::Europe__Madrid),
+  ("Geneva", Tz::Europe__Zurich), ("Mumbai", Tz::Asia__Kolkata), ("Delhi", Tz::Asia__Kolkata),
+  ("Bangalore", Tz::Asia__Kolkata), ("Beijing", Tz::Asia__Shanghai),
+  ("Osaka", Tz::Asia__Tokyo), ("Wellington", Tz::Pacific__Auckland),
+];
+
+/// Abbreviations, which stand for the same offset whatever the time of year, in minutes
+#[rustfmt::skip]
+static ABBREVIATIONS: &[(&str, i32)] = &[
+  ("UTC", 0), ("GMT", 0), ("WET", 0), ("BST", 60), ("CET", 60), ("CEST", 120), ("EET", 120),
+  ("EEST", 180), ("MSK", 180), ("IST", 330), ("SGT", 480), ("HKT", 480), ("JST", 540),
+  ("KST", 540), ("AEST", 600), ("AEDT", 660), ("NZST", 720), ("NZDT", 780), ("HST", -600),
+  ("AKST", -540), ("AKDT", -480), ("PST", -480), ("PDT", -420), ("MST", -420), ("MDT", -360),
+  ("CST", -360), ("CDT", -300), ("EST", -300), ("EDT", -240),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+  /// Wherever the computer is
+  Local,
+  /// An offset that never changes, in seconds. Named when it's written as an abbreviation
+  /// like `PST` rather than an offset like `+01:00`
+  Fixed {
+    name: Option<&'static str>,
+    secs: i32,
+  },
+  /// A zone from the time zone database, along with the place it was asked for by
+  Tz { name: &'static str, tz: Tz },
+}
+
+impl Zone {
+  const UTC: Zone = Zone::Fixed {
+    name: Some("UTC"),
+    secs: 0,
+  };
+
+  /// Finds the zone by its name, like `Berlin` or `PST`
+  fn find(name: &str) -> Option<Zone> {
+    let alias = ALIASES
+      .iter()
+      .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+      .map(|(name, tz)| Zone::Tz { name, tz: *tz });
+    let city = || {
+      TZ_VARIANTS.iter().find_map(|tz| {
+        let city = city(*tz)?;
+        let found = city.replace('_', " ").eq_ignore_ascii_case(name);
+        found.then_some(Zone::Tz {
+          name: city,
+          tz: *tz,
+        })
+      })
+    };
+    let fixed = ABBREVIATIONS
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(name, mins)| Zone::Fixed {
+        name: Some(*name),
+        secs: mins * 60,
+      });
+    alias.or(fixed).or_else(city)
+  }
+
+  /// The offset from UTC at the moment
+  fn offset(&self, at: DateTime<Utc>) -> FixedOffset {
+    let secs = match self {
+      Zone::Local => Local
+        .offset_from_utc_datetime(&at.naive_utc())
+        .fix()
+        .local_minus_utc(),
+      Zone::Fixed { secs, .. } => *secs,
+      Zone::Tz { tz, .. } => tz
+        .offset_from_utc_datetime(&at.naive_utc())
+        .fix()
+        .local_minus_utc(),
+    };
+    FixedOffset::east_opt(secs).unwrap_or(Utc.fix())
+  }
+
+  /// What the clocks here say at the moment
+  fn wall(&self, at: DateTime<Utc>) -> NaiveDateTime {
+    at.with_timezone(&self.offset(at)).naive_local()
+  }
+
+  /// The moment the clocks here say the time. Times repeated as the clocks go back are
+  /// taken the first time round, and ones skipped as they go forward at the time before.
+  /// Nothing when the moment is past the last one that can be worked with
+  fn moment(&self, wall: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match self {
+      Zone::Local => moment_in(&Local, wall),
+      Zone::Fixed { secs, .. } => wall
+        .checked_sub_signed(Duration::seconds((*secs).into()))
+        .map(|at| at.and_utc()),
+      Zone::Tz { tz, .. } => moment_in(tz, wall),
+    }
+  }
+
+  /// What the zone's shown as at the moment
+  fn label(&self, at: DateTime<Utc>) -> String {
+    match self {
+      Zone::Fixed {
+        name: Some(name), ..
+      } => name.to_string(),
+      Zone::Tz { name, .. } => name.replace('_', " "),
+      _ => format!("UTC{}", self.offset(at)),
+    }
+  }
+}
+
+fn moment_in<Z: TimeZone>(zone: &Z, wall: NaiveDateTime) -> Option<DateTime<Utc>> {
+  match zone.from_local_datetime(&wall).earliest() {
+    Some(at) => Some(at.with_timezone(&Utc)),
+    None => {
+      // Clocks only skip ahead by hours, so the day before is sure to be from before
+      let day_before = wall.checked_sub_signed(Duration::days(1))?;
+      let before = zone.offset_from_utc_datetime(&day_before).fix();
+      let at = wall.checked_sub_signed(Duration::seconds(before.local_minus_utc().into()))?;
+      Some(at.and_utc())
+    }
+  }
+}
+
+/// The city a zone from the time zone database is named after, like `Los_Angeles`
+fn city(tz: Tz) -> Option<&'static str> {
+  let (area, place) = tz.name().split_once('/')?;
+  AREAS
+    .contains(&area)
+    .then(|| place.rsplit('/').next())
+    .flatten()
+}
+
+/// A length of time. Months and days are kept apart from the rest since they vary in
+/// length, so adding one lands on the same day of the month or time of day
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Span {
+  months: i64,
+  days: i64,
+  secs: i64,
+}
+
+impl Span {
+  fn neg(self) -> Result<Span, String> {
+    let neg = |n: i64| n.checked_neg().ok_or("Out of range");
+    Ok(Span {
+      months: neg(self.months)?,
+      days: neg(self.days)?,
+      secs: neg(self.secs)?,
+    })
+  }
+
+  fn add(self, other: Span) -> Result<Span, String> {
+    let add = |a: i64, b: i64| a.checked_add(b).ok_or("Out of range");
+    Ok(Span {
+      months: add(self.months, other.months)?,
+      days: add(self.days, other.days)?,
+      secs: add(self.secs, other.secs)?,
+    })
+  }
+
+  /// The days and seconds together, leaving out the months
+  fn clock_secs(self) -> Result<i64, String> {
+    (self.days.checked_mul(86400))
+      .and_then(|days| days.checked_add(self.secs))
+      .ok_or_else(|| "Out of range".into())
+  }
+
+  fn total_secs(self) -> Result<i64, String> {
+    match self.months {
+      0 => self.clock_secs(),
+      _ => Err("Months vary in length, so can't be converted".into()),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Value {
+  At { at: DateTime<Utc>, zone: Zone },
+  Span(Span),
+}
+
+/// What a unit spans are written in is worth
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Per {
+  Months(i64),
+  Days(i64),
+  Secs(i64),
+}
+
+/// Finds the unit by name, along with what it's called when converting to it
+fn unit(name: &str) -> Option<(Per, &'static str)> {
+  Some(match name {
+    "s" | "sec" | "secs" | "second" | "seconds" => (Per::Secs(1), "seconds"),
+    "min" | "mins" | "minute" | "minutes" => (Per::Secs(60), "minutes"),
+    "h" | "hr" | "hrs" | "hour" | "hours" => (Per::Secs(3600), "hours"),
+    "d" | "day" | "days" => (Per::Days(1), "days"),
+    "w" | "wk" | "wks" | "week" | "weeks" => (Per::Days(7), "weeks"),
+    "mo" | "month" | "months" => (Per::Months(1), "months"),
+    "y" | "yr" | "yrs" | "year" | "years" => (Per::Months(12), "years"),
+    _ => return None,
+  })
+}
+
+/// What the result's shown as first
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Target {
+  Zone(Zone),
+  Unix,
+  Iso,
+  Unit(Per, &'static str),
+}
+
+impl Target {
+  fn parse(words: &[&str]) -> Option<Target> {
+    match words {
+      ["unix" | "epoch" | "timestamp"] => Some(Target::Unix),
+      ["iso"] => Some(Target::Iso),
+      [word] => unit(word)
+        .map(|(per, name)| Target::Unit(per, name))
+        .or_else(|| Zone::find(word).map(Target::Zone)),
+      _ => Zone::find(&words.join(" ")).map(Target::Zone),
+    }
+  }
+}
+
+/// Whether the name means something here, so it can't be used for a variable
+pub fn is_keyword(name: &str) -> bool {
+  MOMENTS.contains(&name) || Zone::find(name).is_some()
+}
+
+/// Works out date and time expressions, like `now + 90 days`, `2026-12-01 - today`,
+/// `3pm PST in Berlin` or `unix 1700000000`, giving back the result in each of the forms it
+/// can be copied in, along with what they are. Only expressions with a date or time in them
+/// are worked out here, leaving the rest alone.
+pub fn evaluate(
+  input: &str,
+  now: DateTime<Utc>,
+  local: Zone,
+) -> Option<Result<Vec<(&'static str, String)>, String>> {
+  let tokens = lex(input).ok()?;
+  let is_moment = |t: &Token| match t {
+    Token::Date(_) | Token::Time(_) => true,
+    Token::Word(word) => MOMENTS.contains(&word.as_str()),
+    _ => false,
+  };
+  if !tokens.iter().any(is_moment) {
+    return None;
+  }
+
+  let (tokens, target) = split_target(&tokens);
+  let mut parser = Parser {
+    tokens,
+    pos: 0,
+    now,
+    local,
+  };
+  Some(parser.parse().and_then(|value| forms(value, target, now)))
+}
+
+/// Splits off what to show the result as, like `in Los Angeles` or `to hours`
+fn split_target(tokens: &[Token]) -> (&[Token], Option<Target>) {
+  for len in [2, 1] {
+    let Some(split) = tokens.len().checked_sub(len + 1) else {
+      continue;
+    };
+    let words = words(&tokens[split + 1..]);
+    let is_target = matches!(&tokens[split], Token::Word(w) if TARGETS.contains(&w.as_str()));
+    if let Some(target) = words.filter(|_| is_target).and_then(|w| Target::parse(&w)) {
+      return (&tokens[..split], Some(target));
+    }
+  }
+  (tokens, None)
+}
+
+/// The tokens as words, as long as they're all words
+fn words(tokens: &[Token]) -> Option<Vec<&str>> {
+  (tokens.iter())
+    .map(|t| match t {
+      Token::Word(word) => Some(word.as_str()),
+      _ => None,
+    })
+    .collect()
+}
+
+fn forms(
+  value: Value,
+  target: Option<Target>,
+  now: DateTime<Utc>,
+) -> Result<Vec<(&'static str, String)>, String> {
+  let mut forms = match (value, target) {
+    (Value::At { at, zone }, target) => {
+      let zone = match target {
+        Some(Target::Zone(zone)) => zone,
+        Some(Target::Unit(..)) => return Err("Only lengths of time convert to units".into()),
+        _ => zone,
+      };
+      let wall = at.with_timezone(&zone.offset(at));
+      let time = match wall.timestamp() % 60 {
+        0 => "%a %-d %b %Y %H:%M",
+        _ => "%a %-d %b %Y %H:%M:%S",
+      };
+      let mut forms = vec![
+        ("date", format!("{} {}", wall.format(time), zone.label(at))),
+        ("ISO 8601", wall.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        ("unix", at.timestamp().to_string()),
+        ("relative", relative(at - now)),
+      ];
+      match target {
+        Some(Target::Unix) => forms.swap(0, 2),
+        Some(Target::Iso) => forms.swap(0, 1),
+        _ => {}
+      }
+      forms
+    }
+    (Value::Span(span), target) => {
+      let mut forms = vec![("duration", human(span)?)];
+      let totals = [
+        ("days", Per::Days(1)),
+        ("hours", Per::Secs(3600)),
+        ("minutes", Per::Secs(60)),
+        ("seconds", Per::Secs(1)),
+      ];
+      let asked = match target {
+        Some(Target::Unit(per, name)) => Some((name, per)),
+        Some(_) => return Err("Lengths of time can only be converted to units".into()),
+        None => None,
+      };
+      // Totals only make sense for spans that don't depend on which month they're in
+      if span.months == 0 || asked.is_some() {
+        let total = span.total_secs()?;
+        let rest = totals
+          .into_iter()
+          .filter(|(name, _)| Some(*name) != asked.map(|a| a.0));
+        for (name, per) in asked.into_iter().chain(rest) {
+          let secs = match per {
+            Per::Secs(secs) => secs,
+            Per::Days(days) => days * 86400,
+            Per::Months(_) => return Err("Months vary in length, so can't be converted".into()),
+          };
+          forms.push((
+            name,
+            format!("{} {}", format_number(total as f64 / secs as f64), name),
+          ));
+        }
+        if asked.is_some() {
+          forms.swap(0, 1);
+        }
+      }
+      forms
+    }
+  };
+  forms.dedup_by(|a, b| a.1 == b.1);
+  Ok(forms)
+}
+
+/// How long until or since, roughly
+fn relative(diff: Duration) -> String {
+  let secs = diff.num_seconds();
+  if secs.abs() < 60 {
+    return "now".into();
+  }
+  let parts = [
+    (secs.abs() / 86400, "day"),
+    (secs.abs() % 86400 / 3600, "hour"),
+    (secs.abs() % 3600 / 60, "minute"),
+  ];
+  let parts: Vec<_> = (parts.iter())
+    .skip_while(|(n, _)| *n == 0)
+    .take(2)
+    .filter(|(n, _)| *n != 0)
+    .map(|(n, unit)| plural(*n, unit))
+    .collect();
+  match secs > 0 {
+    true => format!("in {}", parts.join(" ")),
+    false => format!("{} ago", parts.join(" ")),
+  }
+}
+
+/// The span written out in the biggest units that fit, like `1 day 3 hours`
+fn human(span: Span) -> Result<String, String> {
+  let total = span.clock_secs()?;
+  let parts = [
+    (span.months / 12, "year"),
+    (span.months % 12, "month"),
+    (total / 86400, "day"),
+    (total % 86400 / 3600, "hour"),
+    (total % 3600 / 60, "minute"),
+    (total % 60, "second"),
+  ];
+  let parts: Vec<_> = (parts.iter())
+    .filter(|(n, _)| *n != 0)
+    .map(|(n, unit)| plural(*n, unit))
+    .collect();
+  match parts.is_empty() {
+    true => Ok("0 seconds".into()),
+    false => Ok(parts.join(" ")),
+  }
+}
+
+fn plural(n: i64, unit: &str) -> String {
+  match n.abs() {
+    1 => format!("{} {}", n, unit),
+    _ => format!("{} {}s", n, unit),
+  }
+}
+
+fn format_number(n: f64) -> String {
+  let s = format!("{:.4}", n);
+  s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Num(f64),
+  Word(String),
+  Date(Result<NaiveDate, String>),
+  Time(NaiveTime),
+  /// An offset written straight after a time, like `+01:00`, in seconds
+  Offset(i32),
+  Op(char),
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut rest = input.trim_start();
+  while let Some(c) = rest.chars().next() {
+    if c == '+' || c == '-' {
+      tokens.push(Token::Op(c));
+      rest = &rest[1..];
+    } else if let Some((date, after)) = date(rest) {
+      tokens.push(Token::Date(date));
+      rest = after;
+      // A time can follow straight on, ISO style, or after a space
+      let time_at = rest
+        .strip_prefix(['T', 't'])
+        .or_else(|| rest.strip_prefix(' ').filter(|r| time(r).is_some()));
+      if let Some((time, after)) = time_at.and_then(time) {
+        tokens.push(Token::Time(time));
+        rest = after;
+        if let Some((secs, after)) = offset(rest) {
+          tokens.push(Token::Offset(secs));
+          rest = after;
+        }
+      }
+    } else if let Some((time, after)) = time(rest) {
+      tokens.push(Token::Time(time));
+      rest = after;
+    } else if c.is_ascii_digit() || c == '.' {
+      let len = SYNTAX.number_len(rest);
+      tokens.push(Token::Num(SYNTAX.decimal(&rest[..len])?));
+      rest = &rest[len..];
+    } else if c.is_alphabetic() {
+      let len = SYNTAX.name_len(rest);
+      tokens.push(Token::Word(rest[..len].to_lowercase()));
+      rest = &rest[len..];
+    } else {
+      return Err(format!("Unexpected {}", c));
+    }
+    rest = rest.trim_start();
+  }
+  Ok(tokens)
+}
+
+/// Reads a date like `2026-12-01` off the front. Anything shaped like one is taken to be
+/// one, so a day that doesn't exist is complained about rather than read as sums
+fn date(input: &str) -> Option<(Result<NaiveDate, String>, &str)> {
+  let written = input.get(..10)?;
+  let shape = written.char_indices().all(|(idx, c)| match idx {
+    4 | 7 => c == '-',
+    _ => c.is_ascii_digit(),
+  });
+  if !shape {
+    return None;
+  }
+  let date = NaiveDate::parse_from_str(written, "%Y-%m-%d");
+  Some((
+    date.map_err(|_| format!("Invalid date {}", written)),
+    &input[10..],
+  ))
+}
+
+/// Reads a time like `15:30`, `3:30pm` or `3 pm` off the front
+fn time(input: &str) -> Option<(NaiveTime, &str)> {
+  let (hour, mut rest) = digits(input, 2)?;
+  let (mut minute, mut second, mut clock) = (0, 0, false);
+  if let Some((m, after)) = rest.strip_prefix(':').and_then(|r| digits(r, 2)) {
+    (minute, rest, clock) = (m, after, true);
+    if let Some((s, after)) = rest.strip_prefix(':').and_then(|r| digits(r, 2)) {
+      (second, rest) = (s, after);
+    }
+  }
+  let meridiem = ["am", "pm"].into_iter().find_map(|m| {
+    let after = rest.trim_start();
+    let after = after
+      .get(..2)
+      .filter(|w| w.eq_ignore_ascii_case(m))
+      .map(|_| &after[2..])?;
+    Some((m, after)).filter(|(_, after)| !after.starts_with(char::is_alphanumeric))
+  });
+  let hour = match meridiem {
+    Some((m, after)) if (1..=12).contains(&hour) => {
+      rest = after;
+      hour % 12 + if m == "pm" { 12 } else { 0 }
+    }
+    Some(_) => return None,
+    // Numbers on their own are just numbers
+    None if !clock => return None,
+    None => hour,
+  };
+  Some((NaiveTime::from_hms_opt(hour, minute, second)?, rest))
+}
+
+/// Reads up to two digits off the front
+fn digits(input: &str, max: usize) -> Option<(u32, &str)> {
+  let len = input
+    .find(|c: char| !c.is_ascii_digit())
+    .unwrap_or(input.len());
+  Some((input[..len].parse().ok()?, &input[len..])).filter(|_| (1..=max).contains(&len))
+}
+
+/// Reads an offset like `+01:00` or `Z` straight after a time
+fn offset(input: &str) -> Option<(i32, &str)> {
+  if let Some(after) = input.strip_prefix(['Z', 'z']) {
+    return Some((0, after)).filter(|(_, after)| !after.starts_with(char::is_alphanumeric));
+  }
+  let sign = match input.chars().next()? {
+    '+' => 1,
+    '-' => -1,
+    _ => return None,
+  };
+  let hours: i32 = input.get(1..3)?.parse().ok()?;
+  let after = input[3..].strip_prefix(':').unwrap_or(&input[3..]);
+  let minutes: i32 = after.get(..2)?.parse().ok()?;
+  Some((sign * (hours * 3600 + minutes * 60), &after[2..]))
+}
+
+/// The day a wall clock time is on
+enum Day {
+  /// Days from today, wherever the time's for
+  FromToday(i64),
+  On(NaiveDate),
+}
+
+struct Parser<'a> {
+  tokens: &'a [Token],
+  pos: usize,
+  now: DateTime<Utc>,
+  local: Zone,
+}
+
+impl<'a> Parser<'a> {
+  fn parse(&mut self) -> Result<Value, String> {
+    let mut value = self.term()?;
+    while let Some(Token::Op(op)) = self.peek() {
+      self.pos += 1;
+      let rhs = self.term()?;
+      value = combine(value, *op == '-', rhs)?;
+    }
+    match self.peek() {
+      None => Ok(value),
+      Some(Token::Word(word)) => Err(format!("Unknown {}", word)),
+      Some(_) => Err("Unexpected trailing input".into()),
+    }
+  }
+
+  fn peek(&self) -> Option<&'a Token> {
+    self.tokens.get(self.pos)
+  }
+
+  fn next(&mut self) -> Result<&'a Token, String> {
+    let token = self.peek().ok_or("Unfinished expression")?;
+    self.pos += 1;
+    Ok(token)
+  }
+
+  fn term(&mut self) -> Result<Value, String> {
+    let mut span: Option<Span> = None;
+    while let (Some(Token::Num(n)), Some(Token::Word(word))) =
+      (self.peek(), self.tokens.get(self.pos + 1))
+    {
+      let Some((per, _)) = unit(word) else {
+        break;
+      };
+      self.pos += 2;
+      let part = match per {
+        Per::Months(months) if n.fract() == 0.0 => Span {
+          months: (*n as i64).checked_mul(months).ok_or("Out of range")?,
+          ..Default::default()
+        },
+        Per::Months(_) => return Err("Months and years have to be whole".into()),
+        Per::Days(days) if n.fract() == 0.0 => Span {
+          days: (*n as i64).checked_mul(days).ok_or("Out of range")?,
+          ..Default::default()
+        },
+        Per::Days(days) => Span {
+          secs: (n * (days * 86400) as f64).round() as i64,
+          ..Default::default()
+        },
+        Per::Secs(secs) => Span {
+          secs: (n * secs as f64).round() as i64,
+          ..Default::default()
+        },
+      };
+      span = Some(span.unwrap_or_default().add(part)?);
+    }
+    match span {
+      Some(span) => Ok(Value::Span(span)),
+      None => self.moment(),
+    }
+  }
+
+  fn moment(&mut self) -> Result<Value, String> {
+    let (day, time) = match self.next()? {
+      Token::Word(word) if word == "now" => {
+        let zone = self.zone().unwrap_or(self.local);
+        return Ok(Value::At { at: self.now, zone });
+      }
+      Token::Word(word) if word == "unix" || word == "epoch" => {
+        let Token::Num(secs) = self.next()? else {
+          return Err(format!("{} needs a number of seconds after it", word));
+        };
+        let at = DateTime::from_timestamp(*secs as i64, 0).ok_or("Out of range")?;
+        let zone = self.zone().unwrap_or(self.local);
+        return Ok(Value::At { at, zone });
+      }
+      Token::Word(word) => match word.as_str() {
+        "today" => (Day::FromToday(0), self.time()),
+        "tomorrow" => (Day::FromToday(1), self.time()),
+        "yesterday" => (Day::FromToday(-1), self.time()),
+        _ => return Err(format!("Unknown {}", word)),
+      },
+      Token::Date(date) => (Day::On(date.clone()?), self.time()),
+      Token::Time(time) => (Day::FromToday(0), Some(*time)),
+      Token::Num(n) => return Err(format!("{} needs a unit", n)),
+      Token::Op(op) => return Err(format!("Unexpected {}", op)),
+      Token::Offset(_) => return Err("Unexpected offset".into()),
+    };
+    let zone = self.zone().unwrap_or(self.local);
+    let date = match day {
+      Day::FromToday(days) => zone.wall(self.now).date() + Duration::days(days),
+      Day::On(date) => date,
+    };
+    let wall = date.and_time(time.unwrap_or_default());
+    let at = zone.moment(wall).ok_or("Out of range")?;
+    Ok(Value::At { at, zone })
+  }
+
+  fn time(&mut self) -> Option<NaiveTime> {
+    let Some(Token::Time(time)) = self.peek() else {
+      return None;
+    };
+    self.pos += 1;
+    Some(*time)
+  }
+
+  /// A time zone after a moment, for the moment to be in
+  fn zone(&mut self) -> Option<Zone> {
+    if let Some(Token::Offset(secs)) = self.peek() {
+      self.pos += 1;
+      return Some(Zone::Fixed {
+        name: None,
+        secs: *secs,
+      });
+    }
+    for len in [2, 1] {
+      let words = self.tokens.get(self.pos..self.pos + len).and_then(words);
+      if let Some(zone) = words.and_then(|words| Zone::find(&words.join(" "))) {
+        self.pos += len;
+        return Some(zone);
+      }
+    }
+    None
+  }
+}
+
+fn combine(lhs: Value, sub: bool, rhs: Value) -> Result<Value, String> {
+  match (lhs, rhs) {
+    (Value::At { at, zone }, Value::Span(span)) => {
+      let span = if sub { span.neg()? } else { span };
+      Ok(Value::At {
+        at: shift(at, zone, span)?,
+        zone,
+      })
+    }
+    (Value::Span(span), Value::At { at, zone }) if !sub => Ok(Value::At {
+      at: shift(at, zone, span)?,
+      zone,
+    }),
+    (
+      Value::At { at: a, zone },
+      Value::At {
+        at: b,
+        zone: b_zone,
+      },
+    ) if sub => {
+      // By the clock when both are in the same place, so days are days across clock changes
+      let diff = match zone == b_zone {
+        true => zone.wall(a) - zone.wall(b),
+        false => a - b,
+      };
+      Ok(Value::Span(Span {
+        secs: diff.num_seconds(),
+        ..Default::default()
+      }))
+    }
+    (Value::Span(a), Value::Span(b)) => Ok(Value::Span(a.add(if sub { b.neg()? } else { b })?)),
+    (_, _) if sub => Err("Can't take a date away from a length of time".into()),
+    (_, _) => Err("Can't add two dates together".into()),
+  }
+}
+
+/// The moment a span later, going by the clocks where it is for months and days
+fn shift(at: DateTime<Utc>, zone: Zone, span: Span) -> Result<DateTime<Utc>, String> {
+  let mut at = at;
+  if span.months != 0 || span.days != 0 {
+    let wall = zone.wall(at);
+    let months = u32::try_from(span.months.unsigned_abs()).map_err(|_| "Out of range")?;
+    let wall = match span.months < 0 {
+      true => wall.checked_sub_months(Months::new(months)),
+      false => wall.checked_add_months(Months::new(months)),
+    };
+    let wall = wall.zip(Duration::try_days(span.days));
+    let wall = wall.and_then(|(w, days)| w.checked_add_signed(days));
+    at = wall.and_then(|w| zone.moment(w)).ok_or("Out of range")?;
+  }
+  Duration::try_seconds(span.secs)
+    .and_then(|secs| at.checked_add_signed(secs))
+    .ok_or_else(|| "Out of range".into())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Tue 14 Nov 2023 22:13:20 UTC
+  fn now() -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+  }
+
+  fn eval(input: &str) -> Result<Vec<(&'static str, String)>, String> {
+    evaluate(input, now(), Zone::UTC).expect("Should have been a date expression")
+  }
+
+  fn first(input: &str) -> Result<String, String> {
+    eval(input).map(|forms| forms[0].1.clone())
+  }
+
+  #[test]
+  fn shows_moments_in_every_form() {
+    let forms = eval("unix 1700000000").unwrap();
+    assert_eq!(
+      forms,
+      vec![
+        ("date", "Tue 14 Nov 2023 22:13:20 UTC".into()),
+        ("ISO 8601", "2023-11-14T22:13:20Z".into()),
+        ("unix", "1700000000".into()),
+        ("relative", "now".into()),
+      ]
+    );
+    assert_eq!(first("now in unix"), Ok("1700000000".into()));
+    assert_eq!(
+      first("2024-07-01T12:00+02:00 as iso"),
+      Ok("2024-07-01T12:00:00+02:00".into())
+    );
+    assert_eq!(eval("tomorrow 9am").unwrap()[3].1, "in 10 hours 46 minutes");
+  }
+
+  #[test]
+  fn works_out_relative_dates() {
+    assert_eq!(
+      first("now + 90 days"),
+      Ok("Mon 12 Feb 2024 22:13:20 UTC".into())
+    );
+    assert_eq!(
+      first("2024-01-31 + 1 month"),
+      Ok("Thu 29 Feb 2024 00:00 UTC".into())
+    );
+    assert_eq!(
+      first("today - 1 week 2 days"),
+      Ok("Sun 5 Nov 2023 00:00 UTC".into())
+    );
+    assert_eq!(first("2026-12-01 - today"), Ok("1113 days".into()));
+    assert_eq!(
+      first("tomorrow 9am - now"),
+      Ok("10 hours 46 minutes 40 seconds".into())
+    );
+    assert_eq!(first("2026-12-01 - today in weeks"), Ok("159 weeks".into()));
+    assert_eq!(
+      first("now + now"),
+      Err("Can't add two dates together".into())
+    );
+    assert_eq!(first("2024-02-30"), Err("Invalid date 2024-02-30".into()));
+  }
+
+  #[test]
+  fn huge_spans_are_out_of_range() {
+    let out = Err("Out of range".into());
+    assert_eq!(first("now + 200000000000000 days"), out);
+    assert_eq!(first("now + 10000000000000000 seconds"), out);
+    assert_eq!(first("now - 10000000000000000 seconds"), out);
+    assert_eq!(first("now + 1000000000000000000 years"), out);
+    assert_eq!(first("now + 5000000000 months"), out);
+    assert_eq!(
+      first("now + 9000000000000000000 days 9000000000000000000 days"),
+      out
+    );
+    assert_eq!(first("now + 100000000 days"), out);
+  }
+
+  #[test]
+  fn converts_between_time_zones() {
+    assert_eq!(
+      first("3pm PST in Berlin"),
+      Ok("Wed 15 Nov 2023 00:00 Berlin".into())
+    );
+    assert_eq!(
+      first("2024-07-01 12:00 New York in UTC"),
+      Ok("Mon 1 Jul 2024 16:00 UTC".into())
+    );
+    assert_eq!(
+      first("2024-01-15 12:00 Sydney in utc"),
+      Ok("Mon 15 Jan 2024 01:00 UTC".into())
+    );
+    assert_eq!(
+      first("2024-03-31 12:00 berlin to UTC"),
+      Ok("Sun 31 Mar 2024 10:00 UTC".into())
+    );
+    assert_eq!(
+      first("2024-03-10 12:00 Chicago in Los Angeles"),
+      Ok("Sun 10 Mar 2024 10:00 Los Angeles".into())
+    );
+    // Mexico City hasn't changed its clocks since 2022
+    assert_eq!(
+      first("2024-07-01 12:00 mexico city in UTC"),
+      Ok("Mon 1 Jul 2024 18:00 UTC".into())
+    );
+    assert_eq!(
+      first("2024-07-01 12:00 UTC in Buenos Aires"),
+      Ok("Mon 1 Jul 2024 09:00 Buenos Aires".into())
+    );
+    assert_eq!(
+      first("2024-07-01 12:00 NYC in Osaka"),
+      Ok("Tue 2 Jul 2024 01:00 Osaka".into())
+    );
+  }
+
+  #[test]
+  fn clock_changes_follow_the_zone() {
+    // Skipped as the clocks go forward, so taken at the time from before
+    assert_eq!(
+      first("2024-03-10 02:30 New York in UTC"),
+      Ok("Sun 10 Mar 2024 07:30 UTC".into())
+    );
+    // Repeated as they go back, so taken the first time round
+    assert_eq!(
+      first("2024-10-27 01:30 London in UTC"),
+      Ok("Sun 27 Oct 2024 00:30 UTC".into())
+    );
+    assert_eq!(
+      first("2024-10-26 12:00 London + 1 day in UTC"),
+      Ok("Sun 27 Oct 2024 12:00 UTC".into())
+    );
+  }
+
+  #[test]
+  fn leaves_other_expressions_alone() {
+    assert_eq!(evaluate("90 min in h", now(), Zone::UTC), None);
+    assert_eq!(evaluate("1 + 2", now(), Zone::UTC), None);
+    assert_eq!(evaluate("5 in in cm", now(), Zone::UTC), None);
+  }
+}
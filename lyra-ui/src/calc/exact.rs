@@ -0,0 +1,282 @@
+use super::format::Digits;
+use super::syntax::{self, Grammar, Ops, Parser, Syntax, Token};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::ops::Range;
+
+/// Past this many bits, around ten thousand digits, numbers are too big to be worth working
+/// out exactly
+const MAX_BITS: u64 = 36_000;
+/// How many significant digits results that never end are written to, unless the config
+/// says otherwise
+pub const DIGITS: usize = 30;
+
+/// An exact fraction, always in its lowest terms, so `0.1 + 0.2` comes out as `3/10` rather
+/// than a hair over
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ratio(BigRational);
+
+impl Ratio {
+  fn new(num: BigInt, den: BigInt) -> Result<Ratio, String> {
+    if den.is_zero() {
+      return Err("Division by zero".into());
+    }
+    Ratio::checked(BigRational::new(num, den))
+  }
+
+  /// Only kept when it's small enough to be worth working out exactly
+  fn checked(ratio: BigRational) -> Result<Ratio, String> {
+    match ratio.numer().bits() + ratio.denom().bits() > MAX_BITS {
+      true => Err("Too big to work out exactly".into()),
+      false => Ok(Ratio(ratio)),
+    }
+  }
+
+  pub fn is_whole(&self) -> bool {
+    self.0.is_integer()
+  }
+
+  fn neg(self) -> Ratio {
+    Ratio(-self.0)
+  }
+
+  fn add(&self, other: &Ratio) -> Result<Ratio, String> {
+    Ratio::checked(&self.0 + &other.0)
+  }
+
+  fn mul(&self, other: &Ratio) -> Result<Ratio, String> {
+    Ratio::checked(&self.0 * &other.0)
+  }
+
+  fn div(&self, other: &Ratio) -> Result<Ratio, String> {
+    if other.0.is_zero() {
+      return Err("Division by zero".into());
+    }
+    Ratio::checked(&self.0 / &other.0)
+  }
+
+  /// What's left over dividing, taking the sign of what's divided like `%` does for floats
+  fn rem(&self, other: &Ratio) -> Result<Ratio, String> {
+    if other.0.is_zero() {
+      return Err("Division by zero".into());
+    }
+    Ratio::checked(&self.0 % &other.0)
+  }
+
+  /// Raised to a whole power, none when the power isn't whole since that's rarely exact
+  fn pow(&self, exp: &Ratio) -> Option<Result<Ratio, String>> {
+    if !exp.is_whole() {
+      return None;
+    }
+    let Some(n) = exp.0.numer().abs().to_u32() else {
+      return Some(Err("Too big to work out exactly".into()));
+    };
+    // Roughly how many bits it'd take, without working it out first
+    let bits = |n: &BigInt| n.bits().saturating_sub(1);
+    if (bits(self.0.numer()) + bits(self.0.denom())) * u64::from(n) > MAX_BITS {
+      return Some(Err("Too big to work out exactly".into()));
+    }
+    let (num, den) = (self.0.numer().pow(n), self.0.denom().pow(n));
+    Some(match exp.0.is_negative() {
+      false => Ratio::new(num, den),
+      true => Ratio::new(den, num),
+    })
+  }
+
+  /// Written as a fraction like `-1/3`, or just the number when it's whole
+  pub fn fraction(&self) -> String {
+    self.0.to_string()
+  }
+
+  /// Written out in decimal digits. Ones that never end, or go on past `limit` significant
+  /// digits, are rounded to that many. Whole numbers are always written out in full
+  pub fn digits(&self, limit: usize) -> Digits {
+    let (num, den) = (self.0.numer().magnitude(), self.0.denom().magnitude());
+    let (whole, mut rem) = (num / den, num % den);
+    let mut digits: Vec<u8> = match whole.is_zero() {
+      true => Vec::new(),
+      false => whole.to_string().bytes().map(|d| d - b'0').collect(),
+    };
+    let mut exp = digits.len() as i32;
+    while !rem.is_zero() && digits.len() <= limit {
+      rem *= 10u32;
+      let digit = (&rem / den).to_u8().unwrap_or_default();
+      rem %= den;
+      match (digits.is_empty(), digit) {
+        // Zeros straight after the point only say how small it is
+        (true, 0) => exp -= 1,
+        (_, digit) => digits.push(digit),
+      }
+    }
+    let digits = Digits::new(self.0.is_negative(), digits, exp, rem.is_zero());
+    match rem.is_zero() {
+      true => digits,
+      false => digits.round(limit),
+    }
+  }
+}
+
+/// Why an expression wasn't worked out here
+enum Failed {
+  /// It's not something to work out exactly, like `sqrt(2)`, so it's left for floats
+  Unsupported,
+  Err(String),
+}
+
+impl From<String> for Failed {
+  fn from(err: String) -> Self {
+    Failed::Err(err)
+  }
+}
+
+const SYNTAX: Syntax = Syntax::new(&["**", "+", "-", "*", "/", "%", "^", "(", ")"]);
+
+/// Works out plain arithmetic exactly, with numbers as big as they need to be. Anything it
+/// can't do exactly, like functions or fractional powers, is left alone, as are mistakes
+/// so they're pointed out by calc.
+pub fn evaluate(input: &str) -> Option<Result<Ratio, String>> {
+  let tokens = syntax::lex(input, &SYNTAX).ok()?;
+  let mut parser = Parser::new(&tokens, Exact);
+  let res = parser.expr().and_then(|value| match parser.peek() {
+    None => Ok(value),
+    Some(_) => Err(Failed::Unsupported),
+  });
+  match res {
+    Ok(value) => Some(Ok(value)),
+    Err(Failed::Err(err)) => Some(Err(err)),
+    Err(Failed::Unsupported) => None,
+  }
+}
+
+/// Reads a number like `12.5` or `1e-9` exactly
+fn number(literal: &str) -> Option<Ratio> {
+  let literal = literal.replace('_', "");
+  let (mantissa, exp) = match literal.split_once(['e', 'E']) {
+    Some((mantissa, exp)) => (mantissa, exp.parse::<i32>().ok()?),
+    None => (literal.as_str(), 0),
+  };
+  let (whole, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+  if (whole.is_empty() && frac.is_empty()) || frac.contains('.') || exp.abs() > 10_000 {
+    return None;
+  }
+  let num: BigInt = format!("{}{}", whole, frac).parse().ok()?;
+  let scale = exp - frac.len() as i32;
+  let ten = BigInt::from(10).pow(scale.unsigned_abs());
+  match scale < 0 {
+    true => Ratio::new(num, ten).ok(),
+    false => Ratio::new(num * ten, BigInt::one()).ok(),
+  }
+}
+
+struct Exact;
+
+impl<'a> Grammar<'a> for Exact {
+  type Value = Ratio;
+  type Error = Failed;
+
+  const LEVELS: &'static [Ops] = &[&["+", "-"], &["*", "/", "%"]];
+  const PREFIX: Ops = &["-", "+"];
+  const POWER: Ops = &["^", "**"];
+
+  fn binary(
+    &mut self,
+    op: &'static str,
+    _: Range<usize>,
+    lhs: Ratio,
+    rhs: Ratio,
+  ) -> Result<Ratio, Failed> {
+    Ok(match op {
+      "+" => lhs.add(&rhs)?,
+      "-" => lhs.add(&rhs.neg())?,
+      "*" => lhs.mul(&rhs)?,
+      "/" => lhs.div(&rhs)?,
+      "%" => lhs.rem(&rhs)?,
+      _ => lhs.pow(&rhs).ok_or(Failed::Unsupported)??,
+    })
+  }
+
+  fn prefix(&mut self, op: &'static str, _: Range<usize>, value: Ratio) -> Result<Ratio, Failed> {
+    Ok(match op {
+      "-" => value.neg(),
+      _ => value,
+    })
+  }
+
+  fn atom(
+    parser: &mut Parser<'a, Self>,
+    token: Token<'a>,
+    _: Range<usize>,
+  ) -> Result<Ratio, Failed> {
+    match token {
+      Token::Num(literal) => number(literal).ok_or(Failed::Unsupported),
+      Token::Op("(") => parser.bracketed(),
+      _ => Err(Failed::Unsupported),
+    }
+  }
+
+  /// Mistakes are left for calc to point out
+  fn mistake(&self, _: String, _: Range<usize>) -> Failed {
+    Failed::Unsupported
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn eval(input: &str) -> Option<Result<String, String>> {
+    evaluate(input).map(|res| res.map(|value| value.fraction()))
+  }
+
+  #[test]
+  fn works_out_exact_fractions() {
+    assert_eq!(eval("0.1 + 0.2"), Some(Ok("3/10".into())));
+    assert_eq!(eval("1/3 + 1/6"), Some(Ok("1/2".into())));
+    assert_eq!(eval("-(2 - 5) * 4 / 8"), Some(Ok("3/2".into())));
+    assert_eq!(eval("7.5 % 2"), Some(Ok("3/2".into())));
+    assert_eq!(eval("-7 % 3"), Some(Ok("-1".into())));
+    assert_eq!(eval("-2^2 + 2**-1"), Some(Ok("-7/2".into())));
+    assert_eq!(eval("1.5e3 - 2E-1"), Some(Ok("7499/5".into())));
+  }
+
+  #[test]
+  fn keeps_big_numbers_exact() {
+    assert_eq!(
+      eval("2^100 + 1"),
+      Some(Ok("1267650600228229401496703205377".into()))
+    );
+    assert_eq!(
+      eval("123456789012345678901234567890 * 987654321098765432109876543210"),
+      Some(Ok(
+        "121932631137021795226185032733622923332237463801111263526900".into()
+      ))
+    );
+    assert_eq!(eval("(10^30 + 7) / (10^30 + 7)"), Some(Ok("1".into())));
+    assert_eq!(
+      eval("10^20000"),
+      Some(Err("Too big to work out exactly".into()))
+    );
+  }
+
+  #[test]
+  fn leaves_what_it_cant_do_to_floats() {
+    assert_eq!(eval("1 / (2 - 2)"), Some(Err("Division by zero".into())));
+    assert_eq!(eval("5 % 0"), Some(Err("Division by zero".into())));
+    assert_eq!(eval("0 ^ -1"), Some(Err("Division by zero".into())));
+    assert_eq!(eval("sqrt(2)"), None);
+    assert_eq!(eval("2 ^ 0.5"), None);
+    assert_eq!(eval("1 +"), None);
+    assert_eq!(eval("(1"), None);
+  }
+
+  #[test]
+  fn writes_out_digits() {
+    let third = evaluate("1/3").unwrap().unwrap();
+    assert_eq!(third.digits(5), Digits::new(false, vec![3; 5], 0, false));
+    let small = evaluate("-2/3000").unwrap().unwrap();
+    assert_eq!(small.digits(3), Digits::new(true, vec![6, 6, 7], -3, false));
+    let whole = evaluate("10^40").unwrap().unwrap();
+    assert_eq!(whole.digits(3), Digits::new(false, vec![1], 41, true));
+  }
+}
@@ -0,0 +1,233 @@
+use crate::config::{NumberFormat, Scientific};
+use std::env;
+
+/// Past these exponents numbers are written in scientific notation when it's left to us,
+/// the same as most calculators and browsers do
+const MIN_PLAIN_EXP: i32 = -5;
+const MAX_PLAIN_EXP: i32 = 21;
+/// Languages that write a comma between the whole and fractional part
+#[rustfmt::skip]
+const COMMA_LANGUAGES: [&str; 30] = [
+  "bg", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt", "lv",
+  "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+/// A number as its significant digits, `0.DIGITS × 10^exp`, ready to be written out however
+/// the config says
+#[derive(Clone, Debug, PartialEq)]
+pub struct Digits {
+  neg: bool,
+  digits: Vec<u8>,
+  exp: i32,
+  /// Whether the digits are all there is to it, rather than it being rounded
+  exact: bool,
+}
+
+impl Digits {
+  pub fn new(neg: bool, mut digits: Vec<u8>, exp: i32, exact: bool) -> Digits {
+    while digits.last() == Some(&0) {
+      digits.pop();
+    }
+    Digits {
+      neg: neg && !digits.is_empty(),
+      digits,
+      exp,
+      exact,
+    }
+  }
+
+  /// The shortest digits that read back as the same float
+  pub fn from_f64(value: f64) -> Digits {
+    let sci = format!("{:e}", value.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let digits = mantissa
+      .bytes()
+      .filter(u8::is_ascii_digit)
+      .map(|d| d - b'0');
+    // Floats only count every whole number up to 2^53
+    let exact = value.fract() == 0.0 && value.abs() < 2f64.powi(53);
+    let exp = exp.parse::<i32>().unwrap_or_default() + 1;
+    Digits::new(value < 0.0, digits.collect(), exp, exact)
+  }
+
+  /// Rounded half away from zero to that many significant digits
+  pub fn round(mut self, limit: usize) -> Digits {
+    let limit = limit.max(1);
+    if self.digits.len() <= limit {
+      return self;
+    }
+    let dropped = self.digits.split_off(limit);
+    let exact = self.exact && dropped.iter().all(|d| *d == 0);
+    if dropped[0] >= 5 {
+      match self.digits.iter().rposition(|d| *d != 9) {
+        Some(idx) => {
+          self.digits[idx] += 1;
+          self.digits.truncate(idx + 1);
+        }
+        // All nines, which carry over into another digit
+        None => {
+          self.digits = vec![1];
+          self.exp += 1;
+        }
+      }
+    }
+    Digits::new(self.neg, self.digits, self.exp, exact)
+  }
+}
+
+/// Writes the number out the way the format says
+pub fn write(digits: Digits, format: &NumberFormat) -> String {
+  let d = match format.significant_digits {
+    0 => digits,
+    limit => digits.round(limit),
+  };
+  if d.digits.is_empty() {
+    return "0".into();
+  }
+  let point = decimal_separator(format);
+  let len = d.digits.len() as i32;
+  let whole = len <= d.exp;
+  let sci = match format.scientific {
+    Scientific::Always => true,
+    Scientific::Never => false,
+    Scientific::Auto => d.exp < MIN_PLAIN_EXP || (d.exp > MAX_PLAIN_EXP && !(whole && d.exact)),
+  };
+  let text = |digits: &[u8]| -> String { digits.iter().map(|d| (b'0' + d) as char).collect() };
+  let sign = if d.neg { "-" } else { "" };
+  if sci {
+    let (first, rest) = d.digits.split_at(1);
+    return match rest.is_empty() {
+      true => format!("{}{}e{}", sign, text(first), d.exp - 1),
+      false => format!(
+        "{}{}{}{}e{}",
+        sign,
+        text(first),
+        point,
+        text(rest),
+        d.exp - 1
+      ),
+    };
+  }
+  let (int, frac) = match d.exp {
+    exp if exp <= 0 => (
+      "0".into(),
+      "0".repeat(exp.unsigned_abs() as usize) + &text(&d.digits),
+    ),
+    exp if whole => (
+      text(&d.digits) + &"0".repeat((exp - len) as usize),
+      String::new(),
+    ),
+    exp => {
+      let (int, frac) = d.digits.split_at(exp as usize);
+      (text(int), text(frac))
+    }
+  };
+  let int = group(&int, &format.thousands_separator);
+  match frac.is_empty() {
+    true => format!("{}{}", sign, int),
+    false => format!("{}{}{}{}", sign, int, point, frac),
+  }
+}
+
+/// Puts the separator between each group of three digits, counting from the right
+fn group(int: &str, separator: &str) -> String {
+  if separator.is_empty() {
+    return int.to_string();
+  }
+  let mut out = String::with_capacity(int.len() + int.len() / 3 * separator.len());
+  for (idx, c) in int.chars().enumerate() {
+    if idx > 0 && (int.len() - idx).is_multiple_of(3) {
+      out.push_str(separator);
+    }
+    out.push(c);
+  }
+  out
+}
+
+/// The decimal separator that's set, or else the locale's
+pub fn decimal_separator(format: &NumberFormat) -> &str {
+  match &format.decimal_separator {
+    Some(point) => point,
+    None => locale_decimal_separator(),
+  }
+}
+
+/// The decimal separator going by the locale in the environment, like most unix tools do
+fn locale_decimal_separator() -> &'static str {
+  let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+    .into_iter()
+    .filter_map(|var| env::var(var).ok())
+    .find(|locale| !locale.is_empty())
+    .unwrap_or_default();
+  let language = locale
+    .split(['_', '-', '.', '@'])
+    .next()
+    .unwrap_or_default();
+  match COMMA_LANGUAGES.contains(&language.to_lowercase().as_str()) {
+    true => ",",
+    false => ".",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn plain() -> NumberFormat {
+    NumberFormat {
+      decimal_separator: Some(".".into()),
+      ..Default::default()
+    }
+  }
+
+  fn float(value: f64, format: &NumberFormat) -> String {
+    write(Digits::from_f64(value), format)
+  }
+
+  #[test]
+  fn writes_floats_as_they_are_by_default() {
+    let format = plain();
+    assert_eq!(float(0.1 + 0.2, &format), "0.30000000000000004");
+    assert_eq!(float(-1500.0, &format), "-1500");
+    assert_eq!(float(0.0, &format), "0");
+    assert_eq!(float(0.000012, &format), "0.000012");
+    assert_eq!(float(1.5e-7, &format), "1.5e-7");
+    assert_eq!(float(1e20, &format), "100000000000000000000");
+    assert_eq!(float(1e21, &format), "1e21");
+  }
+
+  #[test]
+  fn rounds_to_significant_digits() {
+    let format = NumberFormat {
+      significant_digits: 3,
+      ..plain()
+    };
+    assert_eq!(float(0.1 + 0.2, &format), "0.3");
+    assert_eq!(float(2.0 / 3.0, &format), "0.667");
+    assert_eq!(float(-9.996, &format), "-10");
+    assert_eq!(float(123456.0, &format), "123000");
+    assert_eq!(float(9.99e21, &format), "9.99e21");
+  }
+
+  #[test]
+  fn uses_separators_and_notation() {
+    let format = NumberFormat {
+      thousands_separator: ".".into(),
+      decimal_separator: Some(",".into()),
+      ..plain()
+    };
+    assert_eq!(float(1234567.25, &format), "1.234.567,25");
+    assert_eq!(float(123.5, &format), "123,5");
+    let sci = NumberFormat {
+      scientific: Scientific::Always,
+      ..format
+    };
+    assert_eq!(float(1234.5, &sci), "1,2345e3");
+    assert_eq!(float(-0.02, &sci), "-2e-2");
+    let never = NumberFormat {
+      scientific: Scientific::Never,
+      ..plain()
+    };
+    assert_eq!(float(1.5e-7, &never), "0.00000015");
+  }
+}
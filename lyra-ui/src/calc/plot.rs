@@ -0,0 +1,518 @@
+use super::rates::Rates;
+use super::syntax::{self, Grammar, Ops, Parser, Syntax, Token};
+use super::{dates, format, units};
+use crate::config::NumberFormat;
+use egui::{pos2, vec2, Align2, FontId, Sense, Shape, Stroke, Ui};
+use image::{Rgba, RgbaImage};
+use parking_lot::Mutex;
+use std::f64::consts::{E, PI, TAU};
+use std::ops::Range;
+
+/// How many points the function's sampled at across the plot
+const SAMPLES: usize = 240;
+/// How many rows of values are copied
+const TABLE_ROWS: usize = 21;
+const HEIGHT: f32 = 140.0;
+/// Past this fraction of the range, the highest and lowest values are taken to be running off
+/// towards an asymptote, so they're left out of the range rather than flattening the rest
+const OUTLIERS: f64 = 0.02;
+
+pub type Apply = fn(f64) -> f64;
+
+#[rustfmt::skip]
+const FUNCS: [(&str, Apply); 20] = [
+  ("sin", f64::sin), ("cos", f64::cos), ("tan", f64::tan), ("asin", f64::asin),
+  ("acos", f64::acos), ("atan", f64::atan), ("sinh", f64::sinh), ("cosh", f64::cosh),
+  ("tanh", f64::tanh), ("sqrt", f64::sqrt), ("cbrt", f64::cbrt), ("exp", f64::exp),
+  ("ln", f64::ln), ("log", f64::log10), ("log10", f64::log10), ("log2", f64::log2),
+  ("abs", f64::abs), ("floor", f64::floor), ("ceil", f64::ceil), ("round", f64::round),
+];
+const CONSTS: [(&str, f64); 3] = [("pi", PI), ("e", E), ("tau", TAU)];
+
+#[derive(Clone, Debug)]
+enum Expr {
+  Num(f64),
+  Var,
+  Neg(Box<Expr>),
+  /// A binary operator, with the empty one multiplying
+  Op(&'static str, Box<Expr>, Box<Expr>),
+  Call(Apply, Box<Expr>),
+}
+
+impl Expr {
+  fn at(&self, x: f64) -> f64 {
+    match self {
+      Expr::Num(n) => *n,
+      Expr::Var => x,
+      Expr::Neg(expr) => -expr.at(x),
+      Expr::Op(op, lhs, rhs) => {
+        let (lhs, rhs) = (lhs.at(x), rhs.at(x));
+        match *op {
+          "+" => lhs + rhs,
+          "-" => lhs - rhs,
+          "*" | "" => lhs * rhs,
+          "/" => lhs / rhs,
+          "%" => lhs % rhs,
+          _ => lhs.powf(rhs),
+        }
+      }
+      Expr::Call(func, arg) => func(arg.at(x)),
+    }
+  }
+}
+
+/// A function of one variable, parsed once so it can be sampled as often as the plot's moved
+#[derive(Debug)]
+pub struct Func {
+  expr: Expr,
+  pub var: String,
+}
+
+impl Func {
+  /// Parses an expression with a free variable in it to plot, like `sin(x) * x^2`. Ones
+  /// without exactly one, where it means something else like a unit or a currency there are
+  /// rates for, or that call a function we can't plot are left alone
+  pub fn parse(input: &str, rates: Option<&Rates>) -> Option<Result<Func, String>> {
+    let tokens = syntax::lex(input, &SYNTAX).ok()?;
+    let mut free = vec![];
+    for (i, (token, _)) in tokens.iter().enumerate() {
+      match (token, tokens.get(i + 1).map(|(next, _)| next)) {
+        (Token::Name(name), Some(Token::Op("("))) if function(name).is_none() => return None,
+        (Token::Name(name), _) if !is_known(name) => free.push(*name),
+        _ => {}
+      }
+    }
+    let var = *free.first()?;
+    let other = free.iter().any(|&other| other != var);
+    if other || units::is_unit(var) || units::is_currency(var, rates) || dates::is_keyword(var) {
+      return None;
+    }
+    let mut parser = Parser::new(&tokens, Curve { var });
+    let expr = parser.expr().and_then(|expr| match parser.peek() {
+      None => Ok(expr),
+      Some(token) => Err(format!("Unexpected {}", token)),
+    });
+    Some(expr.map(|expr| Func {
+      expr,
+      var: var.to_string(),
+    }))
+  }
+
+  pub fn at(&self, x: f64) -> f64 {
+    self.expr.at(x)
+  }
+}
+
+/// The function of one number by name, like `sin`
+pub fn function(name: &str) -> Option<Apply> {
+  FUNCS
+    .iter()
+    .find(|(n, _)| *n == name)
+    .map(|(_, func)| *func)
+}
+
+/// The constant by name, like `pi`
+pub fn constant(name: &str) -> Option<f64> {
+  CONSTS
+    .iter()
+    .find(|(n, _)| *n == name)
+    .map(|(_, value)| *value)
+}
+
+fn is_known(name: &str) -> bool {
+  function(name).is_some() || constant(name).is_some()
+}
+
+/// The stretch of the variable that's plotted
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct View {
+  from: f64,
+  to: f64,
+}
+
+impl View {
+  fn width(&self) -> f64 {
+    self.to - self.from
+  }
+
+  /// Zoomed in around the point when the factor's under one, out when it's over
+  fn zoom(&mut self, at: f64, factor: f64) {
+    self.from = at + (self.from - at) * factor;
+    self.to = at + (self.to - at) * factor;
+  }
+}
+
+/// The function plotted, with where it's been panned and zoomed to. It's shared by the
+/// results offering it up, so copying it copies what's shown.
+#[derive(Debug)]
+pub struct Plot {
+  pub func: Func,
+  home: View,
+  view: Mutex<View>,
+}
+
+/// Where the lines are drawn, as fractions across and down the plot
+struct Frame {
+  x: (f64, f64),
+  y: (f64, f64),
+  lines: Vec<Vec<(f64, f64)>>,
+}
+
+impl Frame {
+  fn place(&self, (x, y): (f64, f64)) -> (f32, f32) {
+    let across = (x - self.x.0) / (self.x.1 - self.x.0);
+    let down = (self.y.1 - y) / (self.y.1 - self.y.0);
+    (across as f32, down as f32)
+  }
+}
+
+impl Plot {
+  pub fn new(func: Func, domain: (f64, f64)) -> Plot {
+    let home = match domain.1 > domain.0 {
+      true => View {
+        from: domain.0,
+        to: domain.1,
+      },
+      false => View {
+        from: -10.0,
+        to: 10.0,
+      },
+    };
+    Plot {
+      func,
+      home,
+      view: Mutex::new(home),
+    }
+  }
+
+  /// Values across what's shown, a row to a line with a tab between, ready to paste into
+  /// a spreadsheet
+  pub fn table(&self, format: &NumberFormat) -> String {
+    let view = *self.view.lock();
+    let write = |n: f64| match n.is_finite() {
+      true => format::write(format::Digits::from_f64(n).round(10), format),
+      false => "".into(),
+    };
+    let step = view.width() / (TABLE_ROWS - 1) as f64;
+    let rows = (0..TABLE_ROWS).map(|idx| {
+      let x = view.from + step * idx as f64;
+      format!("{}\t{}", write(x), write(self.func.at(x)))
+    });
+    let header = format!("{}\tf({})", self.func.var, self.func.var);
+    [header]
+      .into_iter()
+      .chain(rows)
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  fn frame(&self) -> Frame {
+    let view = *self.view.lock();
+    let step = view.width() / SAMPLES as f64;
+    let points: Vec<_> = (0..=SAMPLES)
+      .map(|idx| view.from + step * idx as f64)
+      .map(|x| (x, self.func.at(x)))
+      .collect();
+    let y = y_range(&points);
+    // Lines break where there's no value, and where they jump further than the whole plot's
+    // height between samples, which is an asymptote rather than something to join up
+    let mut lines = vec![Vec::new()];
+    for (idx, (x, y_at)) in points.iter().enumerate() {
+      let jumped = idx > 0 && (y_at - points[idx - 1].1).abs() > y.1 - y.0;
+      if !y_at.is_finite() || jumped {
+        lines.push(Vec::new());
+      }
+      if y_at.is_finite() {
+        lines.last_mut().unwrap().push((*x, *y_at));
+      }
+    }
+    lines.retain(|line| line.len() > 1);
+    Frame {
+      x: (view.from, view.to),
+      y,
+      lines,
+    }
+  }
+
+  /// Draws the plot across the row, dragged to pan, scrolled to zoom and double clicked to
+  /// go back to the configured domain
+  pub fn show(&self, ui: &mut Ui) {
+    let size = vec2(ui.available_width(), HEIGHT);
+    let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+    {
+      let mut view = self.view.lock();
+      let per_point = view.width() / rect.width() as f64;
+      if response.dragged() {
+        let moved = -response.drag_delta().x as f64 * per_point;
+        (view.from, view.to) = (view.from + moved, view.to + moved);
+      }
+      let scrolled = ui.input(|i| i.smooth_scroll_delta.y);
+      if let Some(pointer) = response.hover_pos().filter(|_| scrolled != 0.0) {
+        let at = view.from + (pointer.x - rect.left()) as f64 * per_point;
+        view.zoom(at, (-scrolled as f64 * 0.005).exp());
+      }
+      if response.double_clicked() {
+        *view = self.home;
+      }
+    }
+
+    let frame = self.frame();
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+    let at = |point| {
+      let (across, down) = frame.place(point);
+      pos2(
+        rect.left() + across * rect.width(),
+        rect.top() + down * rect.height(),
+      )
+    };
+    painter.rect_stroke(rect, 2.0, visuals.widgets.noninteractive.bg_stroke);
+    let axis = Stroke::new(1.0, visuals.weak_text_color());
+    if frame.x.0 < 0.0 && 0.0 < frame.x.1 {
+      painter.line_segment([at((0.0, frame.y.0)), at((0.0, frame.y.1))], axis);
+    }
+    if frame.y.0 < 0.0 && 0.0 < frame.y.1 {
+      painter.line_segment([at((frame.x.0, 0.0)), at((frame.x.1, 0.0))], axis);
+    }
+    let stroke = Stroke::new(1.5, visuals.text_color());
+    for line in &frame.lines {
+      painter.add(Shape::line(line.iter().map(|p| at(*p)).collect(), stroke));
+    }
+
+    let font = FontId::proportional(10.0);
+    let label = |n: f64| {
+      format!("{:.4}", n)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+    };
+    let color = visuals.weak_text_color();
+    let corners = [
+      (rect.left_top(), Align2::LEFT_TOP, label(frame.y.1)),
+      (rect.left_bottom(), Align2::LEFT_BOTTOM, label(frame.y.0)),
+      (
+        rect.right_bottom(),
+        Align2::RIGHT_BOTTOM,
+        format!(
+          "{} from {} to {}",
+          self.func.var,
+          label(frame.x.0),
+          label(frame.x.1)
+        ),
+      ),
+    ];
+    for (pos, align, text) in corners {
+      let pos = pos + (align.to_sign() * -2.0);
+      painter.text(pos, align, text, font.clone(), color);
+    }
+  }
+
+  /// The plot drawn black on white, as it's shown
+  pub fn image(&self, width: u32, height: u32) -> RgbaImage {
+    let frame = self.frame();
+    let mut image = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+    let at = |point| {
+      let (across, down) = frame.place(point);
+      (across * (width - 1) as f32, down * (height - 1) as f32)
+    };
+    let axis = Rgba([170, 170, 170, 255]);
+    if frame.x.0 < 0.0 && 0.0 < frame.x.1 {
+      draw_line(&mut image, at((0.0, frame.y.0)), at((0.0, frame.y.1)), axis);
+    }
+    if frame.y.0 < 0.0 && 0.0 < frame.y.1 {
+      draw_line(&mut image, at((frame.x.0, 0.0)), at((frame.x.1, 0.0)), axis);
+    }
+    for line in &frame.lines {
+      for pair in line.windows(2) {
+        draw_line(&mut image, at(pair[0]), at(pair[1]), Rgba([0, 0, 0, 255]));
+      }
+    }
+    image
+  }
+}
+
+/// The range of values worth showing, leaving out ones shooting off towards an asymptote
+fn y_range(points: &[(f64, f64)]) -> (f64, f64) {
+  let mut ys: Vec<f64> = points
+    .iter()
+    .map(|p| p.1)
+    .filter(|y| y.is_finite())
+    .collect();
+  if ys.is_empty() {
+    return (-1.0, 1.0);
+  }
+  ys.sort_by(f64::total_cmp);
+  let pick = |fraction: f64| ys[((ys.len() - 1) as f64 * fraction).round() as usize];
+  let (all, trimmed) = (
+    (ys[0], ys[ys.len() - 1]),
+    (pick(OUTLIERS), pick(1.0 - OUTLIERS)),
+  );
+  let (low, high) = match all.1 - all.0 > 3.0 * (trimmed.1 - trimmed.0) {
+    true => trimmed,
+    false => all,
+  };
+  if high - low < 1e-9 {
+    return (low - 1.0, high + 1.0);
+  }
+  let margin = (high - low) * 0.05;
+  (low - margin, high + margin)
+}
+
+/// A line two pixels wide, clipped to the image
+fn draw_line(image: &mut RgbaImage, from: (f32, f32), to: (f32, f32), color: Rgba<u8>) {
+  let steps = (to.0 - from.0)
+    .abs()
+    .max((to.1 - from.1).abs())
+    .ceil()
+    .max(1.0);
+  if !steps.is_finite() || steps > 100_000.0 {
+    return;
+  }
+  for step in 0..=steps as u32 {
+    let t = step as f32 / steps;
+    let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+    for (dx, dy) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)] {
+      let (x, y) = ((x + dx).round(), (y + dy).round());
+      if x >= 0.0 && y >= 0.0 && x < image.width() as f32 && y < image.height() as f32 {
+        image.put_pixel(x as u32, y as u32, color);
+      }
+    }
+  }
+}
+
+const SYNTAX: Syntax = Syntax::new(&["**", "+", "-", "*", "/", "%", "^", "(", ")"]);
+
+/// Builds up the expression of the variable
+struct Curve<'a> {
+  var: &'a str,
+}
+
+impl<'a> Grammar<'a> for Curve<'a> {
+  type Value = Expr;
+  type Error = String;
+
+  /// Numbers written straight before a name or bracket are multiplied, like `2x` or `3(x+1)`
+  const LEVELS: &'static [Ops] = &[&["+", "-"], &["*", "/", "%", ""]];
+  const PREFIX: Ops = &["-", "+"];
+  const POWER: Ops = &["^", "**"];
+
+  fn binary(
+    &mut self,
+    op: &'static str,
+    _: Range<usize>,
+    lhs: Expr,
+    rhs: Expr,
+  ) -> Result<Expr, String> {
+    Ok(Expr::Op(op, Box::new(lhs), Box::new(rhs)))
+  }
+
+  fn prefix(&mut self, op: &'static str, _: Range<usize>, expr: Expr) -> Result<Expr, String> {
+    Ok(match op {
+      "-" => Expr::Neg(Box::new(expr)),
+      _ => expr,
+    })
+  }
+
+  fn atom(
+    parser: &mut Parser<'a, Self>,
+    token: Token<'a>,
+    _: Range<usize>,
+  ) -> Result<Expr, String> {
+    match token {
+      Token::Num(literal) => Ok(Expr::Num(SYNTAX.decimal(literal)?)),
+      Token::Name(name) if name == parser.grammar.var => Ok(Expr::Var),
+      Token::Name(name) => {
+        if let Some(value) = constant(name) {
+          return Ok(Expr::Num(value));
+        }
+        let func = function(name).ok_or_else(|| format!("Unknown {}", name))?;
+        if parser.eat(&["("]).is_none() {
+          return Err(format!("{} needs brackets around what it's of", name));
+        }
+        Ok(Expr::Call(func, Box::new(parser.bracketed()?)))
+      }
+      Token::Op("(") => parser.bracketed(),
+      token => Err(format!("Unexpected {}", token)),
+    }
+  }
+
+  fn mistake(&self, message: String, _: Range<usize>) -> String {
+    message
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn func(input: &str) -> Func {
+    Func::parse(input, None)
+      .expect("Should have been a function")
+      .unwrap()
+  }
+
+  #[test]
+  fn parses_functions_of_one_variable() {
+    assert_eq!(func("sin(x)*x^2").at(2.0), 2f64.sin() * 4.0);
+    assert_eq!(func("2x + 3(x - 1)").at(2.0), 7.0);
+    assert_eq!(func("-theta^2 / 2 + pi").at(2.0), PI - 2.0);
+    assert_eq!(func("2 ** -x").var, "x");
+    assert!(matches!(Func::parse("sin x", None), Some(Err(_))));
+    assert!(Func::parse("sqrt(2) * pi", None).is_none());
+    assert!(Func::parse("x + y", None).is_none());
+    assert!(Func::parse("3 m + 2 ft", None).is_none());
+    assert!(Func::parse("now + 1 day", None).is_none());
+  }
+
+  #[test]
+  fn leaves_other_functions_to_calc() {
+    assert!(Func::parse("deg(pi)", None).is_none());
+    assert!(Func::parse("asinh(x) + 1", None).is_none());
+    assert_eq!(func("sinh(x)").var, "x");
+  }
+
+  #[test]
+  fn leaves_currencies_with_rates_to_units() {
+    let rates = Rates::parse(r#"{"base": "USD", "timestamp": 1, "rates": {"EUR": 0.5}}"#).unwrap();
+    assert!(Func::parse("100 usd * 2", Some(&rates)).is_none());
+    assert!(Func::parse("eur^2", Some(&rates)).is_none());
+    assert_eq!(func("100 usd * 2").var, "usd");
+  }
+
+  #[test]
+  fn scales_to_what_matters() {
+    let plot = Plot::new(func("x^2"), (-2.0, 2.0));
+    let frame = plot.frame();
+    assert_eq!(frame.x, (-2.0, 2.0));
+    assert!((frame.y.0 + 0.2).abs() < 1e-9 && (frame.y.1 - 4.2).abs() < 1e-9);
+    assert_eq!(frame.lines.len(), 1);
+
+    // The asymptote neither stretches the range nor gets joined up across
+    let plot = Plot::new(func("1/x"), (-1.0, 1.0));
+    let frame = plot.frame();
+    assert!(frame.y.1 < 100.0);
+    assert_eq!(frame.lines.len(), 2);
+
+    plot.view.lock().zoom(0.0, 2.0);
+    assert_eq!(plot.frame().x, (-2.0, 2.0));
+  }
+
+  #[test]
+  fn copies_tables_and_images() {
+    let plot = Plot::new(func("x^2"), (0.0, 2.0));
+    let format = NumberFormat {
+      decimal_separator: Some(".".into()),
+      ..Default::default()
+    };
+    let table = plot.table(&format);
+    let rows: Vec<_> = table.lines().collect();
+    assert_eq!(rows.len(), TABLE_ROWS + 1);
+    assert_eq!(rows[0], "x\tf(x)");
+    assert_eq!(rows[2], "0.1\t0.01");
+    assert_eq!(rows[TABLE_ROWS], "2\t4");
+
+    let image = plot.image(100, 50);
+    let inked = image.pixels().filter(|p| p.0 == [0, 0, 0, 255]).count();
+    assert!(inked > 100);
+  }
+}
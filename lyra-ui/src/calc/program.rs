@@ -0,0 +1,368 @@
+use super::syntax::{self, Grammar, Ops, Parser, Spanned, Syntax, Token};
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+/// Words that split off the base or width to work something out in, like `255 in hex`
+const TARGETS: [&str; 3] = ["in", "to", "as"];
+
+/// The integer type things are worked out in, wrapping around like the machine type it's
+/// named after
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Width {
+  bits: u32,
+  signed: bool,
+}
+
+impl Width {
+  const DEFAULT: Width = Width {
+    bits: 64,
+    signed: true,
+  };
+
+  /// Reads names like `u8` or `i32`
+  fn parse(name: &str) -> Option<Width> {
+    let (signed, bits) = match name.split_at_checked(1)? {
+      ("i", bits) => (true, bits),
+      ("u", bits) => (false, bits),
+      _ => return None,
+    };
+    let bits = bits.parse().ok().filter(|b| [8, 16, 32, 64].contains(b))?;
+    Some(Width { bits, signed })
+  }
+
+  fn mask(self) -> u128 {
+    (1u128 << self.bits) - 1
+  }
+
+  /// The value as it'd be stored in this width, overflow and all
+  fn wrap(self, value: i128) -> i128 {
+    let bits = value as u128 & self.mask();
+    match self.signed && bits >> (self.bits - 1) == 1 {
+      true => bits as i128 - (1i128 << self.bits),
+      false => bits as i128,
+    }
+  }
+}
+
+impl Display for Width {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let sign = if self.signed { 'i' } else { 'u' };
+    write!(f, "{}{}", sign, self.bits)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Base {
+  Dec,
+  Hex,
+  Oct,
+  Bin,
+}
+
+impl Base {
+  pub const ALL: [Base; 4] = [Base::Dec, Base::Hex, Base::Oct, Base::Bin];
+
+  fn parse(name: &str) -> Option<Base> {
+    match name {
+      "dec" | "decimal" => Some(Base::Dec),
+      "hex" | "hexadecimal" => Some(Base::Hex),
+      "oct" | "octal" => Some(Base::Oct),
+      "bin" | "binary" => Some(Base::Bin),
+      _ => None,
+    }
+  }
+}
+
+impl Display for Base {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Base::Dec => "dec",
+      Base::Hex => "hex",
+      Base::Oct => "oct",
+      Base::Bin => "bin",
+    })
+  }
+}
+
+/// A worked out integer, along with the width it was worked out in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Int {
+  value: i128,
+  pub width: Width,
+}
+
+impl Int {
+  /// The value written in the base. Negative values are written out as their two's
+  /// complement in every base but decimal, the way they're actually stored
+  pub fn format(&self, base: Base) -> String {
+    let bits = self.value as u128 & self.width.mask();
+    match base {
+      Base::Dec => self.value.to_string(),
+      Base::Hex => format!("{:#x}", bits),
+      Base::Oct => format!("{:#o}", bits),
+      Base::Bin => format!("{:#b}", bits),
+    }
+  }
+}
+
+/// Whether the name picks a base or width to work something out in
+pub fn is_target(name: &str) -> bool {
+  Base::parse(name).is_some() || Width::parse(name).is_some()
+}
+
+/// Works out integer expressions, like `0xff & 0b1010` or `-1 as u8 in hex`, giving back the
+/// result along with the base it's best shown in. Expressions are only worked out here when
+/// they've something only integers have, like a hex literal, a bitwise operator or a base
+/// to show it in, leaving the rest alone.
+pub fn evaluate(input: &str) -> Option<Result<(Int, Base), String>> {
+  let (expr, width, base) = split_targets(input);
+  let targeted = width.is_some() || base.is_some();
+  if !targeted && !is_programmer(expr) {
+    return None;
+  }
+  let tokens = syntax::lex(expr, &SYNTAX);
+
+  let width = width.unwrap_or(Width::DEFAULT);
+  // Without a base asked for, it's shown in the base it was written in
+  let base = base.unwrap_or_else(|| {
+    let written = tokens.iter().flatten().find_map(|(token, _)| match token {
+      Token::Num(literal) => number(literal)
+        .ok()
+        .map(|(_, base)| base)
+        .filter(|base| *base != Base::Dec),
+      _ => None,
+    });
+    written.unwrap_or(Base::Dec)
+  });
+  Some(tokens.and_then(|tokens| {
+    let value = Ints::parse(&tokens, width)?;
+    Ok((Int { value, width }, base))
+  }))
+}
+
+/// Whether the expression has something only integers have, even if it's not valid
+fn is_programmer(expr: &str) -> bool {
+  let has_op = ["&", "|", "~", "<<", ">>"]
+    .iter()
+    .any(|op| expr.contains(op));
+  let has_literal = expr
+    .split(|c: char| !c.is_ascii_alphanumeric())
+    .any(|word| {
+      ["0x", "0o", "0b"]
+        .iter()
+        .any(|p| word.starts_with(p) && word.len() > 2)
+    });
+  has_op || has_literal
+}
+
+/// Splits off trailing targets like `as u8 in hex`, giving back what's left along with the
+/// width and base picked
+fn split_targets(input: &str) -> (&str, Option<Width>, Option<Base>) {
+  let (mut expr, mut width, mut base) = (input.trim_end(), None, None);
+  loop {
+    let Some((rest, name)) = expr.rsplit_once(char::is_whitespace) else {
+      return (expr, width, base);
+    };
+    let Some((rest, word)) = rest.trim_end().rsplit_once(char::is_whitespace) else {
+      return (expr, width, base);
+    };
+    let name = name.to_lowercase();
+    if !TARGETS.contains(&word.to_lowercase().as_str()) {
+      return (expr, width, base);
+    }
+    match (Base::parse(&name), Width::parse(&name)) {
+      (Some(b), _) if base.is_none() => base = Some(b),
+      (_, Some(w)) if width.is_none() => width = Some(w),
+      _ => return (expr, width, base),
+    }
+    expr = rest.trim_end();
+  }
+}
+
+/// Longest first, so `<<` isn't read as two `<`
+const SYNTAX: Syntax = Syntax::new(&[
+  "<<", ">>", "**", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")",
+]);
+
+fn number(literal: &str) -> Result<(i128, Base), String> {
+  let digits = literal.replace('_', "");
+  let lower = digits.to_lowercase();
+  let (base, radix, digits) = match lower.get(..2) {
+    Some("0x") => (Base::Hex, 16, &digits[2..]),
+    Some("0o") => (Base::Oct, 8, &digits[2..]),
+    Some("0b") => (Base::Bin, 2, &digits[2..]),
+    _ => (Base::Dec, 10, digits.as_str()),
+  };
+  if digits.contains('.') {
+    return Err(format!("{} isn't a whole number", literal));
+  }
+  match i128::from_str_radix(digits, radix) {
+    Ok(n) if n <= u64::MAX as i128 => Ok((n, base)),
+    Ok(_) => Err(format!("{} is more than 64 bits", literal)),
+    Err(_) => Err(format!("Invalid number {}", literal)),
+  }
+}
+
+/// Works out the expression, with C's operators but with the bitwise ones binding in the
+/// order they're usually meant: `|` loosest, then `^`, `&`, shifts and arithmetic
+struct Ints {
+  width: Width,
+}
+
+impl Ints {
+  fn parse(tokens: &[Spanned], width: Width) -> Result<i128, String> {
+    let mut parser = Parser::new(tokens, Ints { width });
+    let value = parser.expr()?;
+    match parser.peek() {
+      None => Ok(value),
+      Some(Token::Op(")")) => Err("Unopened )".into()),
+      Some(_) => Err("Unexpected trailing input".into()),
+    }
+  }
+}
+
+impl<'a> Grammar<'a> for Ints {
+  type Value = i128;
+  type Error = String;
+
+  const LEVELS: &'static [Ops] = &[
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+  ];
+  const PREFIX: Ops = &["-", "~"];
+  const POWER: Ops = &["**"];
+
+  fn binary(
+    &mut self,
+    op: &'static str,
+    _: Range<usize>,
+    a: i128,
+    b: i128,
+  ) -> Result<i128, String> {
+    let width = self.width;
+    let value = match op {
+      "|" => a | b,
+      "^" => a ^ b,
+      "&" => a & b,
+      "<<" => shift(width, a, b, true)?,
+      ">>" => shift(width, a, b, false)?,
+      "+" => a.wrapping_add(b),
+      "-" => a.wrapping_sub(b),
+      "*" => a.wrapping_mul(b),
+      "/" | "%" if b == 0 => return Err("Can't divide by zero".into()),
+      "/" => a / b,
+      "%" => a % b,
+      _ => {
+        let exp = u32::try_from(b).map_err(|_| format!("Can't raise to the power of {}", b))?;
+        a.wrapping_pow(exp)
+      }
+    };
+    Ok(width.wrap(value))
+  }
+
+  fn prefix(&mut self, op: &'static str, _: Range<usize>, value: i128) -> Result<i128, String> {
+    Ok(self.width.wrap(match op {
+      "-" => -value,
+      _ => !value,
+    }))
+  }
+
+  fn atom(
+    parser: &mut Parser<'a, Self>,
+    token: Token<'a>,
+    _: Range<usize>,
+  ) -> Result<i128, String> {
+    match token {
+      Token::Num(literal) => Ok(parser.grammar.width.wrap(number(literal)?.0)),
+      Token::Name(name) => Err(format!("Unknown {}", name)),
+      Token::Op("(") => parser.bracketed(),
+      Token::Op(op) => Err(format!("Unexpected {}", op)),
+    }
+  }
+
+  fn mistake(&self, message: String, _: Range<usize>) -> String {
+    message
+  }
+}
+
+fn shift(width: Width, value: i128, by: i128, left: bool) -> Result<i128, String> {
+  if !(0..width.bits as i128).contains(&by) {
+    return Err(format!("Can't shift by {} bits in {}", by, width));
+  }
+  Ok(match left {
+    true => value << by,
+    // Signed values keep their sign, unsigned ones are never negative to begin with
+    false => value >> by,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn eval(input: &str) -> Result<Vec<String>, String> {
+    let (int, base) = evaluate(input).expect("Should have been an integer expression")?;
+    let mut forms = vec![int.format(base)];
+    forms.push(int.width.to_string());
+    Ok(forms)
+  }
+
+  #[test]
+  fn works_out_bitwise_expressions() {
+    assert_eq!(eval("0xff & 0b1010"), Ok(vec!["0xa".into(), "i64".into()]));
+    assert_eq!(eval("1 << 12"), Ok(vec!["4096".into(), "i64".into()]));
+    assert_eq!(eval("255 in hex"), Ok(vec!["0xff".into(), "i64".into()]));
+    assert_eq!(
+      eval("0o17 | 0x30 in dec"),
+      Ok(vec!["63".into(), "i64".into()])
+    );
+    assert_eq!(eval("6 ^ 3 in bin"), Ok(vec!["0b101".into(), "i64".into()]));
+    assert_eq!(eval("1 | 2 & 3 << 1"), Ok(vec!["3".into(), "i64".into()]));
+    assert_eq!(
+      eval("2 ** 10 as u16"),
+      Ok(vec!["1024".into(), "u16".into()])
+    );
+    assert_eq!(eval("1_000 >> 0x1"), Ok(vec!["0x1f4".into(), "i64".into()]));
+  }
+
+  #[test]
+  fn wraps_to_the_width() {
+    assert_eq!(eval("-1 as u8"), Ok(vec!["255".into(), "u8".into()]));
+    assert_eq!(
+      eval("0xff as i8 in dec"),
+      Ok(vec!["-1".into(), "i8".into()])
+    );
+    assert_eq!(
+      eval("~0 as u16 in hex"),
+      Ok(vec!["0xffff".into(), "u16".into()])
+    );
+    assert_eq!(eval("200 + 100 as u8"), Ok(vec!["44".into(), "u8".into()]));
+    assert_eq!(eval("-16 >> 2 as i32"), Ok(vec!["-4".into(), "i32".into()]));
+
+    let (int, _) = evaluate("-2 as i16").unwrap().unwrap();
+    let forms: Vec<_> = Base::ALL.iter().map(|b| int.format(*b)).collect();
+    assert_eq!(
+      forms,
+      vec!["-2", "0xfffe", "0o177776", "0b1111111111111110"]
+    );
+  }
+
+  #[test]
+  fn rejects_what_integers_cant_do() {
+    assert_eq!(eval("1 << 64"), Err("Can't shift by 64 bits in i64".into()));
+    assert_eq!(eval("0xff / 0"), Err("Can't divide by zero".into()));
+    assert_eq!(eval("1.5 & 1"), Err("1.5 isn't a whole number".into()));
+    assert_eq!(eval("sqrt(4) in hex"), Err("Unknown sqrt".into()));
+  }
+
+  #[test]
+  fn leaves_plain_arithmetic_alone() {
+    assert_eq!(evaluate("1 + 2"), None);
+    assert_eq!(evaluate("2 ^ 8"), None);
+    assert_eq!(evaluate("12 in in cm"), None);
+  }
+}
@@ -0,0 +1,304 @@
+use crate::reloader::{self, Reloader};
+use anyhow::{anyhow, Context};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  fs,
+  path::Path,
+  sync::Arc,
+  thread,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+const RATES_FILE: &str = "rates.json";
+/// Rates kept by hand, or by anything that'd rather write CSV
+const RATES_CSV_FILE: &str = "rates.csv";
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// How soon a failed fetch is tried again, unless rates are meant to be fetched sooner
+const RETRY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// Exchange rates against a base currency. Reads what most rate APIs give back, so the
+/// file can be filled in by hand or by anything else just as well as by the refresher
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Rates {
+  /// The currency everything is rated against
+  #[serde(alias = "base_code")]
+  pub base: String,
+  /// When the rates are from, in seconds since the unix epoch
+  #[serde(default, alias = "time_last_update_unix")]
+  pub timestamp: i64,
+  /// Some sources only give the day the rates are from
+  #[serde(default, skip_serializing)]
+  date: Option<String>,
+  /// How much of each currency one of the base buys
+  #[serde(alias = "conversion_rates")]
+  pub rates: HashMap<String, f64>,
+}
+
+impl Rates {
+  /// Reads rates from JSON, or from CSV when it's not an object
+  pub fn parse(input: &str) -> Result<Self, anyhow::Error> {
+    let mut rates = match input.trim_start().starts_with('{') {
+      true => serde_json::from_str(input)?,
+      false => Rates::from_csv(input)?,
+    };
+    if rates.timestamp == 0 {
+      if let Some(date) = &rates.date {
+        let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+          .with_context(|| format!("Invalid rates date {:?}", date))?;
+        rates.timestamp = day
+          .and_hms_opt(0, 0, 0)
+          .unwrap_or_default()
+          .and_utc()
+          .timestamp();
+      }
+    }
+    rates.base = rates.base.to_uppercase();
+    rates.rates = rates
+      .rates
+      .into_iter()
+      .filter(|(_, rate)| rate.is_finite() && *rate > 0.0)
+      .map(|(code, rate)| (code.to_uppercase(), rate))
+      .collect();
+    if rates.base.is_empty() {
+      return Err(anyhow!("Rates are missing their base currency"));
+    }
+    Ok(rates)
+  }
+
+  /// A `code,rate` row per currency, along with `base` and `timestamp` (or `date`) rows
+  /// saying what they're against and when they're from. A `code,rate` header is skipped
+  fn from_csv(csv: &str) -> Result<Self, anyhow::Error> {
+    let mut rates = Rates::default();
+    for (idx, line) in csv.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() {
+        continue;
+      }
+      let (key, value) = line
+        .split_once(',')
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .ok_or_else(|| anyhow!("Line {} isn't a code and a rate: {:?}", idx + 1, line))?;
+      match key.to_lowercase().as_str() {
+        "code" => {}
+        "base" => rates.base = value.to_string(),
+        "date" => rates.date = Some(value.to_string()),
+        "timestamp" => {
+          rates.timestamp = value
+            .parse()
+            .with_context(|| format!("Invalid timestamp on line {}", idx + 1))?
+        }
+        _ => {
+          let rate = value
+            .parse()
+            .with_context(|| format!("Invalid rate for {} on line {}", key, idx + 1))?;
+          rates.rates.insert(key.to_string(), rate);
+        }
+      }
+    }
+    Ok(rates)
+  }
+
+  /// How much of the base currency one of the currency is worth, matching codes like `usd`
+  /// regardless of case
+  pub fn in_base(&self, code: &str) -> Option<f64> {
+    let code = code.to_uppercase();
+    match code == self.base {
+      true => Some(1.0),
+      false => self.rates.get(&code).map(|rate| 1.0 / rate),
+    }
+  }
+
+  /// How long ago the rates are from
+  pub fn age(&self) -> Duration {
+    let secs = Utc::now().timestamp() - self.timestamp;
+    Duration::from_secs(secs.max(0) as u64)
+  }
+}
+
+/// The exchange rates, kept in a file in the cache dir so converting currencies never waits
+/// on the network. The file is read again whenever it changes, which is how rates fetched in
+/// the background show up. Rates can be put in a CSV file alongside too, and whichever of
+/// the two is from later is used.
+#[derive(Debug)]
+pub struct RateStore {
+  file: Reloader<Option<Arc<Rates>>>,
+  csv: Reloader<Option<Arc<Rates>>>,
+}
+
+impl RateStore {
+  pub fn new(cache_dir: &Path) -> Self {
+    RateStore {
+      file: Reloader::new(cache_dir.join(RATES_FILE), "rates", load),
+      csv: Reloader::new(cache_dir.join(RATES_CSV_FILE), "rates", load),
+    }
+  }
+
+  /// The latest rates, none until there's a rates file
+  pub fn rates(&self) -> Option<Arc<Rates>> {
+    [self.file.get(), self.csv.get()]
+      .into_iter()
+      .flatten()
+      .max_by_key(|rates| rates.timestamp)
+  }
+
+  /// Fetches rates from the url into the rates file
+  pub fn refresh(&self, url: &str) -> Result<Rates, anyhow::Error> {
+    let client = reqwest::blocking::Client::builder()
+      .timeout(FETCH_TIMEOUT)
+      .build()?;
+    let body = client.get(url).send()?.error_for_status()?.text()?;
+    let mut rates = Rates::parse(&body).with_context(|| format!("Invalid rates from {}", url))?;
+    if rates.timestamp == 0 {
+      rates.timestamp = Utc::now().timestamp();
+    }
+    // Written alongside then moved into place, so the rates are never read half written
+    let tmp = self.file.file().with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&rates)?)?;
+    fs::rename(&tmp, self.file.file())?;
+    Ok(rates)
+  }
+
+  /// Keeps the rates file up to date from the url in the background, fetching whenever it's
+  /// older than `every`. A fresh enough file is left alone, so restarts don't fetch again.
+  pub fn refresh_in_background(self: &Arc<Self>, url: String, every: Duration) {
+    let store = self.clone();
+    thread::spawn(move || loop {
+      let age = reloader::modified(store.file.file()).and_then(|m| m.elapsed().ok());
+      let wait = match age {
+        Some(age) if age < every => every - age,
+        _ => match store.refresh(&url) {
+          Ok(rates) => {
+            info!("Fetched {} exchange rates from {}", rates.rates.len(), url);
+            every
+          }
+          Err(err) => {
+            warn!("Failed to fetch exchange rates: {:?}", err);
+            RETRY_AFTER.min(every)
+          }
+        },
+      };
+      thread::sleep(wait);
+    });
+  }
+}
+
+fn load(s: &str, modified: Option<SystemTime>) -> Result<Option<Arc<Rates>>, anyhow::Error> {
+  let mut rates = Rates::parse(s)?;
+  // Rates that don't say when they're from are taken to be as old as the file
+  if rates.timestamp == 0 {
+    let since_epoch = modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok());
+    rates.timestamp = since_epoch.unwrap_or_default().as_secs() as i64;
+  }
+  Ok(Some(Arc::new(rates)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+  use std::{
+    io::{Read, Write},
+    net::TcpListener,
+  };
+
+  /// Stands in for a rates endpoint, answering a single request with the status and body
+  fn serve_once(status: &str, body: &str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/latest", listener.local_addr().unwrap());
+    let resp = format!(
+      "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      status,
+      body.len(),
+      body
+    );
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let _ = stream.read(&mut [0; 4096]);
+      stream.write_all(resp.as_bytes()).unwrap();
+    });
+    url
+  }
+
+  #[test]
+  fn reads_common_rate_formats() {
+    let ours = r#"{"base": "usd", "timestamp": 1700000000, "rates": {"eur": 0.5}}"#;
+    let ours = Rates::parse(ours).unwrap();
+    assert_eq!(ours.base, "USD");
+    assert_eq!(ours.in_base("EUR"), Some(2.0));
+    assert_eq!(ours.in_base("Usd"), Some(1.0));
+    assert_eq!(ours.in_base("gbp"), None);
+
+    let by_day = Rates::parse(r#"{"base": "EUR", "date": "2024-01-02", "rates": {"USD": 1.1}}"#);
+    assert_eq!(by_day.unwrap().timestamp, 1704153600);
+
+    let er_api = Rates::parse(
+      r#"{"result": "success", "base_code": "USD", "time_last_update_unix": 1700000000,
+      "rates": {"USD": 1, "JPY": 150.5}}"#,
+    );
+    assert_eq!(er_api.unwrap().timestamp, 1700000000);
+    assert!(Rates::parse(r#"{"rates": {"USD": 1}}"#).is_err());
+  }
+
+  #[test]
+  fn reads_csv_rates() {
+    let csv = Rates::parse("base,eur\ndate,2024-01-02\n\ncode,rate\nusd, 1.25\nJPY,160\n").unwrap();
+    assert_eq!(csv.base, "EUR");
+    assert_eq!(csv.timestamp, 1704153600);
+    assert_eq!(csv.in_base("USD"), Some(0.8));
+    assert_eq!(csv.rates.len(), 2);
+    assert!(Rates::parse("base,EUR\nUSD,lots").is_err());
+    assert!(Rates::parse("base,EUR\nUSD").is_err());
+    assert!(Rates::parse("USD,1.25").is_err());
+  }
+
+  #[test]
+  fn uses_the_later_of_json_and_csv_rates() {
+    let dir = TestDir::new("rates-csv");
+    fs::write(
+      dir.join(RATES_CSV_FILE),
+      "base,EUR\ntimestamp,1700000000\nUSD,1.25",
+    )
+    .unwrap();
+    assert_eq!(
+      RateStore::new(&dir).rates().unwrap().in_base("usd"),
+      Some(0.8)
+    );
+
+    let json = r#"{"base": "EUR", "timestamp": 1600000000, "rates": {"USD": 2}}"#;
+    fs::write(dir.join(RATES_FILE), json).unwrap();
+    assert_eq!(
+      RateStore::new(&dir).rates().unwrap().in_base("usd"),
+      Some(0.8)
+    );
+    let json = r#"{"base": "EUR", "timestamp": 1800000000, "rates": {"USD": 2}}"#;
+    fs::write(dir.join(RATES_FILE), json).unwrap();
+    assert_eq!(
+      RateStore::new(&dir).rates().unwrap().in_base("usd"),
+      Some(0.5)
+    );
+  }
+
+  #[test]
+  fn refreshes_from_the_endpoint() {
+    let dir = TestDir::new("rates-refresh");
+    let store = RateStore::new(&dir);
+    assert_eq!(store.rates(), None);
+
+    let url = serve_once(
+      "200 OK",
+      r#"{"base": "EUR", "timestamp": 1700000000, "rates": {"USD": 1.25}}"#,
+    );
+    store.refresh(&url).unwrap();
+    let rates = store.rates().unwrap();
+    assert_eq!(rates.base, "EUR");
+    assert_eq!(rates.in_base("usd"), Some(0.8));
+
+    // A failed fetch leaves the rates there were alone
+    let url = serve_once("500 Internal Server Error", "{}");
+    assert!(store.refresh(&url).is_err());
+    assert_eq!(store.rates(), Some(rates));
+  }
+}
@@ -0,0 +1,313 @@
+use super::{dates, program, units};
+use std::collections::HashMap;
+
+/// The last result, once there is one
+const ANS: &str = "ans";
+
+/// A line typed into the calculator
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+  /// Worked out and nothing more
+  Expr(String),
+  /// `x = 3`, keeping the result as `x`
+  Assign { name: String, expr: String },
+  /// `f(x, y) = x * y`, which can be called like the built in functions
+  Define {
+    name: String,
+    params: Vec<String>,
+    body: String,
+  },
+}
+
+impl Statement {
+  pub fn parse(input: &str) -> Result<Self, String> {
+    let Some((lhs, rhs)) = input.split_once('=') else {
+      return Ok(Statement::Expr(input.to_string()));
+    };
+    let (lhs, rhs) = (lhs.trim(), rhs.trim());
+    if rhs.is_empty() {
+      return Err("Nothing to assign".into());
+    }
+    if is_name(lhs) {
+      check_name(lhs)?;
+      return Ok(Statement::Assign {
+        name: lhs.to_string(),
+        expr: rhs.to_string(),
+      });
+    }
+    let (name, params) = lhs
+      .strip_suffix(')')
+      .and_then(|call| call.split_once('('))
+      .ok_or_else(|| format!("Can't assign to {}", lhs))?;
+    let name = name.trim();
+    let params: Vec<_> = params
+      .split(',')
+      .map(|p| p.trim().to_string())
+      .filter(|p| !p.is_empty())
+      .collect();
+    if !is_name(name) || !params.iter().all(|p| is_name(p)) {
+      return Err(format!("Can't assign to {}", lhs));
+    }
+    Ok(Statement::Define {
+      name: name.to_string(),
+      params,
+      body: rhs.to_string(),
+    })
+  }
+
+  /// What's worked out for the statement. Defining a function works nothing out
+  pub fn expr(&self) -> Option<&str> {
+    match self {
+      Statement::Expr(expr) | Statement::Assign { expr, .. } => Some(expr),
+      Statement::Define { .. } => None,
+    }
+  }
+}
+
+/// What's been worked out so far, so later calculations can build on it. Lasts as long as
+/// the app does.
+#[derive(Debug, Default)]
+pub struct Session {
+  vars: HashMap<String, String>,
+  funcs: HashMap<String, Func>,
+  ans: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Func {
+  params: Vec<String>,
+  body: String,
+}
+
+impl Session {
+  /// The expression with variables, `ans` and functions filled in, ready to be worked out.
+  /// Names that aren't known are left for the calculator to make sense of
+  pub fn expand(&self, expr: &str) -> Result<String, String> {
+    self.expand_in(expr, &HashMap::new(), &[])
+  }
+
+  /// Keeps what the statement assigns, with its result becoming `ans`
+  pub fn commit(&mut self, statement: &Statement, result: Option<&str>) {
+    if let Some(result) = result {
+      self.ans = Some(result.to_string());
+    }
+    match statement {
+      Statement::Assign { name, .. } => {
+        if let Some(result) = result {
+          self.vars.insert(name.clone(), result.to_string());
+        }
+      }
+      Statement::Define { name, params, body } => {
+        let func = Func {
+          params: params.clone(),
+          body: body.clone(),
+        };
+        self.funcs.insert(name.clone(), func);
+      }
+      Statement::Expr(_) => {}
+    }
+  }
+
+  fn expand_in(
+    &self,
+    expr: &str,
+    locals: &HashMap<&str, String>,
+    calling: &[&str],
+  ) -> Result<String, String> {
+    let mut out = String::with_capacity(expr.len());
+    let mut rest = expr;
+    while let Some(start) = rest.find(is_name_start) {
+      // Letters straight after a number are part of it, like `0x1F` or `1e5`
+      let in_number = rest[..start]
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || c == '.');
+      let len = rest[start..]
+        .find(|c: char| !is_name_char(c))
+        .unwrap_or(rest.len() - start);
+      let (before, name, after) = (
+        &rest[..start],
+        &rest[start..start + len],
+        &rest[start + len..],
+      );
+      out.push_str(before);
+      rest = after;
+
+      if in_number {
+        out.push_str(name);
+      } else if let Some(value) = locals.get(name) {
+        out.push_str(value);
+      } else if let Some((func, (args, after))) = self.funcs.get(name).zip(call_args(after)) {
+        // Calls are expanded in place, so one calling itself would never end
+        if calling.contains(&name) {
+          return Err(format!("{} can't call itself", name));
+        }
+        if args.len() != func.params.len() {
+          return Err(format!(
+            "{} takes {} args, not {}",
+            name,
+            func.params.len(),
+            args.len()
+          ));
+        }
+        let mut bound = HashMap::new();
+        for (param, arg) in func.params.iter().zip(args) {
+          let arg = self.expand_in(arg, locals, calling)?;
+          bound.insert(param.as_str(), format!("({})", arg));
+        }
+        let calling = [calling, &[name]].concat();
+        out.push_str(&format!(
+          "({})",
+          self.expand_in(&func.body, &bound, &calling)?
+        ));
+        rest = after;
+      } else if let Some(value) = self.vars.get(name) {
+        out.push_str(&format!("({})", value));
+      } else if name == ANS {
+        let ans = self
+          .ans
+          .as_ref()
+          .ok_or("Nothing's been worked out for ans yet")?;
+        out.push_str(&format!("({})", ans));
+      } else {
+        out.push_str(name);
+      }
+    }
+    out.push_str(rest);
+    Ok(out)
+  }
+}
+
+/// Splits off the args of a call like `(1, (2 + 3))`, giving them back along with what's
+/// after the closing bracket
+fn call_args(input: &str) -> Option<(Vec<&str>, &str)> {
+  let inner = input.trim_start().strip_prefix('(')?;
+  let (mut depth, mut start, mut args) = (0, 0, Vec::new());
+  for (idx, c) in inner.char_indices() {
+    match c {
+      '(' => depth += 1,
+      ',' if depth == 0 => {
+        args.push(inner[start..idx].trim());
+        start = idx + 1;
+      }
+      ')' if depth == 0 => {
+        let last = inner[start..idx].trim();
+        if !(args.is_empty() && last.is_empty()) {
+          args.push(last);
+        }
+        return Some((args, &inner[idx + 1..]));
+      }
+      ')' => depth -= 1,
+      _ => {}
+    }
+  }
+  None
+}
+
+fn is_name_start(c: char) -> bool {
+  c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+fn is_name(s: &str) -> bool {
+  s.starts_with(is_name_start) && s.chars().all(is_name_char)
+}
+
+/// Variables can't reuse names that already mean something, or expressions would quietly
+/// change meaning. Functions are only ever called, so they don't get in the way
+fn check_name(name: &str) -> Result<(), String> {
+  match () {
+    _ if name == ANS => Err("ans is always the last result".into()),
+    _ if units::is_unit(name) => Err(format!("Can't use {} as a name, it's a unit", name)),
+    _ if program::is_target(name) => Err(format!(
+      "Can't use {} as a name, it picks a base or width",
+      name
+    )),
+    _ if dates::is_keyword(name) => Err(format!(
+      "Can't use {} as a name, it's a date or time zone",
+      name
+    )),
+    _ => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn session_of(lines: &[(&str, &str)]) -> Session {
+    let mut session = Session::default();
+    for (line, result) in lines {
+      let result = Some(*result).filter(|r| !r.is_empty());
+      session.commit(&Statement::parse(line).unwrap(), result);
+    }
+    session
+  }
+
+  #[test]
+  fn parses_statements() {
+    assert_eq!(
+      Statement::parse("1 + 2"),
+      Ok(Statement::Expr("1 + 2".into()))
+    );
+    assert_eq!(
+      Statement::parse(" x = 3 * 2"),
+      Ok(Statement::Assign {
+        name: "x".into(),
+        expr: "3 * 2".into()
+      })
+    );
+    assert_eq!(
+      Statement::parse("area(w, h) = w * h"),
+      Ok(Statement::Define {
+        name: "area".into(),
+        params: vec!["w".into(), "h".into()],
+        body: "w * h".into()
+      })
+    );
+    assert_eq!(Statement::parse("1 = 2"), Err("Can't assign to 1".into()));
+    assert_eq!(
+      Statement::parse("km = 2"),
+      Err("Can't use km as a name, it's a unit".into())
+    );
+    assert!(Statement::parse("ans = 2").is_err());
+    assert!(Statement::parse("today = 2").is_err());
+    assert!(Statement::parse("x =").is_err());
+  }
+
+  #[test]
+  fn fills_in_variables_and_ans() {
+    let session = session_of(&[("x = 3", "3"), ("x * 2", "6")]);
+    assert_eq!(session.expand("x + ans"), Ok("(3) + (6)".into()));
+    assert_eq!(
+      session.expand("xs + 0x1F + 2e5"),
+      Ok("xs + 0x1F + 2e5".into())
+    );
+    assert!(Session::default().expand("ans").is_err());
+  }
+
+  #[test]
+  fn calls_functions() {
+    let session = session_of(&[
+      ("x = 10", "10"),
+      ("sq(x) = x * x", ""),
+      ("hyp(a, b) = sqrt(sq(a) + sq(b))", ""),
+    ]);
+    assert_eq!(session.expand("sq(2) + x"), Ok("((2) * (2)) + (10)".into()));
+    assert_eq!(
+      session.expand("hyp(3, (1 + 3))"),
+      Ok("(sqrt((((3)) * ((3))) + ((((1 + 3))) * (((1 + 3))))))".into())
+    );
+    assert_eq!(
+      session.expand("sq(1, 2)"),
+      Err("sq takes 1 args, not 2".into())
+    );
+    assert_eq!(session.expand("sq + 1"), Ok("sq + 1".into()));
+
+    let forever = session_of(&[("f(n) = g(n) + 1", ""), ("g(n) = f(n) * 2", "")]);
+    assert_eq!(forever.expand("f(1)"), Err("f can't call itself".into()));
+  }
+}
@@ -0,0 +1,520 @@
+use super::plot;
+use super::syntax::{self, Comma, Grammar, Ops, Parser, Syntax, Token};
+use std::ops::Range;
+
+/// Functions of a whole list of numbers, or of numbers written straight in like `sum(1, 2)`
+const AGGREGATES: [&str; 10] = [
+  "sum",
+  "mean",
+  "avg",
+  "average",
+  "median",
+  "stdev",
+  "min",
+  "max",
+  "count",
+  "percentile",
+];
+/// What a list on its own is worked out as, each offered up in turn
+const SUMMARY: [&str; 7] = ["sum", "mean", "median", "min", "max", "stdev", "count"];
+
+/// A mistake, along with where it is in the input so it can be pointed out
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mistake {
+  pub message: String,
+  pub span: Range<usize>,
+}
+
+fn mistake(message: impl Into<String>, span: Range<usize>) -> Mistake {
+  Mistake {
+    message: message.into(),
+    span,
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stats {
+  Value(f64),
+  /// What a list works out as, like its sum and mean
+  Summary(Vec<(&'static str, f64)>),
+}
+
+const SYNTAX: Syntax = Syntax::new(&[
+  "**", "+", "-", "*", "/", "%", "^", "(", ")", "[", "]", ",", "\n", "\r", "\t",
+]);
+/// Between pasted lines or spreadsheet cells
+const SEPS: [&str; 3] = ["\n", "\r", "\t"];
+
+/// Works out lists and percentages, like `mean([2, 4, 9])`, `15% of 240`, `200 + 8%` or a
+/// column of numbers pasted from a spreadsheet. Expressions with none of those are left
+/// alone. Where the decimal separator is a comma, `1,5` is a number rather than a list, and
+/// otherwise commas can group thousands like `1,234`.
+pub fn evaluate(input: &str, decimal_separator: &str) -> Option<Result<Stats, Mistake>> {
+  let comma = match decimal_separator {
+    "," => Comma::Point,
+    _ => Comma::Thousands,
+  };
+  let syntax = Syntax { comma, ..SYNTAX };
+  let mut tokens = syntax::lex(input, &syntax).ok()?;
+  // Blank lines around what's pasted don't make it a list
+  while tokens.last().is_some_and(|(token, _)| is_sep(*token)) {
+    tokens.pop();
+  }
+  let blank = tokens
+    .iter()
+    .take_while(|(token, _)| is_sep(*token))
+    .count();
+  tokens.drain(..blank);
+  if !is_wanted(&tokens) {
+    return None;
+  }
+  let mut parser = Parser::new(&tokens, Lists { syntax });
+  Some(parse(&mut parser, input.len()))
+}
+
+/// Whether there's a list or percentage in there, rather than plain arithmetic. Numbers with
+/// commas in them are too, as nothing else reads them
+fn is_wanted(tokens: &[syntax::Spanned]) -> bool {
+  let mut depth = 0;
+  for (idx, (token, _)) in tokens.iter().enumerate() {
+    match *token {
+      Token::Op("[") => return true,
+      Token::Num(n) if n.contains(',') => return true,
+      Token::Op(",") if depth == 0 => return true,
+      Token::Op("%") if is_percent(&tokens[idx..]) => return true,
+      token if is_sep(token) => return true,
+      Token::Name(name)
+        if AGGREGATES.contains(&name.to_lowercase().as_str())
+          && matches!(tokens.get(idx + 1), Some((Token::Op("("), _))) =>
+      {
+        return true
+      }
+      Token::Op("(") => depth += 1,
+      Token::Op(")") => depth -= 1,
+      _ => {}
+    }
+  }
+  false
+}
+
+fn is_sep(token: Token) -> bool {
+  matches!(token, Token::Op(op) if SEPS.contains(&op))
+}
+
+/// Whether the tokens start with a `%` after a number, as opposed to one between numbers
+/// finding the remainder like `7 % 3`
+fn is_percent(tokens: &[syntax::Spanned]) -> bool {
+  match tokens {
+    [(Token::Op("%"), _), (Token::Name(name), _), ..] => name.eq_ignore_ascii_case("of"),
+    [(Token::Op("%"), _), (Token::Num(_) | Token::Op("("), _), ..] => false,
+    [(Token::Op("%"), _), ..] => true,
+    _ => false,
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+  Num(f64),
+  /// As a fraction, so `8%` is `0.08`
+  Percent(f64),
+  List(Vec<f64>),
+}
+
+impl Value {
+  fn num(&self, span: &Range<usize>) -> Result<f64, Mistake> {
+    match self {
+      Value::Num(n) | Value::Percent(n) => Ok(*n),
+      Value::List(_) => Err(mistake(
+        "Lists only go in functions like sum or mean",
+        span.clone(),
+      )),
+    }
+  }
+
+  fn values(self) -> Vec<f64> {
+    match self {
+      Value::Num(n) | Value::Percent(n) => vec![n],
+      Value::List(values) => values,
+    }
+  }
+}
+
+type Spanned = (Value, Range<usize>);
+
+fn parse(parser: &mut Parser<'_, Lists>, len: usize) -> Result<Stats, Mistake> {
+  let mut items = items(parser)?;
+  if let Some((token, span)) = parser.read() {
+    return Err(mistake(format!("Unexpected {}", describe(token)), span));
+  }
+  match items.len() {
+    0 => Err(mistake("Nothing to work out", 0..len)),
+    1 => match items.remove(0) {
+      (Value::List(values), _) => Ok(summary(&values)),
+      (value, span) => Ok(Stats::Value(value.num(&span)?)),
+    },
+    _ => {
+      let values = items.into_iter().flat_map(|(value, _)| value.values());
+      Ok(summary(&values.collect::<Vec<_>>()))
+    }
+  }
+}
+
+/// Expressions separated by commas or pasted onto lines of their own
+fn items(parser: &mut Parser<'_, Lists>) -> Result<Vec<Spanned>, Mistake> {
+  let mut items = Vec::new();
+  while let Some(token) = parser.peek() {
+    match token {
+      token if is_sep(token) => {
+        parser.read();
+      }
+      Token::Op(")" | "]") => break,
+      _ => {
+        items.push(parser.expr()?);
+        match parser.peek() {
+          Some(token) if token == Token::Op(",") || is_sep(token) => {
+            parser.read();
+          }
+          _ => break,
+        }
+      }
+    }
+  }
+  Ok(items)
+}
+
+/// A number with a `%` after it, which can be of something, like `15% of 240`
+fn percent(
+  parser: &mut Parser<'_, Lists>,
+  value: Value,
+  span: Range<usize>,
+) -> Result<Spanned, Mistake> {
+  let found = match is_percent(parser.rest()) {
+    true => parser.eat(&["%"]),
+    false => None,
+  };
+  let Some((_, percent_span)) = found else {
+    return Ok((value, span));
+  };
+  let span = span.start..percent_span.end;
+  let fraction = value.num(&span)? / 100.0;
+  if !matches!(parser.peek(), Some(Token::Name(name)) if name.eq_ignore_ascii_case("of")) {
+    return Ok((Value::Percent(fraction), span));
+  }
+  parser.read();
+  let (of, of_span) = parser.unary()?;
+  let value = Value::Num(fraction * of.num(&of_span)?);
+  Ok((value, span.start..of_span.end))
+}
+
+/// Works out lists, percentages and the numbers in them
+struct Lists {
+  /// What the numbers were lexed with, so their commas are read the same way
+  syntax: Syntax,
+}
+
+impl<'a> Grammar<'a> for Lists {
+  type Value = Spanned;
+  type Error = Mistake;
+
+  const LEVELS: &'static [Ops] = &[&["+", "-"], &["*", "/", "%"]];
+  const PREFIX: Ops = &["-", "+"];
+  const POWER: Ops = &["^", "**"];
+
+  fn binary(
+    &mut self,
+    op: &'static str,
+    at: Range<usize>,
+    (lhs, span): Spanned,
+    (rhs, rhs_span): Spanned,
+  ) -> Result<Spanned, Mistake> {
+    let lhs = lhs.num(&span)?;
+    let value = match (op, rhs) {
+      // A percentage added on is a percentage of what it's added to
+      ("+", Value::Percent(p)) => lhs * (1.0 + p),
+      ("-", Value::Percent(p)) => lhs * (1.0 - p),
+      (op, rhs) => {
+        let rhs = rhs.num(&rhs_span)?;
+        match op {
+          "+" => lhs + rhs,
+          "-" => lhs - rhs,
+          "*" => lhs * rhs,
+          "^" | "**" => lhs.powf(rhs),
+          _ if rhs == 0.0 => {
+            return Err(mistake("Division by zero", at.start..rhs_span.end));
+          }
+          "/" => lhs / rhs,
+          _ => lhs % rhs,
+        }
+      }
+    };
+    Ok((Value::Num(value), span.start..rhs_span.end))
+  }
+
+  fn prefix(
+    &mut self,
+    op: &'static str,
+    at: Range<usize>,
+    (value, span): Spanned,
+  ) -> Result<Spanned, Mistake> {
+    if op == "+" {
+      return Ok((value, span));
+    }
+    let span = at.start..span.end;
+    let value = match value {
+      Value::Percent(p) => Value::Percent(-p),
+      value => Value::Num(-value.num(&span)?),
+    };
+    Ok((value, span))
+  }
+
+  fn atom(
+    parser: &mut Parser<'a, Self>,
+    token: Token<'a>,
+    span: Range<usize>,
+  ) -> Result<Spanned, Mistake> {
+    let (value, span) = match token {
+      Token::Num(literal) => {
+        let n = (parser.grammar.syntax)
+          .decimal(literal)
+          .map_err(|message| mistake(message, span.clone()))?;
+        (Value::Num(n), span)
+      }
+      Token::Op(open @ ("(" | "[")) => {
+        let close = if open == "(" { ")" } else { "]" };
+        let items = items(parser)?;
+        let (_, close_span) = parser
+          .eat(&[close])
+          .ok_or_else(|| mistake(format!("Missing {}", close), parser.here()))?;
+        let span = span.start..close_span.end;
+        let value = match (open, items.len()) {
+          ("(", 1) => items.into_iter().next().map(|(value, _)| value),
+          ("(", _) => None,
+          _ => Some(Value::List(
+            items
+              .into_iter()
+              .flat_map(|(value, _)| value.values())
+              .collect(),
+          )),
+        };
+        let value =
+          value.ok_or_else(|| mistake("Brackets need one thing in them", span.clone()))?;
+        (value, span)
+      }
+      Token::Name(name) => {
+        let name = name.to_lowercase();
+        if let Some(value) = plot::constant(&name) {
+          return percent(parser, Value::Num(value), span);
+        }
+        let known = AGGREGATES.contains(&name.as_str()) || plot::function(&name).is_some();
+        if !known {
+          return Err(mistake(format!("Unknown {}", name), span));
+        }
+        parser
+          .eat(&["("])
+          .ok_or_else(|| mistake(format!("{} needs brackets", name), span.clone()))?;
+        let args = items(parser)?;
+        let (_, close_span) = parser
+          .eat(&[")"])
+          .ok_or_else(|| mistake("Missing )", parser.here()))?;
+        call(&name, args, span.start..close_span.end)?
+      }
+      token => {
+        return Err(mistake(format!("Unexpected {}", describe(token)), span));
+      }
+    };
+    percent(parser, value, span)
+  }
+
+  fn mistake(&self, message: String, at: Range<usize>) -> Mistake {
+    mistake(message, at)
+  }
+}
+
+fn describe(token: Token) -> String {
+  match is_sep(token) {
+    true => "new line".into(),
+    false => token.to_string(),
+  }
+}
+
+fn call(name: &str, mut args: Vec<Spanned>, span: Range<usize>) -> Result<Spanned, Mistake> {
+  if let Some(func) = plot::function(name) {
+    return match &args[..] {
+      [(value, arg_span)] => Ok((Value::Num(func(value.num(arg_span)?)), span)),
+      _ => Err(mistake(format!("{} takes one number", name), span)),
+    };
+  }
+  let mut at = None;
+  if name == "percentile" {
+    let (p, p_span) = args.pop().ok_or_else(|| {
+      mistake(
+        "percentile needs numbers then which percentile",
+        span.clone(),
+      )
+    })?;
+    let p = match p {
+      Value::Percent(p) => p * 100.0,
+      p => p.num(&p_span)?,
+    };
+    if !(0.0..=100.0).contains(&p) {
+      return Err(mistake("Percentiles go from 0 to 100", p_span));
+    }
+    at = Some(p);
+  }
+  let values: Vec<_> = args
+    .into_iter()
+    .flat_map(|(value, _)| value.values())
+    .collect();
+  let value = match at {
+    Some(p) if !values.is_empty() => Ok(percentile(&values, p)),
+    Some(_) => Err("percentile of nothing".into()),
+    None => aggregate(name, &values),
+  };
+  value
+    .map(|value| (Value::Num(value), span.clone()))
+    .map_err(|message| mistake(message, span))
+}
+
+fn aggregate(name: &str, values: &[f64]) -> Result<f64, String> {
+  let n = values.len() as f64;
+  match name {
+    "sum" => return Ok(values.iter().sum()),
+    "count" => return Ok(n),
+    _ if values.is_empty() => return Err(format!("{} of nothing", name)),
+    _ => {}
+  }
+  let mean = values.iter().sum::<f64>() / n;
+  Ok(match name {
+    "mean" | "avg" | "average" => mean,
+    "median" => percentile(values, 50.0),
+    "min" => values.iter().copied().fold(f64::INFINITY, f64::min),
+    "max" => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    // Of a sample, like spreadsheets' STDEV
+    "stdev" if values.len() > 1 => {
+      let squares: f64 = values.iter().map(|v| (v - mean).powi(2)).sum();
+      (squares / (n - 1.0)).sqrt()
+    }
+    "stdev" => return Err("stdev needs at least two numbers".into()),
+    _ => return Err(format!("Unknown {}", name)),
+  })
+}
+
+/// Going between the closest two when it falls between them, like spreadsheets'
+/// PERCENTILE
+fn percentile(values: &[f64], p: f64) -> f64 {
+  let mut sorted = values.to_vec();
+  sorted.sort_by(f64::total_cmp);
+  let rank = p / 100.0 * (sorted.len() - 1) as f64;
+  let (low, high) = (sorted[rank.floor() as usize], sorted[rank.ceil() as usize]);
+  low + (high - low) * rank.fract()
+}
+
+fn summary(values: &[f64]) -> Stats {
+  let stats = SUMMARY.into_iter().filter_map(|name| {
+    let value = aggregate(name, values).ok()?;
+    Some((name, value))
+  });
+  Stats::Summary(stats.collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn value(input: &str) -> Result<f64, Mistake> {
+    match evaluate(input, ".").expect("Should have been for stats")? {
+      Stats::Value(value) => Ok(value),
+      Stats::Summary(_) => panic!("Should have been one value"),
+    }
+  }
+
+  fn summary(input: &str) -> Vec<(&'static str, f64)> {
+    match evaluate(input, ".")
+      .expect("Should have been for stats")
+      .unwrap()
+    {
+      Stats::Summary(stats) => stats,
+      Stats::Value(_) => panic!("Should have been a summary"),
+    }
+  }
+
+  #[test]
+  fn works_out_aggregates() {
+    assert_eq!(value("sum([1, 2, 3]) * 2"), Ok(12.0));
+    assert_eq!(value("mean(2, [4, 9])"), Ok(5.0));
+    assert_eq!(value("median([5, 1, 3, 2])"), Ok(2.5));
+    assert_eq!(value("MAX(1, 7, 3) - min([4, 2])"), Ok(5.0));
+    assert_eq!(value("percentile([1, 2, 3, 4, 5], 25)"), Ok(2.0));
+    assert_eq!(value("percentile([10, 20], 50%)"), Ok(15.0));
+    assert_eq!(
+      value("stdev([2, 4, 4, 4, 5, 5, 7, 9])"),
+      Ok((32.0f64 / 7.0).sqrt())
+    );
+    assert_eq!(value("sum(sqrt(16), pi - pi)"), Ok(4.0));
+  }
+
+  #[test]
+  fn works_out_percentages() {
+    assert_eq!(value("15% of 240"), Ok(36.0));
+    assert_eq!(value("200 + 8%"), Ok(216.0));
+    assert_eq!(value("200 - 25%"), Ok(150.0));
+    assert_eq!(value("50 * 10%"), Ok(5.0));
+    assert_eq!(value("(10 + 5)% of (200 + 40)"), Ok(36.0));
+    assert_eq!(evaluate("7 % 3", "."), None);
+    assert_eq!(evaluate("1 + 2", "."), None);
+  }
+
+  #[test]
+  fn summarises_pasted_lists() {
+    let stats = summary("3\r\n1\n2\n");
+    assert_eq!(
+      stats,
+      vec![
+        ("sum", 6.0),
+        ("mean", 2.0),
+        ("median", 2.0),
+        ("min", 1.0),
+        ("max", 3.0),
+        ("stdev", 1.0),
+        ("count", 3.0),
+      ]
+    );
+    assert_eq!(summary("4, 8")[1], ("mean", 6.0));
+    assert_eq!(
+      summary("[5]"),
+      vec![
+        ("sum", 5.0),
+        ("mean", 5.0),
+        ("median", 5.0),
+        ("min", 5.0),
+        ("max", 5.0),
+        ("count", 1.0),
+      ]
+    );
+  }
+
+  #[test]
+  fn reads_commas_in_numbers() {
+    assert_eq!(value("1,234"), Ok(1234.0));
+    assert_eq!(value("1,234,567.5 * 2"), Ok(2469135.0));
+    assert_eq!(summary("1,234\n5,678")[0], ("sum", 6912.0));
+    assert_eq!(summary("12,34")[0], ("sum", 46.0));
+    assert_eq!(summary("1,2345")[0], ("sum", 2346.0));
+    let comma = |input| evaluate(input, ",").unwrap();
+    assert_eq!(comma("1,5"), Ok(Stats::Value(1.5)));
+    assert_eq!(comma("0,5 + 1.5"), Ok(Stats::Value(2.0)));
+    assert!(matches!(comma("0,5\n1,5"), Ok(Stats::Summary(stats)) if stats[0] == ("sum", 2.0)));
+    assert!(matches!(comma("4, 8"), Ok(Stats::Summary(stats)) if stats[1] == ("mean", 6.0)));
+  }
+
+  #[test]
+  fn points_out_where_mistakes_are() {
+    let at = |input: &str| value(input).unwrap_err().span;
+    assert_eq!(at("1\n2\nthree"), 4..9);
+    assert_eq!(at("sum([1, 2]"), 10..10);
+    assert_eq!(at("mean([])"), 0..8);
+    assert_eq!(at("percentile([1, 2], 101)"), 19..22);
+    assert_eq!(at("[1, 2] + 3"), 0..6);
+    assert_eq!(at("avg(1, 2) / (3 - 3)"), 10..19);
+  }
+}
@@ -0,0 +1,413 @@
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+/// A piece of an expression as it was written. What numbers and names mean is up to what's
+/// working the expression out, so they're kept as text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token<'a> {
+  /// Like `12.5`, `1e-9`, `1_000` or `0xff`
+  Num(&'a str),
+  Name(&'a str),
+  Op(&'static str),
+}
+
+impl Display for Token<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Token::Num(text) | Token::Name(text) => f.write_str(text),
+      Token::Op(op) => f.write_str(op),
+    }
+  }
+}
+
+/// A token along with where it is in the input
+pub type Spanned<'a> = (Token<'a>, Range<usize>);
+
+pub type Ops = &'static [&'static str];
+
+/// What a `,` straight after a number's digits means
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comma {
+  /// Nothing, it's left to be an operator or a mistake
+  Apart,
+  /// It groups thousands, like `1,234`
+  Thousands,
+  /// It's the decimal point, like `1,5`, as well as `.`
+  Point,
+}
+
+/// What sets one evaluator's expressions apart from another's when lexing
+#[derive(Clone, Copy)]
+pub struct Syntax {
+  /// Longest first, so `**` isn't read as two `*`
+  pub ops: Ops,
+  /// What names are made of. Those starting with a digit are numbers instead
+  pub name: fn(char) -> bool,
+  pub comma: Comma,
+}
+
+impl Syntax {
+  pub const fn new(ops: Ops) -> Syntax {
+    Syntax {
+      ops,
+      name: is_name,
+      comma: Comma::Apart,
+    }
+  }
+
+  /// How long the number at the start is. Digits can be split up with `_`, and an exponent
+  /// like `1e-9` only counts as long as its digits follow rather than it being `e`. Ones in
+  /// hex, octal or binary like `0xff` run on over letters and digits, so they're read whole
+  /// even where they can't be worked with
+  pub fn number_len(&self, input: &str) -> usize {
+    let run = |s: &str, part: fn(char) -> bool| s.find(|c: char| !part(c)).unwrap_or(s.len());
+    let radix = input.get(..2).map(str::to_lowercase);
+    if matches!(radix.as_deref(), Some("0x" | "0o" | "0b"))
+      && input[2..].starts_with(|c: char| c.is_ascii_alphanumeric())
+    {
+      return 2 + run(&input[2..], |c| c.is_ascii_alphanumeric() || c == '_');
+    }
+    let digits = |s: &str| run(s, |c| c.is_ascii_digit());
+    let mut len = digits(input);
+    let comma = |len: usize| input[len..].strip_prefix(',').map(digits);
+    match self.comma {
+      // Up to three digits then groups of three, like `1,234,567`. Digits running on past
+      // three, like `1,2345`, aren't a group
+      Comma::Thousands if (1..=3).contains(&len) => {
+        while comma(len) == Some(3) {
+          len += 4;
+        }
+      }
+      Comma::Point if len > 0 && comma(len).is_some_and(|count| count > 0) => len += 1,
+      _ => {}
+    }
+    len += run(&input[len..], |c| {
+      c.is_ascii_digit() || c == '.' || c == '_'
+    });
+    if let Some(exp) = input[len..].strip_prefix(['e', 'E']) {
+      let digits = exp.strip_prefix(['+', '-']).unwrap_or(exp);
+      let count = run(digits, |c| c.is_ascii_digit());
+      if count > 0 {
+        len += 1 + exp.len() - digits.len() + count;
+      }
+    }
+    len
+  }
+
+  /// How long the name at the start is
+  pub fn name_len(&self, input: &str) -> usize {
+    input.find(|c: char| !(self.name)(c)).unwrap_or(input.len())
+  }
+
+  /// Reads a decimal number for evaluators working in floats
+  pub fn decimal(&self, literal: &str) -> Result<f64, String> {
+    let digits = match self.comma {
+      Comma::Point => literal.replacen(',', ".", 1),
+      _ => literal.replace(',', ""),
+    };
+    digits
+      .replace('_', "")
+      .parse()
+      .map_err(|_| format!("Invalid number {}", literal))
+  }
+}
+
+pub fn is_name(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+/// Splits the input into numbers, names and the syntax's operators, skipping whitespace
+/// that isn't an operator itself
+pub fn lex<'a>(input: &'a str, syntax: &Syntax) -> Result<Vec<Spanned<'a>>, String> {
+  let mut tokens = Vec::new();
+  let mut pos = 0;
+  while let Some(c) = input[pos..].chars().next() {
+    let rest = &input[pos..];
+    let (token, len) = if let Some(op) = syntax.ops.iter().find(|op| rest.starts_with(**op)) {
+      (Some(Token::Op(op)), op.len())
+    } else if c.is_whitespace() {
+      (None, c.len_utf8())
+    } else if c.is_ascii_digit() || c == '.' {
+      let len = syntax.number_len(rest);
+      (Some(Token::Num(&rest[..len])), len)
+    } else if (syntax.name)(c) {
+      let len = syntax.name_len(rest);
+      (Some(Token::Name(&rest[..len])), len)
+    } else {
+      return Err(format!("Unexpected {}", c));
+    };
+    tokens.extend(token.map(|token| (token, pos..pos + len)));
+    pos += len;
+  }
+  Ok(tokens)
+}
+
+/// What an evaluator makes of an expression. How tightly operators bind is worked out the
+/// same way for every one of them, while what they do is up to each
+pub trait Grammar<'a>: Sized {
+  type Value;
+  type Error;
+
+  /// Binary operators at each level of precedence, loosest first, each going left to right.
+  /// An empty one is taken to be between things written one after another, like `2x`
+  const LEVELS: &'static [Ops];
+  /// Operators in front of what they're of, like `-`
+  const PREFIX: Ops;
+  /// Operators raising to a power. These go right to left, and before an operator in front
+  /// so `-2^2` is `-4`
+  const POWER: Ops;
+
+  fn binary(
+    &mut self,
+    op: &'static str,
+    at: Range<usize>,
+    lhs: Self::Value,
+    rhs: Self::Value,
+  ) -> Result<Self::Value, Self::Error>;
+
+  fn prefix(
+    &mut self,
+    op: &'static str,
+    at: Range<usize>,
+    value: Self::Value,
+  ) -> Result<Self::Value, Self::Error>;
+
+  /// What the token that's been read starts, like a number, a call or a bracketed expression,
+  /// along with anything written straight after it
+  fn atom(
+    parser: &mut Parser<'a, Self>,
+    token: Token<'a>,
+    at: Range<usize>,
+  ) -> Result<Self::Value, Self::Error>;
+
+  /// A mistake in how the expression is written
+  fn mistake(&self, message: String, at: Range<usize>) -> Self::Error;
+}
+
+/// Parses an expression by precedence climbing, working it out with the grammar as it goes
+pub struct Parser<'a, G> {
+  tokens: &'a [Spanned<'a>],
+  pos: usize,
+  pub grammar: G,
+}
+
+impl<'a, G: Grammar<'a>> Parser<'a, G> {
+  pub fn new(tokens: &'a [Spanned<'a>], grammar: G) -> Self {
+    Parser {
+      tokens,
+      pos: 0,
+      grammar,
+    }
+  }
+
+  pub fn peek(&self) -> Option<Token<'a>> {
+    self.tokens.get(self.pos).map(|(token, _)| *token)
+  }
+
+  /// The tokens not read yet
+  pub fn rest(&self) -> &'a [Spanned<'a>] {
+    &self.tokens[self.pos..]
+  }
+
+  /// Where the next token is, or just after the last one when there isn't one
+  pub fn here(&self) -> Range<usize> {
+    match self.tokens.get(self.pos) {
+      Some((_, span)) => span.clone(),
+      None => {
+        let end = self.tokens.last().map_or(0, |(_, span)| span.end);
+        end..end
+      }
+    }
+  }
+
+  /// The next token, moving past it
+  pub fn read(&mut self) -> Option<Spanned<'a>> {
+    let token = self.tokens.get(self.pos)?.clone();
+    self.pos += 1;
+    Some(token)
+  }
+
+  pub fn eat(&mut self, ops: &[&str]) -> Option<(&'static str, Range<usize>)> {
+    match self.tokens.get(self.pos) {
+      Some((Token::Op(op), span)) if ops.contains(op) => {
+        self.pos += 1;
+        Some((*op, span.clone()))
+      }
+      _ => None,
+    }
+  }
+
+  pub fn mistake(&self, message: impl Into<String>, at: Range<usize>) -> G::Error {
+    self.grammar.mistake(message.into(), at)
+  }
+
+  /// As much of an expression as there is
+  pub fn expr(&mut self) -> Result<G::Value, G::Error> {
+    self.binary(0)
+  }
+
+  /// The rest of an expression in brackets, once the `(` has been read
+  pub fn bracketed(&mut self) -> Result<G::Value, G::Error> {
+    let value = self.expr()?;
+    match self.eat(&[")"]) {
+      Some(_) => Ok(value),
+      None => Err(self.mistake("Missing )", self.here())),
+    }
+  }
+
+  fn binary(&mut self, level: usize) -> Result<G::Value, G::Error> {
+    let Some(&ops) = G::LEVELS.get(level) else {
+      return self.unary();
+    };
+    let mut value = self.binary(level + 1)?;
+    while let Some((op, at)) = self.operator(ops) {
+      let rhs = self.binary(level + 1)?;
+      value = self.grammar.binary(op, at, value, rhs)?;
+    }
+    Ok(value)
+  }
+
+  /// The next operator when it's one of these, or the empty one when something's written
+  /// straight after and it's there to be taken
+  fn operator(&mut self, ops: Ops) -> Option<(&'static str, Range<usize>)> {
+    let follows = matches!(
+      self.peek(),
+      Some(Token::Num(_) | Token::Name(_) | Token::Op("("))
+    );
+    match follows && ops.contains(&"") {
+      true => Some(("", self.here().start..self.here().start)),
+      false => self.eat(ops),
+    }
+  }
+
+  /// An operator in front of what it's of, or a power
+  pub fn unary(&mut self) -> Result<G::Value, G::Error> {
+    if let Some((op, at)) = self.eat(G::PREFIX) {
+      let value = self.unary()?;
+      return self.grammar.prefix(op, at, value);
+    }
+    let base = match self.read() {
+      Some((token, at)) => G::atom(self, token, at)?,
+      None => return Err(self.mistake("Unfinished expression", self.here())),
+    };
+    match self.eat(G::POWER) {
+      Some((op, at)) => {
+        let exp = self.unary()?;
+        self.grammar.binary(op, at, base, exp)
+      }
+      None => Ok(base),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lexed(input: &str) -> Vec<Token<'_>> {
+    let syntax = Syntax::new(&["**", "*", "-", "(", ")"]);
+    let tokens = lex(input, &syntax).unwrap();
+    tokens.into_iter().map(|(token, _)| token).collect()
+  }
+
+  /// Writes out where the brackets would go
+  struct Brackets;
+
+  impl<'a> Grammar<'a> for Brackets {
+    type Value = String;
+    type Error = String;
+
+    const LEVELS: &'static [Ops] = &[&["-"], &["*", ""]];
+    const PREFIX: Ops = &["-"];
+    const POWER: Ops = &["**"];
+
+    fn binary(
+      &mut self,
+      op: &'static str,
+      _: Range<usize>,
+      lhs: String,
+      rhs: String,
+    ) -> Result<String, String> {
+      Ok(format!("({} {} {})", lhs, op, rhs))
+    }
+
+    fn prefix(
+      &mut self,
+      op: &'static str,
+      _: Range<usize>,
+      value: String,
+    ) -> Result<String, String> {
+      Ok(format!("({}{})", op, value))
+    }
+
+    fn atom(
+      parser: &mut Parser<'a, Self>,
+      token: Token<'a>,
+      _: Range<usize>,
+    ) -> Result<String, String> {
+      match token {
+        Token::Op("(") => parser.bracketed(),
+        token => Ok(token.to_string()),
+      }
+    }
+
+    fn mistake(&self, message: String, at: Range<usize>) -> String {
+      format!("{} at {:?}", message, at)
+    }
+  }
+
+  fn parsed(input: &str) -> Result<String, String> {
+    let syntax = Syntax::new(&["**", "*", "-", "(", ")"]);
+    let tokens = lex(input, &syntax)?;
+    Parser::new(&tokens, Brackets).expr()
+  }
+
+  #[test]
+  fn reads_numbers_the_same_everywhere() {
+    assert_eq!(
+      lexed("1.5e-3x"),
+      vec![Token::Num("1.5e-3"), Token::Name("x")]
+    );
+    assert_eq!(lexed("2e"), vec![Token::Num("2"), Token::Name("e")]);
+    assert_eq!(
+      lexed("2e-x"),
+      vec![
+        Token::Num("2"),
+        Token::Name("e"),
+        Token::Op("-"),
+        Token::Name("x")
+      ]
+    );
+    assert_eq!(
+      lexed("1_000 0xFF_ff"),
+      vec![Token::Num("1_000"), Token::Num("0xFF_ff")]
+    );
+    assert_eq!(lexed("0x"), vec![Token::Num("0"), Token::Name("x")]);
+    let grouped = |comma| Syntax {
+      comma,
+      ..Syntax::new(&[","])
+    };
+    let thousands = grouped(Comma::Thousands);
+    let point = grouped(Comma::Point);
+    assert_eq!(thousands.number_len("1,234,567.5"), 11);
+    assert_eq!(thousands.number_len("1234,567"), 4);
+    assert_eq!(thousands.number_len("1,2345"), 1);
+    assert_eq!(thousands.decimal("1,234,567.5"), Ok(1234567.5));
+    assert_eq!(point.number_len("1,5e3,"), 5);
+    assert_eq!(point.number_len("1, 5"), 1);
+    assert_eq!(point.decimal("1,5"), Ok(1.5));
+    assert_eq!(Syntax::new(&[","]).number_len("1,234"), 1);
+    let decimal = |literal| Syntax::new(&[]).decimal(literal);
+    assert_eq!(decimal("1_000.5"), Ok(1000.5));
+    assert_eq!(decimal("0xff"), Err("Invalid number 0xff".into()));
+  }
+
+  #[test]
+  fn climbs_precedence() {
+    assert_eq!(parsed("1 - 2 - 3"), Ok("((1 - 2) - 3)".into()));
+    assert_eq!(parsed("-2**-x"), Ok("(-(2 ** (-x)))".into()));
+    assert_eq!(parsed("2x - 3(x)"), Ok("((2  x) - (3  x))".into()));
+    assert_eq!(parsed("(1 - "), Err("Unfinished expression at 4..4".into()));
+    assert_eq!(parsed("(1"), Err("Missing ) at 2..2".into()));
+  }
+}
@@ -0,0 +1,653 @@
+use super::rates::Rates;
+use super::syntax::{self, Grammar, Ops, Parser, Spanned, Syntax, Token};
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+/// How many significant digits results are shown with
+const SIGNIFICANT_DIGITS: i32 = 10;
+/// Words that split what's being converted from the unit to convert it to
+const CONVERSIONS: [&str; 3] = ["to", "in", "as"];
+
+/// Exponents of the base dimensions: length, mass, time, temperature, data and money
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Dim([i8; 6]);
+
+const NONE: Dim = Dim([0, 0, 0, 0, 0, 0]);
+const LENGTH: Dim = Dim([1, 0, 0, 0, 0, 0]);
+const MASS: Dim = Dim([0, 1, 0, 0, 0, 0]);
+const TIME: Dim = Dim([0, 0, 1, 0, 0, 0]);
+const TEMPERATURE: Dim = Dim([0, 0, 0, 1, 0, 0]);
+const DATA: Dim = Dim([0, 0, 0, 0, 1, 0]);
+const MONEY: Dim = Dim([0, 0, 0, 0, 0, 1]);
+const AREA: Dim = Dim([2, 0, 0, 0, 0, 0]);
+const VOLUME: Dim = Dim([3, 0, 0, 0, 0, 0]);
+const SPEED: Dim = Dim([1, 0, -1, 0, 0, 0]);
+
+const NAMED_DIMS: [(Dim, &str); 10] = [
+  (NONE, "a plain number"),
+  (LENGTH, "length"),
+  (MASS, "mass"),
+  (TIME, "time"),
+  (TEMPERATURE, "temperature"),
+  (DATA, "data size"),
+  (MONEY, "money"),
+  (AREA, "area"),
+  (VOLUME, "volume"),
+  (SPEED, "speed"),
+];
+/// What each base dimension is measured in, which is what everything's converted through.
+/// Money is in whatever currency the rates are against
+const BASE_UNITS: [&str; 6] = ["m", "kg", "s", "K", "B", "¤"];
+
+impl Dim {
+  fn combine(self, other: Dim, sign: i8) -> Result<Dim, String> {
+    let mut exps = self.0;
+    for (exp, o) in exps.iter_mut().zip(other.0) {
+      *exp = o
+        .checked_mul(sign)
+        .and_then(|o| exp.checked_add(o))
+        .ok_or_else(too_large)?;
+    }
+    Ok(Dim(exps))
+  }
+
+  fn pow(self, n: i8) -> Result<Dim, String> {
+    let mut exps = self.0;
+    for exp in exps.iter_mut() {
+      *exp = exp.checked_mul(n).ok_or_else(too_large)?;
+    }
+    Ok(Dim(exps))
+  }
+
+  /// The dimension written out in base units, like `m/s`
+  fn base_units(&self) -> String {
+    let part = |exp: i8, unit: &str| match exp {
+      1 => unit.to_string(),
+      _ => format!("{}^{}", unit, exp),
+    };
+    let over = |sign: i8| {
+      BASE_UNITS
+        .iter()
+        .zip(self.0)
+        .filter(|(_, exp)| exp.signum() == sign)
+        .map(|(unit, exp)| part(exp.abs(), unit))
+        .collect::<Vec<_>>()
+    };
+    match (over(1), over(-1)) {
+      (num, den) if den.is_empty() => num.join("·"),
+      (num, den) if num.is_empty() => format!("1/{}", den.join("·")),
+      (num, den) => format!("{}/{}", num.join("·"), den.join("·")),
+    }
+  }
+}
+
+fn too_large() -> String {
+  "Dimension exponent too large".into()
+}
+
+impl Display for Dim {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match NAMED_DIMS.iter().find(|(dim, _)| dim == self) {
+      Some((_, name)) => f.write_str(name),
+      None => f.write_str(&self.base_units()),
+    }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Unit {
+  names: &'static [&'static str],
+  dim: Dim,
+  /// How many of the base unit one of this is
+  factor: f64,
+  /// Added before scaling to the base unit, for scales that don't start at zero like °C
+  offset: f64,
+}
+
+const fn unit(names: &'static [&'static str], dim: Dim, factor: f64) -> Unit {
+  Unit {
+    names,
+    dim,
+    factor,
+    offset: 0.0,
+  }
+}
+
+#[rustfmt::skip]
+static UNITS: &[Unit] = &[
+  // Length
+  unit(&["m", "meter", "meters", "metre", "metres"], LENGTH, 1.0),
+  unit(&["km", "kilometer", "kilometers", "kilometre", "kilometres"], LENGTH, 1e3),
+  unit(&["cm", "centimeter", "centimeters", "centimetre", "centimetres"], LENGTH, 1e-2),
+  unit(&["mm", "millimeter", "millimeters", "millimetre", "millimetres"], LENGTH, 1e-3),
+  unit(&["um", "µm", "micrometer", "micrometers"], LENGTH, 1e-6),
+  unit(&["nm", "nanometer", "nanometers"], LENGTH, 1e-9),
+  unit(&["mi", "mile", "miles"], LENGTH, 1609.344),
+  unit(&["yd", "yard", "yards"], LENGTH, 0.9144),
+  unit(&["ft", "foot", "feet"], LENGTH, 0.3048),
+  unit(&["in", "inch", "inches"], LENGTH, 0.0254),
+  unit(&["nmi"], LENGTH, 1852.0),
+  // Mass
+  unit(&["kg", "kilogram", "kilograms"], MASS, 1.0),
+  unit(&["g", "gram", "grams"], MASS, 1e-3),
+  unit(&["mg", "milligram", "milligrams"], MASS, 1e-6),
+  unit(&["t", "tonne", "tonnes"], MASS, 1e3),
+  unit(&["lb", "lbs", "pound", "pounds"], MASS, 0.45359237),
+  unit(&["oz", "ounce", "ounces"], MASS, 0.028349523125),
+  unit(&["st", "stone", "stones"], MASS, 6.35029318),
+  // Volume
+  unit(&["l", "L", "liter", "liters", "litre", "litres"], VOLUME, 1e-3),
+  unit(&["ml", "mL", "milliliter", "milliliters", "millilitre", "millilitres"], VOLUME, 1e-6),
+  unit(&["cl", "cL"], VOLUME, 1e-5),
+  unit(&["dl", "dL"], VOLUME, 1e-4),
+  unit(&["gal", "gallon", "gallons"], VOLUME, 3.785411784e-3),
+  unit(&["qt", "quart", "quarts"], VOLUME, 9.46352946e-4),
+  unit(&["pt", "pint", "pints"], VOLUME, 4.73176473e-4),
+  unit(&["cup", "cups"], VOLUME, 2.365882365e-4),
+  unit(&["floz"], VOLUME, 2.95735295625e-5),
+  unit(&["tbsp"], VOLUME, 1.478676478125e-5),
+  unit(&["tsp"], VOLUME, 4.92892159375e-6),
+  // Temperature
+  unit(&["K", "kelvin"], TEMPERATURE, 1.0),
+  Unit { names: &["C", "°C", "degC", "celsius"], dim: TEMPERATURE, factor: 1.0, offset: 273.15 },
+  Unit { names: &["F", "°F", "degF", "fahrenheit"], dim: TEMPERATURE, factor: 5.0 / 9.0, offset: 459.67 },
+  // Time
+  unit(&["s", "sec", "secs", "second", "seconds"], TIME, 1.0),
+  unit(&["ms", "millisecond", "milliseconds"], TIME, 1e-3),
+  unit(&["us", "µs", "microsecond", "microseconds"], TIME, 1e-6),
+  unit(&["ns", "nanosecond", "nanoseconds"], TIME, 1e-9),
+  unit(&["min", "mins", "minute", "minutes"], TIME, 60.0),
+  unit(&["h", "hr", "hrs", "hour", "hours"], TIME, 3600.0),
+  unit(&["d", "day", "days"], TIME, 86400.0),
+  unit(&["wk", "week", "weeks"], TIME, 604800.0),
+  unit(&["yr", "year", "years"], TIME, 31557600.0),
+  // Speed
+  unit(&["mph"], SPEED, 0.44704),
+  unit(&["kph", "kmh"], SPEED, 1e3 / 3600.0),
+  unit(&["kn", "knot", "knots"], SPEED, 1852.0 / 3600.0),
+  // Area
+  unit(&["ha", "hectare", "hectares"], AREA, 1e4),
+  unit(&["acre", "acres"], AREA, 4046.8564224),
+  unit(&["sqft"], AREA, 0.09290304),
+  unit(&["sqmi"], AREA, 2589988.110336),
+  // Data, in bytes. Decimal and binary prefixes are both used, so both are here as written
+  unit(&["b", "bit", "bits"], DATA, 0.125),
+  unit(&["B", "byte", "bytes"], DATA, 1.0),
+  unit(&["kB", "KB"], DATA, 1e3),
+  unit(&["MB"], DATA, 1e6),
+  unit(&["GB"], DATA, 1e9),
+  unit(&["TB"], DATA, 1e12),
+  unit(&["PB"], DATA, 1e15),
+  unit(&["KiB"], DATA, 1024.0),
+  unit(&["MiB"], DATA, 1048576.0),
+  unit(&["GiB"], DATA, 1073741824.0),
+  unit(&["TiB"], DATA, 1099511627776.0),
+  unit(&["PiB"], DATA, 1125899906842624.0),
+  unit(&["kb", "Kb"], DATA, 125.0),
+  unit(&["Mb"], DATA, 1.25e5),
+  unit(&["Gb"], DATA, 1.25e8),
+  unit(&["Tb"], DATA, 1.25e11),
+];
+
+/// Finds the unit by name. Case only matters when it tells units apart, like `MB` & `Mb`
+fn lookup(name: &str) -> Result<&'static Unit, String> {
+  if let Some(unit) = UNITS.iter().find(|u| u.names.contains(&name)) {
+    return Ok(unit);
+  }
+  let mut found = UNITS
+    .iter()
+    .filter(|u| u.names.iter().any(|n| n.eq_ignore_ascii_case(name)));
+  match (found.next(), found.next()) {
+    (Some(unit), None) => Ok(unit),
+    (Some(_), Some(_)) => Err(format!("Ambiguous unit {}, mind the case", name)),
+    (None, _) => Err(format!("Unknown unit {}", name)),
+  }
+}
+
+/// A currency by its code, like `usd` or `EUR`, when there are rates for it. Units are
+/// looked up first, so the odd code that's also a unit (`cup`) stays a unit
+fn currency(name: &str, rates: Option<&Rates>) -> Option<Quantity> {
+  let is_code = name.len() == 3 && name.chars().all(|c| c.is_ascii_alphabetic());
+  let value = rates.filter(|_| is_code)?.in_base(name)?;
+  Some(Quantity {
+    value,
+    dim: MONEY,
+    offset_unit: None,
+  })
+}
+
+/// Whether the name means something here, as a unit or a word splitting off a conversion
+pub fn is_unit(name: &str) -> bool {
+  CONVERSIONS.contains(&name) || lookup(name).is_ok()
+}
+
+/// Whether the name is a currency with rates, rather than a unit that happens to share its code
+pub fn is_currency(name: &str, rates: Option<&Rates>) -> bool {
+  lookup(name).is_err() && currency(name, rates).is_some()
+}
+
+/// Names are made of letters alone, so `m2` is `m` times `2`, and can have a `°` in them
+const SYNTAX: Syntax = Syntax {
+  ops: &["+", "-", "*", "/", "^", "(", ")", "·", "×", "²", "³"],
+  name: |c| c.is_alphabetic() || c == '°',
+  ..Syntax::new(&[])
+};
+
+fn lex(input: &str) -> Result<Vec<Spanned<'_>>, String> {
+  let tokens = syntax::lex(input, &SYNTAX)?;
+  let radix = |literal: &str| literal[1..].starts_with(['x', 'b', 'o']);
+  match tokens
+    .iter()
+    .any(|(token, _)| matches!(token, Token::Num(n) if radix(n)))
+  {
+    true => Err("Only decimal numbers can have units".into()),
+    false => Ok(tokens),
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Quantity {
+  /// In base units
+  value: f64,
+  dim: Dim,
+  /// A unit like °C on its own. Those don't scale from zero, so they only make sense once
+  /// they've a number in front of them
+  offset_unit: Option<&'static Unit>,
+}
+
+impl Quantity {
+  fn number(value: f64) -> Self {
+    Quantity {
+      value,
+      dim: NONE,
+      offset_unit: None,
+    }
+  }
+
+  fn of(unit: &'static Unit) -> Self {
+    Quantity {
+      value: unit.factor,
+      dim: unit.dim,
+      offset_unit: Some(unit).filter(|u| u.offset != 0.0),
+    }
+  }
+
+  /// Fails for units like °C on their own, which can't be worked with
+  fn plain(self) -> Result<Self, String> {
+    match self.offset_unit {
+      Some(unit) => Err(format!(
+        "{} needs a number in front of it to be worked with",
+        unit.names[0]
+      )),
+      None => Ok(self),
+    }
+  }
+}
+
+/// Every unit used along with how it was written, in order
+type UsedUnits<'a> = Vec<(&'a str, Quantity)>;
+
+/// Works out a quantity, keeping track of the units it's written with
+struct Units<'a> {
+  rates: Option<&'a Rates>,
+  units: UsedUnits<'a>,
+}
+
+impl<'a> Units<'a> {
+  fn parse(
+    tokens: &'a [Spanned<'a>],
+    rates: Option<&'a Rates>,
+  ) -> Result<(Quantity, UsedUnits<'a>), String> {
+    let units = Units {
+      rates,
+      units: Vec::new(),
+    };
+    let mut parser = Parser::new(tokens, units);
+    let q = parser.expr()?;
+    match parser.peek() {
+      None => Ok((q, parser.grammar.units)),
+      Some(Token::Op(")")) => Err("Unopened )".into()),
+      Some(_) => Err("Unexpected trailing input".into()),
+    }
+  }
+}
+
+impl<'a> Grammar<'a> for Units<'a> {
+  type Value = Quantity;
+  type Error = String;
+
+  /// Things written one after another are multiplied, binding tighter than `*` or `/` so
+  /// `10 km / 2 h` is a speed
+  const LEVELS: &'static [Ops] = &[&["+", "-"], &["*", "/", "·", "×"], &[""]];
+  const PREFIX: Ops = &["-"];
+  const POWER: Ops = &["^"];
+
+  fn binary(
+    &mut self,
+    op: &'static str,
+    _: Range<usize>,
+    lhs: Quantity,
+    rhs: Quantity,
+  ) -> Result<Quantity, String> {
+    match op {
+      "+" | "-" => {
+        let (a, b) = (lhs.plain()?, rhs.plain()?);
+        if a.dim != b.dim {
+          return Err(format!("Can't add {} and {}", a.dim, b.dim));
+        }
+        let sign = if op == "-" { -1.0 } else { 1.0 };
+        let mut q = Quantity::number(a.value + sign * b.value);
+        q.dim = a.dim;
+        Ok(q)
+      }
+      "/" => {
+        let (a, b) = (lhs.plain()?, rhs.plain()?);
+        Ok(Quantity {
+          value: a.value / b.value,
+          dim: a.dim.combine(b.dim, -1)?,
+          offset_unit: None,
+        })
+      }
+      "^" => power(lhs, rhs),
+      _ => multiply(lhs, rhs),
+    }
+  }
+
+  fn prefix(&mut self, _: &'static str, _: Range<usize>, q: Quantity) -> Result<Quantity, String> {
+    Ok(Quantity {
+      value: -q.value,
+      ..q.plain()?
+    })
+  }
+
+  fn atom(
+    parser: &mut Parser<'a, Self>,
+    token: Token<'a>,
+    _: Range<usize>,
+  ) -> Result<Quantity, String> {
+    let q = match token {
+      Token::Num(literal) => Quantity::number(SYNTAX.decimal(literal)?),
+      Token::Name(name) => {
+        let unit = match lookup(name) {
+          Ok(unit) => Quantity::of(unit),
+          Err(err) => currency(name, parser.grammar.rates).ok_or(err)?,
+        };
+        parser.grammar.units.push((name, unit));
+        unit
+      }
+      Token::Op("(") => parser.bracketed()?,
+      Token::Op(op) => return Err(format!("Unexpected {}", op)),
+    };
+    // Squared and cubed, like `km²`
+    match parser.eat(&["²", "³"]) {
+      Some((op, _)) => power(q, Quantity::number(if op == "²" { 2.0 } else { 3.0 })),
+      None => Ok(q),
+    }
+  }
+
+  fn mistake(&self, message: String, _: Range<usize>) -> String {
+    message
+  }
+}
+
+fn power(base: Quantity, exp: Quantity) -> Result<Quantity, String> {
+  let (base, exp) = (base.plain()?, exp.plain()?);
+  if exp.dim != NONE {
+    return Err(format!("Can't raise to the power of {}", exp.dim));
+  }
+  if base.dim != NONE && (exp.value.fract() != 0.0 || exp.value.abs() > 8.0) {
+    return Err(format!(
+      "Can't raise {} to a power of {}",
+      base.dim, exp.value
+    ));
+  }
+  Ok(Quantity {
+    value: base.value.powf(exp.value),
+    dim: base.dim.pow(exp.value as i8)?,
+    offset_unit: None,
+  })
+}
+
+fn multiply(a: Quantity, b: Quantity) -> Result<Quantity, String> {
+  // A number in front of a unit like °C is where it gets its offset applied
+  if let (Some(unit), None) = (b.offset_unit, a.offset_unit) {
+    if a.dim == NONE {
+      return Ok(Quantity {
+        value: (a.value + unit.offset) * unit.factor,
+        dim: unit.dim,
+        offset_unit: None,
+      });
+    }
+  }
+  let (a, b) = (a.plain()?, b.plain()?);
+  Ok(Quantity {
+    value: a.value * b.value,
+    dim: a.dim.combine(b.dim, 1)?,
+    offset_unit: None,
+  })
+}
+
+/// A worked out expression along with its unit
+#[derive(Debug, PartialEq)]
+pub struct Converted {
+  pub value: String,
+  /// Whether exchange rates went into it, making it only as good as they are
+  pub with_rates: bool,
+}
+
+/// Works out an expression with units in it, like `5 mi to km` or `60 mph in m/s`, giving
+/// back the result along with its unit. Currencies are units too when there are rates for
+/// them. Without a unit to convert to the result is in the first unit written that fits.
+/// Expressions without any units are left alone.
+pub fn evaluate(input: &str, rates: Option<&Rates>) -> Option<Result<Converted, String>> {
+  let tokens = lex(input).ok()?;
+  let is_conversion = |t: &Spanned| ident(t).is_some_and(|name| CONVERSIONS.contains(&name));
+  let is_currency = |t: &Spanned| ident(t).is_some_and(|name| is_currency(name, rates));
+  let has_units = tokens.iter().any(|t| {
+    is_conversion(t) || is_currency(t) || ident(t).is_some_and(|name| lookup(name).is_ok())
+  });
+  if !has_units {
+    return None;
+  }
+
+  // The last conversion word with a unit after it splits off what to convert to, leaving
+  // `5 in in cm` converting inches
+  let split = (0..tokens.len()).rev().find_map(|idx| {
+    let target = Some(&tokens[idx + 1..])
+      .filter(|rest| is_conversion(&tokens[idx]) && !rest.is_empty())
+      .and_then(|rest| Units::parse(rest, rates).ok())?;
+    Some((idx, target.0))
+  });
+  let res = match split {
+    Some((idx, target)) => Units::parse(&tokens[..idx], rates)
+      .and_then(|(q, _)| convert(q, target))
+      .map(|value| (value, unit_text(&tokens[idx + 1..]))),
+    None => Units::parse(&tokens, rates).and_then(|(q, units)| in_first_unit(q, &units)),
+  };
+  Some(res.map(|(value, unit)| Converted {
+    value: match unit.is_empty() {
+      true => format_value(value),
+      false => format!("{} {}", format_value(value), unit),
+    },
+    with_rates: tokens.iter().any(is_currency),
+  }))
+}
+
+/// The quantity in what it's converted to, giving back the value
+fn convert(q: Quantity, target: Quantity) -> Result<f64, String> {
+  let q = q.plain()?;
+  match target.offset_unit {
+    Some(unit) if q.dim == unit.dim => Ok(q.value / unit.factor - unit.offset),
+    _ if q.dim == target.dim => Ok(q.value / target.value),
+    _ => Err(format!("Can't convert {} to {}", q.dim, target.dim)),
+  }
+}
+
+fn in_first_unit(q: Quantity, units: &[(&str, Quantity)]) -> Result<(f64, String), String> {
+  let q = q.plain()?;
+  if q.dim == NONE {
+    return Ok((q.value, String::new()));
+  }
+  match units.iter().find(|(_, unit)| unit.dim == q.dim) {
+    Some((name, unit)) => Ok((convert(q, *unit)?, name.to_string())),
+    None => Ok((q.value, q.dim.base_units())),
+  }
+}
+
+fn ident<'a>((token, _): &Spanned<'a>) -> Option<&'a str> {
+  match *token {
+    Token::Name(name) => Some(name),
+    _ => None,
+  }
+}
+
+/// The unit converted to, written out as it was typed
+fn unit_text(tokens: &[Spanned]) -> String {
+  tokens.iter().map(|(token, _)| token.to_string()).collect()
+}
+
+/// The value rounded to a sensible number of significant digits, without trailing zeros
+fn format_value(value: f64) -> String {
+  if value == 0.0 {
+    return "0".into();
+  }
+  if !value.is_finite() {
+    return value.to_string();
+  }
+  let magnitude = value.abs().log10().floor() as i32;
+  let trim = |s: String| match s.contains('.') {
+    true => s.trim_end_matches('0').trim_end_matches('.').to_string(),
+    false => s,
+  };
+  if !(-6..15).contains(&magnitude) {
+    let sci = format!("{:.*e}", (SIGNIFICANT_DIGITS - 1) as usize, value);
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    return format!("{}e{}", trim(mantissa.to_string()), exp);
+  }
+  let decimals = (SIGNIFICANT_DIGITS - 1 - magnitude).max(0) as usize;
+  match trim(format!("{:.*}", decimals, value)).as_str() {
+    "-0" => "0".into(),
+    s => s.into(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn eval(input: &str) -> Result<String, String> {
+    let res = evaluate(input, None).expect("Should have had units");
+    res.map(|converted| converted.value)
+  }
+
+  fn rates() -> Rates {
+    Rates::parse(
+      r#"{"base": "USD", "timestamp": 1, "rates": {"EUR": 0.5, "GBP": 0.25, "CUP": 24}}"#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn converts_between_units() {
+    assert_eq!(eval("5 mi to km"), Ok("8.04672 km".into()));
+    assert_eq!(eval("72F in C"), Ok("22.22222222 C".into()));
+    assert_eq!(eval("-40 °C to °F"), Ok("-40 °F".into()));
+    assert_eq!(eval("300 K in C"), Ok("26.85 C".into()));
+    assert_eq!(eval("3.5 GiB in MB"), Ok("3758.096384 MB".into()));
+    assert_eq!(eval("60 mph to m/s"), Ok("26.8224 m/s".into()));
+    assert_eq!(eval("2 l as cups"), Ok("8.453505675 cups".into()));
+    assert_eq!(eval("1 acre to m^2"), Ok("4046.856422 m^2".into()));
+    assert_eq!(eval("90 min in h"), Ok("1.5 h".into()));
+    assert_eq!(eval("1 kg in lb"), Ok("2.204622622 lb".into()));
+  }
+
+  #[test]
+  fn in_is_inches_unless_converting() {
+    assert_eq!(eval("12 in"), Ok("12 in".into()));
+    assert_eq!(eval("12 in in cm"), Ok("30.48 cm".into()));
+  }
+
+  #[test]
+  fn works_out_expressions_in_the_first_unit() {
+    assert_eq!(eval("1 km + 500 m"), Ok("1.5 km".into()));
+    assert_eq!(eval("(2 + 3) ft * 2"), Ok("10 ft".into()));
+    assert_eq!(eval("10 km / 2 h to km/h"), Ok("5 km/h".into()));
+    assert_eq!(eval("3 m * 4 m"), Ok("12 m^2".into()));
+    assert_eq!(eval("2 km²"), Ok("2000000 m^2".into()));
+    assert_eq!(eval("1 GB / 1 MB"), Ok("1000".into()));
+  }
+
+  #[test]
+  fn mismatched_dimensions_fail() {
+    assert_eq!(
+      eval("5 kg to m"),
+      Err("Can't convert mass to length".into())
+    );
+    assert_eq!(eval("1 km + 1 s"), Err("Can't add length and time".into()));
+    assert_eq!(
+      eval("C to F"),
+      Err("C needs a number in front of it to be worked with".into())
+    );
+    assert_eq!(eval("5 parsecs to m"), Err("Unknown unit parsecs".into()));
+    assert_eq!(
+      eval("5 mb to kb"),
+      Err("Ambiguous unit mb, mind the case".into())
+    );
+  }
+
+  #[test]
+  fn dimensions_too_large_fail() {
+    assert_eq!(eval("(m^8)^8 * (m^8)^7"), Ok("1 m^120".into()));
+    let too_large = Err("Dimension exponent too large".into());
+    assert_eq!(eval("((m^8)^8)^2"), too_large);
+    assert_eq!(eval("(m^8)^8 * (m^8)^8"), too_large);
+    assert_eq!(eval("1 / (m^8)^8 / (m^8)^8 / m"), too_large);
+  }
+
+  #[test]
+  fn plain_arithmetic_is_left_alone() {
+    assert_eq!(evaluate("1 + 2 * 3", None), None);
+    assert_eq!(evaluate("sqrt(4)", None), None);
+    assert_eq!(evaluate("0b101 + 0x1F", None), None);
+  }
+
+  #[test]
+  fn converts_currencies_with_rates() {
+    let rates = rates();
+    let eval = |input| evaluate(input, Some(&rates)).expect("Should have had units");
+    let converted = |value: &str| {
+      Ok(Converted {
+        value: value.into(),
+        with_rates: true,
+      })
+    };
+    assert_eq!(eval("100 usd in eur"), converted("50 eur"));
+    assert_eq!(eval("10 EUR to GBP"), converted("5 GBP"));
+    assert_eq!(eval("2 eur + 1 usd"), converted("2.5 eur"));
+    assert_eq!(eval("30 usd / 2 kg in eur/kg"), converted("7.5 eur/kg"));
+    assert_eq!(
+      eval("1 cup in ml").map(|c| c.with_rates),
+      Ok(false),
+      "Units come before currencies"
+    );
+    assert_eq!(
+      eval("5 eur in kg"),
+      Err("Can't convert money to mass".into())
+    );
+    assert_eq!(
+      evaluate("100 usd in eur", None),
+      Some(Err("Unknown unit usd".into()))
+    );
+  }
+
+  #[test]
+  fn formats_significant_digits() {
+    assert_eq!(format_value(1.0 / 3.0), "0.3333333333");
+    assert_eq!(format_value(1500.0), "1500");
+    assert_eq!(format_value(-0.00000000001), "-1e-11");
+    assert_eq!(format_value(2.5e20), "2.5e20");
+    assert_eq!(format_value(-0.0), "0");
+    assert_eq!(format_value(f64::NEG_INFINITY), "-inf");
+    assert_eq!(format_value(f64::INFINITY), "inf");
+  }
+}
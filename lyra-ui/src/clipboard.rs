@@ -0,0 +1,139 @@
+mod history;
+mod offer;
+
+use crate::config::ClipboardConfig;
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use anyhow::anyhow;
+use arboard::Clipboard;
+use egui::{Image, RichText};
+use history::{ClipContent, ClipEntry, ClipHistory};
+use parking_lot::Mutex;
+use std::{path::Path, sync::Arc, time::Duration};
+
+pub const PLUGIN_NAME: &str = "clipboard";
+/// How much of an entry is searched and shown
+const PREVIEW_CHARS: usize = 200;
+
+/// Keeps a history of what's been copied, so any of it can be found and copied again
+pub struct ClipboardPlugin {
+  cfg: ClipboardConfig,
+  history: Arc<ClipHistory>,
+  clip: Mutex<Clipboard>,
+  /// What was last searched for, to search again once an entry's been (un)pinned
+  last_search: Mutex<String>,
+}
+
+impl PluginValue for ClipEntry {
+  fn item_id(&self) -> Option<String> {
+    Some(self.id.clone())
+  }
+}
+impl Renderable for ClipEntry {
+  fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
+    ui.horizontal(|ui| {
+      match &self.content {
+        ClipContent::Text { .. } | ClipContent::LongText { .. } => {
+          ui.label(RichText::new(self.preview()));
+        }
+        ClipContent::Image { file, .. } => {
+          ui.add(
+            Image::new(format!("file://{}", file.display()))
+              .maintain_aspect_ratio(true)
+              .max_height(ui.text_style_height(&egui::TextStyle::Body) * 2.0),
+          );
+          ui.label(RichText::new(self.preview()));
+        }
+      }
+      if self.pinned {
+        ui.label(RichText::new("Pinned").weak().small());
+      }
+    });
+  }
+}
+impl SearchBlocker for ClipEntry {}
+
+impl ClipEntry {
+  /// A single line summary of the entry
+  fn preview(&self) -> String {
+    let (text, cut) = match &self.content {
+      ClipContent::Text { text } => (text, false),
+      ClipContent::LongText { start, .. } => (start, true),
+      ClipContent::Image { width, height, .. } => return format!("Image {}×{}", width, height),
+    };
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(PREVIEW_CHARS) {
+      Some((end, _)) => format!("{}…", &line[..end]),
+      None if cut => format!("{}…", line),
+      None => line,
+    }
+  }
+}
+
+impl ClipboardPlugin {
+  pub fn init(cfg: ClipboardConfig, cache_dir: &Path) -> Result<Self, anyhow::Error> {
+    let history = Arc::new(ClipHistory::init(&cfg, cache_dir)?);
+    history.watch(Duration::from_millis(cfg.poll_ms));
+    Ok(ClipboardPlugin {
+      cfg,
+      history,
+      clip: Mutex::new(Clipboard::new()?),
+      last_search: Mutex::default(),
+    })
+  }
+}
+
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |ctx| {
+    Ok(Box::new(ClipboardPlugin::init(
+      ctx.section()?,
+      &ctx.config.cache_dir,
+    )?))
+  });
+}
+
+impl Plugin for ClipboardPlugin {
+  type PV = ClipEntry;
+
+  fn prefix(&self) -> Option<String> {
+    Some(self.cfg.prefix.clone())
+  }
+
+  /// Copies the entry again, which also brings it back to the top of the history
+  fn action(&self, input: &ClipEntry) -> Result<OkAction, anyhow::Error> {
+    input
+      .content
+      .copy(&mut self.clip.lock())
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Failed to copy {:?}, err: {:?}", input.preview(), err))
+  }
+
+  /// Pins the entry, or unpins it, searching again to show the change
+  fn secondary_action(&self, input: &ClipEntry) -> Result<OkAction, anyhow::Error> {
+    self.history.toggle_pin(&input.id)?;
+    Ok(OkAction {
+      update_input: Some(self.last_search.lock().clone()),
+      ..Default::default()
+    })
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    *self.last_search.lock() = search.to_string();
+    self
+      .history
+      .entries()
+      .into_iter()
+      .map(|entry| FuzzyMatchItem {
+        // The prefix is part of the search being matched, so it has to be matched too
+        against: Arc::new(format!("{}{}", self.cfg.prefix, entry.preview())),
+        value: PluginV::new(PLUGIN_NAME, entry),
+        source: PLUGIN_NAME.to_string(),
+      })
+      .collect()
+  }
+}
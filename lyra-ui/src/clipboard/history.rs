@@ -0,0 +1,494 @@
+use super::offer::Offers;
+use crate::{cacher::Cache, config::ClipboardConfig};
+use anyhow::{anyhow, Context};
+use arboard::{Clipboard, ImageData};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use image::{ImageOutputFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::{
+  borrow::Cow,
+  fs,
+  io::Cursor,
+  path::{Path, PathBuf},
+  sync::{Arc, Weak},
+  thread,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, warn};
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+/// Text longer than this gets a file of its own, keeping the history file that's rewritten
+/// on every copy small
+const INLINE_BYTES: usize = 1024;
+
+/// Everything copied lately, newest first. Entries are told apart by their content, so copying
+/// the same thing again moves it back to the top rather than adding it twice. Short text is
+/// kept in the cache file, while long text and images get a file of their own next to it.
+pub struct ClipHistory {
+  clips: Cache<Clips>,
+  files: PathBuf,
+  max_entries: usize,
+  max_age_days: u64,
+  max_entry_bytes: usize,
+  ignore: GlobSet,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct Clips {
+  entries: Vec<ClipEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClipEntry {
+  /// Hash of the content
+  pub id: String,
+  pub content: ClipContent,
+  /// Seconds since the unix epoch
+  pub copied_at: i64,
+  #[serde(default)]
+  pub pinned: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClipContent {
+  Text {
+    text: String,
+  },
+  /// Text that's too long for the history file, with enough of the start of it kept there
+  /// to search and show
+  LongText {
+    start: String,
+    file: PathBuf,
+  },
+  Image {
+    width: usize,
+    height: usize,
+    file: PathBuf,
+  },
+}
+
+impl ClipHistory {
+  pub fn init(cfg: &ClipboardConfig, cache_dir: &Path) -> Result<Self, anyhow::Error> {
+    let mut ignore = GlobSetBuilder::new();
+    for glob in &cfg.ignore {
+      ignore.add(Glob::new(glob).with_context(|| format!("Invalid ignore glob {:?}", glob))?);
+    }
+    let files = cache_dir.join("clipboard");
+    fs::create_dir_all(&files)?;
+    Ok(ClipHistory {
+      clips: Cache::load(cache_dir.join("clipboard.toml"))?,
+      files,
+      max_entries: cfg.max_entries,
+      max_age_days: cfg.max_age_days,
+      max_entry_bytes: cfg.max_entry_bytes,
+      ignore: ignore.build()?,
+    })
+  }
+
+  /// Pinned entries first, then the rest from newest to oldest
+  pub fn entries(&self) -> Vec<ClipEntry> {
+    let mut entries = self.clips.get().entries.clone();
+    entries.sort_by_key(|e| !e.pinned);
+    entries
+  }
+
+  /// Adds the text to the top of the history, unless it's not worth keeping. Returns whether
+  /// it was kept
+  pub fn record_text(&self, text: &str) -> Result<bool, anyhow::Error> {
+    self.record_text_at(text, now())
+  }
+
+  fn record_text_at(&self, text: &str, at: i64) -> Result<bool, anyhow::Error> {
+    if text.trim().is_empty() || text.len() > self.max_entry_bytes || self.ignore.is_match(text) {
+      return Ok(false);
+    }
+    let id = hash(text.as_bytes());
+    let content = match text.len() > INLINE_BYTES {
+      false => ClipContent::Text {
+        text: text.to_string(),
+      },
+      true => {
+        let file = self.files.join(format!("{}.txt", id));
+        if !file.exists() {
+          fs::write(&file, text)?;
+        }
+        let mut end = INLINE_BYTES;
+        while !text.is_char_boundary(end) {
+          end -= 1;
+        }
+        ClipContent::LongText {
+          start: text[..end].to_string(),
+          file,
+        }
+      }
+    };
+    self.record(id, content, at)?;
+    Ok(true)
+  }
+
+  /// Like `record_text`, for an image given as RGBA pixels
+  pub fn record_image(
+    &self,
+    width: usize,
+    height: usize,
+    rgba: &[u8],
+  ) -> Result<bool, anyhow::Error> {
+    self.record_image_at(width, height, rgba, now())
+  }
+
+  fn record_image_at(
+    &self,
+    width: usize,
+    height: usize,
+    rgba: &[u8],
+    at: i64,
+  ) -> Result<bool, anyhow::Error> {
+    if rgba.is_empty() || rgba.len() > self.max_entry_bytes {
+      return Ok(false);
+    }
+    // The same pixels can make up images of different shapes
+    let id = format!("{}-{}x{}", hash(rgba), width, height);
+    let file = self.files.join(format!("{}.png", id));
+    if !file.exists() {
+      let image = RgbaImage::from_raw(width as u32, height as u32, rgba.to_vec())
+        .ok_or_else(|| anyhow!("Image is not {}x{}", width, height))?;
+      let mut png = Cursor::new(Vec::new());
+      image.write_to(&mut png, ImageOutputFormat::Png)?;
+      fs::write(&file, png.into_inner())?;
+    }
+    let content = ClipContent::Image {
+      width,
+      height,
+      file,
+    };
+    self.record(id, content, at)?;
+    Ok(true)
+  }
+
+  fn record(&self, id: String, content: ClipContent, at: i64) -> Result<(), anyhow::Error> {
+    self.clips.update(|mut clips| {
+      let pinned = match clips.entries.iter().position(|e| e.id == id) {
+        Some(idx) => clips.entries.remove(idx).pinned,
+        None => false,
+      };
+      clips.entries.insert(
+        0,
+        ClipEntry {
+          id,
+          content,
+          copied_at: at,
+          pinned,
+        },
+      );
+    });
+    self.prune(at);
+    self.clips.persist()
+  }
+
+  /// Pins the entry so it's kept regardless of the caps, or unpins it. Returns whether it's
+  /// now pinned
+  pub fn toggle_pin(&self, id: &str) -> Result<bool, anyhow::Error> {
+    let mut pinned = None;
+    self.clips.update(|mut clips| {
+      if let Some(entry) = clips.entries.iter_mut().find(|e| e.id == id) {
+        entry.pinned = !entry.pinned;
+        pinned = Some(entry.pinned);
+      }
+    });
+    let pinned = pinned.ok_or_else(|| anyhow!("No clipboard entry {}", id))?;
+    self.clips.persist()?;
+    Ok(pinned)
+  }
+
+  /// Forgets unpinned entries past the caps, along with their files
+  fn prune(&self, now: i64) {
+    let max_age = self.max_age_days as i64 * DAY_SECS;
+    let mut dropped = vec![];
+    self.clips.update(|mut clips| {
+      let mut kept = 0;
+      clips.entries.retain(|e| {
+        if e.pinned {
+          return true;
+        }
+        kept += 1;
+        let keep = kept <= self.max_entries && (max_age == 0 || now - e.copied_at <= max_age);
+        if !keep {
+          dropped.push(e.content.clone());
+        }
+        keep
+      });
+    });
+    for file in dropped.iter().filter_map(ClipContent::file) {
+      if let Err(err) = fs::remove_file(file) {
+        warn!("Failed to remove clipboard file {:?}: {}", file, err);
+      }
+    }
+  }
+
+  /// Watches the clipboard for as long as the history is around, recording whatever's
+  /// copied. Nothing tells us when the clipboard changes, so it's checked `every` so often.
+  pub fn watch(self: &Arc<Self>, every: Duration) {
+    let history = Arc::downgrade(self);
+    let spawned = thread::Builder::new()
+      .name("clipboard-watcher".into())
+      .spawn(move || run_watcher(history, every));
+    if let Err(err) = spawned {
+      error!("Failed to start clipboard watcher: {}", err);
+    }
+  }
+}
+
+impl ClipContent {
+  /// Puts the content back on the clipboard
+  pub fn copy(&self, clip: &mut Clipboard) -> Result<(), anyhow::Error> {
+    match self {
+      ClipContent::Text { text } => clip.set_text(text)?,
+      ClipContent::LongText { file, .. } => clip.set_text(fs::read_to_string(file)?)?,
+      ClipContent::Image { file, .. } => {
+        let image = image::open(file)?.to_rgba8();
+        clip.set_image(ImageData {
+          width: image.width() as usize,
+          height: image.height() as usize,
+          bytes: Cow::Owned(image.into_raw()),
+        })?
+      }
+    }
+    Ok(())
+  }
+
+  /// The file the content's kept in, if it's not in the history file
+  fn file(&self) -> Option<&Path> {
+    match self {
+      ClipContent::Text { .. } => None,
+      ClipContent::LongText { file, .. } | ClipContent::Image { file, .. } => Some(file),
+    }
+  }
+}
+
+fn run_watcher(history: Weak<ClipHistory>, every: Duration) {
+  let mut clip = match Clipboard::new() {
+    Ok(clip) => clip,
+    Err(err) => {
+      error!("Not watching the clipboard, it's unavailable: {}", err);
+      return;
+    }
+  };
+  let mut offers = None;
+  let mut last = None;
+  // Whether it's been said that copies can't be told apart, so it's only said once in a row
+  let mut blind = false;
+  loop {
+    thread::sleep(every);
+    let Some(history) = history.upgrade() else {
+      return;
+    };
+    if offers.is_none() {
+      offers = Offers::new()
+        .map_err(|err| {
+          if !blind {
+            error!(
+              "Not keeping clipboard history, password manager copies can't be told apart: {:?}",
+              err
+            );
+          }
+        })
+        .ok();
+    }
+    // Only read the clipboard when something new was copied, and never when it couldn't be
+    // told whether that's a secret. Those are asked about again next time round
+    match offers.as_mut().map(Offers::changed) {
+      Some(Ok(None)) => continue,
+      Some(Ok(Some(offer))) if offer.is_secret() => continue,
+      Some(Ok(Some(_))) => blind = false,
+      Some(Err(err)) => {
+        if !blind {
+          warn!(
+            "Not keeping what was copied until it says what it is: {:?}",
+            err
+          );
+        }
+        blind = true;
+        continue;
+      }
+      None => {
+        blind = true;
+        continue;
+      }
+    }
+    // Images are only looked at when there's no text, they're far more work to compare
+    let recorded = match clip.get_text() {
+      Ok(text) if last != Some(hash(text.as_bytes())) => {
+        last = Some(hash(text.as_bytes()));
+        history.record_text(&text)
+      }
+      Ok(_) => continue,
+      Err(_) => match clip.get_image() {
+        Ok(image) if last != Some(hash(&image.bytes)) => {
+          last = Some(hash(&image.bytes));
+          history.record_image(image.width, image.height, &image.bytes)
+        }
+        _ => continue,
+      },
+    };
+    if let Err(err) = recorded {
+      warn!("Failed to record clipboard: {:?}", err);
+    }
+  }
+}
+
+/// FNV-1a, which is stable between runs unlike std's hasher, since ids outlive the process
+fn hash(bytes: &[u8]) -> String {
+  let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, b| {
+    (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+  });
+  format!("{:016x}", hash)
+}
+
+fn now() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+
+  fn history(dir: &Path, cfg: ClipboardConfig) -> ClipHistory {
+    ClipHistory::init(&cfg, dir).unwrap()
+  }
+
+  fn texts(history: &ClipHistory) -> Vec<String> {
+    history
+      .entries()
+      .into_iter()
+      .map(|e| match e.content {
+        ClipContent::Text { text } => text,
+        ClipContent::LongText { file, .. } => fs::read_to_string(file).unwrap(),
+        ClipContent::Image { width, height, .. } => format!("{}x{}", width, height),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn recopies_move_to_the_top() {
+    let dir = TestDir::new("clips-dedupe");
+    let h = history(&dir, ClipboardConfig::default());
+    for text in ["a", "b", "a", "  ", "c"] {
+      h.record_text_at(text, 100).unwrap();
+    }
+    assert_eq!(texts(&h), vec!["c", "a", "b"]);
+
+    // And it all survives a restart
+    let reloaded = history(&dir, ClipboardConfig::default());
+    assert_eq!(texts(&reloaded), vec!["c", "a", "b"]);
+  }
+
+  #[test]
+  fn caps_spare_pinned_entries() {
+    let dir = TestDir::new("clips-caps");
+    let h = history(
+      &dir,
+      ClipboardConfig {
+        max_entries: 3,
+        max_age_days: 1,
+        max_entry_bytes: 8,
+        ..Default::default()
+      },
+    );
+    h.record_text_at("old", 0).unwrap();
+    h.record_text_at("pinned", 0).unwrap();
+    h.toggle_pin(&hash(b"pinned")).unwrap();
+    assert!(!h.record_text_at("far too long", DAY_SECS).unwrap());
+    h.record_text_at("x", DAY_SECS).unwrap();
+    assert_eq!(texts(&h), vec!["pinned", "x", "old"]);
+
+    // Old entries age out, and only so many are kept
+    h.record_text_at("y", DAY_SECS + 1).unwrap();
+    assert_eq!(texts(&h), vec!["pinned", "y", "x"]);
+    h.record_text_at("z", DAY_SECS + 2).unwrap();
+    h.record_text_at("w", DAY_SECS + 3).unwrap();
+    assert_eq!(texts(&h), vec!["pinned", "w", "z", "y"]);
+
+    assert!(!h.toggle_pin(&hash(b"pinned")).unwrap());
+    assert!(h.toggle_pin("missing").is_err());
+  }
+
+  #[test]
+  fn ignores_matching_text() {
+    let dir = TestDir::new("clips-ignore");
+    let h = history(
+      &dir,
+      ClipboardConfig {
+        ignore: vec!["ghp_*".into()],
+        ..Default::default()
+      },
+    );
+    assert!(!h.record_text_at("ghp_abc123", 0).unwrap());
+    assert!(h.record_text_at("hello ghp_", 0).unwrap());
+    assert_eq!(texts(&h), vec!["hello ghp_"]);
+    assert!(ClipHistory::init(
+      &ClipboardConfig {
+        ignore: vec!["[".into()],
+        ..Default::default()
+      },
+      &dir
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn images_are_stored_as_files() {
+    let dir = TestDir::new("clips-images");
+    let h = history(
+      &dir,
+      ClipboardConfig {
+        max_entries: 1,
+        ..Default::default()
+      },
+    );
+    let red = [255, 0, 0, 255].repeat(2);
+    assert!(h.record_image_at(2, 1, &red, 0).unwrap());
+    let file = match &h.entries()[0].content {
+      ClipContent::Image { file, .. } => file.clone(),
+      other => panic!("Expected an image, got {:?}", other),
+    };
+    let decoded = image::open(&file).unwrap().to_rgba8();
+    assert_eq!(decoded.dimensions(), (2, 1));
+
+    // Images pruned from the history go from the disk too
+    h.record_text_at("text", 0).unwrap();
+    assert_eq!(texts(&h), vec!["text"]);
+    assert!(!file.exists());
+  }
+
+  #[test]
+  fn long_text_is_stored_as_a_file() {
+    let dir = TestDir::new("clips-long");
+    let h = history(
+      &dir,
+      ClipboardConfig {
+        max_entries: 1,
+        ..Default::default()
+      },
+    );
+    let long = "é".repeat(INLINE_BYTES);
+    h.record_text_at(&long, 0).unwrap();
+    let (start, file) = match &h.entries()[0].content {
+      ClipContent::LongText { start, file } => (start.clone(), file.clone()),
+      other => panic!("Expected long text, got {:?}", other),
+    };
+    assert_eq!(start, "é".repeat(INLINE_BYTES / 2));
+    assert_eq!(texts(&h), vec![long.clone()]);
+    // The history file only has the start of it
+    let saved = fs::read_to_string(dir.join("clipboard.toml")).unwrap();
+    assert!(saved.len() < long.len());
+
+    h.record_text_at("short", 0).unwrap();
+    assert!(!file.exists());
+  }
+}
@@ -0,0 +1,239 @@
+//! Telling when something new is copied, and what it's on offer as, without reading the
+//! content itself. That's left for once it's known to be new, since images are a lot to read.
+
+/// Types password managers mark their copies with, so clipboard histories leave them out
+const SECRET_TYPES: [&str; 3] = [
+  "x-kde-passwordManagerHint",
+  "org.nspasteboard.ConcealedType",
+  "org.nspasteboard.TransientType",
+];
+
+/// What was copied, as far as can be told without reading it
+#[derive(Debug, Default)]
+pub struct Offer {
+  /// Names of the types the content's on offer as, like `image/png`
+  pub types: Vec<String>,
+}
+
+impl Offer {
+  /// Whether it's been marked as a password or the like that shouldn't be kept
+  pub fn is_secret(&self) -> bool {
+    self
+      .types
+      .iter()
+      .any(|t| SECRET_TYPES.contains(&t.as_str()))
+  }
+}
+
+#[cfg(target_os = "linux")]
+pub use x11::Offers;
+
+/// On X11 the clipboard's owner is set anew on every copy, which the XFixes extension tells
+/// us about. arboard goes through X11 too, so this also covers Wayland through XWayland.
+#[cfg(target_os = "linux")]
+mod x11 {
+  use super::Offer;
+  use anyhow::anyhow;
+  use std::{
+    thread,
+    time::{Duration, Instant},
+  };
+  use x11rb::{
+    connection::Connection,
+    protocol::{
+      xfixes::{ConnectionExt as _, SelectionEventMask},
+      xproto::{Atom, AtomEnum, ConnectionExt as _, CreateWindowAux, Window, WindowClass},
+      Event,
+    },
+    rust_connection::RustConnection,
+    COPY_DEPTH_FROM_PARENT, CURRENT_TIME,
+  };
+
+  /// How long the clipboard's owner has to answer what types it offers
+  const ANSWER_TIMEOUT: Duration = Duration::from_millis(200);
+
+  pub struct Offers {
+    conn: RustConnection,
+    /// Where the clipboard's owner puts its answers
+    window: Window,
+    clipboard: Atom,
+    targets: Atom,
+    property: Atom,
+    /// Copies made since we started, and how many of those we've said something about
+    copies: u64,
+    seen: u64,
+  }
+
+  impl Offers {
+    pub fn new() -> Result<Self, anyhow::Error> {
+      let (conn, screen) = x11rb::connect(None)?;
+      let root = conn.setup().roots[screen].root;
+      let window = conn.generate_id()?;
+      conn.create_window(
+        COPY_DEPTH_FROM_PARENT,
+        window,
+        root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_ONLY,
+        0,
+        &CreateWindowAux::new(),
+      )?;
+      let atom = |name: &str| -> Result<Atom, anyhow::Error> {
+        Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+      };
+      let (clipboard, targets, property) = (atom("CLIPBOARD")?, atom("TARGETS")?, atom("LYRA")?);
+      conn.xfixes_query_version(5, 0)?.reply()?;
+      conn.xfixes_select_selection_input(
+        window,
+        clipboard,
+        SelectionEventMask::SET_SELECTION_OWNER
+          | SelectionEventMask::SELECTION_WINDOW_DESTROY
+          | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+      )?;
+      conn.flush()?;
+      Ok(Offers {
+        conn,
+        window,
+        clipboard,
+        targets,
+        property,
+        // Whatever's on the clipboard to begin with counts as new
+        copies: 1,
+        seen: 0,
+      })
+    }
+
+    /// What was copied, if anything was since last asked
+    pub fn changed(&mut self) -> Result<Option<Offer>, anyhow::Error> {
+      while let Some(event) = self.conn.poll_for_event()? {
+        self.count(&event);
+      }
+      if self.copies == self.seen {
+        return Ok(None);
+      }
+      // Only counted as seen once it's said what it is, so it's asked again otherwise
+      let copies = self.copies;
+      let types = self.types()?;
+      self.seen = copies;
+      Ok(Some(Offer { types }))
+    }
+
+    fn count(&mut self, event: &Event) {
+      if let Event::XfixesSelectionNotify(_) = event {
+        self.copies += 1;
+      }
+    }
+
+    fn types(&mut self) -> Result<Vec<String>, anyhow::Error> {
+      self.conn.convert_selection(
+        self.window,
+        self.clipboard,
+        self.targets,
+        self.property,
+        CURRENT_TIME,
+      )?;
+      self.conn.flush()?;
+      let until = Instant::now() + ANSWER_TIMEOUT;
+      let answered = loop {
+        match self.conn.poll_for_event()? {
+          Some(Event::SelectionNotify(answer)) => break answer,
+          Some(event) => self.count(&event),
+          None if Instant::now() < until => thread::sleep(Duration::from_millis(5)),
+          None => return Err(anyhow!("Clipboard didn't say what it holds in time")),
+        }
+      };
+      // Nothing's on the clipboard, or its owner won't say
+      if answered.property == u32::from(AtomEnum::NONE) {
+        return Ok(vec![]);
+      }
+      let reply = self
+        .conn
+        .get_property(
+          true,
+          self.window,
+          self.property,
+          AtomEnum::ATOM,
+          0,
+          u32::MAX,
+        )?
+        .reply()?;
+      let atoms: Vec<Atom> = reply.value32().into_iter().flatten().collect();
+      let names = atoms
+        .into_iter()
+        .map(|atom| Ok(self.conn.get_atom_name(atom)?))
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+      names
+        .into_iter()
+        .map(|name| Ok(String::from_utf8_lossy(&name.reply()?.name).to_string()))
+        .collect()
+    }
+  }
+}
+
+#[cfg(target_os = "macos")]
+pub use macos::Offers;
+
+/// The pasteboard counts its changes, so there's only anything to ask when the count's moved
+#[cfg(target_os = "macos")]
+mod macos {
+  use super::Offer;
+  use objc2_app_kit::NSPasteboard;
+
+  pub struct Offers {
+    seen: Option<isize>,
+  }
+
+  impl Offers {
+    pub fn new() -> Result<Self, anyhow::Error> {
+      Ok(Offers { seen: None })
+    }
+
+    /// What was copied, if anything was since last asked
+    pub fn changed(&mut self) -> Result<Option<Offer>, anyhow::Error> {
+      let pasteboard = unsafe { NSPasteboard::generalPasteboard() };
+      let count = unsafe { pasteboard.changeCount() };
+      if self.seen == Some(count) {
+        return Ok(None);
+      }
+      self.seen = Some(count);
+      let types = unsafe { pasteboard.types() }
+        .map(|types| types.iter().map(|t| t.to_string()).collect())
+        .unwrap_or_default();
+      Ok(Some(Offer { types }))
+    }
+  }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub struct Offers;
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+impl Offers {
+  pub fn new() -> Result<Self, anyhow::Error> {
+    Err(anyhow::anyhow!("Can't tell when something's copied here"))
+  }
+
+  pub fn changed(&mut self) -> Result<Option<Offer>, anyhow::Error> {
+    Ok(Some(Offer::default()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn password_manager_copies_are_secret() {
+    let offer = |types: &[&str]| Offer {
+      types: types.iter().map(|t| t.to_string()).collect(),
+    };
+    assert!(!offer(&[]).is_secret());
+    assert!(!offer(&["TARGETS", "UTF8_STRING", "text/plain"]).is_secret());
+    assert!(offer(&["UTF8_STRING", "x-kde-passwordManagerHint"]).is_secret());
+    assert!(offer(&["public.utf8-plain-text", "org.nspasteboard.ConcealedType"]).is_secret());
+  }
+}
@@ -1,8 +1,8 @@
-use crate::{cacher::Cache, plugin::PluginName, plugin_manager::PluginManager};
+use crate::{cacher::Cache, keymap::Keymap, plugin::PluginName, plugin_manager::PluginManager};
 use anyhow::Context;
 use egui::{Color32, FontFamily, Margin, Rounding};
 use parking_lot::RwLockWriteGuard;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{collections::HashMap, fs, ops::Deref, path::PathBuf};
 use tracing::{error, info};
 
@@ -25,9 +25,43 @@ pub struct InnerConfig {
   pub plugins: Vec<PluginName>,
   #[serde(default = "default_hotkey")]
   pub hotkey: String,
-  pub apps: AppsConfig,
-  pub calc: CalcConfig,
-  pub webq: WebqConfig,
+  /// How long plugins get to answer a search before they're no longer waited on
+  #[serde(default = "default_query_budget_ms")]
+  pub query_budget_ms: u64,
+  /// Budgets for specific plugins, overriding `query_budget_ms`
+  pub query_budgets: HashMap<PluginName, u64>,
+  pub keymap: Keymap,
+  /// Every other top level section belongs to a plugin, keyed by the plugin's name. Plugins
+  /// own the shape of their section, see `plugin_config`
+  #[serde(flatten)]
+  pub plugin_configs: toml::Table,
+}
+
+impl InnerConfig {
+  /// The config section for the named plugin, or its defaults if the section is missing
+  pub fn plugin_config<T: DeserializeOwned + Default>(
+    &self,
+    name: &str,
+  ) -> Result<T, anyhow::Error> {
+    match self.plugin_configs.get(name) {
+      Some(section) => section
+        .clone()
+        .try_into()
+        .with_context(|| format!("Invalid config for plugin {}", name)),
+      None => Ok(T::default()),
+    }
+  }
+
+  pub fn set_plugin_config<T: Serialize>(
+    &mut self,
+    name: &str,
+    section: &T,
+  ) -> Result<(), anyhow::Error> {
+    self
+      .plugin_configs
+      .insert(name.to_string(), toml::Value::try_from(section)?);
+    Ok(())
+  }
 }
 
 fn default_result_count() -> usize {
@@ -35,7 +69,11 @@ fn default_result_count() -> usize {
 }
 
 fn default_plugins() -> Vec<PluginName> {
-  vec!["apps".into(), "calc".into(), "webq".into()]
+  vec!["apps".into(), "calc".into(), "paths".into(), "webq".into()]
+}
+
+fn default_query_budget_ms() -> u64 {
+  300
 }
 
 fn default_hotkey() -> String {
@@ -63,18 +101,307 @@ pub struct WebqSearchConfig {
 #[serde(default)]
 pub struct CalcConfig {
   pub prefix: String,
+  /// Where exchange rates are fetched from to keep the rates file in the cache dir up to
+  /// date. Without one the file is left for something else to fill in, or rates can be kept
+  /// as `code,rate` rows in a `rates.csv` next to it
+  pub rates_url: Option<String>,
+  /// How long the rates file goes before it's fetched again, 0 leaves it as it is
+  pub rates_refresh_hours: u64,
+  /// Rates older than this are warned about alongside the result
+  pub rates_stale_hours: u64,
+  /// How many of the calculations worked out before are offered up again
+  pub history_size: usize,
+  /// How plain arithmetic is worked out
+  pub backend: CalcBackend,
+  /// How numbers worked out by the backend are written, which is also how they're copied
+  pub format: NumberFormat,
+  /// What's plotted of expressions with a variable in them, until they're panned or zoomed
+  pub plot_domain: (f64, f64),
 }
 
 impl Default for CalcConfig {
   fn default() -> Self {
-    CalcConfig { prefix: "=".into() }
+    CalcConfig {
+      prefix: "=".into(),
+      rates_url: None,
+      rates_refresh_hours: 12,
+      rates_stale_hours: 72,
+      history_size: 50,
+      backend: CalcBackend::default(),
+      format: NumberFormat::default(),
+      plot_domain: (-10.0, 10.0),
+    }
   }
 }
 
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CalcBackend {
+  /// Floating point, quick but `0.1 + 0.2` comes out a hair over `0.3`
+  #[default]
+  Float,
+  /// Exact fractions of numbers as big as they need to be, written as decimals. Anything
+  /// that can't be exact, like `sqrt(2)`, is still worked out with floats
+  Decimal,
+  /// Like `Decimal`, but offering up results that aren't whole as fractions like `1/3` first
+  Rational,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NumberFormat {
+  /// What results are rounded to, 0 for as many as there are. Exact results that never end,
+  /// like a third, stop at 30 either way
+  pub significant_digits: usize,
+  /// Written between groups of thousands, like the `,` in `1,000,000`
+  pub thousands_separator: String,
+  /// Written between the whole and fractional part, going by the locale when it's not set
+  pub decimal_separator: Option<String>,
+  pub scientific: Scientific,
+}
+
+/// When numbers are written like `1.5e21`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scientific {
+  /// For the very big and very small, apart from whole numbers known exactly
+  #[default]
+  Auto,
+  Always,
+  Never,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+  pub prefix: String,
+  /// Unpinned entries past the newest this many are forgotten
+  pub max_entries: usize,
+  /// Unpinned entries copied longer ago than this are forgotten, 0 to keep them regardless
+  pub max_age_days: u64,
+  /// Copies bigger than this aren't kept, which mostly rules out huge images
+  pub max_entry_bytes: usize,
+  /// How often to check the clipboard for something new
+  pub poll_ms: u64,
+  /// Globs of copied text to never keep, like `ghp_*` for GitHub tokens. The clipboard
+  /// doesn't say what app a copy came from, so sensitive sources are told apart by content.
+  /// Copies password managers mark as secret are never kept either, nor is anything where
+  /// those can't be told apart
+  pub ignore: Vec<String>,
+}
+
+impl Default for ClipboardConfig {
+  fn default() -> Self {
+    ClipboardConfig {
+      prefix: "cb ".into(),
+      max_entries: 200,
+      max_age_days: 30,
+      max_entry_bytes: 10 * 1024 * 1024,
+      poll_ms: 500,
+      ignore: Vec::new(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShellConfig {
+  pub prefix: String,
+  /// The shell and the args to run a command with, the command going after them
+  pub shell: Vec<String>,
+  /// The terminal and the args to run something in it with, eg `["kitty", "--hold"]`. The
+  /// shell and command go after them. Defaults to `$TERMINAL -e`
+  pub terminal: Option<Vec<String>>,
+  /// Whether commands run in a terminal rather than headlessly with their output shown
+  /// in the bar. Whichever isn't picked is the secondary action
+  pub in_terminal: bool,
+  /// How long a headless command has before it's killed
+  pub timeout_ms: u64,
+  /// How many of the last lines of output are shown, and copied
+  pub output_lines: usize,
+  /// How many of the commands run before are offered up again
+  pub history_size: usize,
+}
+
+impl Default for ShellConfig {
+  fn default() -> Self {
+    let shell = match cfg!(windows) {
+      true => vec!["cmd".into(), "/C".into()],
+      false => vec!["sh".into(), "-c".into()],
+    };
+    ShellConfig {
+      prefix: ">".into(),
+      shell,
+      terminal: None,
+      in_terminal: false,
+      timeout_ms: 10_000,
+      output_lines: 10,
+      history_size: 100,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SnippetsConfig {
+  /// How `{date}` is written out in a snippet, in strftime format
+  pub date_format: String,
+  /// How `{time}` is written out in a snippet, in strftime format
+  pub time_format: String,
+}
+
+impl Default for SnippetsConfig {
+  fn default() -> Self {
+    SnippetsConfig {
+      date_format: "%Y-%m-%d".into(),
+      time_format: "%H:%M".into(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AppsConfig {
   pub app_paths: Vec<PathBuf>,
   pub app_extension: String,
+  /// Dirs of freedesktop entries (`.desktop` files) to find apps in, most important first.
+  /// Defaults to the XDG data dirs on Linux
+  #[serde(default)]
+  pub desktop_dirs: Option<Vec<PathBuf>>,
+  /// Icon theme to find the icons of desktop entries in. Defaults to the GTK icon theme
+  #[serde(default)]
+  pub icon_theme: Option<String>,
+  /// Scale to pick theme icons at, 2 for HiDPI displays so they don't look blurry
+  #[serde(default = "default_icon_scale")]
+  pub icon_scale: u32,
+  /// How often to rescan the app paths in case a change went unnoticed, 0 to only rescan
+  /// when notified of changes
+  #[serde(default = "default_rescan_secs")]
+  pub rescan_secs: u64,
+}
+
+fn default_rescan_secs() -> u64 {
+  300
+}
+
+fn default_icon_scale() -> u32 {
+  1
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FilesConfig {
+  pub roots: Vec<FilesRoot>,
+  /// How deep below a root to look for files, unless the root says otherwise
+  pub max_depth: usize,
+  /// Globs of paths to leave out, on top of those in `.gitignore` and `.ignore` files.
+  /// They're matched relative to each root, eg `node_modules` or `**/*.log`
+  pub exclude: Vec<String>,
+  /// Whether to find hidden files and dirs
+  pub hidden: bool,
+  /// How many of the best matching files to offer up for each search
+  pub max_results: usize,
+}
+
+impl Default for FilesConfig {
+  fn default() -> Self {
+    FilesConfig {
+      roots: Vec::new(),
+      max_depth: 6,
+      exclude: Vec::new(),
+      hidden: false,
+      max_results: 50,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FilesRoot {
+  pub path: PathBuf,
+  #[serde(default)]
+  pub max_depth: Option<usize>,
+}
+
+impl Default for AppsConfig {
+  fn default() -> Self {
+    AppsConfig {
+      app_paths: Vec::new(),
+      app_extension: String::new(),
+      desktop_dirs: None,
+      icon_theme: None,
+      icon_scale: default_icon_scale(),
+      rescan_secs: default_rescan_secs(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExternalConfig {
+  /// Executable to spawn, which speaks the JSON lines protocol on stdin/stdout
+  pub command: String,
+  pub args: Vec<String>,
+  /// When set, the plugin only runs behind this prefix and is sent the search without it
+  pub prefix: Option<String>,
+  /// How long to wait on the plugin before giving up on a request
+  pub timeout_ms: u64,
+  /// How many times in a row the plugin may die before it's no longer restarted
+  pub max_restarts: usize,
+}
+
+impl Default for ExternalConfig {
+  fn default() -> Self {
+    ExternalConfig {
+      command: String::new(),
+      args: Vec::new(),
+      prefix: None,
+      timeout_ms: 500,
+      max_restarts: 3,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AlfredConfig {
+  /// The Script Filter to run. The query replaces an arg that's just `{query}`, or is
+  /// passed as the final arg when there is none, so scripts read it from `$1`
+  pub command: String,
+  pub args: Vec<String>,
+  /// Directory to run from, typically the workflow's folder so relative icons resolve
+  pub cwd: Option<PathBuf>,
+  /// Alfred keyword; when set the workflow only runs once it's typed followed by a space
+  pub keyword: Option<String>,
+  pub timeout_ms: u64,
+  /// What to do with the `arg` of the item that was actioned
+  pub action: AlfredAction,
+  /// The item modifier (cmd, alt, ctrl, shift, fn) whose alternative is used for the
+  /// secondary action
+  pub secondary_mod: String,
+}
+
+impl Default for AlfredConfig {
+  fn default() -> Self {
+    AlfredConfig {
+      command: String::new(),
+      args: Vec::new(),
+      cwd: None,
+      keyword: None,
+      timeout_ms: 1000,
+      action: AlfredAction::default(),
+      secondary_mod: "cmd".into(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub enum AlfredAction {
+  #[default]
+  Open,
+  Copy,
+  Command {
+    command: String,
+    args: Vec<String>,
+  },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
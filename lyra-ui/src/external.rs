@@ -0,0 +1,303 @@
+mod process;
+pub mod protocol;
+
+use crate::config::{Config, ExternalConfig};
+use crate::icon_ui::Icon;
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginName, PluginV, PluginValue, Renderable,
+  SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use egui::RichText;
+use parking_lot::Mutex;
+use process::PluginProcess;
+use protocol::{ExternalItem, Request};
+use std::{collections::HashMap, sync::Arc};
+use tracing::error;
+
+/// Config section holding every external plugin, keyed by the name it's enabled under
+pub const CONFIG_SECTION: &str = "external";
+
+/// A plugin living in its own process, written in whatever language the user likes. Lyra
+/// talks to it over stdin/stdout with one JSON message per line (see `protocol`), asking it
+/// for items matching the search and telling it when one of those items is actioned.
+pub struct ExternalPlugin {
+  name: PluginName,
+  cfg: ExternalConfig,
+  process: Mutex<PluginProcess>,
+  /// Actions come from the UI thread, so they go to an instance of their own rather than
+  /// waiting for the query worker to be done with `process`
+  actions: Mutex<PluginProcess>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExternalValue {
+  pub plugin: PluginName,
+  pub item: ExternalItem,
+}
+
+impl PluginValue for ExternalValue {
+  fn item_id(&self) -> Option<String> {
+    Some(self.item.label.clone())
+  }
+}
+impl Renderable for ExternalValue {
+  fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
+    let item = &self.item;
+    ui.horizontal(|ui| {
+      if let Some(Ok(ico)) = item
+        .icon
+        .as_ref()
+        .map(|icon| Icon::try_from((icon.as_str(), item.label.as_str())))
+      {
+        ico.render(ui);
+      }
+      ui.label(RichText::new(&item.label));
+      if let Some(subtitle) = &item.subtitle {
+        ui.label(RichText::new(subtitle).weak());
+      }
+    });
+  }
+}
+impl SearchBlocker for ExternalValue {}
+
+impl ExternalPlugin {
+  pub fn init(name: &str, cfg: ExternalConfig) -> Result<Self, anyhow::Error> {
+    Ok(ExternalPlugin {
+      name: name.to_string(),
+      process: Mutex::new(PluginProcess::new(name, cfg.clone())),
+      actions: Mutex::new(PluginProcess::new(name, cfg.clone())),
+      cfg,
+    })
+  }
+
+  pub fn name(&self) -> &PluginName {
+    &self.name
+  }
+}
+
+/// Each `[external.<name>]` section is a plugin of its own, enabled under that name
+pub fn register(registry: &mut PluginRegistry, config: &Config) -> Result<(), anyhow::Error> {
+  let plugins: HashMap<PluginName, ExternalConfig> = config.get().plugin_config(CONFIG_SECTION)?;
+  for (name, cfg) in plugins {
+    registry.register(&name, move |ctx| {
+      Ok(Box::new(ExternalPlugin::init(ctx.name, cfg.clone())?))
+    });
+  }
+  Ok(())
+}
+
+impl Plugin for ExternalPlugin {
+  type PV = ExternalValue;
+
+  fn prefix(&self) -> Option<String> {
+    self.cfg.prefix.clone()
+  }
+
+  fn action(&self, input: &ExternalValue) -> Result<OkAction, anyhow::Error> {
+    self
+      .actions
+      .lock()
+      .request(|id| Request::Action {
+        id,
+        item: &input.item,
+      })
+      .map(|resp| OkAction {
+        close_win: resp.close_win,
+        update_input: resp.update_input,
+        ..Default::default()
+      })
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    let query = match &self.cfg.prefix {
+      Some(pre) => search.strip_prefix(pre.as_str()).unwrap_or(search),
+      None => search,
+    };
+    let items = match self
+      .process
+      .lock()
+      .request(|id| Request::Query { id, query })
+    {
+      Ok(resp) => resp.items,
+      Err(err) => {
+        error!("{:?}", err);
+        return vec![];
+      }
+    };
+    items
+      .into_iter()
+      .map(|item| FuzzyMatchItem {
+        // Prefixed plugins own the search entirely, so match against the whole input
+        // to let everything they return through. Otherwise rank them with the rest
+        against: match self.cfg.prefix {
+          Some(_) => Arc::new(search.to_owned()),
+          None => Arc::new(item.label.clone()),
+        },
+        value: PluginV::new(
+          self.name.clone(),
+          ExternalValue {
+            plugin: self.name.clone(),
+            item,
+          },
+        ),
+        source: self.name.clone(),
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  // Echoes queries back as a single item and closes the window on any action. Queries
+  // for "slow" answer after the timeout, queries for "hang" never answer and queries for
+  // "crash" exit the process
+  const FAKE_PLUGIN: &str = r#"
+    while IFS= read -r line; do
+      id=$(printf '%s' "$line" | sed -n 's/^{"type":"[a-z]*","id":\([0-9]*\).*/\1/p')
+      case "$line" in
+        *'"query":"slow"'*) sleep 0.6; printf '{"id":%s,"items":[]}\n' "$id";;
+        *'"query":"hang"'*) sleep 5;;
+        *'"query":"crash"'*) exit 1;;
+        *'"query":"fail"'*) printf '{"id":%s,"error":"nope"}\n' "$id";;
+        *'"type":"query"'*)
+          q=$(printf '%s' "$line" | sed -n 's/.*"query":"\([^"]*\)".*/\1/p')
+          printf '{"id":%s,"items":[{"label":"hello %s","subtitle":"sub","action":{"q":"%s"}}]}\n' "$id" "$q" "$q";;
+        *'"type":"action"'*) printf '{"id":%s,"close_win":true,"update_input":"done"}\n' "$id";;
+      esac
+    done
+  "#;
+
+  fn fake(prefix: Option<&str>) -> ExternalPlugin {
+    ExternalPlugin::init(
+      "fake",
+      ExternalConfig {
+        command: "sh".into(),
+        args: vec!["-c".into(), FAKE_PLUGIN.into()],
+        prefix: prefix.map(|p| p.to_string()),
+        timeout_ms: 400,
+        max_restarts: 2,
+      },
+    )
+    .unwrap()
+  }
+
+  fn labels(items: Vec<FuzzyMatchItem>) -> Vec<String> {
+    items
+      .into_iter()
+      .map(|fm| {
+        fm.value
+          .downcast::<ExternalValue>()
+          .map(|v| v.item.label.clone())
+          .expect("Unexpected plugin value")
+      })
+      .collect()
+  }
+
+  #[test]
+  fn query_maps_items() {
+    let items = fake(None).options("dogs");
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].as_ref(), "hello dogs");
+    assert_eq!(items[0].source, "fake");
+    let v = items[0].value.downcast::<ExternalValue>().unwrap();
+    assert_eq!(v.item.subtitle, Some("sub".into()));
+    assert_eq!(v.item.action, json!({"q": "dogs"}));
+  }
+
+  #[test]
+  fn prefix_is_stripped_and_matches_whole_search() {
+    let items = fake(Some(">")).options(">dogs");
+    assert_eq!(items[0].as_ref(), ">dogs");
+    assert_eq!(labels(items), vec!["hello dogs".to_string()]);
+  }
+
+  #[test]
+  fn action_maps_outcome() {
+    let plugin = fake(None);
+    let value = ExternalValue {
+      plugin: "fake".into(),
+      item: ExternalItem {
+        label: "hello".into(),
+        ..Default::default()
+      },
+    };
+    let res = plugin.action(&value).unwrap();
+    assert!(res.close_win);
+    assert_eq!(res.update_input, Some("done".into()));
+  }
+
+  #[test]
+  fn actions_dont_wait_on_queries() {
+    let plugin = fake(None);
+    let _querying = plugin.process.lock();
+    let value = ExternalValue {
+      plugin: "fake".into(),
+      item: ExternalItem::default(),
+    };
+    assert!(plugin.action(&value).unwrap().close_win);
+  }
+
+  #[test]
+  fn plugin_errors_yield_no_items() {
+    assert!(fake(None).options("fail").is_empty());
+  }
+
+  #[test]
+  fn slow_responses_time_out_and_are_discarded() {
+    let plugin = fake(None);
+    assert!(plugin.options("slow").is_empty());
+    // The late answer to "slow" must not be mistaken for this one
+    assert_eq!(
+      labels(plugin.options("cats")),
+      vec!["hello cats".to_string()]
+    );
+  }
+
+  #[test]
+  fn crashed_plugin_restarts() {
+    let plugin = fake(None);
+    assert!(plugin.options("crash").is_empty());
+    assert_eq!(
+      labels(plugin.options("cats")),
+      vec!["hello cats".to_string()]
+    );
+  }
+
+  #[test]
+  fn hung_plugin_restarts_after_timeouts() {
+    let plugin = fake(None);
+    for _ in 0..3 {
+      assert!(plugin.options("hang").is_empty());
+    }
+    assert_eq!(
+      labels(plugin.options("cats")),
+      vec!["hello cats".to_string()]
+    );
+  }
+
+  #[test]
+  fn gives_up_after_max_restarts() {
+    let plugin = fake(None);
+    for _ in 0..3 {
+      assert!(plugin.options("crash").is_empty());
+    }
+    assert!(plugin.options("cats").is_empty());
+  }
+
+  #[test]
+  fn missing_command_errors() {
+    let plugin = ExternalPlugin::init(
+      "missing",
+      ExternalConfig {
+        command: "/definitely/not/a/plugin".into(),
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    assert!(plugin.options("dogs").is_empty());
+  }
+}
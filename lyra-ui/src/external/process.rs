@@ -0,0 +1,186 @@
+use super::protocol::{Request, Response};
+use crate::config::ExternalConfig;
+use anyhow::{anyhow, Context};
+use std::{
+  io::{BufRead, BufReader, Write},
+  process::{Child, ChildStdin, Command, ExitStatus, Stdio},
+  sync::mpsc::{self, Receiver, RecvTimeoutError},
+  thread,
+  time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// How many requests in a row can time out before the child is taken to be hung, and is
+/// killed to be started afresh on the next request
+const MAX_TIMEOUTS: usize = 3;
+
+/// Owns the child process backing an external plugin. The child is spawned lazily on the
+/// first request and respawned if it exits or hangs, up until it has failed `max_restarts`
+/// times in a row, at which point the plugin is considered broken and requests fail fast.
+pub struct PluginProcess {
+  name: String,
+  cfg: ExternalConfig,
+  running: Option<Running>,
+  next_id: u64,
+  failures: usize,
+  /// Requests in a row the running child hasn't answered in time
+  timeouts: usize,
+}
+
+struct Running {
+  child: Child,
+  stdin: ChildStdin,
+  lines: Receiver<String>,
+}
+
+impl PluginProcess {
+  pub fn new(name: &str, cfg: ExternalConfig) -> Self {
+    PluginProcess {
+      name: name.to_string(),
+      cfg,
+      running: None,
+      next_id: 0,
+      failures: 0,
+      timeouts: 0,
+    }
+  }
+
+  /// Sends the request built by `mk` (given the id to use) and waits up to the configured
+  /// timeout for the matching response. Responses to earlier requests that timed out are
+  /// discarded as they arrive.
+  pub fn request<'a>(
+    &mut self,
+    mk: impl FnOnce(u64) -> Request<'a>,
+  ) -> Result<Response, anyhow::Error> {
+    self.next_id += 1;
+    let req = mk(self.next_id);
+    let mut running = self.ensure_running()?;
+
+    let line = serde_json::to_string(&req)?;
+    if let Err(err) = writeln!(running.stdin, "{}", line).and_then(|_| running.stdin.flush()) {
+      self.failed(running);
+      return Err(anyhow!("Failed writing to plugin {}: {}", self.name, err));
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(self.cfg.timeout_ms);
+    loop {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      match running.lines.recv_timeout(remaining) {
+        Ok(line) => match serde_json::from_str::<Response>(&line) {
+          Ok(resp) if resp.id == req.id() => {
+            self.running = Some(running);
+            self.failures = 0;
+            self.timeouts = 0;
+            return match resp.error {
+              Some(err) => Err(anyhow!("Plugin {} failed: {}", self.name, err)),
+              None => Ok(resp),
+            };
+          }
+          Ok(resp) => info!("Discarding stale response {} from {}", resp.id, self.name),
+          Err(err) => warn!("Invalid response from {}: {} -> {:?}", self.name, err, line),
+        },
+        Err(RecvTimeoutError::Timeout) => {
+          self.timeouts += 1;
+          if self.timeouts >= MAX_TIMEOUTS {
+            self.failed(running);
+            return Err(anyhow!(
+              "Plugin {} timed out {} times in a row, restarting it",
+              self.name,
+              MAX_TIMEOUTS
+            ));
+          }
+          // Slow, not dead. Keep the child around and let its late answer be discarded
+          self.running = Some(running);
+          return Err(anyhow!(
+            "Plugin {} timed out after {}ms",
+            self.name,
+            self.cfg.timeout_ms
+          ));
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+          self.failed(running);
+          return Err(anyhow!("Plugin {} exited unexpectedly", self.name));
+        }
+      }
+    }
+  }
+
+  /// Stops the child, which is started again on the next request unless it's failed too
+  /// many times
+  fn failed(&mut self, running: Running) {
+    self.failures += 1;
+    self.timeouts = 0;
+    if let Some(status) = running.stop() {
+      info!("Plugin {} stopped: {}", self.name, status);
+    }
+  }
+
+  fn ensure_running(&mut self) -> Result<Running, anyhow::Error> {
+    if let Some(mut running) = self.running.take() {
+      if let Ok(None) = running.child.try_wait() {
+        return Ok(running);
+      }
+      self.failed(running);
+    }
+    if self.failures > self.cfg.max_restarts {
+      return Err(anyhow!(
+        "Plugin {} failed {} times in a row, not restarting",
+        self.name,
+        self.failures
+      ));
+    }
+    info!("Starting plugin {}: {}", self.name, self.cfg.command);
+    Running::spawn(&self.name, &self.cfg)
+  }
+}
+
+impl Running {
+  fn spawn(name: &str, cfg: &ExternalConfig) -> Result<Running, anyhow::Error> {
+    let mut child = Command::new(&cfg.command)
+      .args(&cfg.args)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .with_context(|| format!("Failed to start plugin {}: {}", name, cfg.command))?;
+    let stdin = child.stdin.take().context("Plugin stdin unavailable")?;
+    let stdout = child.stdout.take().context("Plugin stdout unavailable")?;
+    let stderr = child.stderr.take().context("Plugin stderr unavailable")?;
+
+    // Reading happens on dedicated threads so a request can give up waiting on a
+    // child that never answers. The stdout thread ending is how a crash is noticed
+    let (tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+      for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if tx.send(line).is_err() {
+          break;
+        }
+      }
+    });
+    let plugin = name.to_string();
+    thread::spawn(move || {
+      for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+        warn!("[{}] {}", plugin, line);
+      }
+    });
+
+    Ok(Running {
+      child,
+      stdin,
+      lines,
+    })
+  }
+
+  /// Kills the child if it's still going and waits on it, so it isn't left a zombie
+  fn stop(mut self) -> Option<ExitStatus> {
+    let _ = self.child.kill();
+    self.child.wait().ok()
+  }
+}
+
+impl Drop for Running {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
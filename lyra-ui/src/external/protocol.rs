@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Messages sent to an external plugin, one JSON object per line on its stdin.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request<'a> {
+  Query { id: u64, query: &'a str },
+  Action { id: u64, item: &'a ExternalItem },
+}
+
+impl Request<'_> {
+  pub fn id(&self) -> u64 {
+    match self {
+      Request::Query { id, .. } => *id,
+      Request::Action { id, .. } => *id,
+    }
+  }
+}
+
+/// Messages read back from an external plugin's stdout, one JSON object per line. The
+/// id ties the response back to the request that caused it; anything else is optional
+/// so plugins only need to send what is relevant to the request:
+///   - queries respond with `items`
+///   - actions respond with `close_win` and/or `update_input`
+///   - either can respond with `error` instead
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Response {
+  pub id: u64,
+  pub items: Vec<ExternalItem>,
+  pub close_win: bool,
+  pub update_input: Option<String>,
+  pub error: Option<String>,
+}
+
+/// A single result contributed by an external plugin. The action payload is opaque to
+/// Lyra and is handed back verbatim when the item is actioned. It's all the plugin has to
+/// go on then, as actions may go to another instance of it than the one that answered.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct ExternalItem {
+  pub label: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub subtitle: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub icon: Option<String>,
+  #[serde(default)]
+  pub action: Value,
+}
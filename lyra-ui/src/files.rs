@@ -0,0 +1,92 @@
+mod fileindex;
+
+use crate::config::FilesConfig;
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginValue, Renderable, SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use anyhow::anyhow;
+use egui::RichText;
+use fileindex::FileIndex;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, sync::Arc};
+
+pub const PLUGIN_NAME: &str = "files";
+
+/// Finds files under the configured roots by name or path, opening them with whatever the
+/// OS would
+pub struct FilesPlugin {
+  index: Arc<FileIndex>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileHit {
+  /// Path relative to the root it was found in
+  pub rel: String,
+  pub path: String,
+  pub is_dir: bool,
+}
+impl PluginValue for FileHit {
+  fn item_id(&self) -> Option<String> {
+    Some(self.path.clone())
+  }
+}
+impl Renderable for FileHit {
+  fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
+    let name = Path::new(&self.rel)
+      .file_name()
+      .map(|n| n.to_string_lossy().to_string())
+      .unwrap_or_else(|| self.rel.clone());
+    let suffix = if self.is_dir { "/" } else { "" };
+    ui.horizontal(|ui| {
+      ui.label(RichText::new(format!("{}{}", name, suffix)));
+      ui.label(RichText::new(&self.rel).weak().small());
+    });
+  }
+}
+impl SearchBlocker for FileHit {}
+
+impl FilesPlugin {
+  pub fn init(cfg: FilesConfig) -> Result<Self, anyhow::Error> {
+    let index = Arc::new(FileIndex::new(cfg)?);
+    // Roots can be big, so the first build happens in the background along with the watching
+    index.watch();
+    Ok(FilesPlugin { index })
+  }
+}
+
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |ctx| {
+    Ok(Box::new(FilesPlugin::init(ctx.section()?)?))
+  });
+}
+
+impl Plugin for FilesPlugin {
+  type PV = FileHit;
+
+  fn action(&self, input: &FileHit) -> Result<OkAction, anyhow::Error> {
+    open::that(&input.path)
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Failed to open {:?}, err: {:?}", input.path, err))
+  }
+
+  /// Reveals the file in the file manager rather than opening it
+  fn secondary_action(&self, input: &FileHit) -> Result<OkAction, anyhow::Error> {
+    let dir = Path::new(&input.path)
+      .parent()
+      .ok_or_else(|| anyhow!("{} has no containing folder", input.path))?;
+    open::that(dir)
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Reveal failed for {:?}, err: {:?}", input.path, err))
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    self.index.options(search)
+  }
+}
@@ -0,0 +1,562 @@
+use super::{FileHit, PLUGIN_NAME};
+use crate::config::FilesConfig;
+use crate::plugin::{FuzzyMatchItem, PluginV};
+use anyhow::Context;
+use ignore::{
+  overrides::{Override, OverrideBuilder},
+  WalkBuilder,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use nucleo_matcher::{
+  pattern::{CaseMatching, Pattern},
+  Config as NucleoConfig, Matcher,
+};
+use parking_lot::{Mutex, RwLock};
+use std::{
+  cmp::Reverse,
+  collections::{BTreeMap, HashSet},
+  path::{Path, PathBuf},
+  sync::{
+    mpsc::{self, RecvTimeoutError},
+    Arc, Weak,
+  },
+  thread,
+  time::Duration,
+};
+use tracing::{error, info, warn};
+
+/// Changes tend to come as a burst of events (checkouts, builds, ...), so wait for them to
+/// quiet down before updating
+const SETTLE: Duration = Duration::from_millis(300);
+/// Files whose changes can hide or reveal others in the same dir
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Every file under the roots, ready to be matched against. The index is built in the
+/// background, then only the parts of it that change are redone.
+pub struct FileIndex {
+  roots: Vec<Root>,
+  max_results: usize,
+  files: RwLock<BTreeMap<PathBuf, IndexedFile>>,
+  matcher: Mutex<Matcher>,
+}
+
+struct Root {
+  path: PathBuf,
+  max_depth: usize,
+  hidden: bool,
+  exclude: Override,
+}
+
+struct IndexedFile {
+  rel: Arc<String>,
+  is_dir: bool,
+}
+
+/// A file being matched, which is matched by its path relative to its root
+struct Candidate<'a>(&'a Path, &'a IndexedFile);
+
+impl AsRef<str> for Candidate<'_> {
+  fn as_ref(&self) -> &str {
+    self.1.rel.as_str()
+  }
+}
+
+impl Root {
+  fn new(path: &Path, max_depth: usize, cfg: &FilesConfig) -> Result<Root, anyhow::Error> {
+    let mut exclude = OverrideBuilder::new(path);
+    for glob in &cfg.exclude {
+      // Overrides are a whitelist, so excluding means negating
+      exclude
+        .add(&format!("!{}", glob))
+        .with_context(|| format!("Invalid exclude glob {:?}", glob))?;
+    }
+    Ok(Root {
+      path: path.to_path_buf(),
+      max_depth,
+      hidden: cfg.hidden,
+      exclude: exclude.build()?,
+    })
+  }
+
+  /// Everything under the dir that isn't ignored, going at most `depth` levels down. The dir
+  /// itself has to be in the root, and is assumed to not be ignored.
+  fn walk(&self, dir: &Path, depth: Option<usize>) -> Vec<(PathBuf, IndexedFile)> {
+    let below = match dir.strip_prefix(&self.path) {
+      Ok(rel) => rel.components().count(),
+      Err(_) => return vec![],
+    };
+    let Some(remaining) = self.max_depth.checked_sub(below) else {
+      return vec![];
+    };
+    WalkBuilder::new(dir)
+      .max_depth(Some(depth.map_or(remaining, |d| d.min(remaining))))
+      .hidden(!self.hidden)
+      // Plenty of dirs have a .gitignore without being a repo
+      .require_git(false)
+      .overrides(self.exclude.clone())
+      .build()
+      .filter_map(|entry| {
+        entry
+          .map_err(|err| warn!("Skipping file in {:?}: {}", dir, err))
+          .ok()
+      })
+      .filter(|entry| entry.depth() > 0)
+      .filter_map(|entry| {
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        let path = entry.into_path();
+        let rel = path
+          .strip_prefix(&self.path)
+          .ok()?
+          .to_string_lossy()
+          .to_string();
+        let file = IndexedFile {
+          rel: Arc::new(rel),
+          is_dir,
+        };
+        Some((path, file))
+      })
+      .collect()
+  }
+}
+
+impl FileIndex {
+  pub fn new(cfg: FilesConfig) -> Result<Self, anyhow::Error> {
+    let roots = cfg
+      .roots
+      .iter()
+      .map(|root| Root::new(&root.path, root.max_depth.unwrap_or(cfg.max_depth), &cfg))
+      .collect::<Result<_, _>>()?;
+    let mut matcher = NucleoConfig::DEFAULT;
+    matcher.set_match_paths();
+    Ok(FileIndex {
+      roots,
+      max_results: cfg.max_results,
+      files: RwLock::default(),
+      matcher: Mutex::new(Matcher::new(matcher)),
+    })
+  }
+
+  /// The files best matching the search. Files are only offered once something's typed,
+  /// there's far too many of them otherwise
+  pub fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    if search.trim().is_empty() {
+      return vec![];
+    }
+    let files = self.files.read();
+    let candidates = files.iter().map(|(path, file)| Candidate(path, file));
+    let mut matches =
+      Pattern::parse(search, CaseMatching::Ignore).match_list(candidates, &mut self.matcher.lock());
+    matches.sort_by_key(|(_, score)| Reverse(*score));
+    matches
+      .into_iter()
+      .take(self.max_results)
+      .map(|(Candidate(path, file), _)| FuzzyMatchItem {
+        against: file.rel.clone(),
+        value: PluginV::new(
+          PLUGIN_NAME,
+          FileHit {
+            rel: file.rel.to_string(),
+            path: path.to_string_lossy().to_string(),
+            is_dir: file.is_dir,
+          },
+        ),
+        source: PLUGIN_NAME.to_string(),
+      })
+      .collect()
+  }
+
+  /// Rebuilds the whole index
+  pub fn refresh(&self) {
+    let files = self
+      .roots
+      .iter()
+      .flat_map(|root| root.walk(&root.path, None))
+      .collect();
+    *self.files.write() = files;
+  }
+
+  /// Brings the index up to date with changes to the given paths, redoing the dirs they're in
+  /// rather than the whole index
+  pub fn apply(&self, changed: impl IntoIterator<Item = PathBuf>) {
+    let mut changed: Vec<_> = changed
+      .into_iter()
+      .filter_map(|path| match path.file_name().and_then(|n| n.to_str()) {
+        // Whatever was ignored next to it may not be anymore, or the other way round
+        Some(name) if IGNORE_FILES.contains(&name) => path.parent().map(Path::to_path_buf),
+        _ => Some(path),
+      })
+      .collect();
+    changed.sort();
+    // Changes within a changed dir are picked up along with it
+    changed.dedup_by(|path, dir| path.starts_with(dir));
+
+    for path in changed {
+      let Some(root) = self.roots.iter().find(|r| path.starts_with(&r.path)) else {
+        continue;
+      };
+      if path == root.path {
+        self.refresh();
+        return;
+      }
+      self.update(root, &path);
+    }
+  }
+
+  fn update(&self, root: &Root, path: &Path) {
+    let Some(parent) = path.parent() else {
+      return;
+    };
+    // Nothing's found under dirs that aren't in the index themselves
+    if parent != root.path && !self.files.read().contains_key(parent) {
+      self.files.write().retain(|p, _| !p.starts_with(path));
+      return;
+    }
+    // Whether the path should be indexed depends on its siblings' ignore rules and its
+    // parent's place in the root, so look at it from there
+    let siblings = root.walk(parent, Some(1));
+    let listed = siblings.iter().find(|(p, _)| p == path);
+    let below = match listed {
+      Some((_, file)) if file.is_dir => root.walk(path, None),
+      _ => vec![],
+    };
+
+    let mut files = self.files.write();
+    files.retain(|p, _| p.parent() != Some(parent) && !p.starts_with(path));
+    files.extend(siblings);
+    files.extend(below);
+  }
+
+  /// The dirs whose changes can change the index: the roots, and every dir in the index
+  /// that's not so deep its contents are left out
+  fn watched_dirs(&self) -> HashSet<PathBuf> {
+    let files = self.files.read();
+    let dirs = files
+      .iter()
+      .filter(|(_, f)| f.is_dir)
+      .filter_map(|(path, _)| {
+        let root = self.roots.iter().find(|r| path.starts_with(&r.path))?;
+        let depth = path.strip_prefix(&root.path).ok()?.components().count();
+        (depth < root.max_depth).then(|| path.clone())
+      });
+    self.root_dirs().into_iter().chain(dirs).collect()
+  }
+
+  fn root_dirs(&self) -> HashSet<PathBuf> {
+    self
+      .roots
+      .iter()
+      .filter(|r| r.path.exists())
+      .map(|r| r.path.clone())
+      .collect()
+  }
+
+  /// Builds the index, then keeps it up to date until it's dropped
+  pub fn watch(self: &Arc<Self>) {
+    let index = Arc::downgrade(self);
+    let spawned = thread::Builder::new()
+      .name("files-index".into())
+      .spawn(move || run_updater(index));
+    if let Err(err) = spawned {
+      error!("Failed to start file index updater: {}", err);
+    }
+  }
+}
+
+fn run_updater(index: Weak<FileIndex>) {
+  let (tx, rx) = mpsc::channel();
+  let mut watcher = match index.upgrade() {
+    Some(idx) => {
+      let mut watcher = notify::recommended_watcher(tx)
+        .map(DirWatcher::new)
+        .map_err(|err| warn!("Not watching file roots for changes: {:?}", err))
+        .ok();
+      // Watch the roots before building so nothing that changes in them during the build is
+      // missed
+      if let Some(watcher) = &mut watcher {
+        watcher.sync(idx.root_dirs());
+      }
+      idx.refresh();
+      info!("Built file index of {} files", idx.files.read().len());
+      // Everything was only just walked, so the dirs are watched without going over them
+      // again the way `watch_new_dirs` does
+      if let Some(watcher) = &mut watcher {
+        watcher.sync(idx.watched_dirs());
+      }
+      watcher
+    }
+    None => return,
+  };
+
+  loop {
+    let mut changed = vec![];
+    let mut rescan = false;
+    let mut collect = |event: notify::Result<notify::Event>| match event {
+      Ok(event) => {
+        rescan |= event.need_rescan();
+        changed.extend(event.paths);
+      }
+      Err(err) => warn!("File watch error: {}", err),
+    };
+    match rx.recv() {
+      Ok(event) => collect(event),
+      Err(_) => return,
+    }
+    loop {
+      match rx.recv_timeout(SETTLE) {
+        Ok(event) => collect(event),
+        Err(RecvTimeoutError::Timeout) => break,
+        Err(RecvTimeoutError::Disconnected) => return,
+      }
+    }
+    let Some(index) = index.upgrade() else {
+      return;
+    };
+    if rescan {
+      index.refresh();
+    } else {
+      index.apply(changed);
+    }
+    if let Some(watcher) = &mut watcher {
+      watch_new_dirs(&index, watcher);
+    }
+  }
+}
+
+/// Watches the dirs that are new to the index. They could have changed between being indexed
+/// and watched, so they're gone over again once they are.
+fn watch_new_dirs(index: &FileIndex, watcher: &mut DirWatcher) {
+  let mut added = watcher.sync(index.watched_dirs());
+  while !added.is_empty() {
+    index.apply(added);
+    added = watcher.sync(index.watched_dirs());
+  }
+}
+
+/// Watches each dir on its own rather than whole trees, so the dirs that are ignored or too
+/// deep to be indexed aren't watched
+struct DirWatcher {
+  watcher: RecommendedWatcher,
+  watched: HashSet<PathBuf>,
+}
+
+impl DirWatcher {
+  fn new(watcher: RecommendedWatcher) -> Self {
+    DirWatcher {
+      watcher,
+      watched: HashSet::new(),
+    }
+  }
+
+  /// Starts watching the dirs that aren't yet and stops watching those no longer there,
+  /// handing back the newly watched ones. Dirs that can't be watched are only complained about
+  /// and don't stop the rest from being watched, they're tried again on the next sync.
+  fn sync(&mut self, mut dirs: HashSet<PathBuf>) -> Vec<PathBuf> {
+    for gone in self.watched.difference(&dirs) {
+      // Removed dirs stop being watched on their own
+      let _ = self.watcher.unwatch(gone);
+    }
+    let mut added = vec![];
+    let mut failed = vec![];
+    for dir in dirs.difference(&self.watched) {
+      match self.watcher.watch(dir, RecursiveMode::NonRecursive) {
+        Ok(()) => added.push(dir.clone()),
+        Err(err) => failed.push((dir.clone(), err)),
+      }
+    }
+    if let Some((dir, err)) = failed.first() {
+      warn!(
+        "Not watching {} dirs for changes, like {:?}: {}",
+        failed.len(),
+        dir,
+        err
+      );
+    }
+    for (dir, _) in &failed {
+      dirs.remove(dir);
+    }
+    self.watched = dirs;
+    added
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::FilesRoot;
+  use crate::testdir::TestDir;
+  use std::{fs, time::Instant};
+
+  fn write(root: &Path, rel: &str, contents: &str) {
+    let path = root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+  }
+
+  /// A tree with a bit of everything that should and shouldn't be found
+  fn fixture(name: &str) -> (TestDir, FileIndex) {
+    let root = TestDir::new(&format!("files-{}", name));
+    write(&root, ".gitignore", "*.log\nbuild/\n");
+    write(&root, "a.txt", "");
+    write(&root, "x.log", "");
+    write(&root, ".hidden", "");
+    write(&root, "build/out.bin", "");
+    write(&root, "node_modules/m.js", "");
+    write(&root, "sub/b.rs", "");
+    write(&root, "sub/.ignore", "skip.txt\n");
+    write(&root, "sub/skip.txt", "");
+    write(&root, "sub/deep/c.rs", "");
+    write(&root, "sub/deep/deeper/d.rs", "");
+    let index = FileIndex::new(FilesConfig {
+      roots: vec![FilesRoot {
+        path: root.to_path_buf(),
+        max_depth: Some(3),
+      }],
+      exclude: vec!["node_modules".into()],
+      ..Default::default()
+    })
+    .unwrap();
+    index.refresh();
+    (root, index)
+  }
+
+  fn rels(index: &FileIndex) -> Vec<String> {
+    let files = index.files.read();
+    files.values().map(|f| f.rel.to_string()).collect()
+  }
+
+  fn matches(index: &FileIndex, search: &str) -> Vec<String> {
+    index
+      .options(search)
+      .iter()
+      .map(|fm| fm.value.downcast::<FileHit>().unwrap().rel.clone())
+      .collect()
+  }
+
+  #[test]
+  fn indexes_roots_with_ignore_rules() {
+    let (_root, index) = fixture("index");
+    assert_eq!(
+      rels(&index),
+      vec![
+        "a.txt",
+        "sub",
+        "sub/b.rs",
+        "sub/deep",
+        "sub/deep/c.rs",
+        "sub/deep/deeper"
+      ]
+    );
+  }
+
+  #[test]
+  fn matches_names_and_paths() {
+    let (_root, mut index) = fixture("match");
+    assert_eq!(matches(&index, "b.rs")[0], "sub/b.rs");
+    assert_eq!(matches(&index, "deep/c"), vec!["sub/deep/c.rs"]);
+    assert!(matches(&index, "").is_empty());
+    assert!(matches(&index, "zzz").is_empty());
+
+    index.max_results = 1;
+    assert_eq!(matches(&index, "rs").len(), 1);
+  }
+
+  #[test]
+  fn applies_changes_incrementally() {
+    let (root, index) = fixture("apply");
+    write(&root, "new.txt", "");
+    write(&root, "newdir/e.txt", "");
+    write(&root, "more.log", "");
+    write(&root, "build/more/f.txt", "");
+    index.apply(vec![
+      root.join("new.txt"),
+      root.join("newdir"),
+      root.join("newdir/e.txt"),
+      root.join("more.log"),
+      root.join("build/more"),
+    ]);
+    let found = rels(&index);
+    assert!(found.contains(&"new.txt".to_string()));
+    assert!(found.contains(&"newdir/e.txt".to_string()));
+    assert!(!found.iter().any(|f| f.contains("more")));
+
+    fs::remove_dir_all(root.join("sub/deep")).unwrap();
+    index.apply(vec![root.join("sub/deep")]);
+    assert!(!rels(&index).iter().any(|f| f.starts_with("sub/deep")));
+
+    // Ignore rules changing redo the dir they're in
+    write(&root, "sub/.ignore", "b.rs\n");
+    index.apply(vec![root.join("sub/.ignore")]);
+    let under_sub: Vec<_> = rels(&index)
+      .into_iter()
+      .filter(|f| f.starts_with("sub/"))
+      .collect();
+    assert_eq!(under_sub, vec!["sub/skip.txt"]);
+  }
+
+  #[test]
+  fn watcher_builds_and_updates() {
+    let (root, _) = fixture("watch");
+    let index = Arc::new(
+      FileIndex::new(FilesConfig {
+        roots: vec![FilesRoot {
+          path: root.to_path_buf(),
+          max_depth: None,
+        }],
+        ..Default::default()
+      })
+      .unwrap(),
+    );
+    index.watch();
+    let wait_for = |rel: &str| {
+      let until = Instant::now() + Duration::from_secs(5);
+      while !rels(&index).contains(&rel.to_string()) && Instant::now() < until {
+        thread::sleep(Duration::from_millis(50));
+      }
+      assert!(
+        rels(&index).contains(&rel.to_string()),
+        "{} never indexed",
+        rel
+      );
+    };
+    wait_for("sub/deep/deeper/d.rs");
+
+    write(&root, "sub/g.txt", "");
+    wait_for("sub/g.txt");
+
+    // New dirs get watched too
+    write(&root, "new/inner/h.txt", "");
+    wait_for("new/inner/h.txt");
+    write(&root, "new/inner/i.txt", "");
+    wait_for("new/inner/i.txt");
+  }
+
+  #[test]
+  fn retries_dirs_that_failed_to_be_watched() {
+    let root = TestDir::new("files-retry");
+    let (tx, _rx) = mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher = DirWatcher::new(notify::recommended_watcher(tx).unwrap());
+    let dir = root.join("later");
+    assert!(watcher.sync(HashSet::from([dir.clone()])).is_empty());
+    fs::create_dir(&dir).unwrap();
+    assert_eq!(watcher.sync(HashSet::from([dir.clone()])), vec![dir]);
+  }
+
+  #[test]
+  fn watches_only_indexed_dirs() {
+    let (root, index) = fixture("watched");
+    let mut watched: Vec<_> = index
+      .watched_dirs()
+      .into_iter()
+      .map(|dir| {
+        dir
+          .strip_prefix(&root)
+          .unwrap()
+          .to_string_lossy()
+          .to_string()
+      })
+      .collect();
+    watched.sort();
+    // Too deep to have anything indexed in it, ignored or excluded
+    assert_eq!(watched, vec!["", "sub", "sub/deep"]);
+  }
+}
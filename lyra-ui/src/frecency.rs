@@ -0,0 +1,309 @@
+use crate::{
+  cacher::Cache,
+  plugin::{PluginName, PluginV},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  path::PathBuf,
+  time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::error;
+
+/// Launches older than the newest this many are forgotten
+const MAX_LAUNCHES: usize = 2000;
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Remembers what gets launched so the things used most, and most recently, can be ranked
+/// above the rest. Kept in the cache dir since it's history rather than configuration.
+#[derive(Default)]
+pub struct Frecency(Cache<UsageHistory>);
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct UsageHistory {
+  launches: Vec<Launch>,
+  /// Items whose usage is neither recorded nor ranked on
+  excluded: Vec<ItemKey>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Launch {
+  #[serde(flatten)]
+  key: ItemKey,
+  query: String,
+  /// Seconds since the unix epoch
+  at: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ItemKey {
+  pub plugin: PluginName,
+  pub item: String,
+}
+
+/// How an item has been used, as shown when managing the history
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemUsage {
+  pub key: ItemKey,
+  pub launches: usize,
+  pub score: f64,
+}
+
+impl ItemKey {
+  pub fn of(value: &PluginV) -> Option<ItemKey> {
+    value.item_id().map(|item| ItemKey {
+      plugin: value.id(),
+      item,
+    })
+  }
+}
+
+impl Frecency {
+  pub fn init(cache_file: PathBuf) -> Result<Self, anyhow::Error> {
+    Cache::load(cache_file).map(Frecency)
+  }
+
+  /// Records that the value was launched after searching for the query
+  pub fn record(&self, value: &PluginV, query: &str) {
+    self.record_at(value, query, now())
+  }
+
+  fn record_at(&self, value: &PluginV, query: &str, at: i64) {
+    let Some(key) = ItemKey::of(value) else {
+      return;
+    };
+    if self.0.get().excluded.contains(&key) {
+      return;
+    }
+    self.0.update(|mut history| {
+      history.launches.push(Launch {
+        key,
+        query: query.to_owned(),
+        at,
+      });
+      let over = history.launches.len().saturating_sub(MAX_LAUNCHES);
+      history.launches.drain(..over);
+    });
+    self.persist();
+  }
+
+  /// Scores every launched item, favouring recent launches over old ones. Launches from a
+  /// search the current one builds upon count double, since they're likely after the same
+  /// thing again.
+  pub fn scores(&self, query: &str) -> HashMap<ItemKey, f64> {
+    self.scores_at(query, now())
+  }
+
+  fn scores_at(&self, query: &str, now: i64) -> HashMap<ItemKey, f64> {
+    let history = self.0.get();
+    let query = query.to_lowercase();
+    let mut scores = HashMap::new();
+    for launch in &history.launches {
+      if history.excluded.contains(&launch.key) {
+        continue;
+      }
+      let mut weight = recency_weight(now - launch.at);
+      if !launch.query.is_empty() && query.starts_with(&launch.query.to_lowercase()) {
+        weight *= 2.0;
+      }
+      *scores.entry(launch.key.clone()).or_insert(0.0) += weight;
+    }
+    scores
+  }
+
+  /// Every item in the history, most used first
+  pub fn usage(&self) -> Vec<ItemUsage> {
+    let scores = self.scores("");
+    let mut usage: Vec<ItemUsage> = self
+      .0
+      .get()
+      .launches
+      .iter()
+      .fold(HashMap::new(), |mut counts, launch| {
+        *counts.entry(&launch.key).or_insert(0) += 1;
+        counts
+      })
+      .into_iter()
+      .map(|(key, launches)| ItemUsage {
+        score: scores.get(key).copied().unwrap_or_default(),
+        key: key.clone(),
+        launches,
+      })
+      .collect();
+    usage.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.key.cmp(&b.key)));
+    usage
+  }
+
+  pub fn excluded(&self) -> Vec<ItemKey> {
+    self.0.get().excluded.clone()
+  }
+
+  /// Forgets every launch of the item
+  pub fn reset_item(&self, key: &ItemKey) {
+    self
+      .0
+      .update(|mut history| history.launches.retain(|l| l.key != *key));
+    self.persist();
+  }
+
+  /// Forgets everything ever launched, leaving exclusions in place
+  pub fn reset(&self) {
+    self.0.update(|mut history| history.launches.clear());
+    self.persist();
+  }
+
+  /// Stops the item from being recorded or boosted, forgetting its history
+  pub fn exclude(&self, key: ItemKey) {
+    self.0.update(|mut history| {
+      history.launches.retain(|l| l.key != key);
+      if !history.excluded.contains(&key) {
+        history.excluded.push(key);
+      }
+    });
+    self.persist();
+  }
+
+  pub fn include(&self, key: &ItemKey) {
+    self
+      .0
+      .update(|mut history| history.excluded.retain(|k| k != key));
+    self.persist();
+  }
+
+  fn persist(&self) {
+    if let Err(err) = self.0.persist() {
+      error!("Failed to save usage history: {:?}", err);
+    }
+  }
+}
+
+/// How much a launch counts for given how long ago it was, in seconds
+fn recency_weight(age: i64) -> f64 {
+  match age / DAY_SECS {
+    0..=3 => 1.0,
+    4..=13 => 0.7,
+    14..=30 => 0.5,
+    31..=89 => 0.3,
+    _ => 0.1,
+  }
+}
+
+/// Turns a frecency score into a multiplier for the match score. Logarithmic so constant
+/// use can't drown out how well something actually matches what was typed
+pub fn boost(score: f64) -> f64 {
+  1.0 + (1.0 + score).ln() / 4.0
+}
+
+fn now() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::plugin::{AppState, PluginValue, Renderable, SearchBlocker};
+  use crate::testdir::TestDir;
+
+  #[derive(Clone)]
+  struct Item(Option<&'static str>);
+
+  impl PluginValue for Item {
+    fn item_id(&self) -> Option<String> {
+      self.0.map(|s| s.to_string())
+    }
+  }
+  impl Renderable for Item {
+    fn render(&self, _ui: &mut egui::Ui, _state: &AppState) {}
+  }
+  impl SearchBlocker for Item {}
+
+  fn frecency(name: &str) -> (Frecency, TestDir) {
+    let dir = TestDir::new(name);
+    (Frecency::init(dir.join("frecency.toml")).unwrap(), dir)
+  }
+
+  fn key(item: &str) -> ItemKey {
+    ItemKey {
+      plugin: "apps".into(),
+      item: item.into(),
+    }
+  }
+
+  fn value(item: &'static str) -> PluginV {
+    PluginV::new("apps", Item(Some(item)))
+  }
+
+  #[test]
+  fn recent_and_frequent_items_score_higher() {
+    let (fr, _dir) = frecency("frecency-score");
+    let now = now();
+    for _ in 0..3 {
+      fr.record_at(&value("Safari"), "", now);
+    }
+    fr.record_at(&value("Notes"), "", now);
+    fr.record_at(&value("Maps"), "", now - 100 * DAY_SECS);
+    fr.record_at(&PluginV::new("calc", Item(None)), "=1", now);
+
+    let scores = fr.scores_at("", now);
+    assert_eq!(scores.len(), 3);
+    assert!(scores[&key("Safari")] > scores[&key("Notes")]);
+    assert!(scores[&key("Notes")] > scores[&key("Maps")]);
+  }
+
+  #[test]
+  fn matching_queries_count_double() {
+    let (fr, _dir) = frecency("frecency-query");
+    let now = now();
+    fr.record_at(&value("Safari"), "saf", now);
+    fr.record_at(&value("Slack"), "sl", now);
+
+    let scores = fr.scores_at("Safa", now);
+    assert_eq!(scores[&key("Safari")], 2.0);
+    assert_eq!(scores[&key("Slack")], 1.0);
+  }
+
+  #[test]
+  fn history_is_persisted() {
+    let (fr, dir) = frecency("frecency-persist");
+    fr.record(&value("Safari"), "saf");
+    let reloaded = Frecency::init(dir.join("frecency.toml")).unwrap();
+    assert_eq!(reloaded.usage()[0].key, key("Safari"));
+    assert_eq!(reloaded.usage()[0].launches, 1);
+  }
+
+  #[test]
+  fn reset_and_exclude() {
+    let (fr, _dir) = frecency("frecency-reset");
+    fr.record(&value("Safari"), "");
+    fr.record(&value("Notes"), "");
+    fr.record(&value("Maps"), "");
+
+    fr.reset_item(&key("Notes"));
+    assert!(!fr.scores("").contains_key(&key("Notes")));
+
+    fr.exclude(key("Safari"));
+    fr.record(&value("Safari"), "");
+    assert!(!fr.scores("").contains_key(&key("Safari")));
+    assert_eq!(fr.excluded(), vec![key("Safari")]);
+
+    fr.reset();
+    assert!(fr.usage().is_empty());
+    // Exclusions outlive a reset until the item is included again
+    fr.record(&value("Safari"), "");
+    assert!(fr.usage().is_empty());
+    fr.include(&key("Safari"));
+    fr.record(&value("Safari"), "");
+    assert_eq!(fr.usage().len(), 1);
+  }
+
+  #[test]
+  fn boost_grows_slowly() {
+    assert_eq!(boost(0.0), 1.0);
+    assert!(boost(1.0) > 1.0);
+    assert!(boost(50.0) < 2.0);
+  }
+}
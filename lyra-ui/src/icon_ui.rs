@@ -3,7 +3,8 @@ use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::engine::Engine as _;
 use egui::{Image, Ui};
 use reqwest::header::CONTENT_TYPE;
-use tracing::info;
+use std::{fs, path::Path};
+use tracing::{info, warn};
 
 pub struct Icon<'a>(Image<'a>);
 impl TryFrom<(&str, &str)> for Icon<'_> {
@@ -35,6 +36,21 @@ pub fn data_or_url(value: &str) -> Result<String, anyhow::Error> {
     .or_else(|_| convert_image(value))
 }
 
+/// Reads an image file into a data url, if it's a format that can be rendered
+pub fn file_data_url(p: &Path) -> Option<String> {
+  let mime = match p.extension()?.to_str()? {
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "svg" => "image/svg+xml",
+    "ico" => "image/vnd.microsoft.icon",
+    _ => return None,
+  };
+  fs::read(p)
+    .map(|bytes| format!("data:{};base64,{}", mime, BASE64.encode(bytes)))
+    .map_err(|err| warn!("Failed to read icon {:?}: {}", p, err))
+    .ok()
+}
+
 fn parse_image_data(s: &str) -> Option<(String, String)> {
   let prefixes = vec![
     ("image/svg+xml", "svg"),
@@ -0,0 +1,450 @@
+use anyhow::anyhow;
+use derive_more::Display;
+use egui::{Event, InputState, Key, Modifiers};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap, fmt, str::FromStr};
+
+/// Something the powerbar can be told to do from the keyboard
+#[derive(
+  Clone, Copy, Debug, Default, Display, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+  #[default]
+  #[display(fmt = "next")]
+  Next,
+  #[display(fmt = "prev")]
+  Prev,
+  #[display(fmt = "page_down")]
+  PageDown,
+  #[display(fmt = "page_up")]
+  PageUp,
+  #[display(fmt = "first")]
+  First,
+  #[display(fmt = "last")]
+  Last,
+  #[display(fmt = "confirm")]
+  Confirm,
+  #[display(fmt = "secondary_action")]
+  SecondaryAction,
+  /// Fills the search in from the selected item when its plugin can, otherwise moves on to
+  /// the next item
+  #[display(fmt = "complete")]
+  Complete,
+  #[display(fmt = "clear_input")]
+  ClearInput,
+  #[display(fmt = "close")]
+  Close,
+  /// Leaves the chord to the search input, for undoing a binding from the preset
+  #[display(fmt = "ignore")]
+  Ignore,
+}
+
+impl Command {
+  pub const ALL: [Command; 12] = [
+    Command::Next,
+    Command::Prev,
+    Command::PageDown,
+    Command::PageUp,
+    Command::First,
+    Command::Last,
+    Command::Confirm,
+    Command::SecondaryAction,
+    Command::Complete,
+    Command::ClearInput,
+    Command::Close,
+    Command::Ignore,
+  ];
+
+  /// Whether it waits for the key to be let go of rather than going as it's pressed, so
+  /// whatever gets launched doesn't see the rest of the key press
+  pub fn on_release(&self) -> bool {
+    matches!(self, Command::Confirm | Command::SecondaryAction)
+  }
+}
+
+/// A key along with the modifiers that need to be held for it, written like `Ctrl+Shift+N`.
+/// `CmdOrCtrl` is Cmd on macOS and Ctrl everywhere else, same as the global hotkey.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Chord {
+  pub modifiers: Modifiers,
+  pub key: Key,
+}
+
+impl Chord {
+  pub fn new(modifiers: Modifiers, key: Key) -> Self {
+    Chord { modifiers, key }
+  }
+
+  /// Whether pressing this would be taken as typing into the search instead
+  fn types_text(&self) -> bool {
+    let Modifiers {
+      alt,
+      ctrl,
+      command,
+      mac_cmd,
+      ..
+    } = self.modifiers;
+    let printable = !matches!(
+      self.key,
+      Key::ArrowDown | Key::ArrowLeft | Key::ArrowRight | Key::ArrowUp
+    ) && self.key.symbol_or_name().chars().count() == 1;
+    !(alt || ctrl || command || mac_cmd) && (printable || self.key == Key::Space)
+  }
+
+  fn pressed(&self, modifiers: Modifiers, key: Key) -> bool {
+    self.key == key && modifiers.matches_exact(self.modifiers)
+  }
+
+  fn sort_key(&self) -> (Key, [bool; 5]) {
+    let Modifiers {
+      alt,
+      ctrl,
+      shift,
+      mac_cmd,
+      command,
+    } = self.modifiers;
+    (self.key, [command, ctrl, mac_cmd, alt, shift])
+  }
+}
+
+impl PartialOrd for Chord {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Chord {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.sort_key().cmp(&other.sort_key())
+  }
+}
+
+impl fmt::Display for Chord {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mods = self.modifiers;
+    for (held, name) in [
+      (mods.command, "CmdOrCtrl"),
+      (mods.ctrl && !mods.command, "Ctrl"),
+      (mods.mac_cmd && !mods.command, "Cmd"),
+      (mods.alt, "Alt"),
+      (mods.shift, "Shift"),
+    ] {
+      if held {
+        write!(f, "{}+", name)?;
+      }
+    }
+    f.write_str(self.key.name())
+  }
+}
+
+impl FromStr for Chord {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim();
+    // The plus key would otherwise be mistaken for a separator
+    let (mods, key) = match s.strip_suffix("++") {
+      Some(mods) => (Some(mods), "+"),
+      None if s == "+" => (None, s),
+      None => match s.rsplit_once('+') {
+        Some((mods, key)) => (Some(mods), key),
+        None => (None, s),
+      },
+    };
+    let mut modifiers = Modifiers::NONE;
+    for m in mods.into_iter().flat_map(|m| m.split('+')) {
+      modifiers = modifiers
+        | match m.trim().to_lowercase().as_str() {
+          "cmdorctrl" | "commandorcontrol" => Modifiers::COMMAND,
+          "ctrl" | "control" => Modifiers::CTRL,
+          "cmd" | "command" | "super" | "meta" => Modifiers::MAC_CMD,
+          "alt" | "option" | "opt" => Modifiers::ALT,
+          "shift" => Modifiers::SHIFT,
+          other => return Err(anyhow!("Unknown modifier '{}' in {}", other, s)),
+        };
+    }
+    let key =
+      Key::from_name(key.trim()).ok_or_else(|| anyhow!("Unknown key '{}' in {}", key.trim(), s))?;
+    let chord = Chord::new(modifiers, key);
+    if chord.types_text() {
+      return Err(anyhow!(
+        "{} would type into the search, hold Ctrl, Cmd or Alt with it",
+        s
+      ));
+    }
+    Ok(chord)
+  }
+}
+
+impl TryFrom<String> for Chord {
+  type Error = anyhow::Error;
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
+impl From<Chord> for String {
+  fn from(chord: Chord) -> String {
+    chord.to_string()
+  }
+}
+
+/// Sets of bindings to start from, for those used to navigating a certain way. Every
+/// preset includes the default bindings.
+#[derive(Clone, Copy, Debug, Default, Display, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+  #[default]
+  #[display(fmt = "default")]
+  Default,
+  #[display(fmt = "emacs")]
+  Emacs,
+  #[display(fmt = "vim")]
+  Vim,
+}
+
+impl Preset {
+  pub const ALL: [Preset; 3] = [Preset::Default, Preset::Emacs, Preset::Vim];
+
+  pub fn bindings(&self) -> Vec<(Chord, Command)> {
+    let mut bindings = vec![
+      (Modifiers::NONE, Key::ArrowDown, Command::Next),
+      (Modifiers::NONE, Key::Tab, Command::Complete),
+      (Modifiers::NONE, Key::ArrowUp, Command::Prev),
+      (Modifiers::SHIFT, Key::Tab, Command::Prev),
+      (Modifiers::NONE, Key::PageDown, Command::PageDown),
+      (Modifiers::NONE, Key::PageUp, Command::PageUp),
+      (Modifiers::COMMAND, Key::ArrowUp, Command::First),
+      (Modifiers::COMMAND, Key::ArrowDown, Command::Last),
+      (Modifiers::NONE, Key::Enter, Command::Confirm),
+      (Modifiers::COMMAND, Key::Enter, Command::SecondaryAction),
+      (Modifiers::COMMAND, Key::Backspace, Command::ClearInput),
+      (Modifiers::NONE, Key::Escape, Command::Close),
+    ];
+    bindings.extend(match self {
+      Preset::Default => vec![],
+      Preset::Emacs => vec![
+        (Modifiers::CTRL, Key::N, Command::Next),
+        (Modifiers::CTRL, Key::P, Command::Prev),
+        (Modifiers::CTRL, Key::V, Command::PageDown),
+        (Modifiers::ALT, Key::V, Command::PageUp),
+        (Modifiers::CTRL, Key::G, Command::Close),
+        (Modifiers::CTRL, Key::U, Command::ClearInput),
+      ],
+      Preset::Vim => vec![
+        (Modifiers::CTRL, Key::J, Command::Next),
+        (Modifiers::CTRL, Key::K, Command::Prev),
+        (Modifiers::CTRL, Key::D, Command::PageDown),
+        (Modifiers::CTRL, Key::U, Command::PageUp),
+        (Modifiers::CTRL, Key::OpenBracket, Command::Close),
+        (Modifiers::CTRL, Key::W, Command::ClearInput),
+      ],
+    });
+    bindings
+      .into_iter()
+      .map(|(mods, key, cmd)| (Chord::new(mods, key), cmd))
+      .collect()
+  }
+}
+
+/// Which keys do what in the powerbar: a preset, with any bindings of its own on top
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Keymap {
+  pub preset: Preset,
+  /// Bindings that add to, or replace, those of the preset
+  pub bindings: BTreeMap<Chord, Command>,
+}
+
+impl Keymap {
+  /// Every binding in effect, with the configured bindings taking priority
+  pub fn resolved(&self) -> BTreeMap<Chord, Command> {
+    let mut bindings: BTreeMap<_, _> = self.preset.bindings().into_iter().collect();
+    bindings.extend(self.bindings.iter().map(|(ch, cmd)| (*ch, *cmd)));
+    bindings
+  }
+
+  pub fn command(&self, modifiers: Modifiers, key: Key) -> Option<Command> {
+    self
+      .bindings
+      .iter()
+      .chain(self.preset.bindings().iter().map(|(ch, cmd)| (ch, cmd)))
+      .find(|(ch, _)| ch.pressed(modifiers, key))
+      .map(|(_, cmd)| *cmd)
+  }
+
+  /// Takes the keys bound to a command out of the input, so the search box never sees them,
+  /// and hands back the commands in the order they went off. Most go when pressed, those
+  /// [on release](Command::on_release) when the key's let go of.
+  pub fn take_commands(&self, input: &mut InputState) -> Vec<Command> {
+    let mut commands = Vec::new();
+    input.events.retain(|ev| {
+      let Event::Key {
+        key,
+        modifiers,
+        pressed,
+        ..
+      } = ev
+      else {
+        return true;
+      };
+      match self.command(*modifiers, *key) {
+        None | Some(Command::Ignore) => true,
+        Some(cmd) => {
+          if *pressed != cmd.on_release() {
+            commands.push(cmd);
+          }
+          false
+        }
+      }
+    });
+    commands
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chord(s: &str) -> Chord {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn parses_chords() {
+    assert_eq!(chord("Down"), Chord::new(Modifiers::NONE, Key::ArrowDown));
+    assert_eq!(chord("ctrl+n"), Chord::new(Modifiers::CTRL, Key::N));
+    assert_eq!(
+      chord("CmdOrCtrl + Shift + Enter"),
+      Chord::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Enter)
+    );
+    assert_eq!(chord("Alt++"), Chord::new(Modifiers::ALT, Key::Plus));
+    assert_eq!(
+      chord("Cmd+["),
+      Chord::new(Modifiers::MAC_CMD, Key::OpenBracket)
+    );
+  }
+
+  #[test]
+  fn chords_round_trip() {
+    for s in [
+      "Ctrl+N",
+      "CmdOrCtrl+Alt+Shift+Enter",
+      "Cmd+Backspace",
+      "Tab",
+    ] {
+      assert_eq!(chord(s).to_string(), s);
+    }
+  }
+
+  #[test]
+  fn rejects_invalid_chords() {
+    for s in [
+      "",
+      "Ctrl+",
+      "Hyper+N",
+      "Ctrl+Nope",
+      "N",
+      "Shift+N",
+      "Space",
+      "/",
+    ] {
+      assert!(s.parse::<Chord>().is_err(), "{} should be invalid", s);
+    }
+    assert!("Ctrl+Space".parse::<Chord>().is_ok());
+  }
+
+  #[test]
+  fn invalid_chords_fail_config_load() {
+    let err = toml::from_str::<Keymap>("[bindings]\n\"Ctrl+Nope\" = \"next\"")
+      .err()
+      .unwrap();
+    assert!(err.to_string().contains("Unknown key 'Nope'"));
+    assert!(toml::from_str::<Keymap>("[bindings]\n\"Ctrl+N\" = \"nope\"").is_err());
+  }
+
+  #[test]
+  fn bindings_override_the_preset() {
+    let keymap: Keymap = toml::from_str(
+      r#"
+      preset = "emacs"
+      [bindings]
+      "Tab" = "ignore"
+      "Ctrl+G" = "clear_input"
+      "Ctrl+J" = "next"
+      "#,
+    )
+    .unwrap();
+    let cmd = |mods, key| keymap.command(mods, key);
+    assert_eq!(cmd(Modifiers::CTRL, Key::N), Some(Command::Next));
+    assert_eq!(cmd(Modifiers::CTRL, Key::J), Some(Command::Next));
+    assert_eq!(cmd(Modifiers::CTRL, Key::G), Some(Command::ClearInput));
+    assert_eq!(cmd(Modifiers::NONE, Key::Tab), Some(Command::Ignore));
+    assert_eq!(cmd(Modifiers::SHIFT, Key::Tab), Some(Command::Prev));
+    assert_eq!(cmd(Modifiers::NONE, Key::Escape), Some(Command::Close));
+    assert_eq!(cmd(Modifiers::CTRL, Key::K), None);
+    assert_eq!(keymap.resolved()[&chord("Ctrl+G")], Command::ClearInput);
+
+    let round_trip: Keymap = toml::from_str(&toml::to_string(&keymap).unwrap()).unwrap();
+    assert_eq!(round_trip, keymap);
+  }
+
+  #[test]
+  fn presets_are_valid() {
+    for preset in Preset::ALL {
+      for (chord, _) in preset.bindings() {
+        assert_eq!(chord.to_string().parse::<Chord>().unwrap(), chord);
+      }
+    }
+  }
+
+  #[test]
+  fn takes_bound_keys_from_input() {
+    let key = |key, modifiers, pressed| Event::Key {
+      key,
+      physical_key: None,
+      pressed,
+      repeat: false,
+      modifiers,
+    };
+    let mut input = InputState::default();
+    input.events = vec![
+      key(Key::ArrowDown, Modifiers::NONE, true),
+      Event::Text("a".into()),
+      key(Key::A, Modifiers::NONE, true),
+      key(Key::Enter, Modifiers::COMMAND | Modifiers::CTRL, true),
+      key(Key::Tab, Modifiers::SHIFT, true),
+      key(Key::ArrowDown, Modifiers::NONE, false),
+      key(Key::Enter, Modifiers::COMMAND | Modifiers::CTRL, false),
+    ];
+    let commands = Keymap::default().take_commands(&mut input);
+    assert_eq!(
+      commands,
+      vec![Command::Next, Command::Prev, Command::SecondaryAction]
+    );
+    assert_eq!(input.events.len(), 2);
+  }
+
+  #[test]
+  fn confirm_waits_for_release() {
+    let key = |pressed, repeat| Event::Key {
+      key: Key::Enter,
+      physical_key: None,
+      pressed,
+      repeat,
+      modifiers: Modifiers::NONE,
+    };
+    let mut input = InputState::default();
+    input.events = vec![key(true, false), key(true, true)];
+    assert!(Keymap::default().take_commands(&mut input).is_empty());
+    assert!(input.events.is_empty());
+
+    input.events = vec![key(false, false)];
+    assert_eq!(
+      Keymap::default().take_commands(&mut input),
+      vec![Command::Confirm]
+    );
+  }
+}
@@ -1,18 +1,34 @@
 mod cacher;
 mod config;
+mod frecency;
 mod icon_ui;
+mod keymap;
 mod logs;
 mod powerbar;
 mod settings;
 
 // Plugins
+mod alfred;
 mod apps;
 mod calc;
+mod clipboard;
+mod external;
+mod files;
+mod paths;
 mod plugin;
 mod plugin_manager;
+mod plugin_registry;
+mod query;
+mod reloader;
+mod shell;
+mod snippets;
 mod template;
+mod wasm;
 mod webq;
 
+#[cfg(test)]
+mod testdir;
+
 use anyhow::anyhow;
 use egui::{IconData, ViewportBuilder, ViewportId};
 use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
@@ -22,6 +38,7 @@ use parking_lot::RwLock;
 use plugin::AppState;
 use plugin_manager::PluginManager;
 use powerbar::{LyraPowerbar, LyraPowerbarImpl};
+use query::PluginQueries;
 use settings::LyraSettings;
 use std::sync::Arc;
 use std::time::Duration;
@@ -73,7 +90,7 @@ fn main() -> anyhow::Result<()> {
       ..Default::default()
     },
     Box::new(move |cc| {
-      let app = bld.build();
+      let app = bld.build(&cc.egui_ctx);
       init_event_listeners(
         cc.egui_ctx.clone(),
         app.powerbar.clone(),
@@ -115,18 +132,26 @@ struct LyraUiBuilder {
 }
 
 impl LyraUiBuilder {
-  fn build(self) -> LyraUi {
+  fn build(self, ctx: &egui::Context) -> LyraUi {
+    let repaint_ctx = ctx.clone();
+    let frecency = self.plugins.frecency().clone();
     let mut cfg = NucleoConfig::DEFAULT;
     cfg.ignore_case = true;
     cfg.prefer_prefix = true;
     LyraUi {
       powerbar: LyraPowerbar::new(LyraPowerbarImpl {
         state: AppState::default(),
+        queries: PluginQueries::new(
+          self.config.clone(),
+          self.plugins.clone(),
+          Arc::new(move || repaint_ctx.request_repaint()),
+        ),
         plugins: self.plugins,
         matcher: RwLock::new(Matcher::new(cfg)),
         config: self.config.clone(),
+        background: None,
       }),
-      settings: LyraSettings::new(self.config.clone()),
+      settings: LyraSettings::new(self.config.clone(), frecency),
     }
   }
 }
@@ -0,0 +1,240 @@
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use anyhow::anyhow;
+use egui::RichText;
+use serde::{Deserialize, Serialize};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+pub const PLUGIN_NAME: &str = "paths";
+
+/// Browses the filesystem when the search looks like a path, listing what's in the dir
+/// being typed out
+pub struct PathsPlugin;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PathEntry {
+  pub name: String,
+  /// The search that leads here, written the way the user started it (eg with `~`)
+  pub typed: String,
+  pub path: String,
+  pub is_dir: bool,
+}
+impl PluginValue for PathEntry {
+  fn item_id(&self) -> Option<String> {
+    Some(self.path.clone())
+  }
+}
+impl Renderable for PathEntry {
+  fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
+    let (name, kind) = match self.is_dir {
+      true => (format!("{}/", self.name), "Folder"),
+      false => (self.name.clone(), "File"),
+    };
+    ui.horizontal(|ui| {
+      ui.label(RichText::new(name));
+      ui.label(RichText::new(kind).weak().small());
+    });
+  }
+}
+impl SearchBlocker for PathEntry {}
+
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |_| Ok(Box::new(PathsPlugin)));
+}
+
+impl Plugin for PathsPlugin {
+  type PV = PathEntry;
+
+  fn prefixes(&self) -> Vec<String> {
+    vec!["/".into(), "~".into()]
+  }
+
+  fn action(&self, input: &PathEntry) -> Result<OkAction, anyhow::Error> {
+    open::that(&input.path)
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Failed to open {:?}, err: {:?}", input.path, err))
+  }
+
+  fn complete(&self, input: &PathEntry) -> Result<OkAction, anyhow::Error> {
+    let sep = if input.is_dir { "/" } else { "" };
+    Ok(OkAction {
+      update_input: Some(format!("{}{}", input.typed, sep)),
+      ..Default::default()
+    })
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    let (dir, partial) = split_search(search);
+    let Ok(read) = fs::read_dir(expand(dir, |name| std::env::var(name).ok())) else {
+      return vec![];
+    };
+    let mut entries: Vec<_> = read
+      .filter_map(|e| e.ok())
+      .filter_map(|e| {
+        let name = e.file_name().to_str()?.to_string();
+        // Hidden entries only show up once they're asked for, same as in a shell
+        if name.starts_with('.') && !partial.starts_with('.') {
+          return None;
+        }
+        // Following links, so a link to a dir can be browsed into
+        let is_dir = e.path().is_dir();
+        Some(PathEntry {
+          typed: format!("{}{}", dir, name),
+          path: e.path().to_string_lossy().to_string(),
+          name,
+          is_dir,
+        })
+      })
+      .collect();
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    entries
+      .into_iter()
+      .map(|entry| FuzzyMatchItem {
+        // Matched the same way it'd be typed, so the whole search can be matched against it
+        against: Arc::new(entry.typed.clone()),
+        value: PluginV::new(PLUGIN_NAME, entry),
+        source: PLUGIN_NAME.to_string(),
+      })
+      .collect()
+  }
+}
+
+/// Splits the search into the dir being browsed, as typed, and the start of an entry in it
+fn split_search(search: &str) -> (&str, &str) {
+  if search == "~" {
+    return ("~/", "");
+  }
+  match search.rfind('/') {
+    Some(idx) => search.split_at(idx + 1),
+    None => ("", search),
+  }
+}
+
+/// Expands a leading `~` to the home dir, and `$VAR` or `${VAR}` to the variable's value
+/// as `var` looks it up. Variables that aren't set are left as they are.
+fn expand(path: &str, var: impl Fn(&str) -> Option<String>) -> PathBuf {
+  let mut out = String::with_capacity(path.len());
+  let mut rest = path;
+  if let Some(after) = rest.strip_prefix('~') {
+    if after.is_empty() || after.starts_with('/') {
+      match var("HOME").or_else(|| var("USERPROFILE")) {
+        Some(home) => out.push_str(home.trim_end_matches('/')),
+        None => out.push('~'),
+      }
+      rest = after;
+    }
+  }
+  while let Some(idx) = rest.find('$') {
+    out.push_str(&rest[..idx]);
+    let after = &rest[idx + 1..];
+    let (name, len) = match after.strip_prefix('{') {
+      Some(braced) => match braced.find('}') {
+        Some(end) => (&braced[..end], end + 2),
+        None => ("", 0),
+      },
+      None => {
+        let end = after
+          .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+          .unwrap_or(after.len());
+        (&after[..end], end)
+      }
+    };
+    match var(name).filter(|_| !name.is_empty()) {
+      Some(value) => out.push_str(&value),
+      None => out.push_str(&rest[idx..idx + 1 + len]),
+    }
+    rest = &after[len..];
+  }
+  out.push_str(rest);
+  Path::new(&out).to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+
+  fn entries(search: &str) -> Vec<PathEntry> {
+    PathsPlugin
+      .options(search)
+      .iter()
+      .map(|fm| fm.value.downcast::<PathEntry>().unwrap().clone())
+      .collect()
+  }
+
+  #[test]
+  fn splits_dir_from_partial_name() {
+    assert_eq!(split_search("/usr/lo"), ("/usr/", "lo"));
+    assert_eq!(split_search("/usr/"), ("/usr/", ""));
+    assert_eq!(split_search("~"), ("~/", ""));
+    assert_eq!(split_search("~/Doc"), ("~/", "Doc"));
+  }
+
+  #[test]
+  fn expands_home_and_variables() {
+    let expand = |path| {
+      expand(path, |name| match name {
+        "HOME" => Some("/home/lyra/".into()),
+        "LYRA_PATHS_TEST" => Some("/tmp/lyra".into()),
+        _ => None,
+      })
+    };
+    assert_eq!(expand("~/x"), Path::new("/home/lyra/x"));
+    assert_eq!(expand("~"), Path::new("/home/lyra"));
+    assert_eq!(expand("~nobody/x"), Path::new("~nobody/x"));
+    assert_eq!(expand("$LYRA_PATHS_TEST/a"), Path::new("/tmp/lyra/a"));
+    assert_eq!(expand("/a/${LYRA_PATHS_TEST}b"), Path::new("/a//tmp/lyrab"));
+    assert_eq!(
+      expand("/$LYRA_PATHS_UNSET/$"),
+      Path::new("/$LYRA_PATHS_UNSET/$")
+    );
+    assert_eq!(
+      expand("/${LYRA_PATHS_TEST"),
+      Path::new("/${LYRA_PATHS_TEST")
+    );
+  }
+
+  #[test]
+  fn lists_dir_entries_with_dirs_first() {
+    let dir = TestDir::new("paths-list");
+    fs::create_dir(dir.join("b-dir")).unwrap();
+    fs::write(dir.join("a-file"), "").unwrap();
+    fs::write(dir.join(".hidden"), "").unwrap();
+    let typed = format!("{}/", dir.display());
+
+    let listed = entries(&typed);
+    let names: Vec<_> = listed.iter().map(|e| (e.name.as_str(), e.is_dir)).collect();
+    assert_eq!(names, vec![("b-dir", true), ("a-file", false)]);
+    assert_eq!(listed[0].typed, format!("{}b-dir", typed));
+
+    let hidden = entries(&format!("{}.h", typed));
+    assert!(hidden.iter().any(|e| e.name == ".hidden"));
+    assert!(entries(&format!("{}nope/", typed)).is_empty());
+  }
+
+  #[test]
+  fn completes_dirs_with_a_separator() {
+    let dir = TestDir::new("paths-complete");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("file"), "").unwrap();
+    let typed = format!("{}/", dir.display());
+
+    let completions: Vec<_> = entries(&typed)
+      .iter()
+      .map(|e| PathsPlugin.complete(e).unwrap().update_input.unwrap())
+      .collect();
+    assert_eq!(
+      completions,
+      vec![format!("{}sub/", typed), format!("{}file", typed)]
+    );
+  }
+}
@@ -1,113 +1,53 @@
-use crate::apps::{self, AppLaunch, AppsPlugin};
-use crate::calc::{self, CalcPlugin, Evaluated};
-use crate::webq::{self, Searcher, WebqPlugin};
 use anyhow::anyhow;
 use egui::Ui;
-use std::{fmt, sync::Arc};
-
-pub enum Plugins {
-  Apps(AppsPlugin),
-  Calc(CalcPlugin),
-  Webq(WebqPlugin),
-}
+use std::{any::Any, fmt, sync::Arc, thread::JoinHandle};
 
+/// A result contributed by some plugin, with its concrete type erased so values from every
+/// plugin can sit side by side in the same list. The plugin that made it gets the concrete
+/// value back with `downcast` when it's actioned.
 #[derive(Clone)]
-pub enum PluginV {
-  Apps(AppLaunch),
-  Calc(Evaluated),
-  Webq(Searcher),
+pub struct PluginV {
+  source: PluginName,
+  value: Arc<dyn PluginValue>,
 }
 
-impl Plugins {
-  pub fn id(&self) -> PluginName {
-    match self {
-      Plugins::Apps(_) => apps::PLUGIN_NAME.to_owned(),
-      Plugins::Calc(_) => calc::PLUGIN_NAME.to_owned(),
-      Plugins::Webq(_) => webq::PLUGIN_NAME.to_owned(),
-    }
-  }
-
-  pub fn validate_value(&self, input_type: &str, input_value: &str) -> Result<(), anyhow::Error> {
-    match self {
-      Plugins::Apps(pi) => pi.validate_value(input_type, input_value),
-      Plugins::Calc(pi) => pi.validate_value(input_type, input_value),
-      Plugins::Webq(pi) => pi.validate_value(input_type, input_value),
-    }
-  }
-
-  pub fn prefix(&self) -> Option<String> {
-    match self {
-      Plugins::Apps(pi) => pi.prefix(),
-      Plugins::Calc(pi) => pi.prefix(),
-      Plugins::Webq(pi) => pi.prefix(),
-    }
-  }
-
-  pub fn action(&self, input: &PluginV) -> Result<OkAction, anyhow::Error> {
-    match (self, input) {
-      (Plugins::Apps(pi), PluginV::Apps(v)) => pi.action(v),
-      (Plugins::Calc(pi), PluginV::Calc(v)) => pi.action(v),
-      (Plugins::Webq(pi), PluginV::Webq(v)) => pi.action(v),
-      _ => Err(anyhow!("Incompatible plugin and value given")),
-    }
-  }
-
-  pub fn derive_state(&self, state: &AppState) -> Option<AppState> {
-    match self {
-      Plugins::Apps(pi) => pi.derive_state(state),
-      Plugins::Calc(pi) => pi.derive_state(state),
-      Plugins::Webq(pi) => pi.derive_state(state),
+impl PluginV {
+  pub fn new(source: impl Into<PluginName>, value: impl PluginValue) -> Self {
+    PluginV {
+      source: source.into(),
+      value: Arc::new(value),
     }
   }
 
-  pub fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
-    match self {
-      Plugins::Apps(pi) => pi.options(search),
-      Plugins::Calc(pi) => pi.options(search),
-      Plugins::Webq(pi) => pi.options(search),
-    }
+  /// The name of the plugin that produced this value
+  pub fn id(&self) -> PluginName {
+    self.source.clone()
   }
 
-  pub fn has_static_items(&self) -> bool {
-    match self {
-      Plugins::Apps(pi) => pi.has_static_items(),
-      Plugins::Calc(pi) => pi.has_static_items(),
-      Plugins::Webq(pi) => pi.has_static_items(),
-    }
+  pub fn item_id(&self) -> Option<String> {
+    self.value.item_id()
   }
 
-  pub fn static_items(&self) -> Vec<FuzzyMatchItem> {
-    match self {
-      Plugins::Apps(pi) => pi.static_items(),
-      Plugins::Calc(pi) => pi.static_items(),
-      Plugins::Webq(pi) => pi.static_items(),
-    }
+  /// Whether both values stand for the same item, however their details differ. Values
+  /// without an item id are never the same as anything
+  pub fn same_item(&self, other: &PluginV) -> bool {
+    self.source == other.source
+      && match (self.item_id(), other.item_id()) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+      }
   }
-}
 
-impl PluginV {
-  pub fn id(&self) -> PluginName {
-    match self {
-      PluginV::Apps(_) => apps::PLUGIN_NAME.to_owned(),
-      PluginV::Calc(_) => calc::PLUGIN_NAME.to_owned(),
-      PluginV::Webq(_) => webq::PLUGIN_NAME.to_owned(),
-    }
+  pub fn downcast<T: PluginValue>(&self) -> Option<&T> {
+    AsAny::as_any(&*self.value).downcast_ref()
   }
 
   pub fn render(&self, ui: &mut Ui, state: &AppState) {
-    match self {
-      PluginV::Apps(v) => v.render(ui, state),
-      PluginV::Calc(v) => v.render(ui, state),
-      PluginV::Webq(v) => v.render(ui, state),
-    }
+    self.value.render(ui, state)
   }
 
   pub fn blocks_search(&self, state: &AppState) -> bool {
-    match self {
-      PluginV::Apps(v) => v.blocks_search(state),
-      PluginV::Calc(v) => v.blocks_search(state),
-      PluginV::Webq(v) => v.blocks_search(state),
-    }
+    self.value.blocks_search(state)
   }
 }
 
@@ -116,17 +56,54 @@ pub struct AppState {
   pub input: String,
   pub options: Vec<PluginV>,
   pub selected: usize,
+  /// Whether the selection was moved there, rather than left at the top by default. Only
+  /// a chosen item is held onto as the options change
+  pub chosen: bool,
 }
 
 impl AppState {
   pub fn selected(&self) -> Option<&PluginV> {
     self.options.get(self.selected)
   }
+
+  /// Replaces the options, keeping a chosen item selected if it's still among them.
+  /// Otherwise the selection goes back to the top
+  pub fn set_options(&mut self, options: Vec<PluginV>) {
+    let selected = self.selected().filter(|_| self.chosen).cloned();
+    self.options = options;
+    let kept = selected.and_then(|sel| self.options.iter().position(|opt| opt.same_item(&sel)));
+    self.selected = kept.unwrap_or(0);
+    self.chosen = kept.is_some();
+  }
+
+  /// Moves the selection to the given option, stopping at the last one
+  pub fn select(&mut self, idx: usize) {
+    self.chosen = true;
+    self.selected = idx.min(self.options.len().saturating_sub(1));
+  }
+
+  /// Moves the selection down, wrapping around to the top
+  pub fn select_next(&mut self) {
+    self.chosen = true;
+    self.selected = Some(self.selected + 1)
+      .filter(|i| *i < self.options.len())
+      .unwrap_or(0);
+  }
+
+  /// Moves the selection up, wrapping around to the bottom
+  pub fn select_prev(&mut self) {
+    self.chosen = true;
+    self.selected = self
+      .selected
+      .checked_sub(1)
+      .or_else(|| self.options.len().checked_sub(1))
+      .unwrap_or(0);
+  }
 }
 
 pub struct FuzzyMatchItem {
   pub value: PluginV,
-  pub against: Arc<dyn AsRef<str>>,
+  pub against: Arc<dyn AsRef<str> + Send + Sync>,
   pub source: PluginName,
 }
 
@@ -146,6 +123,9 @@ impl fmt::Debug for FuzzyMatchItem {
 pub struct OkAction {
   pub close_win: bool,
   pub update_input: Option<String>,
+  /// Work the action left going in the background. The search is run again once it's
+  /// done, so the options can show what came of it
+  pub search_again_after: Option<JoinHandle<()>>,
 }
 
 pub trait SearchBlocker {
@@ -164,7 +144,25 @@ pub trait Renderable {
   fn render(&self, ui: &mut Ui, state: &AppState);
 }
 
-pub trait PluginValue: SearchBlocker + Renderable {}
+/// Lets a type erased value be turned back into its concrete type
+pub trait AsAny {
+  fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+}
+
+pub trait PluginValue: SearchBlocker + Renderable + AsAny + Send + Sync + 'static {
+  /// Identifies the item this value represents within its plugin, staying the same across
+  /// searches and restarts. Values without one, like a calculation, aren't tracked in the
+  /// usage history.
+  fn item_id(&self) -> Option<String> {
+    None
+  }
+}
 
 pub type PluginName = String;
 pub trait Plugin: Send + Sync {
@@ -186,11 +184,29 @@ pub trait Plugin: Send + Sync {
     None
   }
 
+  /// Every prefix that triggers this plugin, for the plugins that answer to more than one.
+  /// That's just the `prefix` by default
+  fn prefixes(&self) -> Vec<String> {
+    self.prefix().into_iter().collect()
+  }
+
   /// Execute this plugin against the given input (specific to this plugin).
   /// Plugins can choose to close the window after they are done executing by setting the boolean
   /// in the returned OkAction.
   fn action(&self, input: &Self::PV) -> Result<OkAction, anyhow::Error>;
 
+  /// An alternate way of actioning the input, like revealing an app instead of launching it.
+  /// Most plugins only have the one action, so by default there's nothing to do.
+  fn secondary_action(&self, _input: &Self::PV) -> Result<OkAction, anyhow::Error> {
+    Err(anyhow!("No secondary action available"))
+  }
+
+  /// Fills the search in from the input, like finishing off a path, handing the new search
+  /// back in `update_input`. Most plugins have nothing to complete.
+  fn complete(&self, _input: &Self::PV) -> Result<OkAction, anyhow::Error> {
+    Err(anyhow!("Nothing to complete"))
+  }
+
   /// If this plugin wants to manipulate the state of the app, this is a hook
   /// to do so whenever the state changes.
   fn derive_state(&self, _state: &AppState) -> Option<AppState> {
@@ -213,3 +229,167 @@ pub trait Plugin: Send + Sync {
     vec![]
   }
 }
+
+/// The object safe face of a `Plugin`, which is what the rest of the app holds onto. Every
+/// `Plugin` gets this for free, checking values handed back to it are its own.
+pub trait DynPlugin: Send + Sync {
+  fn validate_value(&self, input_type: &str, input_value: &str) -> Result<(), anyhow::Error>;
+  fn prefix(&self) -> Option<String>;
+  fn prefixes(&self) -> Vec<String>;
+  fn action(&self, input: &PluginV) -> Result<OkAction, anyhow::Error>;
+  fn secondary_action(&self, input: &PluginV) -> Result<OkAction, anyhow::Error>;
+  fn complete(&self, input: &PluginV) -> Result<OkAction, anyhow::Error>;
+  fn derive_state(&self, state: &AppState) -> Option<AppState>;
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem>;
+  fn has_static_items(&self) -> bool;
+  fn static_items(&self) -> Vec<FuzzyMatchItem>;
+}
+
+impl<P: Plugin> DynPlugin for P {
+  fn validate_value(&self, input_type: &str, input_value: &str) -> Result<(), anyhow::Error> {
+    Plugin::validate_value(self, input_type, input_value)
+  }
+
+  fn prefix(&self) -> Option<String> {
+    Plugin::prefix(self)
+  }
+
+  fn prefixes(&self) -> Vec<String> {
+    Plugin::prefixes(self)
+  }
+
+  fn action(&self, input: &PluginV) -> Result<OkAction, anyhow::Error> {
+    match input.downcast::<P::PV>() {
+      Some(v) => Plugin::action(self, v),
+      None => Err(anyhow!("Incompatible plugin and value given")),
+    }
+  }
+
+  fn secondary_action(&self, input: &PluginV) -> Result<OkAction, anyhow::Error> {
+    match input.downcast::<P::PV>() {
+      Some(v) => Plugin::secondary_action(self, v),
+      None => Err(anyhow!("Incompatible plugin and value given")),
+    }
+  }
+
+  fn complete(&self, input: &PluginV) -> Result<OkAction, anyhow::Error> {
+    match input.downcast::<P::PV>() {
+      Some(v) => Plugin::complete(self, v),
+      None => Err(anyhow!("Incompatible plugin and value given")),
+    }
+  }
+
+  fn derive_state(&self, state: &AppState) -> Option<AppState> {
+    Plugin::derive_state(self, state)
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    Plugin::options(self, search)
+  }
+
+  fn has_static_items(&self) -> bool {
+    Plugin::has_static_items(self)
+  }
+
+  fn static_items(&self) -> Vec<FuzzyMatchItem> {
+    Plugin::static_items(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Clone)]
+  struct Item(&'static str, Option<&'static str>);
+
+  impl PluginValue for Item {
+    fn item_id(&self) -> Option<String> {
+      self.1.map(|id| id.to_string())
+    }
+  }
+  impl Renderable for Item {
+    fn render(&self, _ui: &mut Ui, _state: &AppState) {}
+  }
+  impl SearchBlocker for Item {}
+
+  fn item(id: &'static str) -> PluginV {
+    PluginV::new("apps", Item(id, Some(id)))
+  }
+
+  fn selected(state: &AppState) -> Option<&'static str> {
+    state
+      .selected()
+      .and_then(|v| v.downcast::<Item>())
+      .map(|i| i.0)
+  }
+
+  #[test]
+  fn same_item_needs_matching_source_and_id() {
+    assert!(item("a").same_item(&PluginV::new("apps", Item("renamed", Some("a")))));
+    assert!(!item("a").same_item(&item("b")));
+    assert!(!item("a").same_item(&PluginV::new("files", Item("a", Some("a")))));
+    let calc = PluginV::new("calc", Item("=1", None));
+    assert!(!calc.same_item(&calc));
+  }
+
+  #[test]
+  fn selection_sticks_to_items_still_present() {
+    let mut state = AppState::default();
+    state.set_options(vec![item("dash"), item("dashlane"), item("web")]);
+    assert_eq!(selected(&state), Some("dash"));
+    state.select_next();
+    assert_eq!(selected(&state), Some("dashlane"));
+
+    // Refining the search reorders and drops some options
+    state.set_options(vec![item("web"), item("dashlane")]);
+    assert_eq!(selected(&state), Some("dashlane"));
+    assert_eq!(state.selected, 1);
+
+    state.set_options(vec![item("web"), item("dash")]);
+    assert_eq!(selected(&state), Some("web"));
+  }
+
+  #[test]
+  fn only_chosen_items_stick() {
+    let mut state = AppState::default();
+    // Results arriving a plugin at a time shouldn't pin whatever happened to be first
+    state.set_options(vec![item("web")]);
+    state.set_options(vec![item("dash"), item("web")]);
+    assert_eq!(selected(&state), Some("dash"));
+
+    state.select_next();
+    state.select_prev();
+    state.set_options(vec![item("web"), item("dash")]);
+    assert_eq!(selected(&state), Some("dash"));
+
+    // Once the chosen item is gone, the top goes back to being the default
+    state.set_options(vec![item("web")]);
+    state.set_options(vec![item("dashlane"), item("web")]);
+    assert_eq!(selected(&state), Some("dashlane"));
+  }
+
+  #[test]
+  fn unidentified_selections_reset() {
+    let mut state = AppState::default();
+    state.set_options(vec![item("a"), PluginV::new("calc", Item("=1", None))]);
+    state.select_next();
+    state.set_options(vec![item("a"), PluginV::new("calc", Item("=12", None))]);
+    assert_eq!(state.selected, 0);
+  }
+
+  #[test]
+  fn navigation_wraps() {
+    let mut state = AppState::default();
+    state.select_prev();
+    assert_eq!(state.selected, 0);
+
+    state.set_options(vec![item("a"), item("b"), item("c")]);
+    state.select_prev();
+    assert_eq!(selected(&state), Some("c"));
+    state.select_next();
+    assert_eq!(selected(&state), Some("a"));
+    state.select_next();
+    assert_eq!(selected(&state), Some("b"));
+  }
+}
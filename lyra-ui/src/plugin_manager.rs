@@ -1,93 +1,132 @@
 use crate::{
-  apps::{self, AppsPlugin},
-  calc::{self, CalcPlugin},
   config::Config,
-  plugin::{OkAction, PluginName, PluginV, Plugins},
-  webq::{self, WebqPlugin},
+  frecency::Frecency,
+  plugin::{DynPlugin, OkAction, PluginName, PluginV},
+  plugin_registry::PluginRegistry,
 };
 use anyhow::anyhow;
-use arboard::Clipboard;
-use std::{
-  collections::{hash_map::Values, HashMap},
-  sync::Arc,
-};
+use std::{collections::HashMap, sync::Arc};
+
+/// Name of the usage history file within the cache dir
+const USAGE_FILE: &str = "usage.toml";
 
 #[derive(Clone)]
-pub struct PluginManager(Arc<HashMap<PluginName, Plugins>>);
+pub struct PluginManager {
+  plugins: Arc<HashMap<PluginName, Box<dyn DynPlugin>>>,
+  frecency: Arc<Frecency>,
+}
 
 impl PluginManager {
   pub fn init(config: &Arc<Config>) -> Result<Self, anyhow::Error> {
-    let cfg = config.get();
-    let plugs: Result<HashMap<_, _>, _> = cfg
-      .plugins
-      .iter()
-      .map(|pn| {
-        let pl = match pn.as_str() {
-          calc::PLUGIN_NAME => {
-            Plugins::Calc(CalcPlugin::init(cfg.calc.clone(), Clipboard::new()?)?)
-          }
-          webq::PLUGIN_NAME => Plugins::Webq(WebqPlugin::init(cfg.webq.clone())?),
-          apps::PLUGIN_NAME => {
-            Plugins::Apps(AppsPlugin::init(cfg.apps.clone(), &config.cache_dir)?)
-          }
-          _ => return Err(anyhow!("{} is an unknown plugin", pn)),
-        };
-        Ok((pl.id(), pl))
-      })
+    let registry = PluginRegistry::init(config)?;
+    let names = config.get().plugins.clone();
+    let plugs: Result<HashMap<_, _>, _> = names
+      .into_iter()
+      .map(|pn| registry.build(&pn, config).map(|pl| (pn, pl)))
       .collect();
-    Ok(PluginManager(Arc::new(plugs?)))
+    let frecency = Frecency::init(config.cache_dir.join(USAGE_FILE))?;
+    Ok(PluginManager::new(plugs?, Arc::new(frecency)))
+  }
+
+  pub fn new(plugins: HashMap<PluginName, Box<dyn DynPlugin>>, frecency: Arc<Frecency>) -> Self {
+    PluginManager {
+      plugins: Arc::new(plugins),
+      frecency,
+    }
+  }
+
+  /// Actions the option, remembering it was chosen for the query when that works out
+  pub fn try_launch(&mut self, opt: &PluginV, query: &str) -> Result<OkAction, anyhow::Error> {
+    let res = self
+      .plugins
+      .get(&opt.id())
+      .ok_or_else(|| anyhow!("Unknown plugin given"))
+      .and_then(|pls| pls.action(opt))?;
+    self.frecency.record(opt, query);
+    Ok(res)
+  }
+
+  /// Same as `try_launch`, but with the plugin's alternate action for the value
+  pub fn try_secondary(&mut self, opt: &PluginV, query: &str) -> Result<OkAction, anyhow::Error> {
+    let res = self
+      .plugins
+      .get(&opt.id())
+      .ok_or_else(|| anyhow!("Unknown plugin given"))
+      .and_then(|pls| pls.secondary_action(opt))?;
+    self.frecency.record(opt, query);
+    Ok(res)
   }
 
-  pub fn try_launch(&mut self, opt: &PluginV) -> Result<OkAction, anyhow::Error> {
+  /// Has the option's plugin complete the search from it. Nothing's been chosen yet, so it
+  /// doesn't count towards frecency
+  pub fn try_complete(&self, opt: &PluginV) -> Result<OkAction, anyhow::Error> {
     self
-      .0
+      .plugins
       .get(&opt.id())
       .ok_or_else(|| anyhow!("Unknown plugin given"))
-      .and_then(|pls| pls.action(opt))
+      .and_then(|pls| pls.complete(opt))
+  }
+
+  pub fn frecency(&self) -> &Arc<Frecency> {
+    &self.frecency
   }
 
-  pub fn get(&self, plug: &PluginName) -> Result<&Plugins, anyhow::Error> {
+  pub fn get(&self, plug: &PluginName) -> Result<&dyn DynPlugin, anyhow::Error> {
     self
-      .0
+      .plugins
       .get(plug)
+      .map(|pl| pl.as_ref())
       .ok_or_else(|| anyhow!("Plugin {} not found", plug))
   }
 
-  pub fn iter(&self) -> Values<'_, String, Plugins> {
-    self.0.values()
+  pub fn iter(&self) -> impl Iterator<Item = &dyn DynPlugin> {
+    self.plugins.values().map(|pl| pl.as_ref())
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &PluginName> {
+    self.plugins.keys()
   }
 
   /// Return the plugins whose prefix are found within the search string, or if none
   /// are found, then return everything
-  pub fn filter_to(&self, search: &str) -> Vec<&Plugins> {
+  pub fn filter_to(&self, search: &str) -> Vec<&PluginName> {
     let plugs: Vec<_> = self
-      .0
-      .values()
-      .filter(|pl| match pl.prefix() {
-        None => false,
-        Some(pre) => search.starts_with(&pre),
-      })
+      .plugins
+      .iter()
+      .filter(|(_, pl)| pl.prefixes().iter().any(|pre| search.starts_with(pre)))
+      .map(|(pn, _)| pn)
       .collect();
 
     if plugs.is_empty() {
       // Everything BUT the prefixed items
-      return self.0.values().filter(|pl| pl.prefix().is_none()).collect();
+      return self
+        .plugins
+        .iter()
+        .filter(|(_, pl)| pl.prefixes().is_empty())
+        .map(|(pn, _)| pn)
+        .collect();
     }
 
     plugs
   }
 
   /// Return the plugins that has something static they want to contribute
-  pub fn always_present(&self, search: &str) -> Vec<&Plugins> {
+  pub fn always_present(&self, search: &str) -> Vec<&PluginName> {
     // Note: optimization here would be to pass a state between filter_to and here
     //       so we don't need to re-check if any prefixes matched
-    if self.0.values().any(|pl| match pl.prefix() {
-      None => false,
-      Some(pre) => search.starts_with(&pre),
-    }) {
+    if self
+      .plugins
+      .values()
+      .any(|pl| pl.prefixes().iter().any(|pre| search.starts_with(pre)))
+    {
       return Vec::new();
     }
 
-    self.0.values().filter(|pl| pl.has_static_items()).collect()
+    self
+      .plugins
+      .iter()
+      .filter(|(_, pl)| pl.has_static_items())
+      .map(|(pn, _)| pn)
+      .collect()
   }
 }
@@ -0,0 +1,219 @@
+use crate::{
+  alfred, apps, calc, clipboard,
+  config::Config,
+  external, files, paths,
+  plugin::{DynPlugin, PluginName},
+  shell, snippets, wasm, webq,
+};
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use tracing::warn;
+
+pub type PluginFactory =
+  Box<dyn Fn(&PluginContext) -> Result<Box<dyn DynPlugin>, anyhow::Error> + Send + Sync>;
+
+/// What a plugin gets to build itself with
+pub struct PluginContext<'a> {
+  /// The name the plugin is being enabled under
+  pub name: &'a str,
+  pub config: &'a Config,
+}
+
+impl PluginContext<'_> {
+  /// This plugin's own section of the config, keyed by its name
+  pub fn section<T: DeserializeOwned + Default>(&self) -> Result<T, anyhow::Error> {
+    self.config.get().plugin_config(self.name)
+  }
+}
+
+/// Every plugin Lyra knows how to build, by name. Which of them actually get built is up to
+/// the `plugins` list in the config.
+#[derive(Default)]
+pub struct PluginRegistry(HashMap<PluginName, PluginFactory>);
+
+impl PluginRegistry {
+  pub fn init(config: &Config) -> Result<Self, anyhow::Error> {
+    let mut registry = PluginRegistry::default();
+    apps::register(&mut registry);
+    calc::register(&mut registry);
+    clipboard::register(&mut registry);
+    webq::register(&mut registry);
+    files::register(&mut registry);
+    paths::register(&mut registry);
+    shell::register(&mut registry);
+    snippets::register(&mut registry);
+    external::register(&mut registry, config)?;
+    alfred::register(&mut registry, config)?;
+    wasm::register(&mut registry, config)?;
+    Ok(registry)
+  }
+
+  /// Registers a plugin under the given name. The first registration of a name wins, so
+  /// user defined plugins can't shadow the builtin ones.
+  pub fn register(
+    &mut self,
+    name: &str,
+    factory: impl Fn(&PluginContext) -> Result<Box<dyn DynPlugin>, anyhow::Error>
+      + Send
+      + Sync
+      + 'static,
+  ) {
+    if self.0.contains_key(name) {
+      warn!("Plugin {} is already registered, ignoring duplicate", name);
+      return;
+    }
+    self.0.insert(name.to_string(), Box::new(factory));
+  }
+
+  pub fn build(&self, name: &str, config: &Config) -> Result<Box<dyn DynPlugin>, anyhow::Error> {
+    let factory = self
+      .0
+      .get(name)
+      .ok_or_else(|| anyhow!("{} is an unknown plugin", name))?;
+    factory(&PluginContext { name, config })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::InnerConfig;
+  use crate::plugin::{
+    AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
+  };
+  use serde::{Deserialize, Serialize};
+  use std::sync::Arc;
+
+  #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+  #[serde(default)]
+  struct EchoConfig {
+    greeting: String,
+  }
+
+  struct EchoPlugin(EchoConfig);
+
+  #[derive(Clone)]
+  struct Echoed(String);
+
+  impl PluginValue for Echoed {}
+  impl Renderable for Echoed {
+    fn render(&self, _ui: &mut egui::Ui, _state: &AppState) {}
+  }
+  impl SearchBlocker for Echoed {}
+
+  #[derive(Clone)]
+  struct Other;
+
+  impl PluginValue for Other {}
+  impl Renderable for Other {
+    fn render(&self, _ui: &mut egui::Ui, _state: &AppState) {}
+  }
+  impl SearchBlocker for Other {}
+
+  impl Plugin for EchoPlugin {
+    type PV = Echoed;
+
+    fn action(&self, input: &Echoed) -> Result<OkAction, anyhow::Error> {
+      Ok(OkAction {
+        close_win: false,
+        update_input: Some(input.0.clone()),
+        ..Default::default()
+      })
+    }
+
+    fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+      vec![FuzzyMatchItem {
+        value: PluginV::new("echo", Echoed(format!("{} {}", self.0.greeting, search))),
+        against: Arc::new(search.to_owned()),
+        source: "echo".into(),
+      }]
+    }
+  }
+
+  fn config(toml: &str) -> Config {
+    let inner: InnerConfig = toml::from_str(toml).unwrap();
+    let config = Config::default();
+    config.update(|mut cfg| *cfg = inner);
+    config
+  }
+
+  fn registry() -> PluginRegistry {
+    let mut registry = PluginRegistry::default();
+    registry.register("echo", |ctx| Ok(Box::new(EchoPlugin(ctx.section()?))));
+    registry
+  }
+
+  #[test]
+  fn builds_registered_plugins_with_their_section() {
+    let config = config("plugins = [\"echo\"]\n[echo]\ngreeting = \"hi\"");
+    let plugin = registry().build("echo", &config).unwrap();
+
+    let opts = plugin.options("there");
+    assert_eq!(opts[0].source, "echo");
+    assert_eq!(opts[0].value.id(), "echo");
+    let res = plugin.action(&opts[0].value).unwrap();
+    assert_eq!(res.update_input, Some("hi there".into()));
+  }
+
+  #[test]
+  fn missing_section_uses_defaults() {
+    let plugin = registry().build("echo", &config("")).unwrap();
+    let opts = plugin.options("there");
+    assert_eq!(opts[0].value.downcast::<Echoed>().unwrap().0, " there");
+  }
+
+  #[test]
+  fn invalid_section_fails_to_build() {
+    let err = registry()
+      .build("echo", &config("[echo]\ngreeting = 1"))
+      .err()
+      .unwrap();
+    assert!(err.to_string().contains("Invalid config for plugin echo"));
+  }
+
+  #[test]
+  fn unknown_plugins_fail_to_build() {
+    assert!(registry().build("nope", &config("")).is_err());
+  }
+
+  #[test]
+  fn first_registration_wins() {
+    let mut registry = registry();
+    registry.register("echo", |_| Err(anyhow!("shadowed")));
+    assert!(registry.build("echo", &config("")).is_ok());
+  }
+
+  #[test]
+  fn foreign_values_are_refused() {
+    let plugin = registry().build("echo", &config("")).unwrap();
+    let value = PluginV::new("echo", Other);
+    assert!(value.downcast::<Echoed>().is_none());
+    assert!(plugin.action(&value).is_err());
+  }
+
+  #[test]
+  fn plugin_sections_round_trip() {
+    let mut inner: InnerConfig =
+      toml::from_str("result_count = 5\n[echo]\ngreeting = \"hi\"").unwrap();
+    assert_eq!(inner.result_count, 5);
+
+    inner
+      .set_plugin_config(
+        "echo",
+        &EchoConfig {
+          greeting: "hello".into(),
+        },
+      )
+      .unwrap();
+    let reloaded: InnerConfig = toml::from_str(&toml::to_string(&inner).unwrap()).unwrap();
+    assert_eq!(reloaded.result_count, 5);
+    assert_eq!(
+      reloaded
+        .plugin_config::<EchoConfig>("echo")
+        .unwrap()
+        .greeting,
+      "hello"
+    );
+  }
+}
@@ -1,21 +1,29 @@
 use crate::{
+  frecency::{self, ItemKey},
+  keymap::Command,
   plugin::{AppState, OkAction, PluginV},
   plugin_manager::PluginManager,
+  query::{PluginQueries, Polled},
 };
 use egui::{
   text::{CCursor, CCursorRange},
-  Align, Event, EventFilter, FontId, InputState, Key, Modifiers, TextBuffer, TextEdit, ViewportId,
+  Align, Event, EventFilter, FontId, TextBuffer, TextEdit, ViewportId,
 };
 use nucleo_matcher::{
   pattern::{CaseMatching, Pattern},
   Matcher,
 };
 use parking_lot::RwLock;
-use std::sync::Arc;
+use std::{sync::Arc, thread::JoinHandle, time::Duration};
 use tracing::error;
 
 use crate::config::{Config, Styles};
 
+/// How far paging moves the selection
+const PAGE_SIZE: usize = 5;
+/// How often to check whether an action's background work is done
+const BACKGROUND_CHECK: Duration = Duration::from_millis(100);
+
 #[derive(Clone)]
 pub struct LyraPowerbar(Arc<RwLock<LyraPowerbarImpl>>);
 
@@ -38,49 +46,91 @@ pub struct LyraPowerbarImpl {
   pub plugins: PluginManager,
   pub config: Arc<Config>,
   pub matcher: RwLock<Matcher>,
+  pub queries: PluginQueries,
+  /// Work an action left going in the background, to search again after
+  pub background: Option<JoinHandle<()>>,
 }
 
 impl LyraPowerbarImpl {
-  pub fn get_options(&self, search: &str) -> Vec<PluginV> {
+  /// Kicks off the search in the background, the results are picked up by `poll_options`.
+  /// Until they are, what's held from the last search is matched against this one, less
+  /// whatever only answers the last search
+  pub fn search(&mut self, search: &str) {
     if search.is_empty() {
       // Special case, empty string == nothing back instead of everything
-      return Vec::new();
+      self.queries.cancel();
+      self.state.set_options(Vec::new());
+      return;
+    }
+    self.queries.start(search);
+    let options = self.ranked_options(search);
+    self.state.set_options(options);
+  }
+
+  /// Brings in whatever results have arrived since the last frame
+  fn poll_options(&mut self) -> Polled {
+    let polled = self.queries.poll();
+    if polled == Polled::Unchanged {
+      return polled;
     }
+    let options = self.ranked_options(&self.state.input);
+    self.state.set_options(options);
+    self.check_plugins_for_state_updates();
+    polled
+  }
 
-    Pattern::parse(search, CaseMatching::Ignore)
-      .match_list(
-        self
-          .plugins
-          .filter_to(&search)
-          .iter()
-          .flat_map(|pl| pl.options(search)),
-        &mut *self.matcher.write(),
-      )
+  /// Orders the matches by how well they match the search, boosted by how often and how
+  /// recently they've been launched
+  fn ranked_options(&self, search: &str) -> Vec<PluginV> {
+    let scores = self.plugins.frecency().scores(search);
+    let mut matches: Vec<_> = Pattern::parse(search, CaseMatching::Ignore)
+      .match_list(self.queries.options(), &mut *self.matcher.write())
+      .into_iter()
+      .map(|(fm, score)| {
+        let usage = ItemKey::of(&fm.value)
+          .and_then(|key| scores.get(&key).copied())
+          .unwrap_or_default();
+        (fm, score as f64 * frecency::boost(usage))
+      })
+      .collect();
+    matches.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    matches
       .into_iter()
       .take(self.config.get().result_count)
-      .map(|(v, _)| v)
-      .chain(
-        self
-          .plugins
-          .always_present(search)
-          .iter()
-          .flat_map(|pl| pl.static_items()),
-      )
-      .map(|sk| sk.value)
+      .map(|(fm, _)| fm)
+      .chain(self.queries.statics())
+      .map(|sk| sk.value.clone())
       .collect()
   }
 
+  /// Searches again once the work an action left going in the background is done, coming
+  /// back around to check until it is
+  fn search_again_when_done(&mut self, ctx: &eframe::egui::Context) {
+    let Some(background) = &self.background else {
+      return;
+    };
+    if !background.is_finished() {
+      ctx.request_repaint_after(BACKGROUND_CHECK);
+      return;
+    }
+    self.background = None;
+    let search = self.state.input.clone();
+    self.search(&search);
+  }
+
   fn reset_state(&mut self) {
+    self.background = None;
+    self.queries.cancel();
     self.state = AppState::default();
   }
 
+  /// Gives every plugin its turn at updating the state, each seeing what the ones before
+  /// it changed
   fn check_plugins_for_state_updates(&mut self) {
-    if let Some(st) = self
-      .plugins
-      .iter()
-      .find_map(|p| p.derive_state(&self.state))
-    {
-      self.state = st;
+    for plugin in self.plugins.iter() {
+      if let Some(st) = plugin.derive_state(&self.state) {
+        self.state = st;
+      }
     }
   }
 
@@ -106,6 +156,55 @@ impl LyraPowerbarImpl {
     }
   }
 
+  /// Completes the search from the selected option, if its plugin can. Returns whether it did
+  fn complete_selected(&mut self) -> bool {
+    let Some(opt) = self.state.selected() else {
+      return false;
+    };
+    match self.plugins.try_complete(opt) {
+      Ok(OkAction {
+        update_input: Some(inp),
+        ..
+      }) => {
+        self.state.input = inp;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Actions the selected option, with its plugin's secondary action if asked. Returns
+  /// whether the action changed the input
+  fn launch_selected(&mut self, ctx: &eframe::egui::Context, secondary: bool) -> bool {
+    let Some(opt) = self.state.selected() else {
+      return false;
+    };
+    let res = match secondary {
+      true => self.plugins.try_secondary(opt, &self.state.input),
+      false => self.plugins.try_launch(opt, &self.state.input),
+    };
+    match res {
+      Ok(OkAction {
+        update_input: Some(inp),
+        search_again_after,
+        ..
+      }) => {
+        self.state.input = inp;
+        self.background = search_again_after;
+        return true;
+      }
+      Ok(OkAction {
+        close_win: true, ..
+      }) => {
+        self.close(ctx, false);
+        self.reset_state();
+      }
+      Ok(_) => self.reset_state(),
+      Err(e) => error!("{:?}", e),
+    }
+    false
+  }
+
   pub fn update(&mut self, ctx: &eframe::egui::Context) {
     // Window does not play well auto-hiding on focus loss on Linux, so we'll
     // leave it as open until manually closed
@@ -115,49 +214,46 @@ impl LyraPowerbarImpl {
       return;
     }
 
-    if ctx.input(|i| i.key_pressed(Key::Escape)) {
-      self.close(ctx, false);
-      return;
-    }
-
-    if ctx.input(is_nav_down) {
-      self.state.selected = Some(self.state.selected + 1)
-        .filter(|i| *i < self.state.options.len())
-        .unwrap_or(0);
-      // (self.state.selected + 1).min(self.state.options.len().checked_sub(1).unwrap_or(0));
-      self.check_plugins_for_state_updates();
-    }
+    let commands = {
+      let config = self.config.get();
+      ctx.input_mut(|i| config.keymap.take_commands(i))
+    };
 
-    if ctx.input(is_nav_up) {
-      self.state.selected = self
-        .state
-        .selected
-        .checked_sub(1)
-        .or_else(|| self.state.options.len().checked_sub(1))
-        .unwrap_or(0);
-      self.check_plugins_for_state_updates();
+    self.search_again_when_done(ctx);
+    let options_arrived = self.poll_options() != Polled::Unchanged;
+    if let Some(wait) = self.queries.next_deadline() {
+      // Make sure we come back around to stop waiting on slow plugins
+      ctx.request_repaint_after(wait);
     }
 
     let mut input_changed = false;
-    if ctx.input(|i| i.key_released(Key::Enter)) {
-      if let Some(opt) = self.state.selected() {
-        match self.plugins.try_launch(opt) {
-          Ok(OkAction {
-            update_input: Some(inp),
-            ..
-          }) => {
-            self.state.input = inp;
-            input_changed = true;
-          }
-          Ok(OkAction {
-            close_win: true, ..
-          }) => {
-            self.close(ctx, false);
-            self.reset_state();
-          }
-          Ok(_) => self.reset_state(),
-          Err(e) => error!("{:?}", e),
+    for cmd in commands {
+      let selected = self.state.selected;
+      match cmd {
+        Command::Close => {
+          self.close(ctx, false);
+          return;
+        }
+        Command::Next => self.state.select_next(),
+        Command::Prev => self.state.select_prev(),
+        Command::PageDown => self.state.select(selected + PAGE_SIZE),
+        Command::PageUp => self.state.select(selected.saturating_sub(PAGE_SIZE)),
+        Command::First => self.state.select(0),
+        Command::Last => self.state.select(usize::MAX),
+        Command::Confirm => input_changed |= self.launch_selected(ctx, false),
+        Command::SecondaryAction => input_changed |= self.launch_selected(ctx, true),
+        Command::Complete => match self.complete_selected() {
+          true => input_changed = true,
+          false => self.state.select_next(),
+        },
+        Command::ClearInput => {
+          self.state.input.clear();
+          input_changed = true;
         }
+        Command::Ignore => {}
+      }
+      if self.state.selected != selected {
+        self.check_plugins_for_state_updates();
       }
     }
 
@@ -215,9 +311,12 @@ impl LyraPowerbarImpl {
               .filter(|pv| pv.blocks_search(&self.state))
               .is_none()
             {
-              self.state.options = self.get_options(&self.state.input);
-              self.state.selected = 0;
+              let search = self.state.input.clone();
+              self.search(&search);
               self.check_plugins_for_state_updates();
+            } else {
+              // Whatever was in flight is no longer wanted
+              self.queries.cancel();
             }
           }
 
@@ -237,7 +336,7 @@ impl LyraPowerbarImpl {
             });
           }
 
-          if res.changed() || input_changed {
+          if res.changed() || input_changed || options_arrived {
             let height = ui.min_rect().height() + (padding * 2.0);
             ctx.send_viewport_cmd_to(
               ViewportId::ROOT,
@@ -249,16 +348,6 @@ impl LyraPowerbarImpl {
   }
 }
 
-fn is_nav_down(i: &InputState) -> bool {
-  i.key_released(Key::ArrowDown)
-    || (!i.modifiers.matches_exact(Modifiers::SHIFT) && i.key_released(Key::Tab))
-}
-
-fn is_nav_up(i: &InputState) -> bool {
-  i.key_released(Key::ArrowUp)
-    || (i.modifiers.matches_exact(Modifiers::SHIFT) && i.key_released(Key::Tab))
-}
-
 fn mk_text_edit<'t>(text: &'t mut dyn TextBuffer) -> TextEdit {
   TextEdit::singleline(text)
     .desired_width(f32::INFINITY)
@@ -275,3 +364,270 @@ fn mk_text_edit<'t>(text: &'t mut dyn TextBuffer) -> TextEdit {
       ..Default::default()
     })
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::InnerConfig;
+  use crate::plugin::{
+    DynPlugin, FuzzyMatchItem, Plugin, PluginName, PluginValue, Renderable, SearchBlocker,
+  };
+  use nucleo_matcher::Config as NucleoConfig;
+  use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Instant,
+  };
+
+  #[derive(Clone)]
+  struct Item(&'static str);
+
+  impl PluginValue for Item {
+    fn item_id(&self) -> Option<String> {
+      Some(self.0.to_string())
+    }
+  }
+  impl Renderable for Item {
+    fn render(&self, _ui: &mut egui::Ui, _state: &AppState) {}
+  }
+  impl SearchBlocker for Item {}
+
+  /// Offers fixed items to match against, plus any items that are always present
+  struct Fixed(&'static str, Vec<&'static str>, Vec<&'static str>);
+
+  impl Fixed {
+    fn items(&self, items: &[&'static str]) -> Vec<FuzzyMatchItem> {
+      items
+        .iter()
+        .map(|item| FuzzyMatchItem {
+          against: Arc::new(item.to_string()),
+          value: PluginV::new(self.0, Item(item)),
+          source: self.0.into(),
+        })
+        .collect()
+    }
+  }
+
+  impl Plugin for Fixed {
+    type PV = Item;
+
+    fn action(&self, _input: &Item) -> Result<OkAction, anyhow::Error> {
+      Ok(OkAction::default())
+    }
+
+    fn options(&self, _search: &str) -> Vec<FuzzyMatchItem> {
+      self.items(&self.1)
+    }
+
+    fn has_static_items(&self) -> bool {
+      !self.2.is_empty()
+    }
+
+    fn static_items(&self) -> Vec<FuzzyMatchItem> {
+      self.items(&self.2)
+    }
+  }
+
+  #[derive(Clone)]
+  struct Echoed(String);
+
+  impl PluginValue for Echoed {
+    fn item_id(&self) -> Option<String> {
+      Some(self.0.clone())
+    }
+  }
+  impl Renderable for Echoed {
+    fn render(&self, _ui: &mut egui::Ui, _state: &AppState) {}
+  }
+  impl SearchBlocker for Echoed {}
+
+  /// Answers the whole search behind a prefix, the way calc does
+  struct Echo;
+
+  impl Plugin for Echo {
+    type PV = Echoed;
+
+    fn prefix(&self) -> Option<String> {
+      Some("=".into())
+    }
+
+    fn action(&self, _input: &Echoed) -> Result<OkAction, anyhow::Error> {
+      Ok(OkAction::default())
+    }
+
+    fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+      vec![FuzzyMatchItem {
+        against: Arc::new(search.to_string()),
+        value: PluginV::new("echo", Echoed(search.to_string())),
+        source: "echo".into(),
+      }]
+    }
+  }
+
+  fn powerbar() -> LyraPowerbarImpl {
+    let config = Config::default();
+    config.update(|mut cfg| {
+      *cfg = InnerConfig {
+        result_count: 9,
+        query_budget_ms: 1000,
+        ..Default::default()
+      }
+    });
+    let config = Arc::new(config);
+    let plugins: Vec<(PluginName, Box<dyn DynPlugin>)> = vec![
+      (
+        "apps".into(),
+        Box::new(Fixed("apps", vec!["Dash", "Dashlane", "Discord"], vec![])),
+      ),
+      ("web".into(), Box::new(Fixed("web", vec![], vec!["Web"]))),
+      ("echo".into(), Box::new(Echo)),
+      ("later".into(), Box::new(Later::default())),
+    ];
+    let plugins = PluginManager::new(plugins.into_iter().collect(), Arc::default());
+    LyraPowerbarImpl {
+      state: AppState::default(),
+      queries: PluginQueries::new(config.clone(), plugins.clone(), Arc::new(|| {})),
+      plugins,
+      config,
+      matcher: RwLock::new(Matcher::new(NucleoConfig::DEFAULT)),
+      background: None,
+    }
+  }
+
+  /// Gets on with its action in the background, its one item saying whether it's done
+  #[derive(Default)]
+  struct Later(Arc<AtomicBool>);
+
+  impl Plugin for Later {
+    type PV = Echoed;
+
+    fn prefix(&self) -> Option<String> {
+      Some("@".into())
+    }
+
+    fn action(&self, _input: &Echoed) -> Result<OkAction, anyhow::Error> {
+      let done = self.0.clone();
+      let working = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        done.store(true, Ordering::SeqCst);
+      });
+      Ok(OkAction {
+        update_input: Some("@later".into()),
+        search_again_after: Some(working),
+        ..Default::default()
+      })
+    }
+
+    fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+      let label = match self.0.load(Ordering::SeqCst) {
+        true => "done",
+        false => "running",
+      };
+      vec![FuzzyMatchItem {
+        against: Arc::new(search.to_string()),
+        value: PluginV::new("later", Echoed(label.to_string())),
+        source: "later".into(),
+      }]
+    }
+  }
+
+  /// Types out the search, waiting on the results like the UI would
+  fn search(pb: &mut LyraPowerbarImpl, input: &str) {
+    pb.state.input = input.into();
+    pb.search(input);
+    let until = Instant::now() + Duration::from_secs(2);
+    while pb.queries.next_deadline().is_some() && Instant::now() < until {
+      pb.poll_options();
+      thread::sleep(Duration::from_millis(5));
+    }
+    pb.poll_options();
+  }
+
+  fn select(pb: &mut LyraPowerbarImpl, item: &str) {
+    let idx = pb
+      .state
+      .options
+      .iter()
+      .position(|v| v.item_id().as_deref() == Some(item))
+      .unwrap();
+    loop {
+      pb.state.select_next();
+      if pb.state.selected == idx {
+        break;
+      }
+    }
+  }
+
+  fn selected(pb: &LyraPowerbarImpl) -> Option<String> {
+    pb.state.selected().and_then(|v| v.item_id())
+  }
+
+  fn options(pb: &LyraPowerbarImpl) -> Vec<String> {
+    pb.state
+      .options
+      .iter()
+      .filter_map(|v| v.item_id())
+      .collect()
+  }
+
+  #[test]
+  fn selection_sticks_while_refining() {
+    let mut pb = powerbar();
+    search(&mut pb, "dash");
+    assert_eq!(options(&pb).len(), 3);
+    select(&mut pb, "Dashlane");
+
+    search(&mut pb, "dashl");
+    assert_eq!(options(&pb), vec!["Dashlane", "Web"]);
+    assert_eq!(selected(&pb), Some("Dashlane".into()));
+
+    select(&mut pb, "Web");
+    search(&mut pb, "dashla");
+    assert_eq!(selected(&pb), Some("Web".into()));
+  }
+
+  #[test]
+  fn selection_resets_when_item_is_gone() {
+    let mut pb = powerbar();
+    search(&mut pb, "d");
+    select(&mut pb, "Discord");
+    search(&mut pb, "dash");
+    assert_eq!(pb.state.selected, 0);
+
+    search(&mut pb, "");
+    assert!(options(&pb).is_empty());
+    assert_eq!(pb.state.selected, 0);
+  }
+
+  #[test]
+  fn answers_to_the_last_search_go_as_soon_as_it_changes() {
+    let mut pb = powerbar();
+    search(&mut pb, "=1+(");
+    assert_eq!(options(&pb), vec!["=1+("]);
+
+    // Shown against the shorter input before its own answer is in, they'd be wrong
+    pb.state.input = "=1+".into();
+    pb.search("=1+");
+    assert!(options(&pb).is_empty());
+    search(&mut pb, "=1+");
+    assert_eq!(options(&pb), vec!["=1+"]);
+  }
+
+  #[test]
+  fn searches_again_once_background_work_is_done() {
+    let mut pb = powerbar();
+    search(&mut pb, "@later");
+    assert_eq!(options(&pb), vec!["running"]);
+
+    let ctx = egui::Context::default();
+    assert!(pb.launch_selected(&ctx, false));
+    let until = Instant::now() + Duration::from_secs(2);
+    while options(&pb) != vec!["done"] && Instant::now() < until {
+      pb.search_again_when_done(&ctx);
+      pb.poll_options();
+      thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(options(&pb), vec!["done"]);
+    assert!(pb.background.is_none());
+  }
+}
@@ -0,0 +1,548 @@
+use crate::{
+  config::Config,
+  plugin::{FuzzyMatchItem, PluginName},
+  plugin_manager::PluginManager,
+};
+use parking_lot::Mutex;
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  panic::{self, AssertUnwindSafe},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc::{self, Receiver, Sender},
+    Arc,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+use tracing::{error, warn};
+
+pub type Repaint = Arc<dyn Fn() + Send + Sync>;
+
+/// Runs plugin queries off the UI thread. Every plugin gets a worker of its own, so a slow
+/// plugin only ever holds up its own results. Each search starts a new generation, anything
+/// still being worked on for an older one is dropped, and plugins that take longer than
+/// their budget are no longer waited on.
+pub struct PluginQueries {
+  config: Arc<Config>,
+  plugins: PluginManager,
+  generation: Arc<AtomicU64>,
+  workers: HashMap<PluginName, Sender<Job>>,
+  /// Only ever read with `&mut self`, the lock just lets the bar be shared across threads
+  results: Mutex<Receiver<QueryResult>>,
+  /// Plugins yet to answer the current generation, and when we stop waiting on them
+  pending: HashMap<PluginName, Instant>,
+  options: BTreeMap<PluginName, Vec<FuzzyMatchItem>>,
+  statics: BTreeMap<PluginName, Vec<FuzzyMatchItem>>,
+  /// Plugins whose results held are from a previous generation. They stay around until the
+  /// plugin answers the current one, so the list doesn't flash empty on every keystroke and
+  /// items don't drop out just because another plugin answered first
+  outdated: HashSet<PluginName>,
+  /// Whether any plugin has answered the current generation yet
+  answered: bool,
+  dirty: bool,
+}
+
+struct Job {
+  generation: u64,
+  search: String,
+  options: bool,
+  statics: bool,
+}
+
+struct QueryResult {
+  generation: u64,
+  plugin: PluginName,
+  options: Vec<FuzzyMatchItem>,
+  statics: Vec<FuzzyMatchItem>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polled {
+  Unchanged,
+  /// Results for a new search replaced the previous ones
+  Replaced,
+  /// More results came in for the current search
+  Merged,
+}
+
+impl PluginQueries {
+  pub fn new(config: Arc<Config>, plugins: PluginManager, repaint: Repaint) -> Self {
+    let generation = Arc::new(AtomicU64::new(0));
+    let (results_tx, results) = mpsc::channel();
+    let workers = plugins
+      .names()
+      .map(|name| {
+        let (tx, jobs) = mpsc::channel();
+        let worker = Worker {
+          name: name.clone(),
+          plugins: plugins.clone(),
+          generation: generation.clone(),
+          results: results_tx.clone(),
+          repaint: repaint.clone(),
+        };
+        thread::Builder::new()
+          .name(format!("query-{}", name))
+          .spawn(move || worker.run(jobs))
+          .expect("Failed to spawn plugin query worker");
+        (name.clone(), tx)
+      })
+      .collect();
+
+    PluginQueries {
+      config,
+      plugins,
+      generation,
+      workers,
+      results: Mutex::new(results),
+      pending: HashMap::new(),
+      options: BTreeMap::new(),
+      statics: BTreeMap::new(),
+      outdated: HashSet::new(),
+      answered: false,
+      dirty: false,
+    }
+  }
+
+  /// Asks every plugin relevant to the search for its options, abandoning whatever was
+  /// still running for the previous search
+  pub fn start(&mut self, search: &str) {
+    let generation = self.cancel();
+    let plugins = self.plugins.clone();
+    let options = plugins.filter_to(search);
+    let statics = plugins.always_present(search);
+    let now = Instant::now();
+    for &name in options.iter().chain(statics.iter()) {
+      if self.pending.contains_key(name) {
+        continue;
+      }
+      let job = Job {
+        generation,
+        search: search.to_owned(),
+        options: options.contains(&name),
+        statics: statics.contains(&name),
+      };
+      match self.workers.get(name).map(|w| w.send(job)) {
+        Some(Ok(())) => {
+          self.pending.insert(name.clone(), now + self.budget(name));
+        }
+        _ => error!("Query worker for {} is gone", name),
+      }
+    }
+    if self.pending.is_empty() {
+      self.clear();
+      return;
+    }
+    // Plugins that weren't asked have nothing to say about this search. Nor do plugins
+    // that own the search, whose results answer the search they were given as a whole so
+    // can't be matched against another
+    let keep: HashSet<_> = self
+      .pending
+      .keys()
+      .filter(|name| !self.owns_search(name))
+      .cloned()
+      .collect();
+    let held = self.options.len();
+    self.options.retain(|name, _| keep.contains(name));
+    self.statics.retain(|name, _| keep.contains(name));
+    self.dirty |= held != self.options.len();
+    self.outdated = self.options.keys().cloned().collect();
+    self.outdated.extend(self.statics.keys().cloned());
+    self.answered = false;
+  }
+
+  /// Stops waiting on the current search, returning the generation that comes after it
+  pub fn cancel(&mut self) -> u64 {
+    self.pending.clear();
+    self.outdated.clear();
+    self.generation.fetch_add(1, Ordering::AcqRel) + 1
+  }
+
+  /// Merges in any results that have arrived for the current search
+  pub fn poll(&mut self) -> Polled {
+    let generation = self.generation.load(Ordering::Acquire);
+    let mut polled = match std::mem::take(&mut self.dirty) {
+      true => Polled::Replaced,
+      false => Polled::Unchanged,
+    };
+
+    while let Ok(res) = self.results.get_mut().try_recv() {
+      if res.generation != generation || self.pending.remove(&res.plugin).is_none() {
+        continue;
+      }
+      polled = self.changed(polled);
+      self.answered = true;
+      self.outdated.remove(&res.plugin);
+      self.options.insert(res.plugin.clone(), res.options);
+      self.statics.insert(res.plugin, res.statics);
+    }
+
+    let now = Instant::now();
+    self.pending.retain(|name, deadline| {
+      let waiting = *deadline > now;
+      if !waiting {
+        warn!("Plugin {} went over its query budget", name);
+      }
+      waiting
+    });
+    // Whatever's left from the last search goes once its plugin is no longer waited on
+    let expired: Vec<_> = self
+      .outdated
+      .iter()
+      .filter(|name| !self.pending.contains_key(*name))
+      .cloned()
+      .collect();
+    for name in expired {
+      self.options.remove(&name);
+      self.statics.remove(&name);
+      self.outdated.remove(&name);
+      polled = self.changed(polled);
+    }
+
+    polled
+  }
+
+  /// How long until the next plugin runs out of budget, if any are still running
+  pub fn next_deadline(&self) -> Option<Duration> {
+    let now = Instant::now();
+    self
+      .pending
+      .values()
+      .min()
+      .map(|deadline| deadline.saturating_duration_since(now))
+  }
+
+  pub fn options(&self) -> impl Iterator<Item = &FuzzyMatchItem> {
+    self.options.values().flatten()
+  }
+
+  pub fn statics(&self) -> impl Iterator<Item = &FuzzyMatchItem> {
+    self.statics.values().flatten()
+  }
+
+  fn clear(&mut self) {
+    self.options.clear();
+    self.statics.clear();
+    self.outdated.clear();
+    self.dirty = true;
+  }
+
+  /// How a change to the held results should be reported. The first change for a search
+  /// replaces what was there, any after only add to it
+  fn changed(&self, polled: Polled) -> Polled {
+    match (polled, self.answered) {
+      (Polled::Replaced, _) | (_, false) => Polled::Replaced,
+      _ => Polled::Merged,
+    }
+  }
+
+  /// Whether the plugin only runs behind a prefix, where it's given the search to itself
+  fn owns_search(&self, name: &PluginName) -> bool {
+    self
+      .plugins
+      .get(name)
+      .is_ok_and(|plugin| !plugin.prefixes().is_empty())
+  }
+
+  fn budget(&self, name: &PluginName) -> Duration {
+    let cfg = self.config.get();
+    Duration::from_millis(
+      cfg
+        .query_budgets
+        .get(name)
+        .copied()
+        .unwrap_or(cfg.query_budget_ms),
+    )
+  }
+}
+
+struct Worker {
+  name: PluginName,
+  plugins: PluginManager,
+  generation: Arc<AtomicU64>,
+  results: Sender<QueryResult>,
+  repaint: Repaint,
+}
+
+impl Worker {
+  fn run(self, jobs: Receiver<Job>) {
+    while let Ok(mut job) = jobs.recv() {
+      // Only the latest search matters, skip any this plugin fell behind on
+      while let Ok(next) = jobs.try_recv() {
+        job = next;
+      }
+      if self.is_stale(&job) {
+        continue;
+      }
+      let Ok(plugin) = self.plugins.get(&self.name) else {
+        return;
+      };
+      // A plugin panicking on one search answers it with nothing, rather than taking its
+      // worker down and going quiet for the rest of the session
+      let found = panic::catch_unwind(AssertUnwindSafe(|| {
+        let options = match job.options {
+          true => plugin.options(&job.search),
+          false => vec![],
+        };
+        let statics = match job.statics {
+          true => plugin.static_items(),
+          false => vec![],
+        };
+        (options, statics)
+      }));
+      let (options, statics) = found.unwrap_or_else(|_| {
+        error!("Query for {} panicked", self.name);
+        (vec![], vec![])
+      });
+      if self.is_stale(&job) {
+        continue;
+      }
+      let res = QueryResult {
+        generation: job.generation,
+        plugin: self.name.clone(),
+        options,
+        statics,
+      };
+      if self.results.send(res).is_err() {
+        return;
+      }
+      (self.repaint)();
+    }
+  }
+
+  fn is_stale(&self, job: &Job) -> bool {
+    job.generation != self.generation.load(Ordering::Acquire)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::InnerConfig;
+  use crate::plugin::{
+    AppState, DynPlugin, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
+  };
+  use std::sync::atomic::AtomicUsize;
+
+  #[derive(Clone)]
+  struct Label(String);
+
+  impl PluginValue for Label {}
+  impl Renderable for Label {
+    fn render(&self, _ui: &mut egui::Ui, _state: &AppState) {}
+  }
+  impl SearchBlocker for Label {}
+
+  /// Answers with the search after sleeping for `delay`, counting how often it was asked.
+  /// Panics when the search is `boom`
+  struct Sleepy {
+    name: &'static str,
+    delay: Duration,
+    prefix: Option<String>,
+    calls: Arc<AtomicUsize>,
+  }
+
+  impl Plugin for Sleepy {
+    type PV = Label;
+
+    fn prefix(&self) -> Option<String> {
+      self.prefix.clone()
+    }
+
+    fn action(&self, _input: &Label) -> Result<OkAction, anyhow::Error> {
+      Ok(OkAction::default())
+    }
+
+    fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+      self.calls.fetch_add(1, Ordering::SeqCst);
+      thread::sleep(self.delay);
+      if search == "boom" {
+        panic!("Blew up on {}", search);
+      }
+      let label = format!("{} {}", self.name, search);
+      vec![FuzzyMatchItem {
+        against: Arc::new(label.clone()),
+        value: PluginV::new(self.name, Label(label)),
+        source: self.name.into(),
+      }]
+    }
+  }
+
+  fn sleepy(name: &'static str, delay_ms: u64) -> (PluginName, Box<dyn DynPlugin>) {
+    (
+      name.into(),
+      Box::new(Sleepy {
+        name,
+        delay: Duration::from_millis(delay_ms),
+        prefix: None,
+        calls: Arc::default(),
+      }),
+    )
+  }
+
+  fn queries(plugins: Vec<(PluginName, Box<dyn DynPlugin>)>, budget_ms: u64) -> PluginQueries {
+    let config = Config::default();
+    config.update(|mut cfg| {
+      *cfg = InnerConfig {
+        query_budget_ms: budget_ms,
+        ..Default::default()
+      }
+    });
+    PluginQueries::new(
+      Arc::new(config),
+      PluginManager::new(plugins.into_iter().collect(), Arc::default()),
+      Arc::new(|| {}),
+    )
+  }
+
+  fn labels(q: &PluginQueries) -> Vec<String> {
+    q.options()
+      .map(|fm| fm.value.downcast::<Label>().unwrap().0.clone())
+      .collect()
+  }
+
+  /// Polls until the queries settle or the wait runs out
+  fn settle(q: &mut PluginQueries, wait_ms: u64) -> Vec<Polled> {
+    let until = Instant::now() + Duration::from_millis(wait_ms);
+    let mut seen = vec![];
+    while Instant::now() < until {
+      match q.poll() {
+        Polled::Unchanged => {}
+        polled => seen.push(polled),
+      }
+      if q.next_deadline().is_none() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(5));
+    }
+    seen
+  }
+
+  #[test]
+  fn fast_results_arrive_before_slow_ones() {
+    let mut q = queries(vec![sleepy("fast", 0), sleepy("slow", 200)], 1000);
+    q.start("dogs");
+    assert!(q.next_deadline().is_some());
+
+    let until = Instant::now() + Duration::from_millis(150);
+    while Instant::now() < until && q.poll() == Polled::Unchanged {
+      thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(labels(&q), vec!["fast dogs".to_string()]);
+
+    assert_eq!(settle(&mut q, 1000), vec![Polled::Merged]);
+    assert_eq!(
+      labels(&q),
+      vec!["fast dogs".to_string(), "slow dogs".to_string()]
+    );
+  }
+
+  #[test]
+  fn plugins_over_budget_are_dropped() {
+    let mut q = queries(vec![sleepy("fast", 0), sleepy("slow", 300)], 100);
+    q.start("dogs");
+    settle(&mut q, 1000);
+    assert_eq!(labels(&q), vec!["fast dogs".to_string()]);
+
+    // Its late answer must not sneak in afterwards
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(q.poll(), Polled::Unchanged);
+    assert_eq!(labels(&q), vec!["fast dogs".to_string()]);
+  }
+
+  #[test]
+  fn plugins_keep_answering_after_a_panic() {
+    let mut q = queries(vec![sleepy("flaky", 0)], 1000);
+    q.start("boom");
+    settle(&mut q, 1000);
+    assert!(q.next_deadline().is_none());
+    assert!(labels(&q).is_empty());
+
+    q.start("dogs");
+    settle(&mut q, 1000);
+    assert_eq!(labels(&q), vec!["flaky dogs".to_string()]);
+  }
+
+  #[test]
+  fn stale_searches_are_discarded() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let plugin: Box<dyn DynPlugin> = Box::new(Sleepy {
+      name: "slow",
+      delay: Duration::from_millis(100),
+      prefix: None,
+      calls: calls.clone(),
+    });
+    let mut q = queries(vec![("slow".into(), plugin)], 1000);
+    q.start("d");
+    thread::sleep(Duration::from_millis(20));
+    for search in ["do", "dog", "dogs"] {
+      q.start(search);
+    }
+    settle(&mut q, 1000);
+
+    assert_eq!(labels(&q), vec!["slow dogs".to_string()]);
+    // The first search was already underway, the middle ones were never run
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+  }
+
+  #[test]
+  fn previous_results_stay_until_replaced() {
+    let mut q = queries(vec![sleepy("slow", 50)], 1000);
+    q.start("cats");
+    settle(&mut q, 1000);
+    q.start("dogs");
+    assert_eq!(q.poll(), Polled::Unchanged);
+    assert_eq!(labels(&q), vec!["slow cats".to_string()]);
+    assert_eq!(settle(&mut q, 1000), vec![Polled::Replaced]);
+    assert_eq!(labels(&q), vec!["slow dogs".to_string()]);
+  }
+
+  #[test]
+  fn slower_plugins_keep_their_results_until_they_answer() {
+    let mut q = queries(vec![sleepy("fast", 0), sleepy("slow", 150)], 1000);
+    q.start("cats");
+    settle(&mut q, 1000);
+    q.start("dogs");
+
+    let until = Instant::now() + Duration::from_millis(100);
+    while Instant::now() < until && q.poll() == Polled::Unchanged {
+      thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(labels(&q), vec!["fast dogs", "slow cats"]);
+    assert_eq!(settle(&mut q, 1000), vec![Polled::Merged]);
+    assert_eq!(labels(&q), vec!["fast dogs", "slow dogs"]);
+  }
+
+  #[test]
+  fn outdated_results_go_once_over_budget() {
+    let mut q = queries(vec![sleepy("fast", 0), sleepy("slow", 50)], 1000);
+    q.start("cats");
+    settle(&mut q, 1000);
+
+    q.config.update(|mut cfg| {
+      cfg.query_budgets.insert("slow".into(), 10);
+    });
+    q.start("dogs");
+    settle(&mut q, 1000);
+    assert_eq!(labels(&q), vec!["fast dogs"]);
+  }
+
+  #[test]
+  fn searches_nobody_answers_clear_results() {
+    let prefixed: Box<dyn DynPlugin> = Box::new(Sleepy {
+      name: "calc",
+      delay: Duration::ZERO,
+      prefix: Some("=".into()),
+      calls: Arc::default(),
+    });
+    let mut q = queries(vec![("calc".into(), prefixed)], 1000);
+    q.start("=1");
+    settle(&mut q, 1000);
+    assert_eq!(labels(&q), vec!["calc =1".to_string()]);
+
+    // Plain searches go to every unprefixed plugin, of which there are none
+    q.start("dogs");
+    assert_eq!(q.poll(), Polled::Replaced);
+    assert!(labels(&q).is_empty());
+  }
+}
@@ -0,0 +1,129 @@
+use anyhow::Context;
+use parking_lot::RwLock;
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+use tracing::warn;
+
+/// What was read from a file, read again whenever the file changes so edits made elsewhere
+/// show up without a restart. A broken edit keeps what was read before.
+#[derive(Debug)]
+pub struct Reloader<T> {
+  file: PathBuf,
+  /// What's in the file, for the errors
+  what: &'static str,
+  /// Reads the contents, given when the file was last modified
+  parse: fn(&str, Option<SystemTime>) -> Result<T, anyhow::Error>,
+  loaded: RwLock<Loaded<T>>,
+}
+
+#[derive(Debug, Default)]
+struct Loaded<T> {
+  modified: Option<SystemTime>,
+  value: T,
+}
+
+impl<T: Clone + Default> Reloader<T> {
+  pub fn new(
+    file: PathBuf,
+    what: &'static str,
+    parse: fn(&str, Option<SystemTime>) -> Result<T, anyhow::Error>,
+  ) -> Self {
+    Reloader {
+      file,
+      what,
+      parse,
+      loaded: RwLock::default(),
+    }
+  }
+
+  pub fn file(&self) -> &Path {
+    &self.file
+  }
+
+  /// What's in the file now, or what was last read from it when it can't be read
+  pub fn get(&self) -> T {
+    if modified(&self.file) != self.loaded.read().modified {
+      if let Err(err) = self.reload() {
+        warn!(
+          "Failed to reload {}, keeping the old ones: {:?}",
+          self.what, err
+        );
+      }
+    }
+    self.loaded.read().value.clone()
+  }
+
+  /// Takes what was just written to the file as read, so it isn't read back again
+  pub fn written(&self, value: T) {
+    let mut loaded = self.loaded.write();
+    loaded.modified = modified(&self.file);
+    loaded.value = value;
+  }
+
+  pub fn reload(&self) -> Result<(), anyhow::Error> {
+    let modified = modified(&self.file);
+    let parsed = fs::read_to_string(&self.file)
+      .map_err(anyhow::Error::from)
+      .and_then(|s| (self.parse)(&s, modified))
+      .with_context(|| format!("Invalid {} file {}", self.what, self.file.display()));
+    let mut loaded = self.loaded.write();
+    // Even when it's broken, so it's only complained about once per change
+    loaded.modified = modified;
+    loaded.value = parsed?;
+    Ok(())
+  }
+}
+
+pub fn modified(file: &Path) -> Option<SystemTime> {
+  fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+  use std::time::Duration;
+
+  fn numbers(dir: &Path) -> Reloader<Vec<u32>> {
+    Reloader::new(dir.join("numbers.txt"), "numbers", |s, _| {
+      Ok(
+        s.split_whitespace()
+          .map(str::parse)
+          .collect::<Result<_, _>>()?,
+      )
+    })
+  }
+
+  /// Writes the file with a modified time of its own, however coarse the timestamps
+  fn write(file: &Path, contents: &str, secs_ago: u64) {
+    fs::write(file, contents).unwrap();
+    let modified = SystemTime::now() - Duration::from_secs(secs_ago);
+    fs::File::options()
+      .write(true)
+      .open(file)
+      .unwrap()
+      .set_modified(modified)
+      .unwrap();
+  }
+
+  #[test]
+  fn reads_again_once_changed() {
+    let dir = TestDir::new("reloader");
+    let numbers = numbers(&dir);
+    assert_eq!(numbers.get(), Vec::<u32>::new());
+
+    write(numbers.file(), "1 2", 60);
+    assert_eq!(numbers.get(), vec![1, 2]);
+    write(numbers.file(), "3", 30);
+    assert_eq!(numbers.get(), vec![3]);
+
+    // A broken edit keeps what was there before, without trying it again until it changes
+    write(numbers.file(), "4 four", 20);
+    assert_eq!(numbers.get(), vec![3]);
+    assert_eq!(numbers.loaded.read().modified, modified(numbers.file()));
+    assert!(numbers.reload().is_err());
+  }
+}
@@ -8,9 +8,13 @@ use std::sync::Arc;
 use tracing::warn;
 
 use crate::{
-  config::{Config, Placement, WebqSearchConfig},
+  config::{Config, Placement, WebqConfig, WebqSearchConfig},
+  frecency::Frecency,
   icon_ui::{data_or_url, Icon},
-  template::Template,
+  keymap::{Chord, Command, Keymap, Preset},
+  snippets::{Snippet, SnippetStore},
+  template::{NamedTemplate, Template},
+  webq,
 };
 
 const LYRA_SETTINGS: &str = "Lyra Settings";
@@ -20,6 +24,11 @@ pub struct LyraSettings {
   pub title: String,
   pub visible: Arc<RwLock<bool>>,
   config: Arc<Config>,
+  frecency: Arc<Frecency>,
+  snippets: Option<SnippetStore>,
+  /// Whether the snippets were changed here since they were last saved. They're only saved
+  /// then, so hand edits to the file aren't overwritten by saving other settings
+  snippets_edited: bool,
   form: LyraSettingsForm,
 }
 
@@ -41,6 +50,28 @@ impl LyraWebqForm {
   }
 }
 
+#[derive(FormResult, Default)]
+struct LyraSnippetForm {
+  label: FormField<WebqLabel>,
+  keyword: FormField<SnippetKeyword>,
+  body: FormField<NamedTemplate>,
+  index: Option<usize>,
+}
+impl LyraSnippetForm {
+  fn clear(&mut self) {
+    self.label = FormField::default();
+    self.keyword = FormField::default();
+    self.body = FormField::default();
+    self.index = None;
+  }
+}
+
+#[derive(Default)]
+struct LyraBindingForm {
+  chord: FormField<FormChord>,
+  command: Command,
+}
+
 #[derive(FormResult, Default)]
 struct LyraSettingsForm {
   window_x: FormField<WindowCoordinate>,
@@ -54,6 +85,12 @@ struct LyraSettingsForm {
   // All other searchers & searcher form
   searcher_form: LyraWebqForm,
   webq_searchers: Vec<WebqSearchConfig>,
+  // Keybindings & binding form
+  keymap: Keymap,
+  binding_form: LyraBindingForm,
+  // Snippets & snippet form
+  snippet_form: LyraSnippetForm,
+  snippets: Vec<Snippet>,
 }
 
 impl Validate for Template {
@@ -65,6 +102,12 @@ impl Validate for Template {
   }
 }
 
+impl Validate for NamedTemplate {
+  fn validate(v: &Self) -> Result<(), String> {
+    Template::validate(v)
+  }
+}
+
 #[derive(Clone, Default, Display, FromStr, FormFieldData)]
 struct FormHotKey(String);
 impl Validate for FormHotKey {
@@ -76,6 +119,17 @@ impl Validate for FormHotKey {
   }
 }
 
+#[derive(Clone, Default, Display, FromStr, FormFieldData)]
+struct FormChord(String);
+impl Validate for FormChord {
+  fn validate(v: &Self) -> Result<(), String> {
+    v.0
+      .parse::<Chord>()
+      .map(|_| ())
+      .map_err(|e| format!("{}", e))
+  }
+}
+
 #[derive(Clone, Default, Display, FromStr, FormFieldData)]
 struct WebqShortname(String);
 impl Validate for WebqShortname {
@@ -87,6 +141,19 @@ impl Validate for WebqShortname {
   }
 }
 
+#[derive(Clone, Default, Display, FromStr, FormFieldData)]
+struct SnippetKeyword(String);
+impl Validate for SnippetKeyword {
+  fn validate(v: &Self) -> Result<(), String> {
+    match v.0.trim().is_empty() {
+      true => Err("Cannot be blank".into()),
+      // The args of a snippet come after the first space
+      false if v.0.contains(char::is_whitespace) => Err("Cannot contain spaces".into()),
+      false => Ok(()),
+    }
+  }
+}
+
 #[derive(Clone, Default, Display, FromStr, FormFieldData)]
 struct WebqLabel(String);
 impl Validate for WebqLabel {
@@ -120,7 +187,7 @@ impl Validate for WindowCoordinate {
 }
 
 impl LyraSettings {
-  pub fn new(config: Arc<Config>) -> Self {
+  pub fn new(config: Arc<Config>, frecency: Arc<Frecency>) -> Self {
     let mut form = LyraSettingsForm::default();
     {
       let cfg = config.get();
@@ -130,22 +197,199 @@ impl LyraSettings {
           form.window_y = FormField::new(WindowCoordinate(y));
         }
       }
-      if let Some(webq) = &cfg.webq.default_searcher {
+      let webq_cfg: WebqConfig = cfg.plugin_config(webq::PLUGIN_NAME).unwrap_or_else(|err| {
+        warn!("Failed to read webq config: {}", err);
+        WebqConfig::default()
+      });
+      if let Some(webq) = &webq_cfg.default_searcher {
         form.webq_label = FormField::new(WebqLabel(webq.label.clone()));
         form.webq_template = FormField::new(webq.template.clone());
         form.webq_image = FormField::new(WebqImage(webq.icon.clone()));
       }
-      form.webq_searchers = cfg.webq.searchers.values().map(|w| w.clone()).collect();
+      form.webq_searchers = webq_cfg.searchers.values().map(|w| w.clone()).collect();
       form.hotkey = FormField::new(FormHotKey(cfg.hotkey.parse().unwrap()));
+      form.keymap = cfg.keymap.clone();
     }
+    let snippets = SnippetStore::load(&config.conf_dir)
+      .inspect_err(|err| warn!("Failed to read snippets: {:?}", err))
+      .ok();
+    form.snippets = snippets.as_ref().map(|s| s.snippets()).unwrap_or_default();
     LyraSettings {
       id: ViewportId::from_hash_of(LYRA_SETTINGS),
       title: LYRA_SETTINGS.into(),
       visible: Arc::new(RwLock::new(false)),
       config,
+      frecency,
+      snippets,
+      snippets_edited: false,
       form,
     }
   }
+
+  /// Snippets, edited like bookmarks and likewise only saved along with everything else.
+  /// They're kept in a file of their own rather than the config
+  fn snippets(&mut self, ui: &mut egui::Ui) {
+    ui.collapsing("Snippets", |ui| {
+      if self.snippets.is_none() {
+        ui.colored_label(Color32::RED, "The snippets file couldn't be read");
+        return;
+      }
+      let form = &mut self.form.snippet_form;
+      ui.add(Input::of("Label:", &mut form.label).desired_width(200.0));
+      ui.add(Input::of("Keyword:", &mut form.keyword).desired_width(200.0));
+      ui.add(Input::of("Body:", &mut form.body).multiline());
+      ui.weak("{0}, {1}.. are typed in after the keyword. {date}, {time} & {clipboard} fill themselves in");
+      ui.horizontal(|ui| {
+        let text = match form.index {
+          None => "Add snippet",
+          Some(_) => "Update snippet",
+        };
+        if ui.button(text).clicked() {
+          if let Ok(res) = TryInto::<LyraSnippetFormFormResult>::try_into(&*form) {
+            let snippet = Snippet {
+              label: res.label.0,
+              keyword: res.keyword.0,
+              body: res.body,
+            };
+            match form.index {
+              Some(idx) => self.form.snippets[idx] = snippet,
+              None => self.form.snippets.push(snippet),
+            }
+            self.snippets_edited = true;
+            form.clear();
+          }
+        }
+        if ui.button("Clear").clicked() {
+          form.clear();
+        }
+      });
+
+      let mut removed = None;
+      for (idx, snippet) in self.form.snippets.iter().enumerate() {
+        ui.horizontal(|ui| {
+          ui.label(format!("{} ({})", snippet.label, snippet.keyword));
+          if ui.small_button("Edit").clicked() {
+            let form = &mut self.form.snippet_form;
+            form.label = FormField::new(WebqLabel(snippet.label.clone()));
+            form.keyword = FormField::new(SnippetKeyword(snippet.keyword.clone()));
+            form.body = FormField::new(snippet.body.clone());
+            form.index = Some(idx);
+          }
+          if ui
+            .add_enabled(
+              self.form.snippet_form.index.is_none(),
+              egui::Button::new("Delete").small(),
+            )
+            .clicked()
+          {
+            removed = Some(idx);
+          }
+        });
+      }
+      if let Some(idx) = removed {
+        self.form.snippets.remove(idx);
+        self.snippets_edited = true;
+      }
+    });
+  }
+
+  /// The keymap preset along with the bindings layered over it. Like bookmarks, changes
+  /// only apply once saved
+  fn keybindings(&mut self, ui: &mut egui::Ui) {
+    ui.collapsing("Keybindings", |ui| {
+      let keymap = &mut self.form.keymap;
+      ui.horizontal(|ui| {
+        ui.label("Preset:");
+        egui::ComboBox::from_id_source("keymap_preset")
+          .selected_text(keymap.preset.to_string())
+          .show_ui(ui, |ui| {
+            for preset in Preset::ALL {
+              ui.selectable_value(&mut keymap.preset, preset, preset.to_string());
+            }
+          });
+      });
+      ui.collapsing("Preset bindings", |ui| {
+        for (chord, cmd) in keymap.preset.bindings() {
+          let text = format!("{}: {}", chord, cmd);
+          match keymap.bindings.contains_key(&chord) {
+            true => ui.weak(format!("{} (overridden)", text)),
+            false => ui.label(text),
+          };
+        }
+      });
+
+      let mut removed = None;
+      for (chord, cmd) in keymap.bindings.iter() {
+        ui.horizontal(|ui| {
+          ui.label(format!("{}: {}", chord, cmd));
+          if ui.small_button("Delete").clicked() {
+            removed = Some(*chord);
+          }
+        });
+      }
+      if let Some(chord) = removed {
+        keymap.bindings.remove(&chord);
+      }
+
+      let form = &mut self.form.binding_form;
+      ui.horizontal(|ui| {
+        ui.add(Input::of("Chord:", &mut form.chord).desired_width(150.0));
+        egui::ComboBox::from_id_source("binding_command")
+          .selected_text(form.command.to_string())
+          .show_ui(ui, |ui| {
+            for cmd in Command::ALL {
+              ui.selectable_value(&mut form.command, cmd, cmd.to_string());
+            }
+          });
+        if ui.button("Add binding").clicked() {
+          match form.chord.buffer.parse::<Chord>() {
+            Ok(chord) => {
+              keymap.bindings.insert(chord, form.command);
+              form.chord = FormField::default();
+            }
+            Err(err) => form.chord.value = Err(err.to_string()),
+          }
+        }
+      });
+    });
+  }
+
+  /// Launch history, where items can be forgotten or kept out of the rankings. Changes
+  /// here apply straight away rather than on save
+  fn usage_history(&self, ui: &mut egui::Ui) {
+    ui.collapsing("Usage History", |ui| {
+      if ui.button("Reset all").clicked() {
+        self.frecency.reset();
+      }
+      egui::ScrollArea::vertical()
+        .id_source("usage_history")
+        .max_height(120.0)
+        .show(ui, |ui| {
+          for usage in self.frecency.usage() {
+            ui.horizontal(|ui| {
+              ui.label(format!(
+                "{}: {} ({} launches)",
+                usage.key.plugin, usage.key.item, usage.launches
+              ));
+              if ui.small_button("Forget").clicked() {
+                self.frecency.reset_item(&usage.key);
+              }
+              if ui.small_button("Exclude").clicked() {
+                self.frecency.exclude(usage.key.clone());
+              }
+            });
+          }
+          for key in self.frecency.excluded() {
+            ui.horizontal(|ui| {
+              ui.weak(format!("{}: {} (excluded)", key.plugin, key.item));
+              if ui.small_button("Include").clicked() {
+                self.frecency.include(&key);
+              }
+            });
+          }
+        });
+    });
+  }
 }
 
 impl LyraSettings {
@@ -174,6 +418,10 @@ impl LyraSettings {
             ui.add(Input::of("Hotkey:", &mut self.form.hotkey).desired_width(200.0));
           });
           ui.separator();
+          self.keybindings(ui);
+          ui.separator();
+          self.snippets(ui);
+          ui.separator();
           ui.vertical(|ui| {
             ui.label("Default Search");
             ui.add(Input::of("Label:", &mut self.form.webq_label).desired_width(200.0));
@@ -378,19 +626,25 @@ impl LyraSettings {
                   .iter()
                   .map(|s| (s.label.clone(), s.to_owned()))
                   .collect();
+                let keymap = self.form.keymap.clone();
                 self.config.update(move |mut inner| {
+                  inner.keymap = keymap;
                   inner.styles.window_placement = Placement::XY(res.window_x.0, res.window_y.0);
-                  inner.webq.default_searcher = Some(WebqSearchConfig {
+                  let mut webq_cfg: WebqConfig =
+                    inner.plugin_config(webq::PLUGIN_NAME).unwrap_or_default();
+                  webq_cfg.default_searcher = Some(WebqSearchConfig {
                     label: res.webq_label.0,
                     shortname: "".into(),
                     template: res.webq_template,
                     icon: res.webq_image.0,
                   });
-                  inner.webq.searchers = searchers;
+                  webq_cfg.searchers = searchers;
+                  if let Err(err) = inner.set_plugin_config(webq::PLUGIN_NAME, &webq_cfg) {
+                    warn!("Failed to update webq config: {}", err);
+                  }
                   // TODO: Add more fields
-                  // inner.apps.app_paths;
-                  // inner.apps.app_extension;
-                  // inner.calc.prefix;
+                  // apps: app_paths, app_extension
+                  // calc: prefix
                   // Top level:
                   //  result_count
                   //  styles
@@ -400,13 +654,20 @@ impl LyraSettings {
                 ui.colored_label(Color32::RED, format!("{}", err));
               }
             }
-            if let Err(err) = self.config.persist() {
+            let saved_snippets = match &self.snippets {
+              Some(store) if self.snippets_edited => store.save(self.form.snippets.clone()),
+              _ => Ok(()),
+            };
+            self.snippets_edited &= saved_snippets.is_err();
+            if let Err(err) = self.config.persist().and(saved_snippets) {
               warn!("Failed to save config update: {}", err);
               ui.colored_label(Color32::RED, format!("{}", err));
             } else {
               ui.colored_label(Color32::GREEN, "Saved!");
             }
           }
+          ui.separator();
+          self.usage_history(ui);
         });
       },
     );
@@ -417,6 +678,7 @@ struct Input<'a, T: FormFieldData> {
   label: &'a str,
   field: &'a mut FormField<T>,
   desired_width: Option<f32>,
+  multiline: bool,
 }
 
 impl<'a, T: FormFieldData> Input<'a, T> {
@@ -425,6 +687,7 @@ impl<'a, T: FormFieldData> Input<'a, T> {
       label,
       field,
       desired_width: None,
+      multiline: false,
     }
   }
 
@@ -433,6 +696,12 @@ impl<'a, T: FormFieldData> Input<'a, T> {
     self.desired_width = Some(v);
     self
   }
+
+  #[inline]
+  fn multiline(mut self) -> Self {
+    self.multiline = true;
+    self
+  }
 }
 
 impl<'a, T: FormFieldData> Widget for Input<'a, T> {
@@ -448,10 +717,11 @@ impl<'a, T: FormFieldData> Widget for Input<'a, T> {
           ui.style_mut().visuals.widgets.hovered.bg_stroke = invalid;
           ui.style_mut().visuals.selection.stroke = invalid;
         };
-        let mut edit = ui.add(
-          TextEdit::singleline(&mut self.field.buffer)
-            .desired_width(self.desired_width.unwrap_or(f32::INFINITY)),
-        );
+        let edit = match self.multiline {
+          true => TextEdit::multiline(&mut self.field.buffer).desired_rows(3),
+          false => TextEdit::singleline(&mut self.field.buffer),
+        };
+        let mut edit = ui.add(edit.desired_width(self.desired_width.unwrap_or(f32::INFINITY)));
         if let Err(err) = &self.field.value {
           edit = edit.on_hover_text(err);
         }
@@ -0,0 +1,288 @@
+mod run;
+
+use crate::cacher::Cache;
+use crate::config::ShellConfig;
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use anyhow::anyhow;
+use arboard::Clipboard;
+use egui::RichText;
+use parking_lot::Mutex;
+use run::RunOutput;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, sync::Arc, thread, time::Duration};
+use tracing::warn;
+
+pub const PLUGIN_NAME: &str = "shell";
+
+/// Runs whatever's typed through the shell, either in a terminal or headlessly with the end
+/// of its output shown in the bar. Commands that were run before are offered up again.
+pub struct ShellPlugin {
+  cfg: ShellConfig,
+  history: Cache<ShellHistory>,
+  /// The last command run headlessly, shown in place of running it again until the search
+  /// moves on
+  last: Arc<Mutex<Option<LastRun>>>,
+  clip: Mutex<Clipboard>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct ShellHistory {
+  /// Newest first
+  commands: Vec<String>,
+}
+
+/// A command run headlessly, which goes on in the background until it's done
+#[derive(Clone, Debug)]
+enum LastRun {
+  Running(String),
+  Done(RunOutput),
+}
+
+#[derive(Clone, Debug)]
+pub enum ShellValue {
+  /// A command to run, either the one being typed or one from the history
+  Command { cmd: String, history: bool },
+  /// A command that's been run headlessly and isn't done yet
+  Running { cmd: String },
+  /// What came of running a command headlessly, along with how many lines of it to show
+  Output { run: RunOutput, lines: usize },
+}
+
+impl PluginValue for ShellValue {
+  fn item_id(&self) -> Option<String> {
+    Some(self.cmd().to_string())
+  }
+}
+impl Renderable for ShellValue {
+  fn render(&self, ui: &mut egui::Ui, _state: &AppState) {
+    match self {
+      ShellValue::Command { cmd, history } => {
+        ui.horizontal(|ui| {
+          ui.label(RichText::new(format!("Run: {}", cmd)));
+          if *history {
+            ui.label(RichText::new("History").weak().small());
+          }
+        });
+      }
+      ShellValue::Running { cmd } => {
+        ui.horizontal(|ui| {
+          ui.label(RichText::new(cmd));
+          ui.label(RichText::new("Running…").weak().small());
+        });
+      }
+      ShellValue::Output { run, lines } => {
+        let status = match (run.timed_out, run.code) {
+          (true, _) => "Timed out".to_string(),
+          (false, Some(code)) => format!("Exited with {}", code),
+          (false, None) => "Killed".to_string(),
+        };
+        ui.vertical(|ui| {
+          ui.horizontal(|ui| {
+            ui.label(RichText::new(&run.cmd));
+            ui.label(RichText::new(status).weak().small());
+          });
+          for line in run::tail(&run.stdout, *lines) {
+            ui.label(RichText::new(line).monospace());
+          }
+          let error = ui.visuals().error_fg_color;
+          for line in run::tail(&run.stderr, *lines) {
+            ui.label(RichText::new(line).monospace().color(error));
+          }
+        });
+      }
+    }
+  }
+}
+impl SearchBlocker for ShellValue {}
+
+impl ShellValue {
+  fn cmd(&self) -> &str {
+    match self {
+      ShellValue::Command { cmd, .. } | ShellValue::Running { cmd } => cmd,
+      ShellValue::Output { run, .. } => &run.cmd,
+    }
+  }
+}
+
+impl ShellHistory {
+  /// Puts the command at the top, dropping the oldest ones past the max
+  fn record(&mut self, cmd: &str, max: usize) {
+    self.commands.retain(|c| c != cmd);
+    self.commands.insert(0, cmd.to_string());
+    self.commands.truncate(max);
+  }
+}
+
+impl ShellPlugin {
+  pub fn init(cfg: ShellConfig, cache_dir: &Path) -> Result<Self, anyhow::Error> {
+    Ok(ShellPlugin {
+      cfg,
+      history: Cache::load(cache_dir.join("shell_history.toml"))?,
+      last: Arc::default(),
+      clip: Mutex::new(Clipboard::new()?),
+    })
+  }
+
+  fn run(&self, cmd: &str, in_terminal: bool) -> Result<OkAction, anyhow::Error> {
+    let max = self.cfg.history_size;
+    self.history.update(|mut history| history.record(cmd, max));
+    if let Err(err) = self.history.persist() {
+      warn!("Failed to save shell history: {:?}", err);
+    }
+
+    if in_terminal {
+      let terminal = self
+        .cfg
+        .terminal
+        .clone()
+        .unwrap_or_else(run::default_terminal);
+      run::in_terminal(&terminal, &self.cfg.shell, cmd)
+        .map_err(|err| anyhow!("Failed to run {:?} in a terminal, err: {:?}", cmd, err))?;
+      return Ok(OkAction {
+        close_win: true,
+        ..Default::default()
+      });
+    }
+    let timeout = Duration::from_millis(self.cfg.timeout_ms);
+    let running = run::headless(&self.cfg.shell, cmd, timeout)
+      .map_err(|err| anyhow!("Failed to run {:?}, err: {:?}", cmd, err))?;
+    *self.last.lock() = Some(LastRun::Running(cmd.to_string()));
+    // It's waited on in the background, the bar searching again once it's done
+    let last = self.last.clone();
+    let waiting = thread::Builder::new()
+      .name("shell-run".into())
+      .spawn(move || {
+        let output = running.wait();
+        let mut last = last.lock();
+        // Unless another command was run since
+        if matches!(&*last, Some(LastRun::Running(cmd)) if *cmd == output.cmd) {
+          *last = Some(LastRun::Done(output));
+        }
+      })
+      .map_err(|err| anyhow!("Failed to wait on {:?}, err: {:?}", cmd, err))?;
+    // Searching for the command again brings up its output
+    Ok(OkAction {
+      update_input: Some(input_for(&self.cfg.prefix, cmd)),
+      search_again_after: Some(waiting),
+      ..Default::default()
+    })
+  }
+}
+
+/// The input that searches for the command
+fn input_for(prefix: &str, cmd: &str) -> String {
+  match prefix.ends_with(char::is_whitespace) {
+    true => format!("{}{}", prefix, cmd),
+    false => format!("{} {}", prefix, cmd),
+  }
+}
+
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |ctx| {
+    Ok(Box::new(ShellPlugin::init(
+      ctx.section()?,
+      &ctx.config.cache_dir,
+    )?))
+  });
+}
+
+impl Plugin for ShellPlugin {
+  type PV = ShellValue;
+
+  fn prefix(&self) -> Option<String> {
+    Some(self.cfg.prefix.clone())
+  }
+
+  /// Runs the command the configured way, or copies the end of the output once it's run
+  fn action(&self, input: &ShellValue) -> Result<OkAction, anyhow::Error> {
+    match input {
+      ShellValue::Command { cmd, .. } => self.run(cmd, self.cfg.in_terminal),
+      ShellValue::Running { cmd } => Err(anyhow!("{:?} is still running", cmd)),
+      ShellValue::Output { run, .. } => {
+        let text = run.tail(self.cfg.output_lines).join("\n");
+        self
+          .clip
+          .lock()
+          .set_text(text)
+          .map(|_| OkAction {
+            close_win: true,
+            ..Default::default()
+          })
+          .map_err(|err| anyhow!("Failed to copy output of {:?}, err: {:?}", run.cmd, err))
+      }
+    }
+  }
+
+  /// Runs the command the other way to the configured one, or runs it again once it's run
+  fn secondary_action(&self, input: &ShellValue) -> Result<OkAction, anyhow::Error> {
+    match input {
+      ShellValue::Command { cmd, .. } => self.run(cmd, !self.cfg.in_terminal),
+      ShellValue::Running { cmd } => Err(anyhow!("{:?} is still running", cmd)),
+      ShellValue::Output { run, .. } => self.run(&run.cmd, false),
+    }
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    let typed = search
+      .strip_prefix(&self.cfg.prefix)
+      .unwrap_or(search)
+      .trim();
+    let item = |value: ShellValue, against: String| FuzzyMatchItem {
+      against: Arc::new(against),
+      value: PluginV::new(PLUGIN_NAME, value),
+      source: PLUGIN_NAME.to_string(),
+    };
+
+    let mut options = Vec::new();
+    if !typed.is_empty() {
+      let value = match self.last.lock().clone() {
+        Some(LastRun::Running(cmd)) if cmd == typed => ShellValue::Running { cmd },
+        Some(LastRun::Done(run)) if run.cmd == typed => ShellValue::Output {
+          run,
+          lines: self.cfg.output_lines,
+        },
+        _ => ShellValue::Command {
+          cmd: typed.to_string(),
+          history: false,
+        },
+      };
+      // Matched against the search as is, so what's typed is always offered
+      options.push(item(value, search.to_string()));
+    }
+    let history = self.history.get();
+    options.extend(history.commands.iter().filter(|c| *c != typed).map(|cmd| {
+      item(
+        ShellValue::Command {
+          cmd: cmd.clone(),
+          history: true,
+        },
+        input_for(&self.cfg.prefix, cmd),
+      )
+    }));
+    options
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn history_is_newest_first_without_repeats() {
+    let mut history = ShellHistory::default();
+    for cmd in ["ls", "pwd", "ls", "date"] {
+      history.record(cmd, 2);
+    }
+    assert_eq!(history.commands, vec!["date", "ls"]);
+  }
+
+  #[test]
+  fn searches_for_commands_after_the_prefix() {
+    assert_eq!(input_for(">", "ls -la"), "> ls -la");
+    assert_eq!(input_for("sh ", "ls"), "sh ls");
+  }
+}
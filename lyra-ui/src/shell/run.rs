@@ -0,0 +1,236 @@
+use anyhow::anyhow;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+  io::Read,
+  process::{Child, Command, Stdio},
+  sync::Arc,
+  thread::{self, JoinHandle},
+  time::{Duration, Instant},
+};
+
+/// How much of each stream is held onto. Only the end of the output is ever shown, so a
+/// chatty command can't eat up memory
+const MAX_CAPTURE_BYTES: usize = 64 * 1024;
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct RunOutput {
+  pub cmd: String,
+  pub stdout: String,
+  pub stderr: String,
+  /// None when the command didn't exit by itself, like when it was killed
+  pub code: Option<i32>,
+  pub timed_out: bool,
+}
+
+impl RunOutput {
+  /// The last `count` lines of output, from stdout unless there was only stderr
+  pub fn tail(&self, count: usize) -> Vec<&str> {
+    let out = match self.stdout.trim().is_empty() {
+      true => &self.stderr,
+      false => &self.stdout,
+    };
+    tail(out, count)
+  }
+}
+
+/// The last `count` lines of the text, leaving off the trailing newline
+pub fn tail(text: &str, count: usize) -> Vec<&str> {
+  let lines: Vec<_> = text.trim_end().lines().collect();
+  lines[lines.len().saturating_sub(count)..].to_vec()
+}
+
+/// A command running headlessly, with its output being captured as it goes
+pub struct Headless {
+  cmd: String,
+  child: Child,
+  stdout: Capture,
+  stderr: Capture,
+  deadline: Instant,
+}
+
+/// Starts the command through the shell, capturing its output. It's killed, along with
+/// anything it started, once it's run longer than the timeout
+pub fn headless(shell: &[String], cmd: &str, timeout: Duration) -> Result<Headless, anyhow::Error> {
+  let mut command = shell_command(shell, cmd)?;
+  command
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+  // In a group of its own, so whatever the shell starts can be killed along with it
+  #[cfg(unix)]
+  std::os::unix::process::CommandExt::process_group(&mut command, 0);
+  let mut child = command.spawn()?;
+  Ok(Headless {
+    cmd: cmd.to_string(),
+    stdout: Capture::start(child.stdout.take()),
+    stderr: Capture::start(child.stderr.take()),
+    child,
+    deadline: Instant::now() + timeout,
+  })
+}
+
+impl Headless {
+  /// Waits for the command to exit or run out of time, whichever's first
+  pub fn wait(mut self) -> RunOutput {
+    let status = loop {
+      match self.child.try_wait() {
+        Ok(Some(status)) => break Some(status),
+        Ok(None) if Instant::now() < self.deadline => thread::sleep(POLL_INTERVAL),
+        // Out of time, or there's no telling whether it's still going
+        _ => {
+          kill(&mut self.child);
+          break None;
+        }
+      }
+    };
+    // Anything the command left running in the background could hold onto the output
+    // forever, so it's only waited on until the deadline
+    while !(self.stdout.finished() && self.stderr.finished()) && Instant::now() < self.deadline {
+      thread::sleep(POLL_INTERVAL);
+    }
+    RunOutput {
+      cmd: self.cmd,
+      stdout: self.stdout.text(),
+      stderr: self.stderr.text(),
+      code: status.and_then(|s| s.code()),
+      timed_out: status.is_none(),
+    }
+  }
+}
+
+/// Opens the terminal running the command through the shell, leaving it to get on with it
+pub fn in_terminal(terminal: &[String], shell: &[String], cmd: &str) -> Result<(), anyhow::Error> {
+  let (program, args) = terminal
+    .split_first()
+    .ok_or_else(|| anyhow!("No terminal configured"))?;
+  let mut child = Command::new(program)
+    .args(args)
+    .args(shell)
+    .arg(cmd)
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()?;
+  // Reap the terminal once it exits so it doesn't linger as a zombie
+  thread::spawn(move || child.wait());
+  Ok(())
+}
+
+/// The terminal to use when one isn't configured, the same one desktop entries run in
+pub fn default_terminal() -> Vec<String> {
+  let term = std::env::var("TERMINAL").unwrap_or_else(|_| "x-terminal-emulator".into());
+  vec![term, "-e".into()]
+}
+
+fn shell_command(shell: &[String], cmd: &str) -> Result<Command, anyhow::Error> {
+  let (program, args) = shell
+    .split_first()
+    .ok_or_else(|| anyhow!("No shell configured"))?;
+  let mut command = Command::new(program);
+  command.args(args).arg(cmd);
+  Ok(command)
+}
+
+fn kill(child: &mut Child) {
+  #[cfg(unix)]
+  {
+    // The child leads its own group, so this takes everything it started with it
+    let pgid = child.id() as libc::pid_t;
+    unsafe { libc::kill(-pgid, libc::SIGKILL) };
+  }
+  let _ = child.kill();
+  let _ = child.wait();
+}
+
+/// Reads a stream in the background, keeping only the end of it
+struct Capture {
+  buf: Arc<Mutex<Vec<u8>>>,
+  reader: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+  fn start(stream: Option<impl Read + Send + 'static>) -> Self {
+    let buf = Arc::new(Mutex::new(Vec::new()));
+    let reader = stream.map(|mut stream| {
+      let buf = buf.clone();
+      thread::spawn(move || {
+        let mut chunk = [0; 8192];
+        while let Ok(read @ 1..) = stream.read(&mut chunk) {
+          let mut buf = buf.lock();
+          buf.extend_from_slice(&chunk[..read]);
+          let over = buf.len().saturating_sub(MAX_CAPTURE_BYTES);
+          buf.drain(..over);
+        }
+      })
+    });
+    Capture { buf, reader }
+  }
+
+  fn finished(&self) -> bool {
+    self.reader.as_ref().is_none_or(|r| r.is_finished())
+  }
+
+  fn text(&self) -> String {
+    String::from_utf8_lossy(&self.buf.lock()).to_string()
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+
+  fn sh() -> Vec<String> {
+    vec!["sh".into(), "-c".into()]
+  }
+
+  #[test]
+  fn captures_output_and_exit_code() {
+    let out = headless(
+      &sh(),
+      "echo one; echo two; echo oops >&2; exit 3",
+      Duration::from_secs(5),
+    )
+    .unwrap()
+    .wait();
+    assert_eq!(out.stdout, "one\ntwo\n");
+    assert_eq!(out.stderr, "oops\n");
+    assert_eq!(out.code, Some(3));
+    assert!(!out.timed_out);
+    assert_eq!(out.tail(1), vec!["two"]);
+  }
+
+  #[test]
+  fn tails_stderr_without_stdout() {
+    let out = headless(&sh(), "echo a >&2; echo b >&2", Duration::from_secs(5))
+      .unwrap()
+      .wait();
+    assert_eq!(out.tail(5), vec!["a", "b"]);
+  }
+
+  #[test]
+  fn kills_runaway_commands() {
+    let started = Instant::now();
+    let out = headless(
+      &sh(),
+      "echo started; sleep 30; echo done",
+      Duration::from_millis(300),
+    )
+    .unwrap()
+    .wait();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(out.timed_out);
+    assert_eq!(out.code, None);
+    assert_eq!(out.stdout, "started\n");
+  }
+
+  #[test]
+  fn keeps_only_the_end_of_big_output() {
+    let out = headless(&sh(), "seq 1 100000", Duration::from_secs(10))
+      .unwrap()
+      .wait();
+    assert!(out.stdout.len() <= MAX_CAPTURE_BYTES);
+    assert_eq!(out.tail(2), vec!["99999", "100000"]);
+  }
+}
@@ -0,0 +1,261 @@
+mod store;
+
+pub use store::{Snippet, SnippetStore};
+
+use crate::config::SnippetsConfig;
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use crate::template::{NamedTemplate, TemplatingState};
+use anyhow::anyhow;
+use arboard::Clipboard;
+use chrono::Local;
+use egui::{RichText, Ui};
+use parking_lot::Mutex;
+use std::{path::Path, str::FromStr, sync::Arc};
+
+pub const PLUGIN_NAME: &str = "snippets";
+
+/// Expands snippets of boilerplate text, like addresses or signatures, onto the clipboard.
+/// Snippets with args are filled in the same way as webq templates, typing the keyword then
+/// the args after it.
+pub struct SnippetsPlugin {
+  cfg: SnippetsConfig,
+  store: SnippetStore,
+  clip: Mutex<Clipboard>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SnippetValue {
+  pub snippet: Snippet,
+  args: Vec<String>,
+  state: TemplatingState,
+}
+
+impl PluginValue for SnippetValue {
+  fn item_id(&self) -> Option<String> {
+    Some(self.snippet.keyword.clone())
+  }
+}
+impl SearchBlocker for SnippetValue {
+  fn blocks_search(&self, state: &AppState) -> bool {
+    state.options.iter().any(is_templating_snippet)
+  }
+}
+
+impl Renderable for SnippetValue {
+  fn render(&self, ui: &mut Ui, _state: &AppState) {
+    let sn = &self.snippet;
+    ui.horizontal(|ui| {
+      match self.state.templating() {
+        true => ui.label(RichText::new(format!(
+          "{}: {}",
+          sn.label,
+          preview(&sn.body.partial_hydrate(&self.args))
+        ))),
+        false => ui.label(RichText::new(&sn.label)),
+      };
+      ui.label(RichText::new(&sn.keyword).weak().small());
+    });
+  }
+}
+
+impl SnippetsPlugin {
+  pub fn init(cfg: SnippetsConfig, conf_dir: &Path) -> Result<Self, anyhow::Error> {
+    Ok(SnippetsPlugin {
+      cfg,
+      store: SnippetStore::load(conf_dir)?,
+      clip: Mutex::new(Clipboard::new()?),
+    })
+  }
+
+  /// The current value of a named marker, for those that are known
+  fn named_value(&self, name: &str) -> Option<String> {
+    match name {
+      "date" => Some(Local::now().format(&self.cfg.date_format).to_string()),
+      "time" => Some(Local::now().format(&self.cfg.time_format).to_string()),
+      "clipboard" => self.clip.lock().get_text().ok(),
+      _ => None,
+    }
+  }
+}
+
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |ctx| {
+    Ok(Box::new(SnippetsPlugin::init(
+      ctx.section()?,
+      &ctx.config.conf_dir,
+    )?))
+  });
+}
+
+fn is_templating_snippet(opt: &PluginV) -> bool {
+  opt
+    .downcast::<SnippetValue>()
+    .is_some_and(|sv| sv.state.templating())
+}
+
+/// The body squashed onto a single line
+fn preview(body: &str) -> String {
+  body.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Fills in the args of the snippets from the input, leaving only the ones being filled in
+/// while their args are typed out
+fn fill_in_args(state: &AppState) -> Option<AppState> {
+  let mut changed = false;
+  let options: Vec<PluginV> = state
+    .options
+    .iter()
+    .map(|opt| {
+      match opt
+        .downcast::<SnippetValue>()
+        .and_then(|sv| sv.update(&state.input))
+      {
+        Some(sv) => {
+          changed = true;
+          PluginV::new(PLUGIN_NAME, sv)
+        }
+        None => opt.clone(),
+      }
+    })
+    .collect();
+  if !changed {
+    return None;
+  }
+
+  let mut new_state = state.clone();
+  match options.iter().any(is_templating_snippet) {
+    true => new_state.set_options(options.into_iter().filter(is_templating_snippet).collect()),
+    false => new_state.options = options,
+  }
+  Some(new_state)
+}
+
+impl Plugin for SnippetsPlugin {
+  type PV = SnippetValue;
+
+  fn validate_value(&self, input_type: &str, input_value: &str) -> Result<(), anyhow::Error> {
+    match input_type {
+      "body" => NamedTemplate::from_str(input_value)
+        .map(|_| ())
+        .map_err(|e| e.into()),
+      _ => Err(anyhow!("Unknown input type: {}", input_type)),
+    }
+  }
+
+  fn derive_state(&self, state: &AppState) -> Option<AppState> {
+    fill_in_args(state)
+  }
+
+  /// Copies the expanded snippet, or starts filling in its args if they're still to come
+  fn action(&self, input: &SnippetValue) -> Result<OkAction, anyhow::Error> {
+    let sn = &input.snippet;
+    if sn.body.markers > 0 && !input.state.is_complete() {
+      return Ok(OkAction {
+        close_win: false,
+        update_input: Some(format!("{} ", sn.keyword)),
+        ..Default::default()
+      });
+    }
+    sn.body
+      .expand(&input.args, |name| self.named_value(name))
+      .map_err(anyhow::Error::from)
+      .and_then(|text| self.clip.lock().set_text(text).map_err(|err| err.into()))
+      .map(|_| OkAction {
+        close_win: true,
+        ..Default::default()
+      })
+      .map_err(|err| anyhow!("Failed to expand {:?}, err: {:?}", sn.label, err))
+  }
+
+  fn options(&self, _: &str) -> Vec<FuzzyMatchItem> {
+    self
+      .store
+      .snippets()
+      .into_iter()
+      .map(|snippet| FuzzyMatchItem {
+        against: Arc::new(format!("{} {}", snippet.keyword, snippet.label)),
+        value: PluginV::new(
+          PLUGIN_NAME,
+          SnippetValue {
+            snippet,
+            args: Vec::new(),
+            state: TemplatingState::default(),
+          },
+        ),
+        source: PLUGIN_NAME.to_string(),
+      })
+      .collect()
+  }
+}
+
+impl SnippetValue {
+  /// The snippet with its args as typed in the input, if that's different to what it had.
+  /// Snippets without args have nothing to fill in
+  fn update(&self, input: &str) -> Option<SnippetValue> {
+    if self.snippet.body.markers == 0 {
+      return None;
+    }
+    let (state, args) = self.snippet.body.args_for(&self.snippet.keyword, input);
+    if state == self.state && args == self.args {
+      return None;
+    }
+    Some(SnippetValue {
+      state,
+      args,
+      ..self.clone()
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn value(keyword: &str, body: &str) -> PluginV {
+    PluginV::new(
+      PLUGIN_NAME,
+      SnippetValue {
+        snippet: Snippet {
+          label: keyword.to_uppercase(),
+          keyword: keyword.into(),
+          body: NamedTemplate::from_str(body).unwrap(),
+        },
+        args: Vec::new(),
+        state: TemplatingState::default(),
+      },
+    )
+  }
+
+  #[test]
+  fn args_are_filled_in_after_the_keyword() {
+    let mut state = AppState {
+      input: "addr".into(),
+      ..Default::default()
+    };
+    state.set_options(vec![value("addr", "{0}\n{1}"), value("sig", "Thanks")]);
+    assert!(fill_in_args(&state).is_none());
+
+    state.input = "addr Jo".into();
+    let started = fill_in_args(&state).unwrap();
+    assert_eq!(started.options.len(), 1);
+
+    state.input = "addr Jo Main St".into();
+    let state = fill_in_args(&state).unwrap();
+    let sv = state.options[0].downcast::<SnippetValue>().unwrap();
+    assert!(sv.state.is_complete());
+    assert_eq!(sv.args, vec!["Jo".to_string(), "Main St".to_string()]);
+    assert!(state.options[0].blocks_search(&state));
+    assert_eq!(
+      sv.snippet.body.expand(&sv.args, |_| None),
+      Ok("Jo\nMain St".into())
+    );
+  }
+
+  #[test]
+  fn previews_fit_on_a_line() {
+    assert_eq!(preview("Thanks,\n  Jo\n"), "Thanks, Jo");
+  }
+}
@@ -0,0 +1,109 @@
+use crate::{reloader::Reloader, template::NamedTemplate};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+const SNIPPETS_FILE: &str = "snippets.toml";
+
+/// The snippets, kept in a file of their own next to the config since bodies tend to be
+/// long. The file is read again whenever it changes, so edits made elsewhere (by hand, or
+/// from the settings) show up without a restart.
+#[derive(Debug)]
+pub struct SnippetStore {
+  file: Reloader<Vec<Snippet>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct SnippetFile {
+  snippets: Vec<Snippet>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Snippet {
+  pub label: String,
+  /// What's typed to bring the snippet up, followed by its args if it has any
+  pub keyword: String,
+  /// The text it expands to. `{0}`, `{1}`.. are filled in with the args typed after the
+  /// keyword, and named markers like `{date}` with their current value
+  pub body: NamedTemplate,
+}
+
+impl SnippetStore {
+  pub fn load(conf_dir: &Path) -> Result<Self, anyhow::Error> {
+    let store = SnippetStore {
+      file: Reloader::new(conf_dir.join(SNIPPETS_FILE), "snippets", |s, _| {
+        Ok(toml::from_str::<SnippetFile>(s)?.snippets)
+      }),
+    };
+    match store.file.file().exists() {
+      true => store.file.reload()?,
+      false => store.save(Vec::new())?,
+    }
+    Ok(store)
+  }
+
+  /// Every snippet, in the order they were added
+  pub fn snippets(&self) -> Vec<Snippet> {
+    self.file.get()
+  }
+
+  pub fn save(&self, snippets: Vec<Snippet>) -> Result<(), anyhow::Error> {
+    let file = SnippetFile { snippets };
+    fs::write(self.file.file(), toml::to_string(&file)?)?;
+    self.file.written(file.snippets);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testdir::TestDir;
+  use std::{str::FromStr, time::SystemTime};
+
+  fn snippet(keyword: &str, body: &str) -> Snippet {
+    Snippet {
+      label: keyword.to_uppercase(),
+      keyword: keyword.into(),
+      body: NamedTemplate::from_str(body).unwrap(),
+    }
+  }
+
+  #[test]
+  fn saved_snippets_load_again() {
+    let dir = TestDir::new("snippets-save");
+    let store = SnippetStore::load(&dir).unwrap();
+    assert!(store.snippets().is_empty());
+
+    let snippets = vec![snippet("sig", "Thanks,\n{0}"), snippet("today", "{date}")];
+    store.save(snippets.clone()).unwrap();
+    assert_eq!(SnippetStore::load(&dir).unwrap().snippets(), snippets);
+  }
+
+  #[test]
+  fn edits_to_the_file_are_picked_up() {
+    let dir = TestDir::new("snippets-reload");
+    let store = SnippetStore::load(&dir).unwrap();
+    store.save(vec![snippet("a", "one")]).unwrap();
+
+    // Pushed into the past, so the edit is seen as a change however coarse the timestamps
+    let file = dir.join(SNIPPETS_FILE);
+    fs::write(
+      &file,
+      "[[snippets]]\nlabel = \"B\"\nkeyword = \"b\"\nbody = \"two\"\n",
+    )
+    .unwrap();
+    let past = SystemTime::now() - std::time::Duration::from_secs(60);
+    fs::File::options()
+      .write(true)
+      .open(&file)
+      .unwrap()
+      .set_modified(past)
+      .unwrap();
+    assert_eq!(store.snippets(), vec![snippet("b", "two")]);
+
+    // A broken edit keeps what was there before
+    fs::write(&file, "[[snippets]]\nbody = \"{\"\n").unwrap();
+    assert_eq!(store.snippets(), vec![snippet("b", "two")]);
+  }
+}
@@ -1,44 +1,405 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::engine::Engine as _;
 use form::FormFieldData;
 use serde::{Deserialize, Serialize};
 use std::{fmt::Display, ops::Deref, str::FromStr};
 
+/// Text with markers that are filled in with args, like `https://www.google.com?q={0}`.
+///
+/// Markers are either positional, `{0}`, `{1}` and so on, or named, `{query}`, which are
+/// typed in after the positional ones in the order they first appear. A marker can have a
+/// default for when its arg isn't given, `{lang=en}`, and filters the value goes through,
+/// `{query|lower|urlencode}`. Braces that aren't markers are written twice, `{{` and `}}`.
 #[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize, FormFieldData)]
 #[serde(try_from = "String", into = "String")]
 pub struct Template {
   val: String,
+  /// How many args there are to fill in
   pub markers: usize,
+  /// Whether named markers are filled in by whatever expands the template, rather than
+  /// typed in as args
+  named: bool,
+  /// The text and markers it's made of, read once when it's parsed
+  segments: Vec<Segment>,
 }
 
 impl Template {
   pub fn hydrate(&self, args: &Vec<String>) -> Result<String, TemplateError> {
-    if args.len() != self.markers {
-      return Err(TemplateError::HydrateError(
-        "Not enough args provided to hydrate".into(),
-      ));
+    self.check_args(args)?;
+    Ok(self.fill(|marker| self.arg(marker, args)))
+  }
+
+  /// Fills in the args there are so far, leaving the markers for the rest as they are
+  pub fn partial_hydrate(&self, args: &Vec<String>) -> String {
+    self.fill(|marker| {
+      self
+        .slot(&marker.param)
+        .and_then(|idx| args.get(idx).cloned())
+    })
+  }
+
+  /// Fills in the positional markers from `args` along with any named markers, in one pass
+  /// so nothing filled in is taken for a marker. Named markers `named` has no value or
+  /// default for are left as they are
+  pub fn expand(
+    &self,
+    args: &[String],
+    named: impl Fn(&str) -> Option<String>,
+  ) -> Result<String, TemplateError> {
+    self.check_args(args)?;
+    Ok(self.fill(|marker| match (&marker.param, self.named) {
+      (Param::Name(name), true) => named(name).or_else(|| self.default(&marker.param)),
+      _ => self.arg(marker, args),
+    }))
+  }
+
+  /// The names of the named markers in the template, each once in the order they first
+  /// appear
+  pub fn names(&self) -> Vec<&str> {
+    let mut names = Vec::new();
+    for segment in &self.segments {
+      if let Segment::Marker(Marker {
+        param: Param::Name(name),
+        ..
+      }) = segment
+      {
+        if !names.contains(&name.as_str()) {
+          names.push(name.as_str());
+        }
+      }
+    }
+    names
+  }
+
+  /// How many of the args have to be given, the rest having defaults
+  pub fn required(&self) -> usize {
+    (0..self.markers)
+      .rposition(|slot| {
+        let param = self.param(slot);
+        param.is_none_or(|param| self.default(&param).is_none())
+      })
+      .map_or(0, |slot| slot + 1)
+  }
+
+  /// Works out how far along filling in the template the input is, where it's filled in
+  /// by typing the `keyword` followed by each of the args separated by spaces. The last arg
+  /// takes the rest of the input, spaces and all
+  pub fn args_for(&self, keyword: &str, input: &str) -> (TemplatingState, Vec<String>) {
+    match input.split_once(' ') {
+      Some((kw, _)) if kw == keyword => {
+        let args: Vec<String> = input
+          .trim()
+          .splitn(self.markers + 1, ' ')
+          .skip(1)
+          .map(|s| s.to_owned())
+          .collect();
+        let state = if args.len() >= self.required() {
+          TemplatingState::Complete
+        } else {
+          TemplatingState::Started
+        };
+        (state, args)
+      }
+      None | Some(_) => (TemplatingState::NotStarted, Vec::new()),
+    }
+  }
+
+  /// Parses the template, where `named` has named markers like `{date}` filled in by
+  /// whatever expands it rather than typed in as args
+  fn parse(s: &str, named: bool) -> Result<Self, TemplateError> {
+    let segments = segments(s)?;
+    let mut seen: Vec<usize> = Vec::new();
+    let mut names = Vec::new();
+    for segment in &segments {
+      match segment {
+        Segment::Marker(Marker {
+          param: Param::Index(idx),
+          ..
+        }) if !seen.contains(idx) => seen.push(*idx),
+        Segment::Marker(Marker {
+          param: Param::Name(name),
+          ..
+        }) if !names.contains(&name) => names.push(name),
+        _ => {}
+      }
+    }
+
+    // Every index below the highest has to be there, so none are skipped when typing args
+    let skipped = segments.iter().find_map(|segment| match segment {
+      Segment::Marker(Marker {
+        param: Param::Index(idx),
+        at,
+        ..
+      }) if *idx >= seen.len() => Some(*at),
+      _ => None,
+    });
+    if let Some(at) = skipped {
+      return Err(invalid(s, at, "Markers are not sequential from 0"));
     }
-    let mut hydration = self.val.clone();
-    for idx in 0..self.markers {
-      let marker = format!("{{{}}}", idx);
-      let arg = args
+
+    let markers = match named {
+      true => seen.len(),
+      false => seen.len() + names.len(),
+    };
+    Ok(Template {
+      val: s.to_string(),
+      markers,
+      named,
+      segments,
+    })
+  }
+
+  /// Which arg fills in the param, if one does
+  fn slot(&self, param: &Param) -> Option<usize> {
+    match param {
+      Param::Index(idx) => Some(*idx),
+      Param::Name(_) if self.named => None,
+      Param::Name(name) => {
+        let names = self.names();
+        let positional = self.markers - names.len();
+        names
+          .iter()
+          .position(|n| n == name)
+          .map(|idx| positional + idx)
+      }
+    }
+  }
+
+  /// The param the arg fills in, the opposite of `slot`
+  fn param(&self, slot: usize) -> Option<Param> {
+    match slot.checked_sub(self.positional()) {
+      None => Some(Param::Index(slot)),
+      Some(_) if self.named => None,
+      Some(idx) => self
+        .names()
         .get(idx)
-        .ok_or_else(|| TemplateError::HydrateError(format!("Missing arg {} for template", idx)))?;
-      hydration = hydration.replace(&marker, arg);
+        .map(|name| Param::Name(name.to_string())),
     }
-    Ok(hydration)
   }
 
-  pub fn partial_hydrate(&self, args: &Vec<String>) -> String {
-    let mut hydration = self.val.clone();
-    for idx in 0..self.markers {
-      let marker = format!("{{{}}}", idx);
-      if let Some(arg) = args.get(idx) {
-        hydration = hydration.replace(&marker, arg);
-      } else {
-        return hydration;
+  /// How many of the args are for positional markers, which come before the named ones
+  fn positional(&self) -> usize {
+    match self.named {
+      true => self.markers,
+      false => self.markers - self.names().len(),
+    }
+  }
+
+  /// The first default given for the param, which goes for everywhere it's used
+  fn default(&self, param: &Param) -> Option<String> {
+    self.segments.iter().find_map(|segment| match segment {
+      Segment::Marker(marker) if marker.param == *param => marker.default.clone(),
+      _ => None,
+    })
+  }
+
+  fn arg(&self, marker: &Marker, args: &[String]) -> Option<String> {
+    let arg = self.slot(&marker.param).and_then(|idx| args.get(idx));
+    arg.cloned().or_else(|| self.default(&marker.param))
+  }
+
+  fn check_args(&self, args: &[String]) -> Result<(), TemplateError> {
+    match args.len() {
+      n if n < self.required() => Err(TemplateError::HydrateError(
+        "Not enough args provided to hydrate".into(),
+      )),
+      n if n > self.markers => Err(TemplateError::HydrateError(
+        "Too many args provided to hydrate".into(),
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  /// Writes out the template with each marker's value run through its filters, leaving
+  /// the markers without one as they're written
+  fn fill(&self, value: impl Fn(&Marker) -> Option<String>) -> String {
+    let mut filled = String::with_capacity(self.val.len());
+    for segment in &self.segments {
+      match segment {
+        Segment::Text(text) => filled.push_str(text),
+        Segment::Marker(marker) => match value(marker) {
+          Some(value) => {
+            let value = marker.filters.iter().fold(value, |v, f| f.apply(&v));
+            filled.push_str(&value);
+          }
+          None => filled.push_str(&marker.raw),
+        },
+      }
+    }
+    filled
+  }
+}
+
+/// A piece of a template, where escaped braces are text of their own
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Segment {
+  Text(String),
+  Marker(Marker),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Marker {
+  /// As it's written, braces and all
+  raw: String,
+  /// Where it starts in the template, in bytes
+  at: usize,
+  param: Param,
+  default: Option<String>,
+  filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Param {
+  Index(usize),
+  Name(String),
+}
+
+/// What a marker's value goes through before it's filled in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Filter {
+  UrlEncode,
+  Lower,
+  Upper,
+  Base64,
+}
+
+impl Filter {
+  fn parse(name: &str) -> Option<Filter> {
+    match name {
+      "urlencode" => Some(Filter::UrlEncode),
+      "lower" => Some(Filter::Lower),
+      "upper" => Some(Filter::Upper),
+      "base64" => Some(Filter::Base64),
+      _ => None,
+    }
+  }
+
+  fn apply(self, value: &str) -> String {
+    match self {
+      Filter::UrlEncode => url_encode(value),
+      Filter::Lower => value.to_lowercase(),
+      Filter::Upper => value.to_uppercase(),
+      Filter::Base64 => BASE64.encode(value),
+    }
+  }
+}
+
+/// Percent-encodes everything but the characters that never need it in a URL, so the
+/// value can go anywhere in one, spaces included
+fn url_encode(value: &str) -> String {
+  let mut encoded = String::with_capacity(value.len());
+  for b in value.bytes() {
+    match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+        encoded.push(b as char)
       }
+      b => encoded.push_str(&format!("%{:02X}", b)),
     }
-    hydration
   }
+  encoded
+}
+
+/// Splits the template into its text and markers, checking each marker as it goes
+fn segments(s: &str) -> Result<Vec<Segment>, TemplateError> {
+  let mut segments = Vec::new();
+  let mut text = 0;
+  let mut idx = 0;
+  while let Some(c) = s[idx..].chars().next() {
+    let rest = &s[idx..];
+    if rest.starts_with("{{") || rest.starts_with("}}") {
+      // Keeping the first of the two as text
+      segments.push(Segment::Text(s[text..idx + 1].to_string()));
+      idx += 2;
+      text = idx;
+      continue;
+    }
+    match c {
+      '{' => {
+        let end = rest[1..]
+          .find(['{', '}'])
+          .map(|len| idx + 1 + len)
+          .filter(|end| s[*end..].starts_with('}'))
+          .ok_or_else(|| invalid(s, idx, "Missing closing marker: }"))?;
+        if text < idx {
+          segments.push(Segment::Text(s[text..idx].to_string()));
+        }
+        segments.push(Segment::Marker(marker(s, idx, end)?));
+        idx = end + 1;
+        text = idx;
+      }
+      '}' => return Err(invalid(s, idx, "Did not contain number between markers")),
+      c => idx += c.len_utf8(),
+    }
+  }
+  if text < s.len() {
+    segments.push(Segment::Text(s[text..].to_string()));
+  }
+  Ok(segments)
+}
+
+/// Checks the marker between the braces at `start` and `end`, which is a param, then
+/// maybe a default after `=`, then any filters each after a `|`
+fn marker(s: &str, start: usize, end: usize) -> Result<Marker, TemplateError> {
+  let body = &s[start + 1..end];
+  let mut pieces = body.split('|');
+  let head = pieces.next().unwrap_or_default();
+  let (param, default) = match head.split_once('=') {
+    Some((param, default)) => (param, Some(default)),
+    None => (head, None),
+  };
+
+  let at = start + 1;
+  let invalid_char =
+    |message: &str, (idx, c): (usize, char)| invalid(s, at + idx, &format!("{}: {}", message, c));
+  let param = match param.chars().next() {
+    None => return Err(invalid(s, start, "Did not contain number between markers")),
+    Some(c) if c.is_ascii_digit() => {
+      if let Some(bad) = param.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        return Err(invalid_char("Marker contains not a digit", bad));
+      }
+      let idx = param
+        .parse()
+        .map_err(|_| invalid(s, at, "Marker number is too big"))?;
+      Param::Index(idx)
+    }
+    Some(c) if is_name_char(c) => {
+      if let Some(bad) = param.char_indices().find(|(_, c)| !is_name_char(*c)) {
+        return Err(invalid_char(
+          "Marker name contains an invalid character",
+          bad,
+        ));
+      }
+      Param::Name(param.to_string())
+    }
+    Some(c) => return Err(invalid_char("Marker contains not a digit", (0, c))),
+  };
+
+  let mut filters = Vec::new();
+  let mut filter_at = at + head.len() + 1;
+  for name in pieces {
+    let filter = Filter::parse(name)
+      .ok_or_else(|| invalid(s, filter_at, &format!("Unknown filter: {}", name)))?;
+    filters.push(filter);
+    filter_at += name.len() + 1;
+  }
+
+  Ok(Marker {
+    raw: s[start..=end].to_string(),
+    at: start,
+    param,
+    default: default.map(str::to_string),
+    filters,
+  })
+}
+
+/// The error for what's wrong at the byte `at`, which is reported as the character it's on
+/// counting from 1
+fn invalid(s: &str, at: usize, reason: &str) -> TemplateError {
+  TemplateError::InvalidFormat(reason.into(), s[..at].chars().count() + 1)
+}
+
+fn is_name_char(c: char) -> bool {
+  c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
 }
 
 impl Deref for Template {
@@ -61,78 +422,71 @@ impl Display for Template {
   }
 }
 
-#[derive(PartialEq)]
-enum State {
-  Opened,
-  Digit,
-  Closed,
+/// How far along filling in a template's args from the input is
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub enum TemplatingState {
+  #[default]
+  NotStarted,
+  Started,
+  Complete,
 }
 
-impl FromStr for Template {
+impl TemplatingState {
+  pub fn templating(&self) -> bool {
+    *self != TemplatingState::NotStarted
+  }
+
+  pub fn is_complete(&self) -> bool {
+    *self == TemplatingState::Complete
+  }
+}
+
+/// A `Template` that can also have named markers, like `{date}`, for values that are
+/// filled in by whatever expands it rather than typed in as args
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize, FormFieldData)]
+#[serde(try_from = "String", into = "String")]
+pub struct NamedTemplate(Template);
+
+impl Deref for NamedTemplate {
+  type Target = Template;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+impl FromStr for NamedTemplate {
   type Err = TemplateError;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    let mut seen = 0x1FF; // Tracking 0-9 as bit vec
-    let mut markers = 0;
-    let mut state = State::Closed;
-
-    for c in s.chars() {
-      match c {
-        '{' => {
-          if state != State::Closed {
-            return Err(TemplateError::InvalidFormat(
-              "Missing closing marker: }".into(),
-            ));
-          }
-          state = State::Opened;
-        }
-        '}' => {
-          if state != State::Digit {
-            return Err(TemplateError::InvalidFormat(
-              "Did not contain number between markers".into(),
-            ));
-          }
-          state = State::Closed;
-        }
-        k => {
-          if state != State::Opened {
-            continue;
-          }
-          let Some(d) = k.to_digit(10) else {
-            return Err(TemplateError::InvalidFormat(format!(
-              "Marker contains not a digit: {}",
-              k
-            )));
-          };
-          if seen & 1 << d == 0 {
-            return Err(TemplateError::InvalidFormat(format!(
-              "Marker repeated digit: {}",
-              d
-            )));
-          }
-          seen ^= 1 << d;
-          markers += 1;
-          state = State::Digit;
-        }
-      }
-    }
+    Template::parse(s, true).map(NamedTemplate)
+  }
+}
 
-    if 0x1FF != seen + (0..markers).fold(0, |acc, x| acc | 1 << x) {
-      return Err(TemplateError::InvalidFormat(
-        "Markers are not sequential from 0".into(),
-      ));
-    }
+impl TryFrom<String> for NamedTemplate {
+  type Error = TemplateError;
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    value.parse()
+  }
+}
 
-    if state != State::Closed {
-      return Err(TemplateError::InvalidFormat(
-        "Missing closing marker: }".into(),
-      ));
-    }
+impl From<NamedTemplate> for String {
+  fn from(s: NamedTemplate) -> String {
+    s.0.val
+  }
+}
 
-    Ok(Template {
-      val: s.to_string(),
-      markers,
-    })
+impl Display for NamedTemplate {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    self.0.fmt(f)
+  }
+}
+
+impl FromStr for Template {
+  type Err = TemplateError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Template::parse(s, false)
   }
 }
 
@@ -145,13 +499,14 @@ impl TryFrom<String> for Template {
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum TemplateError {
-  InvalidFormat(String),
+  /// Why the template's invalid, and which character it's at counting from 1
+  InvalidFormat(String, usize),
   HydrateError(String),
 }
 impl Display for TemplateError {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
-      TemplateError::InvalidFormat(reason) => f.write_str(reason),
+      TemplateError::InvalidFormat(reason, at) => write!(f, "{} at character {}", reason, at),
       TemplateError::HydrateError(reason) => f.write_str(reason),
     }
   }
@@ -170,7 +525,9 @@ mod tests {
       Template::from_str(inp),
       Ok(Template {
         val: inp.to_owned(),
-        markers: 0
+        markers: 0,
+        named: false,
+        segments: segments(inp).unwrap(),
       })
     );
   }
@@ -182,7 +539,9 @@ mod tests {
       Template::from_str(inp),
       Ok(Template {
         val: inp.to_owned(),
-        markers: 1
+        markers: 1,
+        named: false,
+        segments: segments(inp).unwrap(),
       })
     );
   }
@@ -194,7 +553,9 @@ mod tests {
       Template::from_str(inp),
       Ok(Template {
         val: inp.to_owned(),
-        markers: 2
+        markers: 2,
+        named: false,
+        segments: segments(inp).unwrap(),
       })
     );
   }
@@ -205,7 +566,8 @@ mod tests {
     assert_eq!(
       Template::from_str(inp),
       Err(TemplateError::InvalidFormat(
-        "Missing closing marker: }".into()
+        "Missing closing marker: }".into(),
+        26
       ))
     );
   }
@@ -216,7 +578,8 @@ mod tests {
     assert_eq!(
       Template::from_str(inp),
       Err(TemplateError::InvalidFormat(
-        "Did not contain number between markers".into()
+        "Did not contain number between markers".into(),
+        27
       ))
     );
   }
@@ -227,7 +590,8 @@ mod tests {
     assert_eq!(
       Template::from_str(inp),
       Err(TemplateError::InvalidFormat(
-        "Missing closing marker: }".into()
+        "Missing closing marker: }".into(),
+        26
       ))
     );
   }
@@ -238,19 +602,20 @@ mod tests {
     assert_eq!(
       Template::from_str(inp),
       Err(TemplateError::InvalidFormat(
-        "Missing closing marker: }".into()
+        "Missing closing marker: }".into(),
+        26
       ))
     );
   }
 
   #[test]
-  fn nested_braces() {
+  fn escaped_braces() {
     let inp = "https://www.google.com?q={{1}}&r={0}";
+    let tmpl = Template::from_str(inp).unwrap();
+    assert_eq!(tmpl.markers, 1);
     assert_eq!(
-      Template::from_str(inp),
-      Err(TemplateError::InvalidFormat(
-        "Missing closing marker: }".into()
-      ))
+      tmpl.hydrate(&vec!["dogs".into()]),
+      Ok("https://www.google.com?q={1}&r=dogs".into())
     );
   }
 
@@ -260,7 +625,8 @@ mod tests {
     assert_eq!(
       Template::from_str(inp),
       Err(TemplateError::InvalidFormat(
-        "Did not contain number between markers".into()
+        "Did not contain number between markers".into(),
+        32
       ))
     );
   }
@@ -271,7 +637,8 @@ mod tests {
     assert_eq!(
       Template::from_str(inp),
       Err(TemplateError::InvalidFormat(
-        "Markers are not sequential from 0".into()
+        "Markers are not sequential from 0".into(),
+        26
       ))
     );
   }
@@ -282,7 +649,8 @@ mod tests {
     assert_eq!(
       Template::from_str(inp),
       Err(TemplateError::InvalidFormat(
-        "Markers are not sequential from 0".into()
+        "Markers are not sequential from 0".into(),
+        32
       ))
     );
   }
@@ -322,6 +690,52 @@ mod tests {
     );
   }
 
+  #[test]
+  fn named_markers_are_args_unless_expanded() {
+    let inp = "Sent {date} to {0}";
+    let tmpl = Template::from_str(inp).unwrap();
+    assert_eq!(tmpl.markers, 2);
+    assert_eq!(
+      tmpl.hydrate(&vec!["Jo".into(), "today".into()]),
+      Ok("Sent today to Jo".into())
+    );
+    let named = NamedTemplate::from_str(inp).unwrap();
+    assert_eq!(named.markers, 1);
+    assert_eq!(named.names(), vec!["date"]);
+    assert!(NamedTemplate::from_str("{Date}").is_err());
+    assert!(NamedTemplate::from_str("{date").is_err());
+  }
+
+  #[test]
+  fn expand_fills_args_and_names_once() {
+    let named = NamedTemplate::from_str("{greeting} {0}, {1} {nope}").unwrap();
+    let args = vec!["{1}".into(), "bye".into()];
+    let res = named.expand(&args, |name| (name == "greeting").then(|| "{0}".into()));
+    assert_eq!(res, Ok("{0} {1}, bye {nope}".into()));
+    assert!(named.expand(&args[..1], |_| None).is_err());
+  }
+
+  #[test]
+  fn args_follow_the_keyword() {
+    let tmpl = Template::from_str("{0} and {1}").unwrap();
+    assert_eq!(
+      tmpl.args_for("kw", "kw"),
+      (TemplatingState::NotStarted, vec![])
+    );
+    assert_eq!(
+      tmpl.args_for("kw", "kwx a"),
+      (TemplatingState::NotStarted, vec![])
+    );
+    assert_eq!(
+      tmpl.args_for("kw", "kw a"),
+      (TemplatingState::Started, vec!["a".into()])
+    );
+    assert_eq!(
+      tmpl.args_for("kw", "kw a b c"),
+      (TemplatingState::Complete, vec!["a".into(), "b c".into()])
+    );
+  }
+
   #[test]
   fn hydrate_out_of_order() {
     let inp = vec!["dogs".into(), "cats".into()];
@@ -332,4 +746,58 @@ mod tests {
       Ok("https://www.google.com?q=cats&r=dogs".into())
     );
   }
+
+  #[test]
+  fn defaults_fill_in_missing_args() {
+    let tmpl = Template::from_str("https://example.com/{0}?lang={lang=en}").unwrap();
+    assert_eq!((tmpl.markers, tmpl.required()), (2, 1));
+    assert_eq!(
+      tmpl.hydrate(&vec!["rust".into()]),
+      Ok("https://example.com/rust?lang=en".into())
+    );
+    assert_eq!(
+      tmpl.hydrate(&vec!["rust".into(), "de".into()]),
+      Ok("https://example.com/rust?lang=de".into())
+    );
+    assert_eq!(
+      tmpl.args_for("kw", "kw rust"),
+      (TemplatingState::Complete, vec!["rust".into()])
+    );
+    let named = NamedTemplate::from_str("{clipboard=nothing} {0=}").unwrap();
+    assert_eq!(named.expand(&[], |_| None), Ok("nothing ".into()));
+  }
+
+  #[test]
+  fn filters_apply_in_order() {
+    let tmpl = Template::from_str("q={0|lower|urlencode}&b={0|base64}&u={0|upper}").unwrap();
+    assert_eq!(
+      tmpl.hydrate(&vec!["Cats & Dogs".into()]),
+      Ok("q=cats%20%26%20dogs&b=Q2F0cyAmIERvZ3M=&u=CATS & DOGS".into())
+    );
+    assert_eq!(
+      tmpl.partial_hydrate(&vec![]),
+      "q={0|lower|urlencode}&b={0|base64}&u={0|upper}"
+    );
+  }
+
+  #[test]
+  fn errors_say_which_character() {
+    let err = |inp: &str| Template::from_str(inp).unwrap_err();
+    assert_eq!(
+      err("é {0|shout}"),
+      TemplateError::InvalidFormat("Unknown filter: shout".into(), 6)
+    );
+    assert_eq!(
+      err("{0}{query-x}"),
+      TemplateError::InvalidFormat("Marker name contains an invalid character: -".into(), 10)
+    );
+    assert_eq!(
+      err("{0}}").to_string(),
+      "Did not contain number between markers at character 4"
+    );
+    let de = serde_json::from_str::<Template>("\"{1}\"").unwrap_err();
+    assert!(de
+      .to_string()
+      .contains("Markers are not sequential from 0 at character 1"));
+  }
 }
@@ -0,0 +1,62 @@
+//! Scratch directories for tests that need real files, cleaned up once the test's done
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// An empty directory under the system temp dir that's removed again when dropped, so keep
+/// it alive for as long as the test uses what's in it
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+  /// The name's only there to tell directories apart when one gets left behind, each call
+  /// gets its own directory either way
+  pub fn new(name: &str) -> Self {
+    let dir = std::env::temp_dir().join(format!(
+      "lyra-{}-{}-{}",
+      name,
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    TestDir(dir)
+  }
+}
+
+impl Deref for TestDir {
+  type Target = Path;
+
+  fn deref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl AsRef<Path> for TestDir {
+  fn as_ref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for TestDir {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_dir_all(&self.0);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn removed_once_dropped() {
+    let dir = TestDir::new("testdir");
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("nested/file"), "contents").unwrap();
+    let path = dir.to_path_buf();
+    assert_ne!(path, TestDir::new("testdir").to_path_buf());
+    drop(dir);
+    assert!(!path.exists());
+  }
+}
@@ -0,0 +1,402 @@
+mod guest;
+
+use crate::config::Config;
+use crate::external::{protocol::ExternalItem, ExternalValue};
+use crate::plugin::{
+  AppState, FuzzyMatchItem, OkAction, Plugin, PluginName, PluginV, PluginValue, Renderable,
+  SearchBlocker,
+};
+use crate::plugin_registry::PluginRegistry;
+use anyhow::Context;
+use guest::{Guest, Host, SystemHost};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+use tracing::{error, info};
+use wasmtime::{Config as WasmConfig, Engine, Module};
+
+/// Directory under the config dir holding `<name>.wasm` modules and their `<name>.toml`
+/// manifests
+pub const PLUGINS_DIR: &str = "plugins";
+
+/// Hosts a third party plugin compiled to WebAssembly. Modules run sandboxed: they can only
+/// compute over what they're given, bounded in memory and time, unless their manifest
+/// declares a capability granting them a specific host side effect.
+pub struct WasmPlugin {
+  name: PluginName,
+  /// Both read once at load, as they're asked for on the UI thread, which mustn't wait for
+  /// a query to be done with `guest`
+  prefix: Option<String>,
+  has_static_items: bool,
+  guest: Mutex<Guest>,
+  /// Actions come from the UI thread, so they run in an instance of their own rather than
+  /// waiting for a query to be done with `guest`
+  actions: Mutex<Guest>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WasmManifest {
+  pub capabilities: Vec<Capability>,
+  #[serde(skip)]
+  pub module: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+  OpenUrl,
+  ClipboardSet,
+}
+
+impl Capability {
+  /// The capability a host import needs, if any
+  fn required_by(import: &str) -> Option<Capability> {
+    match import {
+      "open_url" => Some(Capability::OpenUrl),
+      "clipboard_set" => Some(Capability::ClipboardSet),
+      _ => None,
+    }
+  }
+}
+
+/// Shares the presentation of external plugin results; only the plugin producing it differs
+#[derive(Clone, Debug)]
+pub struct WasmValue(pub ExternalValue);
+
+impl PluginValue for WasmValue {
+  fn item_id(&self) -> Option<String> {
+    self.0.item_id()
+  }
+}
+impl Renderable for WasmValue {
+  fn render(&self, ui: &mut egui::Ui, state: &AppState) {
+    self.0.render(ui, state)
+  }
+}
+impl SearchBlocker for WasmValue {}
+
+/// Finds every module in the plugins dir, keyed by the name it will be enabled under.
+/// Modules with a manifest that can't be read are skipped, so they don't hold up the rest
+pub fn discover(dir: &Path) -> Result<HashMap<PluginName, WasmManifest>, anyhow::Error> {
+  if !dir.exists() {
+    return Ok(HashMap::new());
+  }
+  let found = dir
+    .read_dir()?
+    .filter_map(Result::ok)
+    .map(|de| de.path())
+    .filter(|p| p.extension().is_some_and(|ext| ext == "wasm"))
+    .filter_map(|module| {
+      let name = module
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+      match read_manifest(&module) {
+        Ok(manifest) => Some((name, WasmManifest { module, ..manifest })),
+        Err(err) => {
+          error!("Skipping wasm plugin {}: {:?}", name, err);
+          None
+        }
+      }
+    })
+    .collect();
+  Ok(found)
+}
+
+/// The manifest alongside the module, which is optional
+fn read_manifest(module: &Path) -> Result<WasmManifest, anyhow::Error> {
+  let manifest_file = module.with_extension("toml");
+  if !manifest_file.exists() {
+    return Ok(WasmManifest::default());
+  }
+  let manifest = fs::read_to_string(&manifest_file)
+    .with_context(|| format!("Failed to read manifest {:?}", manifest_file))?;
+  toml::from_str(&manifest).with_context(|| format!("Invalid manifest {:?}", manifest_file))
+}
+
+/// Every module in the plugins dir is a plugin, enabled under its file name
+pub fn register(registry: &mut PluginRegistry, config: &Config) -> Result<(), anyhow::Error> {
+  let found = discover(&config.conf_dir.join(PLUGINS_DIR)).unwrap_or_else(|err| {
+    error!("Failed to look for wasm plugins: {:?}", err);
+    HashMap::new()
+  });
+  for (name, manifest) in found {
+    registry.register(&name, move |ctx| {
+      Ok(Box::new(WasmPlugin::init(ctx.name, &manifest)?))
+    });
+  }
+  Ok(())
+}
+
+impl WasmPlugin {
+  pub fn init(name: &str, manifest: &WasmManifest) -> Result<Self, anyhow::Error> {
+    let bytes = fs::read(&manifest.module)
+      .with_context(|| format!("Failed to read plugin {:?}", manifest.module))?;
+    WasmPlugin::from_bytes(name, &bytes, manifest, || Box::new(SystemHost::default()))
+  }
+
+  /// Each instance of the module gets a host of its own from `host`
+  fn from_bytes(
+    name: &str,
+    bytes: &[u8],
+    manifest: &WasmManifest,
+    host: impl Fn() -> Box<dyn Host>,
+  ) -> Result<Self, anyhow::Error> {
+    let mut cfg = WasmConfig::new();
+    cfg.consume_fuel(true);
+    let engine = Engine::new(&cfg)?;
+    let module = Module::new(&engine, bytes)?;
+    let load = || Guest::load(name, &engine, &module, &manifest.capabilities, host());
+    let mut guest = load()?;
+    let prefix = guest.prefix()?;
+    info!(
+      "Loaded wasm plugin {} with {:?}",
+      name, manifest.capabilities
+    );
+    Ok(WasmPlugin {
+      name: name.to_string(),
+      prefix,
+      has_static_items: guest.has_static_items(),
+      guest: Mutex::new(guest),
+      actions: Mutex::new(load()?),
+    })
+  }
+
+  pub fn name(&self) -> &PluginName {
+    &self.name
+  }
+
+  fn to_fuzzy(&self, search: &str, item: ExternalItem) -> FuzzyMatchItem {
+    FuzzyMatchItem {
+      // Prefixed plugins own the search entirely, same as external plugins
+      against: match self.prefix {
+        Some(_) => Arc::new(search.to_owned()),
+        None => Arc::new(item.label.clone()),
+      },
+      value: PluginV::new(
+        self.name.clone(),
+        WasmValue(ExternalValue {
+          plugin: self.name.clone(),
+          item,
+        }),
+      ),
+      source: self.name.clone(),
+    }
+  }
+}
+
+impl Plugin for WasmPlugin {
+  type PV = WasmValue;
+
+  fn prefix(&self) -> Option<String> {
+    self.prefix.clone()
+  }
+
+  fn action(&self, input: &WasmValue) -> Result<OkAction, anyhow::Error> {
+    self
+      .actions
+      .lock()
+      .action(&input.0.item)
+      .map(|resp| OkAction {
+        close_win: resp.close_win,
+        update_input: resp.update_input,
+        ..Default::default()
+      })
+  }
+
+  fn options(&self, search: &str) -> Vec<FuzzyMatchItem> {
+    let query = match &self.prefix {
+      Some(pre) => search.strip_prefix(pre.as_str()).unwrap_or(search),
+      None => search,
+    };
+    match self.guest.lock().options(query) {
+      Ok(items) => items
+        .into_iter()
+        .map(|item| self.to_fuzzy(search, item))
+        .collect(),
+      Err(err) => {
+        error!("Plugin {} failed: {:?}", self.name, err);
+        vec![]
+      }
+    }
+  }
+
+  fn has_static_items(&self) -> bool {
+    self.has_static_items
+  }
+
+  fn static_items(&self) -> Vec<FuzzyMatchItem> {
+    match self.guest.lock().static_items() {
+      Ok(items) => items
+        .into_iter()
+        .map(|item| self.to_fuzzy("", item))
+        .collect(),
+      Err(err) => {
+        error!("Plugin {} failed: {:?}", self.name, err);
+        vec![]
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Always answers with the same items, and opens a url when actioned
+  const HELLO: &str = r#"
+    (module
+      (import "lyra" "open_url" (func $open_url (param i32 i32) (result i32)))
+      (memory (export "memory") 1)
+      (global $heap (mut i32) (i32.const 1024))
+      (data (i32.const 0) "w ")
+      (data (i32.const 16) "[{\"label\":\"hello\",\"subtitle\":\"from wasm\",\"action\":\"https://example.com\"}]")
+      (data (i32.const 128) "{\"close_win\":true}")
+      (data (i32.const 160) "https://example.com")
+      (data (i32.const 192) "[{\"label\":\"always\"}]")
+      (func (export "lyra_alloc") (param $len i32) (result i32)
+        (local $ptr i32)
+        (local.set $ptr (global.get $heap))
+        (global.set $heap (i32.add (global.get $heap) (local.get $len)))
+        (local.get $ptr))
+      (func (export "lyra_prefix") (result i64)
+        (i64.const 2))
+      (func (export "lyra_options") (param i32 i32) (result i64)
+        (i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const 73)))
+      (func (export "lyra_static_items") (result i64)
+        (i64.or (i64.shl (i64.const 192) (i64.const 32)) (i64.const 20)))
+      (func (export "lyra_action") (param i32 i32) (result i64)
+        (drop (call $open_url (i32.const 160) (i32.const 19)))
+        (i64.or (i64.shl (i64.const 128) (i64.const 32)) (i64.const 18))))
+  "#;
+
+  // Never returns from a query
+  const SPIN: &str = r#"
+    (module
+      (memory (export "memory") 1)
+      (func (export "lyra_alloc") (param i32) (result i32) (i32.const 0))
+      (func (export "lyra_options") (param i32 i32) (result i64)
+        (loop $forever (br $forever))
+        (i64.const 0))
+      (func (export "lyra_action") (param i32 i32) (result i64) (i64.const 0)))
+  "#;
+
+  #[derive(Clone, Default)]
+  struct Recorder(Arc<Mutex<Vec<String>>>);
+  impl Host for Recorder {
+    fn open_url(&mut self, url: &str) -> Result<(), anyhow::Error> {
+      self.0.lock().push(format!("open:{}", url));
+      Ok(())
+    }
+    fn set_clipboard(&mut self, text: &str) -> Result<(), anyhow::Error> {
+      self.0.lock().push(format!("clip:{}", text));
+      Ok(())
+    }
+  }
+
+  fn load(
+    wat: &str,
+    capabilities: Vec<Capability>,
+    host: Recorder,
+  ) -> Result<WasmPlugin, anyhow::Error> {
+    let manifest = WasmManifest {
+      capabilities,
+      ..Default::default()
+    };
+    WasmPlugin::from_bytes("test", wat.as_bytes(), &manifest, || Box::new(host.clone()))
+  }
+
+  fn item(fm: &FuzzyMatchItem) -> &ExternalItem {
+    &fm
+      .value
+      .downcast::<WasmValue>()
+      .expect("Unexpected plugin value")
+      .0
+      .item
+  }
+
+  #[test]
+  fn exposes_prefix_options_and_static_items() {
+    let plugin = load(HELLO, vec![Capability::OpenUrl], Recorder::default()).unwrap();
+    assert_eq!(plugin.prefix(), Some("w ".into()));
+
+    let items = plugin.options("w dogs");
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].as_ref(), "w dogs");
+    assert_eq!(items[0].source, "test");
+    assert_eq!(item(&items[0]).label, "hello");
+    assert_eq!(item(&items[0]).subtitle, Some("from wasm".into()));
+
+    // Deciding which plugins to ask mustn't wait for whatever query has the plugin
+    let querying = plugin.guest.lock();
+    assert_eq!(plugin.prefix(), Some("w ".into()));
+    assert!(plugin.has_static_items());
+    drop(querying);
+    let items = plugin.static_items();
+    assert_eq!(item(&items[0]).label, "always");
+  }
+
+  #[test]
+  fn action_uses_granted_capability() {
+    let host = Recorder::default();
+    let plugin = load(HELLO, vec![Capability::OpenUrl], host.clone()).unwrap();
+    let items = plugin.options("w dogs");
+    let value = items[0].value.downcast::<WasmValue>().unwrap();
+    // Acting mustn't wait for whatever query has the plugin
+    let _querying = plugin.guest.lock();
+    let res = plugin.action(value).unwrap();
+    assert!(res.close_win);
+    assert_eq!(*host.0.lock(), vec!["open:https://example.com".to_string()]);
+  }
+
+  #[test]
+  fn undeclared_capability_is_refused() {
+    let err = load(HELLO, vec![Capability::ClipboardSet], Recorder::default())
+      .err()
+      .unwrap();
+    assert!(err.to_string().contains("lyra.open_url"));
+  }
+
+  #[test]
+  fn runaway_plugin_is_stopped() {
+    let plugin = load(SPIN, vec![], Recorder::default()).unwrap();
+    assert_eq!(plugin.prefix(), None);
+    assert!(!plugin.has_static_items());
+    assert!(plugin.options("dogs").is_empty());
+  }
+
+  #[test]
+  fn discovers_modules_and_manifests() {
+    let dir = std::env::temp_dir().join(format!("lyra-wasm-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hello.wasm"), HELLO).unwrap();
+    fs::write(dir.join("hello.toml"), "capabilities = [\"open-url\"]").unwrap();
+    fs::write(dir.join("bare.wasm"), SPIN).unwrap();
+    fs::write(dir.join("broken.wasm"), SPIN).unwrap();
+    fs::write(dir.join("broken.toml"), "capabilities = [\"everything\"]").unwrap();
+    fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+    let found = discover(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(found.len(), 2);
+    assert_eq!(found["hello"].capabilities, vec![Capability::OpenUrl]);
+    assert_eq!(found["hello"].module, dir.join("hello.wasm"));
+    assert!(found["bare"].capabilities.is_empty());
+  }
+
+  #[test]
+  fn only_web_urls_are_opened() {
+    assert!(guest::web_url("https://example.com/?q=a b").is_ok());
+    assert!(guest::web_url("HTTP://example.com").is_ok());
+    assert!(guest::web_url("file:///etc/passwd").is_err());
+    assert!(guest::web_url("/usr/share/applications/evil.desktop").is_err());
+    assert!(guest::web_url("javascript:alert(1)").is_err());
+  }
+}
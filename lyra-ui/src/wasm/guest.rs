@@ -0,0 +1,255 @@
+use super::Capability;
+use crate::external::protocol::{ExternalItem, Response};
+use anyhow::{anyhow, Context};
+use arboard::Clipboard;
+use tracing::{info, warn};
+use url::Url;
+use wasmtime::{
+  Caller, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+/// Upper bound on the work a module may do per call, so a runaway plugin traps instead of
+/// hanging the bar. Roughly a count of wasm instructions.
+const FUEL_PER_CALL: u64 = 100_000_000;
+const MAX_MEMORY_BYTES: usize = 64 << 20;
+const HOST_MODULE: &str = "lyra";
+
+/// Host side effects a module can ask for, each gated by a capability in its manifest
+pub trait Host: Send {
+  fn open_url(&mut self, url: &str) -> Result<(), anyhow::Error>;
+  fn set_clipboard(&mut self, text: &str) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Default)]
+pub struct SystemHost {
+  clip: Option<Clipboard>,
+}
+
+impl Host for SystemHost {
+  fn open_url(&mut self, url: &str) -> Result<(), anyhow::Error> {
+    open::that(web_url(url)?.as_str()).map_err(|err| err.into())
+  }
+
+  fn set_clipboard(&mut self, text: &str) -> Result<(), anyhow::Error> {
+    let clip = match self.clip.take() {
+      Some(clip) => clip,
+      None => Clipboard::new()?,
+    };
+    self
+      .clip
+      .insert(clip)
+      .set_text(text)
+      .map_err(|err| anyhow!(err))
+  }
+}
+
+/// Only web pages can be opened, rather than anything `open` would take like files, scripts
+/// or desktop entries that would run whatever they say
+pub fn web_url(url: &str) -> Result<Url, anyhow::Error> {
+  let parsed = Url::parse(url).with_context(|| format!("Invalid url {:?}", url))?;
+  match parsed.scheme() {
+    "http" | "https" => Ok(parsed),
+    scheme => Err(anyhow!("Can't open {} urls, only http and https", scheme)),
+  }
+}
+
+pub struct HostState {
+  name: String,
+  host: Box<dyn Host>,
+  limits: StoreLimits,
+}
+
+/// An instantiated module along with the exports making up the plugin ABI. Strings cross
+/// the boundary as UTF-8 in the module's memory: the host asks `lyra_alloc` for space to
+/// write its input into, and the module answers with its output's location packed into an
+/// i64 as `ptr << 32 | len`. Structured data is the same JSON the external plugins speak.
+///
+/// Exports: memory, lyra_alloc(len) -> ptr, lyra_options(ptr, len) -> packed,
+///          lyra_action(ptr, len) -> packed, and optionally lyra_prefix() -> packed,
+///          lyra_static_items() -> packed
+/// Imports: lyra.log(ptr, len), and with capabilities lyra.open_url(ptr, len) -> status,
+///          lyra.clipboard_set(ptr, len) -> status
+pub struct Guest {
+  store: Store<HostState>,
+  memory: Memory,
+  alloc: TypedFunc<i32, i32>,
+  prefix: Option<TypedFunc<(), i64>>,
+  options: TypedFunc<(i32, i32), i64>,
+  action: TypedFunc<(i32, i32), i64>,
+  static_items: Option<TypedFunc<(), i64>>,
+}
+
+impl Guest {
+  pub fn load(
+    name: &str,
+    engine: &Engine,
+    module: &Module,
+    capabilities: &[Capability],
+    host: Box<dyn Host>,
+  ) -> Result<Guest, anyhow::Error> {
+    for import in module.imports() {
+      let granted = import.module() == HOST_MODULE
+        && match Capability::required_by(import.name()) {
+          Some(cap) => capabilities.contains(&cap),
+          None => import.name() == "log",
+        };
+      if !granted {
+        return Err(anyhow!(
+          "Plugin {} imports {}.{} which its manifest does not grant",
+          name,
+          import.module(),
+          import.name()
+        ));
+      }
+    }
+
+    let mut linker: Linker<HostState> = Linker::new(engine);
+    linker.func_wrap(
+      HOST_MODULE,
+      "log",
+      |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<()> {
+        let msg = read_str(&mut caller, ptr, len)?;
+        info!("[{}] {}", caller.data().name, msg);
+        Ok(())
+      },
+    )?;
+    if capabilities.contains(&Capability::OpenUrl) {
+      linker.func_wrap(
+        HOST_MODULE,
+        "open_url",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<i32> {
+          let url = read_str(&mut caller, ptr, len)?;
+          let state = caller.data_mut();
+          Ok(status(&state.name, state.host.open_url(&url)))
+        },
+      )?;
+    }
+    if capabilities.contains(&Capability::ClipboardSet) {
+      linker.func_wrap(
+        HOST_MODULE,
+        "clipboard_set",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<i32> {
+          let text = read_str(&mut caller, ptr, len)?;
+          let state = caller.data_mut();
+          Ok(status(&state.name, state.host.set_clipboard(&text)))
+        },
+      )?;
+    }
+
+    let mut store = Store::new(
+      engine,
+      HostState {
+        name: name.to_string(),
+        host,
+        limits: StoreLimitsBuilder::new()
+          .memory_size(MAX_MEMORY_BYTES)
+          .build(),
+      },
+    );
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(FUEL_PER_CALL)?;
+    let instance = linker
+      .instantiate(&mut store, module)
+      .with_context(|| format!("Failed to instantiate plugin {}", name))?;
+
+    Ok(Guest {
+      memory: instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| anyhow!("Plugin {} does not export its memory", name))?,
+      alloc: instance.get_typed_func(&mut store, "lyra_alloc")?,
+      prefix: instance.get_typed_func(&mut store, "lyra_prefix").ok(),
+      options: instance.get_typed_func(&mut store, "lyra_options")?,
+      action: instance.get_typed_func(&mut store, "lyra_action")?,
+      static_items: instance
+        .get_typed_func(&mut store, "lyra_static_items")
+        .ok(),
+      store,
+    })
+  }
+
+  pub fn prefix(&mut self) -> Result<Option<String>, anyhow::Error> {
+    let Some(func) = self.prefix.clone() else {
+      return Ok(None);
+    };
+    self.store.set_fuel(FUEL_PER_CALL)?;
+    let packed = func.call(&mut self.store, ())?;
+    let prefix = String::from_utf8(self.read(packed)?)?;
+    Ok(Some(prefix).filter(|p| !p.is_empty()))
+  }
+
+  pub fn options(&mut self, search: &str) -> Result<Vec<ExternalItem>, anyhow::Error> {
+    let out = self.call(self.options.clone(), search.as_bytes())?;
+    serde_json::from_slice(&out).context("Plugin returned invalid items")
+  }
+
+  pub fn action(&mut self, item: &ExternalItem) -> Result<Response, anyhow::Error> {
+    let out = self.call(self.action.clone(), &serde_json::to_vec(item)?)?;
+    let resp: Response = serde_json::from_slice(&out).context("Plugin returned invalid outcome")?;
+    match resp.error {
+      Some(err) => Err(anyhow!(err)),
+      None => Ok(resp),
+    }
+  }
+
+  pub fn has_static_items(&self) -> bool {
+    self.static_items.is_some()
+  }
+
+  pub fn static_items(&mut self) -> Result<Vec<ExternalItem>, anyhow::Error> {
+    let Some(func) = self.static_items.clone() else {
+      return Ok(vec![]);
+    };
+    self.store.set_fuel(FUEL_PER_CALL)?;
+    let packed = func.call(&mut self.store, ())?;
+    serde_json::from_slice(&self.read(packed)?).context("Plugin returned invalid items")
+  }
+
+  fn call(
+    &mut self,
+    func: TypedFunc<(i32, i32), i64>,
+    input: &[u8],
+  ) -> Result<Vec<u8>, anyhow::Error> {
+    self.store.set_fuel(FUEL_PER_CALL)?;
+    let len = i32::try_from(input.len())?;
+    let ptr = self.alloc.call(&mut self.store, len)?;
+    self
+      .memory
+      .write(&mut self.store, ptr as u32 as usize, input)?;
+    let packed = func.call(&mut self.store, (ptr, len))?;
+    self.read(packed)
+  }
+
+  fn read(&self, packed: i64) -> Result<Vec<u8>, anyhow::Error> {
+    let ptr = (packed as u64 >> 32) as usize;
+    let len = (packed as u64 & 0xFFFF_FFFF) as usize;
+    self
+      .memory
+      .data(&self.store)
+      .get(ptr..ptr + len)
+      .map(|bytes| bytes.to_vec())
+      .ok_or_else(|| anyhow!("Plugin returned out of bounds memory"))
+  }
+}
+
+fn read_str(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> anyhow::Result<String> {
+  let memory = caller
+    .get_export("memory")
+    .and_then(|ex| ex.into_memory())
+    .ok_or_else(|| anyhow!("Plugin does not export its memory"))?;
+  let start = ptr as u32 as usize;
+  let bytes = memory
+    .data(&caller)
+    .get(start..start + len as u32 as usize)
+    .ok_or_else(|| anyhow!("Plugin passed out of bounds memory"))?;
+  Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+fn status(name: &str, res: Result<(), anyhow::Error>) -> i32 {
+  match res {
+    Ok(()) => 0,
+    Err(err) => {
+      warn!("[{}] host call failed: {:?}", name, err);
+      1
+    }
+  }
+}
@@ -3,7 +3,8 @@ use crate::icon_ui::Icon;
 use crate::plugin::{
   AppState, FuzzyMatchItem, OkAction, Plugin, PluginV, PluginValue, Renderable, SearchBlocker,
 };
-use crate::template::Template;
+use crate::plugin_registry::PluginRegistry;
+use crate::template::{Template, TemplatingState};
 use anyhow::anyhow;
 use egui::{RichText, Ui};
 use serde::{Deserialize, Serialize};
@@ -44,13 +45,14 @@ pub struct TemplateData {
   state: TemplatingState,
 }
 
-impl PluginValue for Searcher {}
+impl PluginValue for Searcher {
+  fn item_id(&self) -> Option<String> {
+    Some(self.metadata().label.clone())
+  }
+}
 impl SearchBlocker for Searcher {
   fn blocks_search(&self, state: &AppState) -> bool {
-    state.options.iter().any(|opt| match opt {
-      PluginV::Webq(s) => s.is_non_default_templating(),
-      _ => false,
-    })
+    state.options.iter().any(is_templating_searcher)
   }
 }
 
@@ -80,6 +82,18 @@ impl WebqPlugin {
   }
 }
 
+pub fn register(registry: &mut PluginRegistry) {
+  registry.register(PLUGIN_NAME, |ctx| {
+    Ok(Box::new(WebqPlugin::init(ctx.section()?)?))
+  });
+}
+
+fn is_templating_searcher(opt: &PluginV) -> bool {
+  opt
+    .downcast::<Searcher>()
+    .is_some_and(Searcher::is_non_default_templating)
+}
+
 impl Plugin for WebqPlugin {
   type PV = Searcher;
 
@@ -97,34 +111,29 @@ impl Plugin for WebqPlugin {
       .options
       .clone()
       .into_iter()
-      .map(|opt| match opt {
-        PluginV::Webq(s) => match s.update(&state.input) {
-          Some(su) => PluginV::Webq(su),
-          None => PluginV::Webq(s),
-        },
-        x => x,
+      .map(|opt| {
+        match opt
+          .downcast::<Searcher>()
+          .and_then(|s| s.update(&state.input))
+        {
+          Some(su) => PluginV::new(PLUGIN_NAME, su),
+          None => opt,
+        }
       })
       .collect();
 
-    let is_templating = templates.iter().any(|opt| match opt {
-      PluginV::Webq(s) => s.is_non_default_templating(),
-      _ => false,
-    });
+    let is_templating = templates.iter().any(is_templating_searcher);
 
     let mut new_state: AppState = (*state).clone();
     if is_templating {
-      // TODO this will move selection every time templates
-      // update if multiple match the same prefix or if you type space after partially matching
-      // the prefix. should compute this to instead keep selected item if it's a templating item
-      // otherwise default to 0 with the first one matching
-      new_state.selected = 0;
-      new_state.options = templates
-        .into_iter()
-        .filter(|opt| match opt {
-          PluginV::Webq(s) => s.is_non_default_templating(),
-          _ => false,
-        })
-        .collect();
+      // Keep the selected searcher selected while it's being filled in, falling back to
+      // the first one matching
+      new_state.set_options(
+        templates
+          .into_iter()
+          .filter(is_templating_searcher)
+          .collect(),
+      );
     } else {
       new_state.options = templates;
     }
@@ -137,6 +146,7 @@ impl Plugin for WebqPlugin {
         return Ok(OkAction {
           close_win: false,
           update_input: Some(format!("{} ", md.shortname)),
+          ..Default::default()
         });
       }
     }
@@ -196,20 +206,12 @@ impl From<&WebqSearchConfig> for FuzzyMatchItem {
     let searcher = Into::<Searcher>::into(sh);
     FuzzyMatchItem {
       against: Arc::new(searcher.metadata().shortname.clone()),
-      value: PluginV::Webq(searcher),
+      value: PluginV::new(PLUGIN_NAME, searcher),
       source: PLUGIN_NAME.to_string(),
     }
   }
 }
 
-#[derive(Clone, Default, Debug, PartialEq, Eq)]
-enum TemplatingState {
-  #[default]
-  NotStarted,
-  Started,
-  Complete,
-}
-
 impl Metadata {
   fn is_default(&self) -> bool {
     self.shortname.is_empty()
@@ -261,23 +263,7 @@ impl Searcher {
       };
     }
 
-    let (state, args) = match inp.split_once(" ") {
-      Some((p, _)) if p == sn => {
-        let args: Vec<String> = inp
-          .trim()
-          .splitn(td.template.markers + 1, ' ')
-          .skip(1)
-          .map(|s| s.to_owned())
-          .collect();
-        let state = if args.len() == td.template.markers {
-          TemplatingState::Complete
-        } else {
-          TemplatingState::Started
-        };
-        (state, args)
-      }
-      None | Some(_) => (TemplatingState::NotStarted, Vec::new()),
-    };
+    let (state, args) = td.template.args_for(sn, inp);
 
     if td.state != state || td.args.len() != args.len() || td.args != args {
       // Updates detected
@@ -294,12 +280,95 @@ impl Searcher {
   }
 }
 
-impl TemplatingState {
-  fn templating(&self) -> bool {
-    *self != TemplatingState::NotStarted
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  fn searcher(label: &str, shortname: &str, template: &str) -> WebqSearchConfig {
+    WebqSearchConfig {
+      label: label.into(),
+      shortname: shortname.into(),
+      template: Template::from_str(template).unwrap(),
+      icon: "".into(),
+    }
   }
 
-  fn is_complete(&self) -> bool {
-    *self == TemplatingState::Complete
+  fn plugin() -> WebqPlugin {
+    let searchers = [
+      searcher("Google", "g", "https://google.com?q={0}"),
+      searcher("GitHub", "g", "https://github.com/search?q={0}"),
+      searcher("Gmail", "gm", "https://mail.google.com"),
+    ];
+    WebqPlugin::init(WebqConfig {
+      default_searcher: None,
+      searchers: searchers
+        .into_iter()
+        .map(|s| (s.label.clone(), s))
+        .collect::<HashMap<_, _>>(),
+    })
+    .unwrap()
+  }
+
+  fn labels(state: &AppState) -> Vec<String> {
+    state.options.iter().filter_map(|v| v.item_id()).collect()
+  }
+
+  fn selected(state: &AppState) -> Option<String> {
+    state.selected().and_then(|v| v.item_id())
+  }
+
+  fn searchers(plugin: &WebqPlugin, names: &[&str]) -> Vec<PluginV> {
+    let all = plugin.options("");
+    names
+      .iter()
+      .map(|name| {
+        let found = all
+          .iter()
+          .find(|fm| fm.value.item_id().as_deref() == Some(name));
+        found.unwrap().value.clone()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn templating_keeps_selected_searcher() {
+    let plugin = plugin();
+    let mut state = AppState {
+      input: "g".into(),
+      ..Default::default()
+    };
+    state.set_options(searchers(&plugin, &["Google", "GitHub", "Gmail"]));
+    state.select_next();
+    assert_eq!(selected(&state), Some("GitHub".into()));
+
+    // Starting to fill in the template narrows to the searchers for it
+    state.input = "g ".into();
+    let state = plugin.derive_state(&state).unwrap();
+    assert_eq!(labels(&state), vec!["Google", "GitHub"]);
+    assert_eq!(selected(&state), Some("GitHub".into()));
+
+    let mut state = AppState {
+      input: "g rust".into(),
+      ..state
+    };
+    state = plugin.derive_state(&state).unwrap();
+    assert_eq!(selected(&state), Some("GitHub".into()));
+    assert!(state.selected().unwrap().blocks_search(&state));
+  }
+
+  #[test]
+  fn templating_falls_back_to_first_searcher() {
+    let plugin = plugin();
+    let mut state = AppState {
+      input: "gm".into(),
+      ..Default::default()
+    };
+    state.set_options(searchers(&plugin, &["Gmail", "Google", "GitHub"]));
+    assert_eq!(selected(&state), Some("Gmail".into()));
+
+    state.input = "g ".into();
+    let state = plugin.derive_state(&state).unwrap();
+    assert_eq!(selected(&state), Some("Google".into()));
   }
 }